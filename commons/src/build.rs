@@ -0,0 +1,31 @@
+//! Build information, embedded at compile time.
+
+use serde_derive::Serialize;
+
+/// Git commit the services were built from (`unknown` if unavailable).
+static GIT_COMMIT: &str = env!("FCOS_CINCINNATI_GIT_COMMIT");
+
+/// Build time, as a UTC timestamp.
+static BUILD_TIMESTAMP: &str = env!("FCOS_CINCINNATI_BUILD_TIMESTAMP");
+
+/// Build information of a running service, served on `/v1/version`.
+#[derive(Clone, Debug, Serialize)]
+pub struct BuildInfo {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub git_commit: &'static str,
+    /// Build time, as a UTC timestamp.
+    pub build_timestamp: i64,
+}
+
+impl BuildInfo {
+    /// Build information for a service, with its crate name and version.
+    pub fn new(name: &'static str, version: &'static str) -> Self {
+        Self {
+            name,
+            version,
+            git_commit: GIT_COMMIT,
+            build_timestamp: BUILD_TIMESTAMP.parse().unwrap_or_default(),
+        }
+    }
+}
@@ -0,0 +1,275 @@
+//! Configuration helpers shared by all services.
+
+use failure::{bail, format_err, Fallible};
+use serde_derive::Deserialize;
+use std::convert::TryFrom;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// List drop-in configuration fragments for a configuration file.
+///
+/// Fragments are `*.toml` files in a `<config_path>.d/` directory (e.g.
+/// `config.toml.d/` for `config.toml`), returned in lexical order so that
+/// later fragments take precedence. A missing directory yields no fragments.
+pub fn dropin_fragments(config_path: impl AsRef<Path>) -> Fallible<Vec<PathBuf>> {
+    let mut dir = config_path.as_ref().as_os_str().to_owned();
+    dir.push(".d");
+    let dir = PathBuf::from(dir);
+
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => bail!("failed to read directory '{}': {}", dir.display(), e),
+    };
+    let mut fragments = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_file() && path.extension() == Some(OsStr::new("toml")) {
+            fragments.push(path);
+        }
+    }
+    fragments.sort();
+    Ok(fragments)
+}
+
+/// Collect configuration overrides from environment variables.
+///
+/// Variables are named `<prefix><SECTION>_<KEY>`, e.g. `FCOS_GB_SERVICE_PORT`
+/// maps to `port` in the `[service]` section. Values are parsed as TOML values
+/// (e.g. `8080`, `true`, `["a", "b"]`), falling back to plain strings otherwise.
+/// The result is a TOML table with the same layout as a configuration file.
+pub fn env_overrides<I>(prefix: &str, vars: I) -> Fallible<toml::Value>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut root = toml::value::Table::new();
+    for (name, raw) in vars {
+        let var = match name.strip_prefix(prefix) {
+            Some(v) => v,
+            None => continue,
+        };
+        let (section, key) = match var.find('_') {
+            Some(index) if index > 0 && index + 1 < var.len() => (&var[..index], &var[index + 1..]),
+            _ => bail!("invalid environment variable '{}'", name),
+        };
+
+        let value = match toml::from_str::<toml::value::Table>(&format!("v = {}", raw)) {
+            Ok(mut parsed) => parsed.remove("v").unwrap_or_else(|| raw.into()),
+            Err(_) => toml::Value::String(raw),
+        };
+        let section = root
+            .entry(section.to_lowercase())
+            .or_insert_with(|| toml::Value::Table(toml::value::Table::new()));
+        if let toml::Value::Table(table) = section {
+            table.insert(key.to_lowercase(), value);
+        }
+    }
+    Ok(toml::Value::Table(root))
+}
+
+/// Human-friendly duration value (e.g. "30s", "15m", "2h").
+///
+/// A unit suffix is always required, bare numbers are rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct HumanDuration(pub Duration);
+
+impl TryFrom<String> for HumanDuration {
+    type Error = failure::Error;
+
+    fn try_from(input: String) -> Result<Self, Self::Error> {
+        parse_duration(&input).map(HumanDuration)
+    }
+}
+
+/// Human-friendly size value, in bytes (e.g. "512KiB", "10MiB", "1GB").
+///
+/// A unit suffix is always required, bare numbers are rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ByteSize(pub u64);
+
+impl TryFrom<String> for ByteSize {
+    type Error = failure::Error;
+
+    fn try_from(input: String) -> Result<Self, Self::Error> {
+        parse_size(&input).map(ByteSize)
+    }
+}
+
+/// Parse a duration with a mandatory unit suffix (`ms`, `s`, `m`, `h`, `d`).
+pub fn parse_duration(input: &str) -> Fallible<Duration> {
+    let (value, unit) = split_value_unit(input)?;
+    let millis_per_unit: u64 = match unit {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        _ => bail!(
+            "invalid duration '{}': unknown unit '{}' (expected one of: ms, s, m, h, d)",
+            input,
+            unit
+        ),
+    };
+    let millis = value
+        .checked_mul(millis_per_unit)
+        .ok_or_else(|| format_err!("invalid duration '{}': value too large", input))?;
+    Ok(Duration::from_millis(millis))
+}
+
+/// Parse a size in bytes with a mandatory unit suffix (`B`, `KB`, `KiB`, `MB`, `MiB`, `GB`, `GiB`).
+pub fn parse_size(input: &str) -> Fallible<u64> {
+    let (value, unit) = split_value_unit(input)?;
+    let multiplier: u64 = match unit {
+        "B" => 1,
+        "KB" => 1000,
+        "KiB" => 1024,
+        "MB" => 1000 * 1000,
+        "MiB" => 1024 * 1024,
+        "GB" => 1000 * 1000 * 1000,
+        "GiB" => 1024 * 1024 * 1024,
+        _ => bail!(
+            "invalid size '{}': unknown unit '{}' (expected one of: B, KB, KiB, MB, MiB, GB, GiB)",
+            input,
+            unit
+        ),
+    };
+    value
+        .checked_mul(multiplier)
+        .ok_or_else(|| format_err!("invalid size '{}': value too large", input))
+}
+
+/// Split an input like "15m" into its numeric value and unit suffix.
+fn split_value_unit(input: &str) -> Fallible<(u64, &str)> {
+    let trimmed = input.trim();
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(trimmed.len());
+    let (digits, unit) = trimmed.split_at(split);
+    if digits.is_empty() {
+        bail!("invalid value '{}': missing numeric value", input);
+    }
+    let unit = unit.trim_start();
+    if unit.is_empty() {
+        bail!("invalid value '{}': missing unit", input);
+    }
+    let value = digits
+        .parse::<u64>()
+        .map_err(|e| format_err!("invalid value '{}': {}", input, e))?;
+    Ok((value, unit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dropin_fragments() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let config_path = tmpdir.path().join("config.toml");
+        assert!(dropin_fragments(&config_path).unwrap().is_empty());
+
+        let dropins = tmpdir.path().join("config.toml.d");
+        std::fs::create_dir(&dropins).unwrap();
+        for name in &["20-b.toml", "10-a.toml", "30-c.conf"] {
+            std::fs::write(dropins.join(name), "").unwrap();
+        }
+        let fragments = dropin_fragments(&config_path).unwrap();
+        assert_eq!(
+            fragments,
+            vec![dropins.join("10-a.toml"), dropins.join("20-b.toml")]
+        );
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let vars = vec![
+            ("FCOS_GB_SERVICE_PORT", "8090"),
+            ("FCOS_GB_SERVICE_ADDRESS", "::"),
+            (
+                "FCOS_GB_SERVICE_ORIGIN_ALLOWLIST",
+                r#"["https://example.com"]"#,
+            ),
+            ("FCOS_GB_FEATURES_OCI_GRAPHS", "false"),
+            ("FCOS_PE_SERVICE_PORT", "9999"),
+            ("HOME", "/root"),
+        ];
+        let input = vars
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()));
+        let table = env_overrides("FCOS_GB_", input).unwrap();
+
+        let service = table.get("service").unwrap();
+        assert_eq!(service.get("port"), Some(&toml::Value::Integer(8090)));
+        assert_eq!(
+            service.get("address"),
+            Some(&toml::Value::String("::".to_string()))
+        );
+        assert!(service.get("origin_allowlist").unwrap().is_array());
+        let features = table.get("features").unwrap();
+        assert_eq!(
+            features.get("oci_graphs"),
+            Some(&toml::Value::Boolean(false))
+        );
+        assert_eq!(table.as_table().unwrap().len(), 2);
+
+        let invalid = vec![("FCOS_GB_PORT_".to_string(), "1".to_string())];
+        env_overrides("FCOS_GB_", invalid).unwrap_err();
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        let cases = vec![
+            ("250ms", Duration::from_millis(250)),
+            ("30s", Duration::from_secs(30)),
+            ("15m", Duration::from_secs(15 * 60)),
+            ("2h", Duration::from_secs(2 * 60 * 60)),
+            (" 1 d ", Duration::from_secs(24 * 60 * 60)),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_duration(input).unwrap(), expected, "{}", input);
+        }
+
+        let invalid = vec!["", "30", "s", "-5s", "1.5s", "10x", "99999999999999999999d"];
+        for input in invalid {
+            parse_duration(input).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_parse_size() {
+        let cases = vec![
+            ("512B", 512),
+            ("4KB", 4000),
+            ("4KiB", 4096),
+            ("10MiB", 10 * 1024 * 1024),
+            ("1GB", 1_000_000_000),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_size(input).unwrap(), expected, "{}", input);
+        }
+
+        let invalid = vec!["", "1024", "MiB", "10mib", "1TiB"];
+        for input in invalid {
+            parse_size(input).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_deserialize_units() {
+        #[derive(Debug, Deserialize)]
+        struct Sample {
+            timeout: HumanDuration,
+            size: ByteSize,
+        }
+
+        let sample: Sample = serde_json::from_str(r#"{"timeout":"45s","size":"1MiB"}"#).unwrap();
+        assert_eq!(sample.timeout, HumanDuration(Duration::from_secs(45)));
+        assert_eq!(sample.size, ByteSize(1024 * 1024));
+
+        serde_json::from_str::<Sample>(r#"{"timeout":45,"size":"1MiB"}"#).unwrap_err();
+        serde_json::from_str::<Sample>(r#"{"timeout":"45s","size":"1M"}"#).unwrap_err();
+    }
+}
@@ -0,0 +1,147 @@
+//! Feature flags, for gating new behaviors per deployment environment.
+
+use failure::{format_err, Fallible};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+/// Known feature flags.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Feature {
+    /// Serve OCI graphs (i.e. `oci=true` requests).
+    OciGraphs,
+    /// Serve pre-built graph variants per rollout wariness tier.
+    WarinessTiers,
+    /// Serve experimental payload variants to a fraction of nodes.
+    PayloadVariants,
+    /// Serve throttled graphs precomputed per rollout wariness bucket.
+    WarinessBuckets,
+    /// Reject graph requests with unknown query parameters.
+    StrictQueries,
+    /// Reject graph requests with a malformed `rollout_wariness`.
+    StrictWariness,
+    /// Salt node-derived rollout wariness per release.
+    SaltedWariness,
+}
+
+impl Feature {
+    /// All known feature flags.
+    pub const ALL: [Feature; 7] = [
+        Feature::OciGraphs,
+        Feature::WarinessTiers,
+        Feature::PayloadVariants,
+        Feature::WarinessBuckets,
+        Feature::StrictQueries,
+        Feature::StrictWariness,
+        Feature::SaltedWariness,
+    ];
+
+    /// Stable name of this flag, as used in configuration and status output.
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::OciGraphs => "oci_graphs",
+            Feature::WarinessTiers => "wariness_tiers",
+            Feature::PayloadVariants => "payload_variants",
+            Feature::WarinessBuckets => "wariness_buckets",
+            Feature::StrictQueries => "strict_queries",
+            Feature::StrictWariness => "strict_wariness",
+            Feature::SaltedWariness => "salted_wariness",
+        }
+    }
+
+    /// Whether this flag is enabled when not explicitly configured.
+    pub fn default_state(self) -> bool {
+        match self {
+            Feature::OciGraphs => true,
+            Feature::WarinessTiers => false,
+            Feature::PayloadVariants => false,
+            Feature::WarinessBuckets => false,
+            Feature::StrictQueries => false,
+            Feature::StrictWariness => false,
+            Feature::SaltedWariness => false,
+        }
+    }
+}
+
+impl FromStr for Feature {
+    type Err = failure::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|f| f.name() == input)
+            .ok_or_else(|| format_err!("unknown feature flag '{}'", input))
+    }
+}
+
+/// State of all feature flags.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeatureFlags {
+    flags: BTreeMap<Feature, bool>,
+}
+
+impl Default for FeatureFlags {
+    fn default() -> Self {
+        let flags = Feature::ALL
+            .iter()
+            .map(|&f| (f, f.default_state()))
+            .collect();
+        Self { flags }
+    }
+}
+
+impl FeatureFlags {
+    /// Apply named overrides on top of current flags state.
+    pub fn apply_overrides(&mut self, overrides: &HashMap<String, bool>) -> Fallible<()> {
+        for (name, &enabled) in overrides {
+            let feature = name.parse::<Feature>()?;
+            self.set(feature, enabled);
+        }
+        Ok(())
+    }
+
+    /// Return whether a feature is enabled.
+    pub fn is_enabled(&self, feature: Feature) -> bool {
+        self.flags
+            .get(&feature)
+            .copied()
+            .unwrap_or_else(|| feature.default_state())
+    }
+
+    /// Enable or disable a feature.
+    pub fn set(&mut self, feature: Feature, enabled: bool) {
+        self.flags.insert(feature, enabled);
+    }
+
+    /// Return the state of all flags, keyed by name.
+    pub fn to_named_map(&self) -> BTreeMap<&'static str, bool> {
+        self.flags
+            .iter()
+            .map(|(&f, &enabled)| (f.name(), enabled))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feature_flags_overrides() {
+        let defaults = FeatureFlags::default();
+        assert!(defaults.is_enabled(Feature::OciGraphs));
+
+        let overrides = maplit::hashmap! {
+            "oci_graphs".to_string() => false,
+        };
+        let mut flags = FeatureFlags::default();
+        flags.apply_overrides(&overrides).unwrap();
+        assert!(!flags.is_enabled(Feature::OciGraphs));
+        assert_eq!(flags.to_named_map().get("oci_graphs"), Some(&false));
+
+        let unknown = maplit::hashmap! {
+            "no_such_flag".to_string() => true,
+        };
+        flags.apply_overrides(&unknown).unwrap_err();
+    }
+}
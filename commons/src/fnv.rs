@@ -0,0 +1,24 @@
+//! FNV-1a hashing, stable across processes and builds (unlike `DefaultHasher`).
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Hash a sequence of bytes with 64-bit FNV-1a.
+pub fn fnv1a64(input: impl IntoIterator<Item = u8>) -> u64 {
+    input.into_iter().fold(FNV_OFFSET, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fnv1a64() {
+        // Reference vectors from the FNV specification.
+        assert_eq!(fnv1a64(Vec::new()), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a64(b"a".iter().copied()), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a64("foobar".bytes()), 0x8594_4171_f739_67e8);
+    }
+}
@@ -1,7 +1,7 @@
 use crate::{metadata, policy};
 use failure::Fallible;
 use serde_derive::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 
 /// Single release entry in the Cincinnati update-graph.
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -11,6 +11,59 @@ pub struct CincinnatiPayload {
     pub payload: String,
 }
 
+/// Experimental alternative payload for a release, served to a fraction of nodes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PayloadVariant {
+    pub payload: String,
+    /// Percentage of nodes served this payload.
+    pub weight: u8,
+}
+
+impl PayloadVariant {
+    /// Encode variants as a metadata value, i.e. comma-separated `weight:payload` entries.
+    pub fn encode(variants: &[PayloadVariant]) -> String {
+        let entries: Vec<String> = variants
+            .iter()
+            .map(|v| format!("{}:{}", v.weight, v.payload))
+            .collect();
+        entries.join(",")
+    }
+
+    /// Decode variants from a metadata value.
+    ///
+    /// This returns `None` for malformed values, or if weights add up to
+    /// more than 100%.
+    pub fn decode(value: &str) -> Option<Vec<PayloadVariant>> {
+        let mut variants = vec![];
+        let mut total = 0u32;
+        for entry in value.split(',') {
+            let (weight, payload) = entry.split_once(':')?;
+            let weight: u8 = weight.parse().ok()?;
+            if payload.is_empty() {
+                return None;
+            }
+            total += u32::from(weight);
+            variants.push(PayloadVariant {
+                payload: payload.to_string(),
+                weight,
+            });
+        }
+        if total > 100 {
+            return None;
+        }
+        Some(variants)
+    }
+}
+
+/// Payload which a graph may instruct nodes to fetch, e.g. for mirroring.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct PayloadArtifact {
+    pub version: String,
+    /// Payload scheme, i.e. `checksum` or `oci`.
+    pub scheme: String,
+    pub payload: String,
+}
+
 /// Cincinnati update-graph, a DAG with releases (nodes) and update paths (edges).
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Graph {
@@ -20,25 +73,47 @@ pub struct Graph {
 
 impl Graph {
     /// Assemble a graph from release-index and updates metadata.
+    ///
+    /// Metadata is only borrowed, so that the same documents can be used to
+    /// assemble graphs for several scopes.
     pub fn from_metadata(
-        releases: Vec<metadata::Release>,
-        updates: metadata::UpdatesJSON,
-        scope: GraphScope,
+        releases: &[metadata::Release],
+        updates: &metadata::UpdatesJSON,
+        scope: &GraphScope,
     ) -> Fallible<Self> {
-        let nodes: Vec<CincinnatiPayload> = releases
+        // Order by epoch first, so that rebases do not depend on index ordering.
+        let mut ordered: Vec<&metadata::Release> = releases.iter().collect();
+        ordered.sort_by_key(|entry| entry.epoch);
+
+        let (epochs, mut nodes): (Vec<u64>, Vec<CincinnatiPayload>) = ordered
             .into_iter()
             .enumerate()
             .filter_map(|(age_index, entry)| {
                 let mut current = CincinnatiPayload {
-                    version: entry.version,
+                    version: entry.version.clone(),
                     payload: "".to_string(),
                     metadata: maplit::hashmap! {
                         metadata::AGE_INDEX.to_string() => age_index.to_string(),
                     },
                 };
+                if entry.epoch > 0 {
+                    current
+                        .metadata
+                        .insert(metadata::EPOCH.to_string(), entry.epoch.to_string());
+                }
+                let published = entry
+                    .published
+                    .as_deref()
+                    .and_then(|ts| chrono::DateTime::parse_from_rfc3339(ts).ok());
+                if let Some(published) = published {
+                    current.metadata.insert(
+                        metadata::PUBLISHED.to_string(),
+                        published.timestamp().to_string(),
+                    );
+                }
                 let mut has_basearch = false;
                 if scope.oci {
-                    if let Some(oci_images) = entry.oci_images {
+                    if let Some(oci_images) = &entry.oci_images {
                         for oci_image in oci_images {
                             if oci_image.architecture != scope.basearch
                                 || oci_image.digest_ref.is_empty()
@@ -46,22 +121,23 @@ impl Graph {
                                 continue;
                             }
                             has_basearch = true;
-                            current.payload = oci_image.digest_ref;
+                            current.payload = oci_image.digest_ref.clone();
                             current
                                 .metadata
                                 .insert(metadata::SCHEME.to_string(), "oci".to_string());
+                            Self::inject_payload_variants(&oci_image.variants, &mut current);
                         }
                     } else {
                         // This release doesn't have OCI images, skip it.
                         return None;
                     }
                 } else {
-                    for commit in entry.commits {
+                    for commit in &entry.commits {
                         if commit.architecture != scope.basearch || commit.checksum.is_empty() {
                             continue;
                         }
                         has_basearch = true;
-                        current.payload = commit.checksum;
+                        current.payload = commit.checksum.clone();
                         current
                             .metadata
                             .insert(metadata::SCHEME.to_string(), "checksum".to_string());
@@ -74,17 +150,20 @@ impl Graph {
                 }
 
                 // Augment with dead-ends metadata.
-                Self::inject_deadend_reason(&updates, &mut current);
+                Self::inject_deadend_reason(updates, &mut current);
 
                 // Augment with barriers metadata.
-                Self::inject_barrier_reason(&updates, &mut current);
+                Self::inject_barrier_reason(updates, &mut current);
 
                 // Augment with rollouts metadata.
-                Self::inject_throttling_params(&updates, &mut current);
+                Self::inject_throttling_params(updates, &mut current);
 
-                Some(current)
+                Some((entry.epoch, current))
             })
-            .collect();
+            .unzip();
+
+        // Augment with rebase barriers.
+        Self::inject_rebase_barriers(&epochs, &mut nodes);
 
         // Compute the update graph.
         let edges = Self::compute_edges(&nodes)?;
@@ -96,9 +175,156 @@ impl Graph {
         Ok(final_graph)
     }
 
+    /// Collect payloads of all update targets, including their variants.
+    ///
+    /// Releases which are not the target of any update path are never
+    /// fetched by nodes, and are thus skipped.
+    pub fn payload_artifacts(&self) -> BTreeSet<PayloadArtifact> {
+        let targets: BTreeSet<u64> = self.edges.iter().map(|(_from, to)| *to).collect();
+        let mut artifacts = BTreeSet::new();
+        for node in targets.iter().filter_map(|&i| self.nodes.get(i as usize)) {
+            let scheme = node
+                .metadata
+                .get(metadata::SCHEME)
+                .cloned()
+                .unwrap_or_default();
+            let variants = node
+                .metadata
+                .get(metadata::PAYLOAD_VARIANTS)
+                .and_then(|value| PayloadVariant::decode(value))
+                .unwrap_or_default();
+            let payloads = Some(node.payload.clone())
+                .into_iter()
+                .chain(variants.into_iter().map(|v| v.payload));
+            for payload in payloads {
+                artifacts.insert(PayloadArtifact {
+                    version: node.version.clone(),
+                    scheme: scheme.clone(),
+                    payload,
+                });
+            }
+        }
+        artifacts
+    }
+
+    /// UTC timestamp of the most recently published release, if known.
+    pub fn last_published(&self) -> Option<i64> {
+        self.nodes
+            .iter()
+            .filter_map(|n| n.metadata.get(metadata::PUBLISHED)?.parse().ok())
+            .max()
+    }
+
+    /// Describe differences with another graph for the same scope, ignoring payload form.
+    ///
+    /// Graphs are compared on releases, update paths and release metadata
+    /// (e.g. barriers and rollouts). History predating the first release
+    /// shared by both graphs is ignored, as older releases may only ship one
+    /// payload form.
+    pub fn divergences(&self, other: &Graph) -> Vec<String> {
+        let ours: HashMap<&str, &CincinnatiPayload> =
+            self.nodes.iter().map(|n| (n.version.as_str(), n)).collect();
+        let theirs: HashMap<&str, &CincinnatiPayload> = other
+            .nodes
+            .iter()
+            .map(|n| (n.version.as_str(), n))
+            .collect();
+        let mut divergences = vec![];
+
+        // Releases present on one side only, after the shared history start.
+        for (graph, others, side) in [(self, &theirs, "this"), (other, &ours, "other")] {
+            let start = graph
+                .nodes
+                .iter()
+                .position(|n| others.contains_key(n.version.as_str()))
+                .unwrap_or(graph.nodes.len());
+            for node in &graph.nodes[start..] {
+                if !others.contains_key(node.version.as_str()) {
+                    divergences.push(format!("release {} only in {} graph", node.version, side));
+                }
+            }
+        }
+
+        // Metadata of shared releases, except payload-specific entries.
+        let payload_keys = [
+            metadata::SCHEME,
+            metadata::AGE_INDEX,
+            metadata::PAYLOAD_VARIANTS,
+        ];
+        for node in &self.nodes {
+            if let Some(their_node) = theirs.get(node.version.as_str()) {
+                let strip = |n: &CincinnatiPayload| -> BTreeSet<(String, String)> {
+                    n.metadata
+                        .iter()
+                        .filter(|(k, _)| !payload_keys.contains(&k.as_str()))
+                        .map(|(k, v)| (k.clone(), v.clone()))
+                        .collect()
+                };
+                if strip(node) != strip(their_node) {
+                    divergences.push(format!("metadata differs for release {}", node.version));
+                }
+            }
+        }
+
+        // Update paths between shared releases.
+        let shared: HashSet<&str> = ours
+            .keys()
+            .filter(|v| theirs.contains_key(*v))
+            .cloned()
+            .collect();
+        let paths = |graph: &Graph| -> BTreeSet<(String, String)> {
+            graph
+                .edges
+                .iter()
+                .filter_map(|(from, to)| {
+                    let from = graph.nodes.get(*from as usize)?.version.as_str();
+                    let to = graph.nodes.get(*to as usize)?.version.as_str();
+                    if shared.contains(from) && shared.contains(to) {
+                        Some((from.to_string(), to.to_string()))
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        };
+        let (our_paths, their_paths) = (paths(self), paths(other));
+        for (from, to) in our_paths.difference(&their_paths) {
+            divergences.push(format!("update path {} -> {} only in this graph", from, to));
+        }
+        for (from, to) in their_paths.difference(&our_paths) {
+            divergences.push(format!(
+                "update path {} -> {} only in other graph",
+                from, to
+            ));
+        }
+
+        divergences
+    }
+
+    /// Augment a release with its experimental payload variants, if valid.
+    fn inject_payload_variants(
+        variants: &[metadata::ReleaseOciImageVariant],
+        release: &mut CincinnatiPayload,
+    ) {
+        let variants: Vec<PayloadVariant> = variants
+            .iter()
+            .map(|v| PayloadVariant {
+                payload: v.digest_ref.clone(),
+                weight: v.weight,
+            })
+            .collect();
+        let value = PayloadVariant::encode(&variants);
+        if variants.is_empty() || PayloadVariant::decode(&value).is_none() {
+            release.metadata.remove(metadata::PAYLOAD_VARIANTS);
+            return;
+        }
+        release
+            .metadata
+            .insert(metadata::PAYLOAD_VARIANTS.to_string(), value);
+    }
+
     /// Compute edges based on graph metadata.
     fn compute_edges(nodes: &[CincinnatiPayload]) -> Fallible<Vec<(u64, u64)>> {
-        use std::collections::BTreeSet;
         use std::ops::Bound;
 
         // Collect all rollouts and barriers.
@@ -149,6 +375,26 @@ impl Graph {
         Ok(edges)
     }
 
+    /// Turn the first release of each rebased epoch into a barrier, so that
+    /// older releases never update across a rebase in a single step.
+    fn inject_rebase_barriers(epochs: &[u64], nodes: &mut [CincinnatiPayload]) {
+        for index in 1..nodes.len().min(epochs.len()) {
+            if epochs[index] == epochs[index - 1] {
+                continue;
+            }
+            let release = &mut nodes[index];
+            if release.metadata.contains_key(metadata::BARRIER) {
+                continue;
+            }
+            release
+                .metadata
+                .insert(metadata::BARRIER.to_string(), true.to_string());
+            release
+                .metadata
+                .insert(metadata::BARRIER_REASON.to_string(), "rebase".to_string());
+        }
+    }
+
     fn inject_barrier_reason(updates: &metadata::UpdatesJSON, release: &mut CincinnatiPayload) {
         for entry in &updates.releases {
             if entry.version != release.version {
@@ -225,10 +471,139 @@ impl Graph {
     }
 }
 
-/// The scope of a cached graph, i.e. the specific stream and basearch that it is valid for.
-#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+/// The scope of a cached graph, i.e. the specific product, stream and basearch that it is valid for.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct GraphScope {
+    pub product: String,
     pub basearch: String,
     pub stream: String,
     pub oci: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release(version: &str, scheme: &str, barrier: bool) -> CincinnatiPayload {
+        let mut metadata = maplit::hashmap! {
+            metadata::SCHEME.to_string() => scheme.to_string(),
+        };
+        if barrier {
+            metadata.insert(metadata::BARRIER.to_string(), "true".to_string());
+        }
+        CincinnatiPayload {
+            version: version.to_string(),
+            metadata,
+            payload: format!("{}-{}", scheme, version),
+        }
+    }
+
+    #[test]
+    fn test_rebase_epochs() {
+        let entry = |version: &str, epoch: u64| metadata::Release {
+            commits: vec![metadata::ReleaseCommit {
+                architecture: "x86_64".to_string(),
+                checksum: format!("checksum-{}", version),
+            }],
+            oci_images: None,
+            version: version.to_string(),
+            metadata: String::new(),
+            epoch,
+            published: None,
+        };
+        // Rebased release listed before older ones.
+        let mut releases = vec![
+            entry("38.0", 0),
+            entry("39.0", 1),
+            entry("38.1", 0),
+            entry("39.1", 1),
+        ];
+        releases[0].published = Some("2023-08-01T12:00:00Z".to_string());
+        releases[3].published = Some("2023-11-01T12:00:00Z".to_string());
+        let updates = metadata::UpdatesJSON {
+            stream: "stable".to_string(),
+            releases: vec![],
+        };
+        let scope = GraphScope {
+            product: metadata::DEFAULT_PRODUCT.to_string(),
+            basearch: "x86_64".to_string(),
+            stream: "stable".to_string(),
+            oci: false,
+        };
+        let graph = Graph::from_metadata(&releases, &updates, &scope).unwrap();
+
+        let versions: Vec<&str> = graph.nodes.iter().map(|n| n.version.as_str()).collect();
+        assert_eq!(versions, vec!["38.0", "38.1", "39.0", "39.1"]);
+        assert_eq!(graph.nodes[2].metadata[metadata::AGE_INDEX], "2");
+        assert_eq!(graph.nodes[2].metadata[metadata::EPOCH], "1");
+        assert_eq!(graph.nodes[2].metadata[metadata::BARRIER_REASON], "rebase");
+        assert!(!graph.nodes[0].metadata.contains_key(metadata::EPOCH));
+        assert_eq!(graph.edges, vec![(0, 2), (1, 2)]);
+        assert_eq!(graph.nodes[3].metadata[metadata::PUBLISHED], "1698840000");
+        assert_eq!(graph.last_published(), Some(1698840000));
+    }
+
+    #[test]
+    fn test_graph_divergences() {
+        let checksum = Graph {
+            nodes: vec![
+                release("v0", "checksum", false),
+                release("v1", "checksum", false),
+                release("v2", "checksum", true),
+                release("v3", "checksum", false),
+            ],
+            edges: vec![(0, 2), (1, 2), (2, 3)],
+        };
+        // OCI images only shipped since v1.
+        let oci = Graph {
+            nodes: vec![
+                release("v1", "oci", false),
+                release("v2", "oci", true),
+                release("v3", "oci", false),
+            ],
+            edges: vec![(0, 1), (1, 2)],
+        };
+        assert!(checksum.divergences(&oci).is_empty());
+        assert!(oci.divergences(&checksum).is_empty());
+
+        let mut broken = oci.clone();
+        broken.nodes[1].metadata.remove(metadata::BARRIER);
+        broken.nodes.push(release("v4", "oci", false));
+        broken.edges = vec![(0, 1)];
+        let divergences = checksum.divergences(&broken);
+        assert_eq!(
+            divergences,
+            vec![
+                "release v4 only in other graph",
+                "metadata differs for release v2",
+                "update path v2 -> v3 only in this graph",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_payload_artifacts() {
+        let mut graph = Graph {
+            nodes: vec![
+                release("v0", "oci", false),
+                release("v1", "oci", false),
+                release("v2", "oci", false),
+            ],
+            edges: vec![(0, 1), (0, 2), (1, 2)],
+        };
+        graph.nodes[2].metadata.insert(
+            metadata::PAYLOAD_VARIANTS.to_string(),
+            "10:oci-v2-zstd".to_string(),
+        );
+
+        let payloads: Vec<String> = graph
+            .payload_artifacts()
+            .into_iter()
+            .map(|artifact| {
+                assert_eq!(artifact.scheme, "oci");
+                artifact.payload
+            })
+            .collect();
+        assert_eq!(payloads, vec!["oci-v1", "oci-v2", "oci-v2-zstd"]);
+    }
+}
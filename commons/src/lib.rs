@@ -1,5 +1,21 @@
+pub mod build;
+pub mod config;
+pub mod features;
+pub mod fnv;
 pub mod graph;
+pub mod listen;
 pub mod metadata;
 pub mod metrics;
+pub mod openapi;
 pub mod policy;
+pub mod probe;
+pub mod protobuf;
+pub mod proxy;
+pub mod report;
+pub mod runtime;
+pub mod shard;
+pub mod siphash;
+pub mod systemd;
+pub mod tls;
+pub mod trace;
 pub mod web;
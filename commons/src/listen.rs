@@ -0,0 +1,85 @@
+//! Listening sockets for servers, possibly bound to several addresses.
+
+use failure::{bail, Fallible, ResultExt};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr, TcpListener};
+
+/// Maximum number of pending connections per listener.
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Validate listen addresses configured either as a single `address`, or as
+/// a list of `addresses` (e.g. for dual-stack setups).
+pub fn listen_addresses(
+    address: Option<IpAddr>,
+    addresses: Option<Vec<IpAddr>>,
+) -> Fallible<Option<Vec<IpAddr>>> {
+    match (address, addresses) {
+        (Some(_), Some(_)) => bail!("only one of 'address' and 'addresses' may be set"),
+        (Some(addr), None) => Ok(Some(vec![addr])),
+        (None, Some(addrs)) => {
+            if addrs.is_empty() {
+                bail!("empty 'addresses'");
+            }
+            let mut seen = HashSet::new();
+            if let Some(addr) = addrs.iter().find(|addr| !seen.insert(**addr)) {
+                bail!("duplicate entry '{}' in 'addresses'", addr);
+            }
+            Ok(Some(addrs))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+/// Bind a TCP listener for a server.
+///
+/// IPv6 listeners only accept IPv6 connections, so that IPv4 and IPv6
+/// wildcard addresses can be bound side by side on the same port.
+pub fn bind(addr: SocketAddr) -> Fallible<TcpListener> {
+    let domain = match addr {
+        SocketAddr::V4(_) => Domain::ipv4(),
+        SocketAddr::V6(_) => Domain::ipv6(),
+    };
+    let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket
+        .bind(&SockAddr::from(addr))
+        .with_context(|_| format!("failed to bind to '{}'", addr))?;
+    socket.listen(LISTEN_BACKLOG)?;
+    Ok(socket.into_tcp_listener())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn test_listen_addresses() {
+        let v4 = IpAddr::from(Ipv4Addr::UNSPECIFIED);
+        let v6 = IpAddr::from(Ipv6Addr::UNSPECIFIED);
+        assert_eq!(listen_addresses(None, None).unwrap(), None);
+        assert_eq!(listen_addresses(Some(v4), None).unwrap(), Some(vec![v4]));
+        assert_eq!(
+            listen_addresses(None, Some(vec![v4, v6])).unwrap(),
+            Some(vec![v4, v6])
+        );
+        listen_addresses(Some(v4), Some(vec![v6])).unwrap_err();
+        listen_addresses(None, Some(vec![])).unwrap_err();
+        listen_addresses(None, Some(vec![v4, v4])).unwrap_err();
+    }
+
+    #[test]
+    fn test_bind_dual_stack() {
+        let v4 = bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))).unwrap();
+        let port = v4.local_addr().unwrap().port();
+        match bind(SocketAddr::from((Ipv6Addr::UNSPECIFIED, port))) {
+            Ok(v6) => assert_eq!(v6.local_addr().unwrap().port(), port),
+            // IPv6 may be unavailable in the test environment.
+            Err(e) => eprintln!("skipping IPv6 bind: {}", e),
+        }
+    }
+}
@@ -2,6 +2,9 @@
 
 use serde_derive::Deserialize;
 
+/// Default product, when not explicitly requested.
+pub static DEFAULT_PRODUCT: &str = "fedora-coreos";
+
 /// Templated URL for release index.
 pub static RELEASES_JSON: &str =
     "https://builds.coreos.fedoraproject.org/prod/streams/${stream}/releases.json";
@@ -13,6 +16,9 @@ pub static SCHEME: &str = "org.fedoraproject.coreos.scheme";
 
 pub static AGE_INDEX: &str = "org.fedoraproject.coreos.releases.age_index";
 pub static ARCH_PREFIX: &str = "org.fedoraproject.coreos.releases.arch";
+pub static EPOCH: &str = "org.fedoraproject.coreos.releases.epoch";
+pub static PUBLISHED: &str = "org.fedoraproject.coreos.releases.published";
+pub static PAYLOAD_VARIANTS: &str = "org.fedoraproject.coreos.experimental.payload_variants";
 
 pub static BARRIER: &str = "org.fedoraproject.coreos.updates.barrier";
 pub static BARRIER_REASON: &str = "org.fedoraproject.coreos.updates.barrier_reason";
@@ -31,11 +37,21 @@ pub struct ReleasesJSON {
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Release {
+    /// OSTree commits, missing for OCI-only products.
+    #[serde(default)]
     pub commits: Vec<ReleaseCommit>,
     #[serde(rename = "oci-images")]
     pub oci_images: Option<Vec<ReleaseOciImage>>,
     pub version: String,
     pub metadata: String,
+    /// Stream epoch, bumped when the stream is rebased (e.g. on a new Fedora major).
+    ///
+    /// Releases of a later epoch are always newer than the ones of previous
+    /// epochs, regardless of their position in the index.
+    #[serde(default)]
+    pub epoch: u64,
+    /// Publication time (RFC 3339), if recorded in the index.
+    pub published: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -50,6 +66,17 @@ pub struct ReleaseOciImage {
     pub image: String,
     #[serde(rename = "digest-ref")]
     pub digest_ref: String,
+    /// Experimental alternative images, e.g. with a different compression format.
+    #[serde(default)]
+    pub variants: Vec<ReleaseOciImageVariant>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ReleaseOciImageVariant {
+    #[serde(rename = "digest-ref")]
+    pub digest_ref: String,
+    /// Percentage of nodes served this image.
+    pub weight: u8,
 }
 
 /// Fedora CoreOS updates metadata
@@ -1,9 +1,265 @@
-//! Metrics endpoint.
+//! Metrics endpoint and OTLP export, and collectors common to all services.
 
+use crate::build::BuildInfo;
+use crate::config::HumanDuration;
+use crate::runtime::RuntimeSettings;
+use actix_web::http::StatusCode;
 use actix_web::HttpResponse;
+use failure::{bail, Fallible, ResultExt};
+use prometheus::proto::{Metric, MetricFamily, MetricType};
+use prometheus::{
+    GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry,
+};
+use serde_derive::Deserialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Default interval between OTLP metrics exports.
+const DEFAULT_EXPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Config section for metrics export.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Whether to serve the Prometheus `/metrics` endpoint.
+    pub prometheus: Option<bool>,
+    /// OTLP/HTTP metrics endpoint, e.g. `http://localhost:4318/v1/metrics`.
+    pub otlp_endpoint: Option<String>,
+    /// Interval between OTLP metrics exports.
+    pub export_interval: Option<HumanDuration>,
+}
+
+/// Runtime settings for metrics export.
+#[derive(Clone, Debug)]
+pub struct MetricsSettings {
+    /// Whether to serve the Prometheus `/metrics` endpoint.
+    pub prometheus: bool,
+    /// OTLP/HTTP metrics endpoint (push export disabled if unset).
+    pub otlp_endpoint: Option<reqwest::Url>,
+    pub export_interval: Duration,
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        Self {
+            prometheus: true,
+            otlp_endpoint: None,
+            export_interval: DEFAULT_EXPORT_INTERVAL,
+        }
+    }
+}
+
+impl MetricsSettings {
+    /// Apply a configuration section on top of current settings.
+    pub fn apply_config(&mut self, cfg: MetricsConfig) -> Fallible<()> {
+        if let Some(prometheus) = cfg.prometheus {
+            self.prometheus = prometheus;
+        }
+        if let Some(endpoint) = cfg.otlp_endpoint {
+            let url = reqwest::Url::parse(&endpoint).context("invalid 'otlp_endpoint'")?;
+            if url.scheme() != "http" && url.scheme() != "https" {
+                bail!(
+                    "invalid 'otlp_endpoint': unsupported scheme '{}'",
+                    url.scheme()
+                );
+            }
+            self.otlp_endpoint = Some(url);
+        }
+        if let Some(interval) = cfg.export_interval {
+            if interval.0.as_secs() == 0 {
+                bail!("invalid 'export_interval': must be at least one second");
+            }
+            self.export_interval = interval.0;
+        }
+        Ok(())
+    }
+}
+
+/// Collectors exported by all services, with consistent names.
+///
+/// Service-specific collectors are still defined by each service.
+#[derive(Clone, Debug)]
+pub struct Collectors {
+    /// Start time of the process, as a UTC timestamp.
+    pub process_start_time: IntGauge,
+    /// Build and runtime information, as labels.
+    pub build_info: IntGaugeVec,
+    /// Incoming requests to `/v1/graph`.
+    pub graph_requests: IntCounter,
+    /// Size of `/v1/graph` response bodies.
+    pub graph_response_size: Histogram,
+    /// Request handling latency, by route and status class.
+    pub request_duration: HistogramVec,
+    /// Responses, by route and status code.
+    pub responses: IntCounterVec,
+    /// Graph requests looked up in the local cache, by scope and result
+    /// (`hit` or `miss`).
+    pub cache_lookups: IntCounterVec,
+    /// Completed requests to upstream, by product, stream and result
+    /// (`success` or `failure`).
+    pub upstream_requests: IntCounterVec,
+    /// Delay before the next request to upstream, by product and stream.
+    pub upstream_backoff: GaugeVec,
+    /// Whether the circuit breaker towards upstream is open, by product and stream.
+    pub upstream_circuit_open: IntGaugeVec,
+}
+
+impl Collectors {
+    /// Create collectors and register them, with names prefixed by `namespace`.
+    ///
+    /// `process_start_time_seconds` is a standard Prometheus metric, and
+    /// thus not namespaced.
+    pub fn register(namespace: &str, registry: &Registry) -> Fallible<Self> {
+        let process_start_time = IntGauge::with_opts(Opts::new(
+            "process_start_time_seconds",
+            "Start time of the process since unix epoch in seconds.",
+        ))?;
+        let build_info = IntGaugeVec::new(
+            Opts::new(
+                "build_info",
+                "Build and runtime information, with a constant value of 1.",
+            )
+            .namespace(namespace),
+            &[
+                "version",
+                "git_commit",
+                "build_timestamp",
+                "workers",
+                "blocking_threads",
+            ],
+        )?;
+        let graph_requests = IntCounter::with_opts(
+            Opts::new(
+                "v1_graph_incoming_requests_total",
+                "Total number of incoming HTTP client request to /v1/graph",
+            )
+            .namespace(namespace),
+        )?;
+        let graph_response_size = Histogram::with_opts(
+            HistogramOpts::new(
+                "v1_graph_response_size_bytes",
+                "Size of serialized response graphs.",
+            )
+            .namespace(namespace)
+            .buckets(prometheus::exponential_buckets(1024.0, 2.0, 12)?),
+        )?;
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time spent handling HTTP requests, by route and status class.",
+            )
+            .namespace(namespace)
+            .buckets(prometheus::exponential_buckets(0.001, 2.0, 15)?),
+            &["route", "status"],
+        )?;
+        let responses = IntCounterVec::new(
+            Opts::new(
+                "http_responses_total",
+                "Total number of HTTP responses, by route and status code.",
+            )
+            .namespace(namespace),
+            &["route", "code"],
+        )?;
+
+        let cache_lookups = IntCounterVec::new(
+            Opts::new(
+                "graph_cache_lookups_total",
+                "Total number of graph requests looked up in cache, by scope and result.",
+            )
+            .namespace(namespace),
+            &["product", "basearch", "stream", "type", "result"],
+        )?;
+        let upstream_requests = IntCounterVec::new(
+            Opts::new(
+                "upstream_requests_total",
+                "Total number of completed requests to upstream, by result.",
+            )
+            .namespace(namespace),
+            &["product", "stream", "result"],
+        )?;
+        let upstream_backoff = GaugeVec::new(
+            Opts::new(
+                "upstream_backoff_seconds",
+                "Delay before the next request to upstream.",
+            )
+            .namespace(namespace),
+            &["product", "stream"],
+        )?;
+        let upstream_circuit_open = IntGaugeVec::new(
+            Opts::new(
+                "upstream_circuit_open",
+                "Whether the circuit breaker towards upstream is open.",
+            )
+            .namespace(namespace),
+            &["product", "stream"],
+        )?;
+
+        registry.register(Box::new(process_start_time.clone()))?;
+        registry.register(Box::new(build_info.clone()))?;
+        registry.register(Box::new(graph_requests.clone()))?;
+        registry.register(Box::new(graph_response_size.clone()))?;
+        registry.register(Box::new(request_duration.clone()))?;
+        registry.register(Box::new(responses.clone()))?;
+        registry.register(Box::new(cache_lookups.clone()))?;
+        registry.register(Box::new(upstream_requests.clone()))?;
+        registry.register(Box::new(upstream_backoff.clone()))?;
+        registry.register(Box::new(upstream_circuit_open.clone()))?;
+
+        let collectors = Self {
+            process_start_time,
+            build_info,
+            graph_requests,
+            graph_response_size,
+            request_duration,
+            responses,
+            cache_lookups,
+            upstream_requests,
+            upstream_backoff,
+            upstream_circuit_open,
+        };
+        Ok(collectors)
+    }
+}
+
+impl Collectors {
+    /// Report build information and effective runtime sizing.
+    pub fn set_build_info(&self, build: &BuildInfo, runtime: &RuntimeSettings) {
+        self.build_info
+            .with_label_values(&[
+                build.version,
+                build.git_commit,
+                &build.build_timestamp.to_string(),
+                &runtime.workers.to_string(),
+                &runtime.blocking_threads.to_string(),
+            ])
+            .set(1);
+    }
+
+    /// Record a handled request, labeled with its route (see
+    /// `web::route_label`).
+    pub fn record_request(&self, route: &str, status: StatusCode, latency: Duration) {
+        self.request_duration
+            .with_label_values(&[route, status_class(status)])
+            .observe(latency.as_secs_f64());
+        self.responses
+            .with_label_values(&[route, status.as_str()])
+            .inc();
+    }
+}
+
+/// Class of a status code, e.g. `2xx`, for low-cardinality labels.
+fn status_class(status: StatusCode) -> &'static str {
+    match status.as_u16() {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    }
+}
 
 /// Serve metrics requests (Prometheus textual format).
-pub async fn serve_metrics() -> Result<HttpResponse, failure::Error> {
+pub async fn serve_metrics() -> Result<HttpResponse, crate::web::HandlerError> {
     use prometheus::Encoder;
 
     let content = {
@@ -16,3 +272,272 @@ pub async fn serve_metrics() -> Result<HttpResponse, failure::Error> {
 
     Ok(HttpResponse::Ok().body(content))
 }
+
+/// Periodic push of all registered metrics to an OTLP/HTTP endpoint.
+#[derive(Clone, Debug)]
+pub struct OtlpExporter {
+    service_name: &'static str,
+    endpoint: reqwest::Url,
+    client: reqwest::Client,
+    /// Start of cumulative counters and histograms, i.e. process start.
+    start_time: SystemTime,
+}
+
+impl OtlpExporter {
+    /// Build an exporter, if an OTLP endpoint is configured.
+    pub fn new(
+        service_name: &'static str,
+        settings: &MetricsSettings,
+        start_time: SystemTime,
+    ) -> Fallible<Option<Self>> {
+        let endpoint = match &settings.otlp_endpoint {
+            Some(endpoint) => endpoint.clone(),
+            None => return Ok(None),
+        };
+        let client = reqwest::Client::builder()
+            .timeout(settings.export_interval)
+            .build()?;
+        Ok(Some(Self {
+            service_name,
+            endpoint,
+            client,
+            start_time,
+        }))
+    }
+
+    /// Export current values of all metrics in the default registry.
+    pub async fn export(&self) -> Fallible<()> {
+        let families = prometheus::default_registry().gather();
+        let request = otlp_request(
+            self.service_name,
+            &families,
+            self.start_time,
+            SystemTime::now(),
+        );
+        let body = serde_json::to_vec(&request)?;
+        self.client
+            .post(self.endpoint.clone())
+            .header("content-type", "application/json")
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// OTLP `ExportMetricsServiceRequest`, in its JSON encoding.
+///
+/// Counters and histograms are cumulative since `start`, as in Prometheus.
+fn otlp_request(
+    service_name: &str,
+    families: &[MetricFamily],
+    start: SystemTime,
+    now: SystemTime,
+) -> serde_json::Value {
+    let (start, now) = (unix_nanos(start), unix_nanos(now));
+    let metrics: Vec<serde_json::Value> = families
+        .iter()
+        .filter_map(|family| {
+            let points = |value: fn(&Metric) -> serde_json::Value| -> Vec<serde_json::Value> {
+                family
+                    .get_metric()
+                    .iter()
+                    .map(|metric| {
+                        let mut point = value(metric);
+                        point["attributes"] = otlp_attributes(metric);
+                        point["startTimeUnixNano"] = start.clone().into();
+                        point["timeUnixNano"] = now.clone().into();
+                        point
+                    })
+                    .collect()
+            };
+            let (kind, data) = match family.get_field_type() {
+                MetricType::COUNTER => (
+                    "sum",
+                    serde_json::json!({
+                        "dataPoints": points(|m| {
+                            serde_json::json!({ "asDouble": m.get_counter().get_value() })
+                        }),
+                        "aggregationTemporality": 2,
+                        "isMonotonic": true,
+                    }),
+                ),
+                MetricType::GAUGE => (
+                    "gauge",
+                    serde_json::json!({
+                        "dataPoints": points(|m| {
+                            serde_json::json!({ "asDouble": m.get_gauge().get_value() })
+                        }),
+                    }),
+                ),
+                MetricType::HISTOGRAM => (
+                    "histogram",
+                    serde_json::json!({
+                        "dataPoints": points(otlp_histogram_point),
+                        "aggregationTemporality": 2,
+                    }),
+                ),
+                _ => return None,
+            };
+            let mut metric = serde_json::json!({
+                "name": family.get_name(),
+                "description": family.get_help(),
+            });
+            metric[kind] = data;
+            Some(metric)
+        })
+        .collect();
+    serde_json::json!({
+        "resourceMetrics": [{
+            "resource": {
+                "attributes": [{
+                    "key": "service.name",
+                    "value": { "stringValue": service_name },
+                }],
+            },
+            "scopeMetrics": [{
+                "scope": { "name": "fcos-cincinnati" },
+                "metrics": metrics,
+            }],
+        }],
+    })
+}
+
+/// Histogram data point, with per-bucket (rather than cumulative) counts.
+fn otlp_histogram_point(metric: &Metric) -> serde_json::Value {
+    let histogram = metric.get_histogram();
+    let mut bounds = vec![];
+    let mut counts = vec![];
+    let mut previous = 0;
+    for bucket in histogram.get_bucket() {
+        let cumulative = bucket.get_cumulative_count();
+        if bucket.get_upper_bound().is_finite() {
+            bounds.push(bucket.get_upper_bound());
+            counts.push(cumulative.saturating_sub(previous).to_string());
+            previous = cumulative;
+        }
+    }
+    let total = histogram.get_sample_count();
+    counts.push(total.saturating_sub(previous).to_string());
+    serde_json::json!({
+        "count": total.to_string(),
+        "sum": histogram.get_sample_sum(),
+        "bucketCounts": counts,
+        "explicitBounds": bounds,
+    })
+}
+
+fn otlp_attributes(metric: &Metric) -> serde_json::Value {
+    metric
+        .get_label()
+        .iter()
+        .map(|label| {
+            serde_json::json!({
+                "key": label.get_name(),
+                "value": { "stringValue": label.get_value() },
+            })
+        })
+        .collect()
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collectors_register() {
+        let registry = Registry::new();
+        let collectors = Collectors::register("fcos_cincinnati_test", &registry).unwrap();
+        collectors.graph_requests.inc();
+        collectors.record_request(
+            "/v1/graph",
+            StatusCode::NOT_MODIFIED,
+            Duration::from_millis(3),
+        );
+        collectors.set_build_info(
+            &BuildInfo::new("test", "0.1.0"),
+            &RuntimeSettings::default(),
+        );
+
+        let names: Vec<String> = registry
+            .gather()
+            .iter()
+            .map(|family| family.get_name().to_string())
+            .collect();
+        assert!(names.contains(&"process_start_time_seconds".to_string()));
+        assert!(
+            names.contains(&"fcos_cincinnati_test_v1_graph_incoming_requests_total".to_string())
+        );
+        assert!(names.contains(&"fcos_cincinnati_test_v1_graph_response_size_bytes".to_string()));
+        assert!(names.contains(&"fcos_cincinnati_test_build_info".to_string()));
+        assert_eq!(
+            collectors
+                .request_duration
+                .with_label_values(&["/v1/graph", "3xx"])
+                .get_sample_count(),
+            1
+        );
+        assert_eq!(
+            collectors
+                .responses
+                .with_label_values(&["/v1/graph", "304"])
+                .get(),
+            1
+        );
+        assert_eq!(status_class(StatusCode::SERVICE_UNAVAILABLE), "5xx");
+
+        collectors
+            .upstream_requests
+            .with_label_values(&["fedora-coreos", "stable", "success"])
+            .inc();
+        let names: Vec<String> = registry
+            .gather()
+            .iter()
+            .map(|family| family.get_name().to_string())
+            .collect();
+        assert!(names.contains(&"fcos_cincinnati_test_upstream_requests_total".to_string()));
+
+        // Collectors can only be registered once per registry.
+        Collectors::register("fcos_cincinnati_test", &registry).unwrap_err();
+    }
+
+    #[test]
+    fn test_otlp_request() {
+        let registry = Registry::new();
+        let collectors = Collectors::register("fcos_cincinnati_test", &registry).unwrap();
+        collectors.graph_requests.inc_by(3);
+        collectors.graph_response_size.observe(1000.0);
+        collectors.graph_response_size.observe(5000.0);
+
+        let start = UNIX_EPOCH + Duration::from_secs(1);
+        let now = UNIX_EPOCH + Duration::from_secs(2);
+        let request = otlp_request("test", &registry.gather(), start, now);
+        let metrics = request["resourceMetrics"][0]["scopeMetrics"][0]["metrics"]
+            .as_array()
+            .unwrap();
+        let find = |name: &str| metrics.iter().find(|m| m["name"] == name).unwrap();
+
+        let requests = find("fcos_cincinnati_test_v1_graph_incoming_requests_total");
+        let point = &requests["sum"]["dataPoints"][0];
+        assert_eq!(point["asDouble"], 3.0);
+        assert_eq!(point["startTimeUnixNano"], "1000000000");
+        assert_eq!(point["timeUnixNano"], "2000000000");
+
+        let sizes = find("fcos_cincinnati_test_v1_graph_response_size_bytes");
+        let point = &sizes["histogram"]["dataPoints"][0];
+        assert_eq!(point["count"], "2");
+        let counts = point["bucketCounts"].as_array().unwrap();
+        assert_eq!(counts.len(), 13);
+        assert_eq!(counts[0], "1");
+        assert_eq!(counts[3], "1");
+        assert_eq!(point["explicitBounds"][0], 1024.0);
+    }
+}
@@ -0,0 +1,320 @@
+//! OpenAPI 3 description of the HTTP API, served on `/openapi.json`.
+
+use crate::web::{Endpoint, GraphFormat};
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+
+/// Query parameter of the graph endpoint.
+#[derive(Clone, Debug)]
+pub struct QueryParam {
+    pub name: &'static str,
+    /// JSON schema type, e.g. `string` or `boolean`.
+    pub kind: &'static str,
+    pub description: &'static str,
+}
+
+impl QueryParam {
+    pub fn string(name: &'static str, description: &'static str) -> Self {
+        Self {
+            name,
+            kind: "string",
+            description,
+        }
+    }
+
+    pub fn boolean(name: &'static str, description: &'static str) -> Self {
+        Self {
+            name,
+            kind: "boolean",
+            description,
+        }
+    }
+
+    pub fn integer(name: &'static str, description: &'static str) -> Self {
+        Self {
+            name,
+            kind: "integer",
+            description,
+        }
+    }
+}
+
+/// OpenAPI document.
+#[derive(Clone, Debug, Serialize)]
+pub struct OpenApi {
+    openapi: &'static str,
+    info: Info,
+    /// path -> method -> operation
+    paths: BTreeMap<&'static str, BTreeMap<String, Operation>>,
+    components: Components,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct Info {
+    title: &'static str,
+    version: &'static str,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct Operation {
+    summary: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    parameters: Vec<Parameter>,
+    /// status -> response
+    responses: BTreeMap<&'static str, Response>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct Parameter {
+    name: &'static str,
+    #[serde(rename = "in")]
+    location: &'static str,
+    description: &'static str,
+    schema: Schema,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct Response {
+    description: &'static str,
+    /// media type -> content
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    content: BTreeMap<&'static str, MediaType>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct MediaType {
+    schema: Schema,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct Components {
+    schemas: BTreeMap<&'static str, Schema>,
+}
+
+/// Subset of JSON schema, as used by OpenAPI.
+#[derive(Clone, Debug, Default, Serialize)]
+struct Schema {
+    #[serde(rename = "$ref", skip_serializing_if = "Option::is_none")]
+    reference: Option<&'static str>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    kind: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'static str>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    properties: BTreeMap<&'static str, Schema>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    items: Option<Box<Schema>>,
+    #[serde(
+        rename = "additionalProperties",
+        skip_serializing_if = "Option::is_none"
+    )]
+    additional_properties: Option<Box<Schema>>,
+}
+
+impl Schema {
+    fn of(kind: &'static str) -> Self {
+        Self {
+            kind: Some(kind),
+            ..Self::default()
+        }
+    }
+
+    fn reference(reference: &'static str) -> Self {
+        Self {
+            reference: Some(reference),
+            ..Self::default()
+        }
+    }
+
+    fn array(items: Schema) -> Self {
+        Self {
+            items: Some(Box::new(items)),
+            ..Self::of("array")
+        }
+    }
+
+    fn object(properties: Vec<(&'static str, Schema)>) -> Self {
+        Self {
+            properties: properties.into_iter().collect(),
+            ..Self::of("object")
+        }
+    }
+}
+
+impl OpenApi {
+    /// Describe the endpoints of a service, with the query parameters of
+    /// its graph endpoint and the errors it may return.
+    pub fn new(
+        title: &'static str,
+        version: &'static str,
+        endpoints: &[Endpoint],
+        graph_params: &[QueryParam],
+        graph_errors: &[(&'static str, &'static str)],
+    ) -> Self {
+        let mut paths: BTreeMap<&'static str, BTreeMap<String, Operation>> = BTreeMap::new();
+        for endpoint in endpoints {
+            let operation = if endpoint.path == "/v1/graph" {
+                graph_operation(endpoint.description, graph_params, graph_errors)
+            } else {
+                Operation {
+                    summary: endpoint.description,
+                    parameters: vec![],
+                    responses: maplit::btreemap! {
+                        "200" => Response {
+                            description: "Success",
+                            content: BTreeMap::new(),
+                        },
+                    },
+                }
+            };
+            paths
+                .entry(endpoint.path)
+                .or_default()
+                .insert(endpoint.method.to_ascii_lowercase(), operation);
+        }
+        Self {
+            openapi: "3.0.3",
+            info: Info { title, version },
+            paths,
+            components: Components {
+                schemas: component_schemas(),
+            },
+        }
+    }
+}
+
+/// Describe the graph endpoint.
+fn graph_operation(
+    summary: &'static str,
+    params: &[QueryParam],
+    errors: &[(&'static str, &'static str)],
+) -> Operation {
+    let parameters = params
+        .iter()
+        .map(|param| Parameter {
+            name: param.name,
+            location: "query",
+            description: param.description,
+            schema: Schema::of(param.kind),
+        })
+        .collect();
+    let graph = [
+        GraphFormat::Json,
+        GraphFormat::CincinnatiJson,
+        GraphFormat::Protobuf,
+    ]
+    .iter()
+    .map(|format| {
+        let schema = match format {
+            GraphFormat::Protobuf => Schema {
+                format: Some("binary"),
+                description: Some("`cincinnati.graph.v1.Graph` protobuf message"),
+                ..Schema::of("string")
+            },
+            _ => Schema::reference("#/components/schemas/Graph"),
+        };
+        (format.content_type(), MediaType { schema })
+    })
+    .collect();
+    let mut responses = maplit::btreemap! {
+        "200" => Response {
+            description: "Update graph",
+            content: graph,
+        },
+        "304" => Response {
+            description: "Graph unchanged since the `If-None-Match` entity tag",
+            content: BTreeMap::new(),
+        },
+    };
+    for (status, description) in errors {
+        responses.insert(
+            status,
+            Response {
+                description,
+                content: maplit::btreemap! {
+                    "application/problem+json" => MediaType {
+                        schema: Schema::reference("#/components/schemas/ClientError"),
+                    },
+                },
+            },
+        );
+    }
+    Operation {
+        summary,
+        parameters,
+        responses,
+    }
+}
+
+/// Schemas of graphs and error bodies.
+fn component_schemas() -> BTreeMap<&'static str, Schema> {
+    let node = Schema::object(vec![
+        ("version", Schema::of("string")),
+        ("payload", Schema::of("string")),
+        (
+            "metadata",
+            Schema {
+                additional_properties: Some(Box::new(Schema::of("string"))),
+                ..Schema::of("object")
+            },
+        ),
+    ]);
+    let edge = Schema {
+        description: Some("Indices of the source and target nodes"),
+        ..Schema::array(Schema::of("integer"))
+    };
+    maplit::btreemap! {
+        "Graph" => Schema::object(vec![
+            ("nodes", Schema::array(node)),
+            ("edges", Schema::array(edge)),
+        ]),
+        "ClientError" => Schema::object(vec![
+            ("type", Schema::of("string")),
+            ("title", Schema::of("string")),
+            ("status", Schema::of("integer")),
+            ("detail", Schema {
+                description: Some("Human-readable error details"),
+                ..Schema::of("string")
+            }),
+            ("kind", Schema {
+                description: Some("Machine-readable error kind"),
+                ..Schema::of("string")
+            }),
+            ("value", Schema {
+                description: Some("Same as `detail`, for older clients"),
+                ..Schema::of("string")
+            }),
+        ]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_openapi() {
+        let endpoints = vec![
+            Endpoint::get("/v1/graph", "Update graph"),
+            Endpoint::get("/v1/streams", "Served scopes"),
+        ];
+        let params = vec![QueryParam::string("stream", "Update stream")];
+        let errors = vec![("400", "Invalid scope")];
+        let doc = OpenApi::new("test", "0.1.0", &endpoints, &params, &errors);
+        let graph = &doc.paths["/v1/graph"]["get"];
+        assert_eq!(graph.parameters.len(), 1);
+        assert_eq!(graph.parameters[0].name, "stream");
+        assert_eq!(
+            graph.responses.keys().collect::<Vec<_>>(),
+            vec![&"200", &"304", &"400"]
+        );
+        assert_eq!(graph.responses["200"].content.len(), 3);
+        assert!(doc.paths["/v1/streams"]["get"].parameters.is_empty());
+
+        let json = serde_json::to_string(&doc).unwrap();
+        assert!(json.contains(r#""in":"query""#));
+        assert!(json.contains(r##""$ref":"#/components/schemas/Graph""##));
+    }
+}
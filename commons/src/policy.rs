@@ -1,6 +1,61 @@
-use crate::graph::Graph;
+use crate::graph::{Graph, PayloadVariant};
 use crate::metadata;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Number of rollout wariness tiers, for pre-built graph variants.
+///
+/// Tier `n` corresponds to a wariness of `n / WARINESS_TIERS`.
+pub const WARINESS_TIERS: u8 = 10;
+
+/// Map a rollout wariness to its tier, rounding up.
+///
+/// Rounding up makes clients at most as eager as their exact wariness, so
+/// they never see a rollout earlier than they would otherwise.
+pub fn wariness_tier(wariness: f64) -> u8 {
+    let scaled = (wariness.clamp(0.0, 1.0) * f64::from(WARINESS_TIERS)).ceil();
+    scaled as u8
+}
+
+/// Rollout wariness for a tier.
+pub fn tier_wariness(tier: u8) -> f64 {
+    f64::from(tier.min(WARINESS_TIERS)) / f64::from(WARINESS_TIERS)
+}
+
+/// Number of rollout wariness buckets, for precomputed throttled graphs.
+///
+/// Bucket `n` corresponds to a wariness of `n / WARINESS_BUCKETS`.
+pub const WARINESS_BUCKETS: u8 = 100;
+
+/// Map a rollout wariness to its bucket, rounding up as for tiers.
+pub fn wariness_bucket(wariness: f64) -> u8 {
+    let scaled = (wariness.clamp(0.0, 1.0) * f64::from(WARINESS_BUCKETS)).ceil();
+    scaled as u8
+}
+
+/// Rollout wariness for a bucket.
+pub fn bucket_wariness(bucket: u8) -> f64 {
+    f64::from(bucket.min(WARINESS_BUCKETS)) / f64::from(WARINESS_BUCKETS)
+}
+
+/// Whether policies may rewrite a serialized graph, checked without decoding it.
+///
+/// This looks for the metadata keys which policies act upon, so graphs
+/// without rollouts, deadends or payload variants can be served as-is.
+/// False positives only cost a rewrite which turns out to be a no-op.
+pub fn may_rewrite(data: &[u8]) -> bool {
+    [
+        metadata::ROLLOUT,
+        metadata::DEADEND,
+        metadata::PAYLOAD_VARIANTS,
+    ]
+    .iter()
+    .any(|key| {
+        let needle = format!("\"{}\"", key);
+        data.windows(needle.len())
+            .any(|window| window == needle.as_bytes())
+    })
+}
 
 /// Prune outgoing edges from "deadend" nodes.
 pub fn filter_deadends(input: Graph) -> Graph {
@@ -24,10 +79,64 @@ pub fn filter_deadends(input: Graph) -> Graph {
 
 /// Conditionally prune incoming edges towards throttled rollouts.
 pub fn throttle_rollouts(input: Graph, client_wariness: f64) -> Graph {
-    let mut graph = input;
-    let mut hidden = HashSet::new();
+    throttle_rollouts_at(input, client_wariness, chrono::Utc::now().timestamp())
+}
+
+/// Conditionally prune incoming edges towards rollouts throttled at the
+/// given time (UTC timestamp).
+pub fn throttle_rollouts_at(input: Graph, client_wariness: f64, now: i64) -> Graph {
+    throttle_releases(input, |_| client_wariness, now)
+}
+
+/// Conditionally prune incoming edges towards throttled rollouts, with a
+/// client wariness depending on the release version.
+///
+/// This allows salting the wariness of a client per release, so that the
+/// same nodes are not always the first ones to update.
+pub fn throttle_rollouts_per_release(input: Graph, client_wariness: impl Fn(&str) -> f64) -> Graph {
+    throttle_releases(input, client_wariness, chrono::Utc::now().timestamp())
+}
+
+fn throttle_releases(input: Graph, client_wariness: impl Fn(&str) -> f64, now: i64) -> Graph {
+    let hidden: HashSet<usize> = rollout_throttling(&input, now)
+        .into_iter()
+        .filter(|(index, throttling)| client_wariness(&input.nodes[*index].version) > *throttling)
+        .map(|(index, _)| index)
+        .collect();
+    hide_releases(input, &hidden)
+}
+
+/// Throttle rollouts for all wariness buckets at once.
+///
+/// Entry `n` is the graph for a wariness of `n / WARINESS_BUCKETS`. Buckets
+/// hiding the same releases share a single graph, so there are at most as
+/// many distinct graphs as rollouts in progress, plus one.
+pub fn throttle_rollouts_buckets(input: &Graph) -> Vec<Arc<Graph>> {
     let now = chrono::Utc::now().timestamp();
+    let throttling = rollout_throttling(input, now);
 
+    let mut buckets: Vec<Arc<Graph>> = Vec::with_capacity(usize::from(WARINESS_BUCKETS) + 1);
+    let mut previous: Option<HashSet<usize>> = None;
+    for bucket in 0..=WARINESS_BUCKETS {
+        let wariness = bucket_wariness(bucket);
+        let hidden: HashSet<usize> = throttling
+            .iter()
+            .filter(|(_, throttling)| wariness > *throttling)
+            .map(|(index, _)| *index)
+            .collect();
+        let graph = match (&previous, buckets.last()) {
+            (Some(previous), Some(graph)) if *previous == hidden => Arc::clone(graph),
+            _ => Arc::new(hide_releases(input.clone(), &hidden)),
+        };
+        buckets.push(graph);
+        previous = Some(hidden);
+    }
+    buckets
+}
+
+/// Current throttling of releases being rolled out, by node index.
+fn rollout_throttling(graph: &Graph, now: i64) -> Vec<(usize, f64)> {
+    let mut throttled = vec![];
     for (index, release) in graph.nodes.iter().enumerate() {
         // Skip if this release is not being rolled out.
         if !release.metadata.contains_key(metadata::ROLLOUT) {
@@ -74,11 +183,14 @@ pub fn throttle_rollouts(input: Graph, client_wariness: f64) -> Graph {
             }
         }
 
-        if client_wariness > throttling {
-            hidden.insert(index);
-        }
+        throttled.push((index, throttling));
     }
+    throttled
+}
 
+/// Prune incoming edges towards hidden releases, by node index.
+fn hide_releases(input: Graph, hidden: &HashSet<usize>) -> Graph {
+    let mut graph = input;
     graph.edges.retain(|(_from, to)| {
         let index = *to as usize;
         !hidden.contains(&index)
@@ -87,3 +199,259 @@ pub fn throttle_rollouts(input: Graph, client_wariness: f64) -> Graph {
 
     graph
 }
+
+/// Route clients running very old releases through barriers only.
+///
+/// If the client's `current_version` is at least `min_release_lag` releases
+/// behind the newest one, edges towards intermediate releases are pruned:
+/// nodes with an update path to a barrier only keep those, while nodes past
+/// the last reachable barrier only keep the update path to the newest release.
+pub fn prune_for_old_client(input: Graph, current_version: &str, min_release_lag: u64) -> Graph {
+    let mut graph = input;
+
+    let client_index = match graph
+        .nodes
+        .iter()
+        .position(|release| release.version == current_version)
+    {
+        Some(index) => index,
+        None => return graph,
+    };
+    let lag = graph.nodes.len().saturating_sub(client_index + 1);
+    if (lag as u64) < min_release_lag {
+        return graph;
+    }
+
+    let barriers: HashSet<u64> = graph
+        .nodes
+        .iter()
+        .enumerate()
+        .filter(|(_, release)| release.metadata.contains_key(metadata::BARRIER))
+        .map(|(index, _)| index as u64)
+        .collect();
+
+    // For each source node, pick whether to keep barrier edges or the newest target.
+    let mut has_barrier_edge = HashSet::new();
+    let mut newest_target = HashMap::new();
+    for &(from, to) in &graph.edges {
+        if barriers.contains(&to) {
+            has_barrier_edge.insert(from);
+        }
+        let newest = newest_target.entry(from).or_insert(to);
+        *newest = (*newest).max(to);
+    }
+
+    graph.edges.retain(|(from, to)| {
+        if has_barrier_edge.contains(from) {
+            barriers.contains(to)
+        } else {
+            newest_target.get(from) == Some(to)
+        }
+    });
+    graph.edges.shrink_to_fit();
+
+    graph
+}
+
+/// Resolve experimental payload variants for a node, dropping their metadata.
+///
+/// Each node is deterministically assigned a percentile per release, and is
+/// served the variant covering it by cumulative weight. Nodes without an ID,
+/// or not covered by any variant, are served the primary payload.
+pub fn resolve_payload_variants(input: Graph, node_uuid: Option<&str>) -> Graph {
+    let mut graph = input;
+
+    for release in graph.nodes.iter_mut() {
+        let value = match release.metadata.remove(metadata::PAYLOAD_VARIANTS) {
+            Some(value) => value,
+            None => continue,
+        };
+        let (uuid, variants) = match (node_uuid, PayloadVariant::decode(&value)) {
+            (Some(uuid), Some(variants)) => (uuid, variants),
+            _ => continue,
+        };
+
+        let percentile = node_percentile(uuid, &release.version);
+        let mut threshold = 0u32;
+        for variant in variants {
+            threshold += u32::from(variant.weight);
+            if percentile < threshold {
+                release.payload = variant.payload;
+                break;
+            }
+        }
+    }
+
+    graph
+}
+
+/// Stable percentile (0-99) of a node for a release (FNV-1a), identical across processes.
+///
+/// Salting with the release version keeps experiments on different releases
+/// independent from each other.
+fn node_percentile(node_uuid: &str, version: &str) -> u32 {
+    let input = node_uuid.bytes().chain(Some(0)).chain(version.bytes());
+    (crate::fnv::fnv1a64(input) % 100) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::CincinnatiPayload;
+
+    fn release(version: &str, barrier: bool) -> CincinnatiPayload {
+        let mut metadata = HashMap::new();
+        if barrier {
+            metadata.insert(metadata::BARRIER.to_string(), "true".to_string());
+        }
+        CincinnatiPayload {
+            version: version.to_string(),
+            metadata,
+            payload: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_wariness_tiers() {
+        assert_eq!(wariness_tier(0.0), 0);
+        assert_eq!(wariness_tier(0.000_001), 1);
+        assert_eq!(wariness_tier(0.1), 1);
+        assert_eq!(wariness_tier(0.55), 6);
+        assert_eq!(wariness_tier(1.0), WARINESS_TIERS);
+        assert_eq!(wariness_tier(7.0), WARINESS_TIERS);
+        assert!(tier_wariness(wariness_tier(0.42)) >= 0.42);
+        assert!((tier_wariness(5) - 0.5).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_throttle_rollouts_buckets() {
+        let mut graph = Graph {
+            nodes: vec![release("v0", false), release("v1", false)],
+            edges: vec![(0, 1)],
+        };
+        let rollout = &mut graph.nodes[1].metadata;
+        rollout.insert(metadata::ROLLOUT.to_string(), "true".to_string());
+        rollout.insert(metadata::START_VALUE.to_string(), "0.5".to_string());
+
+        assert_eq!(wariness_bucket(0.0), 0);
+        assert_eq!(wariness_bucket(0.421), 43);
+        assert_eq!(wariness_bucket(1.0), WARINESS_BUCKETS);
+
+        let buckets = throttle_rollouts_buckets(&graph);
+        assert_eq!(buckets.len(), usize::from(WARINESS_BUCKETS) + 1);
+        assert!(Arc::ptr_eq(&buckets[0], &buckets[50]));
+        assert!(Arc::ptr_eq(&buckets[51], &buckets[100]));
+        assert_eq!(buckets[50].edges, vec![(0, 1)]);
+        assert!(buckets[51].edges.is_empty());
+        for wariness in &[0.2, 0.5, 0.7] {
+            let bucket = usize::from(wariness_bucket(*wariness));
+            let exact = throttle_rollouts(graph.clone(), *wariness);
+            assert_eq!(buckets[bucket].edges, exact.edges);
+        }
+    }
+
+    #[test]
+    fn test_throttle_rollouts_per_release() {
+        let mut graph = Graph {
+            nodes: vec![
+                release("v0", false),
+                release("v1", false),
+                release("v2", false),
+            ],
+            edges: vec![(0, 1), (0, 2)],
+        };
+        for node in &mut graph.nodes[1..] {
+            let rollout = &mut node.metadata;
+            rollout.insert(metadata::ROLLOUT.to_string(), "true".to_string());
+            rollout.insert(metadata::START_VALUE.to_string(), "0.5".to_string());
+        }
+
+        let salted = throttle_rollouts_per_release(graph, |version| match version {
+            "v1" => 0.2,
+            _ => 0.8,
+        });
+        assert_eq!(salted.edges, vec![(0, 1)]);
+    }
+
+    #[test]
+    fn test_prune_for_old_client() {
+        // 0 -> {1, 2(barrier)}, 2 -> {3, 4}
+        let graph = Graph {
+            nodes: vec![
+                release("v0", false),
+                release("v1", false),
+                release("v2", true),
+                release("v3", false),
+                release("v4", false),
+            ],
+            edges: vec![(0, 1), (0, 2), (1, 2), (2, 3), (2, 4), (3, 4)],
+        };
+
+        let recent = prune_for_old_client(graph.clone(), "v3", 2);
+        assert_eq!(recent.edges, graph.edges);
+
+        let unknown = prune_for_old_client(graph.clone(), "v9", 0);
+        assert_eq!(unknown.edges, graph.edges);
+
+        let pruned = prune_for_old_client(graph, "v0", 2);
+        assert_eq!(pruned.edges, vec![(0, 2), (1, 2), (2, 4), (3, 4)]);
+    }
+
+    #[test]
+    fn test_resolve_payload_variants() {
+        let variants = vec![
+            PayloadVariant {
+                payload: "quay.io/fcos@sha256:zstd".to_string(),
+                weight: 30,
+            },
+            PayloadVariant {
+                payload: "quay.io/fcos@sha256:chunked".to_string(),
+                weight: 20,
+            },
+        ];
+        let value = PayloadVariant::encode(&variants);
+        assert_eq!(PayloadVariant::decode(&value).unwrap(), variants);
+        assert!(PayloadVariant::decode("60:a,50:b").is_none());
+        assert!(PayloadVariant::decode("").is_none());
+
+        let mut node = release("v0", false);
+        node.payload = "quay.io/fcos@sha256:gzip".to_string();
+        node.metadata
+            .insert(metadata::PAYLOAD_VARIANTS.to_string(), value);
+        let graph = Graph {
+            nodes: vec![node],
+            edges: vec![],
+        };
+
+        let anonymous = resolve_payload_variants(graph.clone(), None);
+        assert_eq!(anonymous.nodes[0].payload, "quay.io/fcos@sha256:gzip");
+        assert!(anonymous.nodes[0].metadata.is_empty());
+
+        let mut served = HashMap::new();
+        for n in 0..1000 {
+            let uuid = format!("node-{}", n);
+            let resolved = resolve_payload_variants(graph.clone(), Some(&uuid));
+            let again = resolve_payload_variants(graph.clone(), Some(&uuid));
+            assert_eq!(resolved.nodes[0].payload, again.nodes[0].payload);
+            *served.entry(resolved.nodes[0].payload.clone()).or_insert(0) += 1;
+        }
+        assert!((250..350).contains(&served["quay.io/fcos@sha256:zstd"]));
+        assert!((150..250).contains(&served["quay.io/fcos@sha256:chunked"]));
+        assert!((450..550).contains(&served["quay.io/fcos@sha256:gzip"]));
+    }
+
+    #[test]
+    fn test_may_rewrite() {
+        let mut graph = Graph {
+            nodes: vec![release("v0", true), release("v1", false)],
+            edges: vec![(0, 1)],
+        };
+        assert!(!may_rewrite(&serde_json::to_vec(&graph).unwrap()));
+
+        graph.nodes[1]
+            .metadata
+            .insert(metadata::ROLLOUT.to_string(), "true".to_string());
+        assert!(may_rewrite(&serde_json::to_vec(&graph).unwrap()));
+        assert!(may_rewrite(&serde_json::to_vec_pretty(&graph).unwrap()));
+    }
+}
@@ -0,0 +1,64 @@
+//! Local health probing, for container healthchecks.
+
+use failure::{bail, Fallible, ResultExt};
+use reqwest::Url;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+/// Timeout for each probe request.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Base URL for reaching a local server bound to the given address.
+///
+/// Wildcard addresses are mapped to the corresponding loopback address.
+pub fn local_base_url(addr: SocketAddr) -> Fallible<Url> {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    let base = format!("http://{}/", SocketAddr::new(ip, addr.port()));
+    let url = Url::parse(&base).with_context(|_| format!("invalid base URL '{}'", base))?;
+    Ok(url)
+}
+
+/// Check that all targets respond with a successful HTTP status.
+pub async fn check_endpoints(targets: &[Url]) -> Fallible<()> {
+    let hclient = reqwest::ClientBuilder::new()
+        .timeout(PROBE_TIMEOUT)
+        .build()?;
+
+    for url in targets {
+        let resp = hclient
+            .get(url.clone())
+            .send()
+            .await
+            .with_context(|_| format!("failed to probe '{}'", url))?;
+        let status = resp.status();
+        if !status.is_success() {
+            bail!("probe for '{}' failed with status {}", url, status);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_base_url() {
+        let wildcard = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 9080);
+        let url = local_base_url(wildcard).unwrap();
+        assert_eq!(url.as_str(), "http://127.0.0.1:9080/");
+        assert_eq!(url.join("readyz").unwrap().path(), "/readyz");
+
+        let wildcard6 = SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 9080);
+        let url = local_base_url(wildcard6).unwrap();
+        assert_eq!(url.as_str(), "http://[::1]:9080/");
+
+        let specific = SocketAddr::new(Ipv4Addr::new(10, 0, 0, 1).into(), 8080);
+        let url = local_base_url(specific).unwrap();
+        assert_eq!(url.as_str(), "http://10.0.0.1:8080/");
+    }
+}
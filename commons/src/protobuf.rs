@@ -0,0 +1,121 @@
+//! Compact protobuf encoding of update-graphs.
+//!
+//! Graphs are encoded per the following schema, with metadata entries sorted
+//! by key so that encoding is deterministic:
+//!
+//! ```protobuf
+//! syntax = "proto3";
+//! package cincinnati.graph.v1;
+//!
+//! message Graph {
+//!   repeated Node nodes = 1;
+//!   // Flattened (from, to) pairs of node indices.
+//!   repeated uint64 edges = 2;
+//! }
+//!
+//! message Node {
+//!   string version = 1;
+//!   string payload = 2;
+//!   map<string, string> metadata = 3;
+//! }
+//! ```
+
+use crate::graph::{CincinnatiPayload, Graph};
+
+/// Wire type of length-delimited fields.
+const WIRE_LEN: u8 = 2;
+
+/// Encode a graph as a `cincinnati.graph.v1.Graph` message.
+pub fn encode_graph(graph: &Graph) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for node in &graph.nodes {
+        put_message(&mut buf, 1, &encode_node(node));
+    }
+    if !graph.edges.is_empty() {
+        let mut edges = Vec::with_capacity(graph.edges.len() * 2);
+        for (from, to) in &graph.edges {
+            put_varint(&mut edges, *from);
+            put_varint(&mut edges, *to);
+        }
+        put_message(&mut buf, 2, &edges);
+    }
+    buf
+}
+
+/// Encode a release as a `cincinnati.graph.v1.Node` message.
+fn encode_node(node: &CincinnatiPayload) -> Vec<u8> {
+    let mut buf = Vec::new();
+    put_string(&mut buf, 1, &node.version);
+    put_string(&mut buf, 2, &node.payload);
+    let mut metadata: Vec<(&String, &String)> = node.metadata.iter().collect();
+    metadata.sort();
+    for (key, value) in metadata {
+        let mut entry = Vec::with_capacity(key.len() + value.len() + 4);
+        put_string(&mut entry, 1, key);
+        put_string(&mut entry, 2, value);
+        put_message(&mut buf, 3, &entry);
+    }
+    buf
+}
+
+/// Append a string field, omitted if empty (the proto3 default).
+fn put_string(buf: &mut Vec<u8>, field: u32, value: &str) {
+    if !value.is_empty() {
+        put_message(buf, field, value.as_bytes());
+    }
+}
+
+/// Append a length-delimited field.
+fn put_message(buf: &mut Vec<u8>, field: u32, data: &[u8]) {
+    put_varint(buf, u64::from(field << 3 | u32::from(WIRE_LEN)));
+    put_varint(buf, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+/// Append a base-128 varint.
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maplit::hashmap;
+
+    #[test]
+    fn test_put_varint() {
+        let mut buf = vec![];
+        put_varint(&mut buf, 1);
+        put_varint(&mut buf, 300);
+        assert_eq!(buf, vec![0x01, 0xac, 0x02]);
+    }
+
+    #[test]
+    fn test_encode_graph() {
+        assert!(encode_graph(&Graph::default()).is_empty());
+
+        let graph = Graph {
+            nodes: vec![CincinnatiPayload {
+                version: "1".to_string(),
+                payload: String::new(),
+                metadata: hashmap! {
+                    "b".to_string() => "2".to_string(),
+                    "a".to_string() => "1".to_string(),
+                },
+            }],
+            edges: vec![(0, 300)],
+        };
+        let expected = vec![
+            0x0a, 0x13, // nodes, 19 bytes
+            0x0a, 0x01, b'1', // version
+            0x1a, 0x06, 0x0a, 0x01, b'a', 0x12, 0x01, b'1', // metadata "a"
+            0x1a, 0x06, 0x0a, 0x01, b'b', 0x12, 0x01, b'2', // metadata "b"
+            0x12, 0x03, 0x00, 0xac, 0x02, // edges, packed
+        ];
+        assert_eq!(encode_graph(&graph), expected);
+    }
+}
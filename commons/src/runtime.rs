@@ -0,0 +1,89 @@
+//! Sizing of the async runtime, shared by all services.
+
+use failure::{bail, Fallible};
+use serde_derive::Deserialize;
+
+/// Config section for the async runtime.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuntimeConfig {
+    /// Worker threads for the main service.
+    pub workers: Option<usize>,
+    /// Threads for blocking operations.
+    pub blocking_threads: Option<usize>,
+}
+
+/// Runtime settings for the async runtime.
+///
+/// Defaults match the ones of actix, i.e. one worker per CPU and five
+/// blocking threads per CPU.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RuntimeSettings {
+    pub workers: usize,
+    pub blocking_threads: usize,
+}
+
+impl RuntimeSettings {
+    /// Environment variable sizing the actix pool for blocking operations.
+    const BLOCKING_THREADS_VAR: &'static str = "ACTIX_THREADPOOL";
+
+    /// Apply a configuration section on top of current settings.
+    pub fn apply_config(&mut self, cfg: RuntimeConfig) -> Fallible<()> {
+        if let Some(workers) = cfg.workers {
+            if workers == 0 {
+                bail!("invalid 'workers': must be non-zero");
+            }
+            self.workers = workers;
+        }
+        if let Some(threads) = cfg.blocking_threads {
+            if threads == 0 {
+                bail!("invalid 'blocking_threads': must be non-zero");
+            }
+            self.blocking_threads = threads;
+        }
+        Ok(())
+    }
+
+    /// Size the pool for blocking operations.
+    ///
+    /// The pool is lazily created on first use, so this must be called
+    /// before starting the runtime.
+    pub fn init_blocking_pool(&self) {
+        std::env::set_var(
+            Self::BLOCKING_THREADS_VAR,
+            self.blocking_threads.to_string(),
+        );
+    }
+}
+
+impl Default for RuntimeSettings {
+    fn default() -> Self {
+        let cpus = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        Self {
+            workers: cpus,
+            blocking_threads: cpus * 5,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runtime_settings() {
+        let mut settings = RuntimeSettings::default();
+        assert!(settings.workers > 0);
+
+        let cfg: RuntimeConfig = toml::from_str("blocking_threads = 4").unwrap();
+        let workers = settings.workers;
+        settings.apply_config(cfg).unwrap();
+        assert_eq!(settings.workers, workers);
+        assert_eq!(settings.blocking_threads, 4);
+
+        let zero: RuntimeConfig = toml::from_str("workers = 0").unwrap();
+        settings.apply_config(zero).unwrap_err();
+    }
+}
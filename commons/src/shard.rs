@@ -0,0 +1,98 @@
+//! Deterministic assignment of graph scopes to graph-builder shards.
+
+use failure::{bail, Fallible};
+use serde_derive::Deserialize;
+
+/// A shard of the scopes space, i.e. this instance's index out of a total count.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Shard {
+    pub index: u32,
+    pub count: u32,
+}
+
+impl Shard {
+    /// Validate sharding parameters.
+    pub fn new(index: u32, count: u32) -> Fallible<Self> {
+        if count == 0 {
+            bail!("invalid shard count: must be non-zero");
+        }
+        if index >= count {
+            bail!(
+                "invalid shard index {}: must be lower than shard count {}",
+                index,
+                count
+            );
+        }
+        Ok(Self { index, count })
+    }
+
+    /// Whether this shard owns the given scope.
+    pub fn owns(&self, product: &str, stream: &str, basearch: &str) -> bool {
+        shard_for(product, stream, basearch, self.count) == self.index
+    }
+}
+
+/// Return the shard index owning a scope, out of `count` shards.
+///
+/// Both OCI and checksum graphs for a (product, stream, basearch) tuple belong
+/// to the same shard, as they are built from the same upstream metadata.
+/// This uses jump consistent hashing, so that changing the number of shards
+/// only moves the minimum amount of scopes across shards.
+pub fn shard_for(product: &str, stream: &str, basearch: &str, count: u32) -> u32 {
+    jump_hash(scope_key(product, stream, basearch), count.max(1))
+}
+
+/// Stable 64-bit key for a scope (FNV-1a), identical across processes and builds.
+fn scope_key(product: &str, stream: &str, basearch: &str) -> u64 {
+    let input = product
+        .bytes()
+        .chain(Some(0))
+        .chain(stream.bytes())
+        .chain(Some(0))
+        .chain(basearch.bytes());
+    crate::fnv::fnv1a64(input)
+}
+
+/// Jump consistent hash (Lamping and Veach, 2014).
+fn jump_hash(mut key: u64, buckets: u32) -> u32 {
+    let mut bucket: i64 = -1;
+    let mut next: i64 = 0;
+    while next < i64::from(buckets) {
+        bucket = next;
+        key = key.wrapping_mul(2_862_933_555_777_941_757).wrapping_add(1);
+        next = ((bucket + 1) as f64 * ((1i64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    bucket as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shard_assignment() {
+        Shard::new(0, 0).unwrap_err();
+        Shard::new(3, 3).unwrap_err();
+
+        let scopes: Vec<(String, String)> = (0..200)
+            .map(|n| (format!("stream{}", n), "x86_64".to_string()))
+            .collect();
+        for (stream, arch) in &scopes {
+            assert_eq!(shard_for("fedora-coreos", stream, arch, 1), 0);
+            // Each scope is owned by exactly one shard.
+            let owners = (0..4)
+                .filter(|&i| {
+                    Shard::new(i, 4)
+                        .unwrap()
+                        .owns("fedora-coreos", stream, arch)
+                })
+                .count();
+            assert_eq!(owners, 1);
+            // Growing the shards count only moves scopes to the new shard.
+            let before = shard_for("fedora-coreos", stream, arch, 4);
+            let after = shard_for("fedora-coreos", stream, arch, 5);
+            assert!(after == before || after == 4);
+        }
+    }
+}
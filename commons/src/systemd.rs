@@ -0,0 +1,93 @@
+//! Service notifications for systemd (`sd_notify` protocol), for `Type=notify` units.
+
+use failure::{bail, Fallible};
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+/// Environment variable with the notification socket path.
+const NOTIFY_SOCKET_VAR: &str = "NOTIFY_SOCKET";
+/// Environment variable with the watchdog timeout, in microseconds.
+const WATCHDOG_USEC_VAR: &str = "WATCHDOG_USEC";
+/// Environment variable with the PID expected to send watchdog pings.
+const WATCHDOG_PID_VAR: &str = "WATCHDOG_PID";
+
+/// Send a state update to systemd, e.g. `READY=1`.
+///
+/// This returns `false` if the process is not running under systemd, or
+/// the unit does not expect notifications.
+pub fn notify(state: &str) -> Fallible<bool> {
+    let path = match std::env::var_os(NOTIFY_SOCKET_VAR) {
+        Some(path) if !path.is_empty() => path,
+        _ => return Ok(false),
+    };
+    if path.to_string_lossy().starts_with('@') {
+        bail!("abstract notification sockets are not supported");
+    }
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to(state.as_bytes(), &path)?;
+    Ok(true)
+}
+
+/// Interval between watchdog pings, if the watchdog is enabled for this process.
+///
+/// Pings are sent at twice the rate of the watchdog timeout, as suggested by
+/// `sd_watchdog_enabled(3)`.
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec = std::env::var(WATCHDOG_USEC_VAR).ok();
+    let pid = std::env::var(WATCHDOG_PID_VAR).ok();
+    parse_watchdog_interval(usec.as_deref(), pid.as_deref(), std::process::id())
+}
+
+fn parse_watchdog_interval(
+    usec: Option<&str>,
+    pid: Option<&str>,
+    own_pid: u32,
+) -> Option<Duration> {
+    if let Some(pid) = pid {
+        if pid.parse::<u32>().ok()? != own_pid {
+            return None;
+        }
+    }
+    let usec: u64 = usec?.parse().ok().filter(|&usec| usec > 0)?;
+    Some(Duration::from_micros(usec / 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog_interval() {
+        assert_eq!(parse_watchdog_interval(None, None, 42), None);
+        assert_eq!(
+            parse_watchdog_interval(Some("30000000"), None, 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            parse_watchdog_interval(Some("30000000"), Some("42"), 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            parse_watchdog_interval(Some("30000000"), Some("7"), 42),
+            None
+        );
+        assert_eq!(parse_watchdog_interval(Some("0"), None, 42), None);
+        assert_eq!(parse_watchdog_interval(Some("soon"), None, 42), None);
+    }
+
+    #[test]
+    fn test_notify() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let listener = UnixDatagram::bind(&path).unwrap();
+
+        std::env::set_var(NOTIFY_SOCKET_VAR, &path);
+        assert!(notify("READY=1").unwrap());
+        std::env::remove_var(NOTIFY_SOCKET_VAR);
+        assert!(!notify("READY=1").unwrap());
+
+        let mut buf = [0u8; 16];
+        let len = listener.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+    }
+}
@@ -0,0 +1,91 @@
+//! TLS for servers, with mandatory client certificates (mutual TLS).
+
+use failure::{format_err, Fallible, ResultExt};
+use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod, SslVerifyMode};
+use serde_derive::Deserialize;
+use std::path::PathBuf;
+
+/// Config section for mutual TLS.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// Server certificate chain (PEM).
+    pub cert_file: Option<PathBuf>,
+    /// Server private key (PEM).
+    pub key_file: Option<PathBuf>,
+    /// CA certificates for verifying client certificates (PEM).
+    pub client_ca_file: Option<PathBuf>,
+}
+
+/// Runtime settings for mutual TLS.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TlsSettings {
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+    pub client_ca_file: PathBuf,
+}
+
+impl TlsSettings {
+    /// Build settings from a configuration section, requiring all entries.
+    pub fn from_config(cfg: TlsConfig) -> Fallible<Self> {
+        let required = |path: Option<PathBuf>, name: &str| {
+            path.filter(|p| !p.as_os_str().is_empty())
+                .ok_or_else(|| format_err!("missing '{}'", name))
+        };
+        Ok(Self {
+            cert_file: required(cfg.cert_file, "cert_file")?,
+            key_file: required(cfg.key_file, "key_file")?,
+            client_ca_file: required(cfg.client_ca_file, "client_ca_file")?,
+        })
+    }
+
+    /// Build a TLS acceptor, rejecting clients without a valid certificate.
+    pub fn acceptor(&self) -> Fallible<SslAcceptorBuilder> {
+        let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+        builder
+            .set_certificate_chain_file(&self.cert_file)
+            .with_context(|_| {
+                format!(
+                    "failed to load TLS certificate '{}'",
+                    self.cert_file.display()
+                )
+            })?;
+        builder
+            .set_private_key_file(&self.key_file, SslFiletype::PEM)
+            .with_context(|_| format!("failed to load TLS key '{}'", self.key_file.display()))?;
+        builder.check_private_key().context("mismatched TLS key")?;
+        builder
+            .set_ca_file(&self.client_ca_file)
+            .with_context(|_| {
+                format!(
+                    "failed to load client CA '{}'",
+                    self.client_ca_file.display()
+                )
+            })?;
+        builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        Ok(builder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tls_settings() {
+        let cfg: TlsConfig = toml::from_str(
+            r#"
+                cert_file = "/etc/pki/status.crt"
+                key_file = "/etc/pki/status.key"
+                client_ca_file = "/etc/pki/clients-ca.crt"
+            "#,
+        )
+        .unwrap();
+        let settings = TlsSettings::from_config(cfg).unwrap();
+        assert_eq!(settings.key_file, PathBuf::from("/etc/pki/status.key"));
+        assert!(settings.acceptor().is_err());
+
+        let partial: TlsConfig = toml::from_str(r#"cert_file = "status.crt""#).unwrap();
+        TlsSettings::from_config(partial).unwrap_err();
+    }
+}
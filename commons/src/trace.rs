@@ -0,0 +1,456 @@
+//! Distributed tracing, with spans exported via OTLP/HTTP (JSON encoding).
+//!
+//! Trace context is propagated across services with the W3C `traceparent`
+//! header, per the W3C Trace Context recommendation.
+
+use crate::config::HumanDuration;
+use actix_web::HttpRequest;
+use failure::{bail, Fallible, ResultExt};
+use serde_derive::Deserialize;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Header carrying the trace context.
+pub static TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Maximum finished spans buffered between exports, beyond which spans are dropped.
+const MAX_BUFFERED_SPANS: usize = 10_000;
+
+/// Default interval between span exports.
+const DEFAULT_EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Config section for distributed tracing.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TracingConfig {
+    /// OTLP/HTTP traces endpoint, e.g. `http://localhost:4318/v1/traces`.
+    pub otlp_endpoint: Option<String>,
+    /// Fraction of traces started by this service to sample.
+    pub sample_rate: Option<f64>,
+    /// Interval between span exports.
+    pub export_interval: Option<HumanDuration>,
+}
+
+/// Runtime settings for distributed tracing.
+#[derive(Clone, Debug)]
+pub struct TracingSettings {
+    /// OTLP/HTTP traces endpoint (tracing disabled if unset).
+    pub otlp_endpoint: Option<reqwest::Url>,
+    pub sample_rate: f64,
+    pub export_interval: Duration,
+}
+
+impl Default for TracingSettings {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            sample_rate: 1.0,
+            export_interval: DEFAULT_EXPORT_INTERVAL,
+        }
+    }
+}
+
+impl TracingSettings {
+    /// Apply a configuration section on top of current settings.
+    pub fn apply_config(&mut self, cfg: TracingConfig) -> Fallible<()> {
+        if let Some(endpoint) = cfg.otlp_endpoint {
+            let url = reqwest::Url::parse(&endpoint).context("invalid 'otlp_endpoint'")?;
+            if url.scheme() != "http" && url.scheme() != "https" {
+                bail!(
+                    "invalid 'otlp_endpoint': unsupported scheme '{}'",
+                    url.scheme()
+                );
+            }
+            self.otlp_endpoint = Some(url);
+        }
+        if let Some(rate) = cfg.sample_rate {
+            if !(0.0..=1.0).contains(&rate) {
+                bail!("invalid 'sample_rate': must be between 0.0 and 1.0");
+            }
+            self.sample_rate = rate;
+        }
+        if let Some(interval) = cfg.export_interval {
+            if interval.0.as_secs() == 0 {
+                bail!("invalid 'export_interval': must be at least one second");
+            }
+            self.export_interval = interval.0;
+        }
+        Ok(())
+    }
+}
+
+/// Trace context of a span, as propagated across services.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// Parse a `traceparent` header value.
+    pub fn parse(input: &str) -> Option<Self> {
+        let parts: Vec<&str> = input.trim().split('-').collect();
+        if parts.len() < 4 || parts.iter().any(|part| !is_hex(part)) {
+            return None;
+        }
+        let (version, trace_id, span_id, flags) = (parts[0], parts[1], parts[2], parts[3]);
+        // Later versions may append fields, but must keep these ones.
+        if version.len() != 2 || version == "ff" || (version == "00" && parts.len() != 4) {
+            return None;
+        }
+        if trace_id.len() != 32 || span_id.len() != 16 || flags.len() != 2 {
+            return None;
+        }
+        let trace_id = u128::from_str_radix(trace_id, 16).ok()?;
+        let span_id = u64::from_str_radix(span_id, 16).ok()?;
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        if trace_id == 0 || span_id == 0 {
+            return None;
+        }
+        Some(Self {
+            trace_id,
+            span_id,
+            sampled: flags & 0x01 != 0,
+        })
+    }
+
+    /// Trace context propagated by the client of a request, if any.
+    pub fn from_request(req: &HttpRequest) -> Option<Self> {
+        req.headers()
+            .get(TRACEPARENT_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(Self::parse)
+    }
+
+    /// Format as a `traceparent` header value.
+    pub fn to_header(&self) -> String {
+        format!(
+            "00-{:032x}-{:016x}-{:02x}",
+            self.trace_id,
+            self.span_id,
+            u8::from(self.sampled)
+        )
+    }
+}
+
+fn is_hex(input: &str) -> bool {
+    input
+        .bytes()
+        .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// Role of a span within a trace, as numbered by OTLP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpanKind {
+    Internal = 1,
+    Server = 2,
+    Client = 3,
+}
+
+/// Span, recorded for export when dropped.
+#[derive(Debug)]
+pub struct Span {
+    tracer: Tracer,
+    data: SpanData,
+}
+
+impl Span {
+    /// Trace context of this span, to propagate to child spans.
+    pub fn context(&self) -> TraceContext {
+        self.data.context
+    }
+
+    /// Attach an attribute to this span.
+    pub fn set_attribute(&mut self, key: &'static str, value: impl ToString) {
+        self.data.attributes.push((key, value.to_string()));
+    }
+
+    /// Mark this span as failed.
+    pub fn set_error(&mut self) {
+        self.data.error = true;
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if !self.data.context.sampled {
+            return;
+        }
+        if let Some(inner) = &self.tracer.inner {
+            let mut data = self.data.clone();
+            data.end = SystemTime::now();
+            inner.record(data);
+        }
+    }
+}
+
+/// Finished (or in progress) span data.
+#[derive(Clone, Debug)]
+struct SpanData {
+    name: &'static str,
+    kind: SpanKind,
+    context: TraceContext,
+    parent_span_id: Option<u64>,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(&'static str, String)>,
+    error: bool,
+}
+
+/// Source of spans, buffering them for periodic export.
+///
+/// The default tracer is disabled: spans still propagate trace context,
+/// but are never recorded.
+#[derive(Clone, Debug, Default)]
+pub struct Tracer {
+    inner: Option<Arc<TracerInner>>,
+}
+
+#[derive(Debug)]
+struct TracerInner {
+    service_name: &'static str,
+    endpoint: reqwest::Url,
+    sample_rate: f64,
+    client: reqwest::Client,
+    spans: Mutex<Vec<SpanData>>,
+    /// Spans dropped since the last export, as the buffer was full.
+    dropped: AtomicU64,
+}
+
+impl TracerInner {
+    fn record(&self, span: SpanData) {
+        match self.spans.lock() {
+            Ok(mut spans) if spans.len() < MAX_BUFFERED_SPANS => spans.push(span),
+            _ => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl Tracer {
+    /// Build a tracer for a service, disabled unless an endpoint is configured.
+    pub fn new(service_name: &'static str, settings: &TracingSettings) -> Fallible<Self> {
+        let endpoint = match &settings.otlp_endpoint {
+            Some(endpoint) => endpoint.clone(),
+            None => return Ok(Self::default()),
+        };
+        let client = reqwest::Client::builder()
+            .timeout(settings.export_interval)
+            .build()?;
+        let inner = TracerInner {
+            service_name,
+            endpoint,
+            sample_rate: settings.sample_rate,
+            client,
+            spans: Mutex::new(Vec::new()),
+            dropped: AtomicU64::new(0),
+        };
+        Ok(Self {
+            inner: Some(Arc::new(inner)),
+        })
+    }
+
+    /// Whether spans are recorded and exported.
+    pub fn is_enabled(&self) -> bool {
+        self.inner.is_some()
+    }
+
+    /// Start a span, as a child of the given context or else as a trace root.
+    ///
+    /// Sampling decisions of parents are honored, so that traces are
+    /// complete across services.
+    pub fn start_span(
+        &self,
+        name: &'static str,
+        kind: SpanKind,
+        parent: Option<TraceContext>,
+    ) -> Span {
+        let span_id = random_u64();
+        let context = match parent {
+            Some(parent) => TraceContext { span_id, ..parent },
+            None => {
+                let trace_id = u128::from(random_u64()) << 64 | u128::from(random_u64());
+                let sample_rate = self.inner.as_ref().map_or(0.0, |inner| inner.sample_rate);
+                TraceContext {
+                    trace_id,
+                    span_id,
+                    sampled: is_sampled(trace_id, sample_rate),
+                }
+            }
+        };
+        let now = SystemTime::now();
+        Span {
+            tracer: self.clone(),
+            data: SpanData {
+                name,
+                kind,
+                context,
+                parent_span_id: parent.map(|parent| parent.span_id),
+                start: now,
+                end: now,
+                attributes: vec![],
+                error: false,
+            },
+        }
+    }
+
+    /// Export all buffered spans, returning how many were exported.
+    pub async fn export(&self) -> Fallible<usize> {
+        let inner = match &self.inner {
+            Some(inner) => inner,
+            None => return Ok(0),
+        };
+        let dropped = inner.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            log::warn!("span buffer full, dropped {} spans", dropped);
+        }
+        let spans = match inner.spans.lock() {
+            Ok(mut spans) => std::mem::take(&mut *spans),
+            Err(_) => bail!("poisoned span buffer"),
+        };
+        if spans.is_empty() {
+            return Ok(0);
+        }
+
+        let body = serde_json::to_vec(&otlp_request(inner.service_name, &spans))?;
+        inner
+            .client
+            .post(inner.endpoint.clone())
+            .header("content-type", "application/json")
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(spans.len())
+    }
+}
+
+/// Whether to sample a new trace, deterministically from its ID.
+fn is_sampled(trace_id: u128, sample_rate: f64) -> bool {
+    if sample_rate >= 1.0 {
+        return true;
+    }
+    ((trace_id as u64) as f64 / u64::MAX as f64) < sample_rate
+}
+
+/// Random non-zero ID.
+pub(crate) fn random_u64() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    loop {
+        // Each `RandomState` is randomly keyed, so this does not need a RNG.
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        let id = hasher.finish();
+        if id != 0 {
+            return id;
+        }
+    }
+}
+
+/// OTLP `ExportTraceServiceRequest`, in its JSON encoding.
+fn otlp_request(service_name: &str, spans: &[SpanData]) -> serde_json::Value {
+    let spans: Vec<serde_json::Value> = spans
+        .iter()
+        .map(|span| {
+            let attributes: Vec<serde_json::Value> = span
+                .attributes
+                .iter()
+                .map(|(key, value)| otlp_attribute(key, value))
+                .collect();
+            let status = if span.error { 2 } else { 0 };
+            let mut json = serde_json::json!({
+                "traceId": format!("{:032x}", span.context.trace_id),
+                "spanId": format!("{:016x}", span.context.span_id),
+                "name": span.name,
+                "kind": span.kind as u8,
+                "startTimeUnixNano": unix_nanos(span.start).to_string(),
+                "endTimeUnixNano": unix_nanos(span.end).to_string(),
+                "attributes": attributes,
+                "status": { "code": status },
+            });
+            if let Some(parent) = span.parent_span_id {
+                json["parentSpanId"] = format!("{:016x}", parent).into();
+            }
+            json
+        })
+        .collect();
+    serde_json::json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [otlp_attribute("service.name", service_name)],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "fcos-cincinnati" },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+fn otlp_attribute(key: &str, value: &str) -> serde_json::Value {
+    serde_json::json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_context() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let context = TraceContext::parse(header).unwrap();
+        assert_eq!(context.trace_id, 0x4bf9_2f35_77b3_4da6_a3ce_929d_0e0e_4736);
+        assert_eq!(context.span_id, 0x00f0_67aa_0ba9_02b7);
+        assert!(context.sampled);
+        assert_eq!(context.to_header(), header);
+
+        let unsampled = TraceContext::parse(&header.replace("-01", "-00")).unwrap();
+        assert!(!unsampled.sampled);
+        assert!(TraceContext::parse(&format!("{}-extra", header)).is_none());
+        assert!(TraceContext::parse(&header.replacen("00-", "01-", 1)).is_some());
+        assert!(TraceContext::parse(&header.to_uppercase()).is_none());
+        assert!(
+            TraceContext::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_span_export() {
+        let settings = TracingSettings {
+            otlp_endpoint: Some("http://localhost:4318/v1/traces".parse().unwrap()),
+            ..TracingSettings::default()
+        };
+        let tracer = Tracer::new("test", &settings).unwrap();
+        let parent = TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01");
+        {
+            let mut span = tracer.start_span("GET /v1/graph", SpanKind::Server, parent);
+            span.set_attribute("http.status_code", 200);
+            assert_eq!(span.context().trace_id, parent.unwrap().trace_id);
+            assert_ne!(span.context().span_id, parent.unwrap().span_id);
+        }
+        let spans = tracer.inner.as_ref().unwrap().spans.lock().unwrap().clone();
+        assert_eq!(spans.len(), 1);
+
+        let json = otlp_request("test", &spans).to_string();
+        assert!(json.contains(r#""traceId":"4bf92f3577b34da6a3ce929d0e0e4736""#));
+        assert!(json.contains(r#""parentSpanId":"00f067aa0ba902b7""#));
+        assert!(json.contains(r#""stringValue":"200""#));
+
+        // Disabled tracers still propagate context, but record nothing.
+        let disabled = Tracer::default().start_span("test", SpanKind::Internal, None);
+        assert!(!disabled.context().sampled);
+    }
+}
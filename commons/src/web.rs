@@ -1,7 +1,518 @@
 use crate::graph::GraphScope;
 use actix_cors::CorsFactory;
-use failure::{bail, ensure, err_msg};
-use std::collections::HashSet;
+use actix_web::dev::Payload;
+use actix_web::http::header::{
+    HeaderName, HeaderValue, ACCEPT, ACCEPT_ENCODING, ETAG, IF_NONE_MATCH, RETRY_AFTER,
+};
+use actix_web::http::StatusCode;
+use actix_web::{FromRequest, HttpRequest, HttpResponse, ResponseError};
+use failure::{bail, Fallible};
+use prometheus::{IntCounter, IntCounterVec, IntGauge, Opts, Registry};
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Response header reporting the upstream artifacts a graph was built from.
+pub static GRAPH_SOURCE_HEADER: &str = "X-Graph-Source";
+
+/// Response header reporting whether a graph has OCI payloads.
+///
+/// Graph-builders predating OCI graphs ignore the `oci` parameter and do
+/// not set this header.
+pub static GRAPH_OCI_HEADER: &str = "X-Graph-OCI";
+
+/// Response header reporting the age in seconds of a stale graph, served
+/// as last-known-good while upstream is unreachable.
+pub static GRAPH_STALE_HEADER: &str = "X-Graph-Stale";
+
+/// Response header identifying the generation of a graph, as computed by
+/// `graph_version`, and propagated by the policy-engine.
+pub static GRAPH_VERSION_HEADER: &str = "X-Graph-Version";
+
+/// Request and response header carrying a request ID, for tracing across services.
+pub static REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Maximum length of a client-provided request ID.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Maximum clients tracked per rate limiter, as client input is untrusted.
+const MAX_RATE_LIMITED_CLIENTS: usize = 100_000;
+
+/// Query parameters understood by graph endpoints, in canonical order.
+///
+/// Other parameters do not affect responses, and are dropped.
+pub static GRAPH_QUERY_PARAMS: &[&str] = &[
+    "basearch",
+    "node_uuid",
+    "oci",
+    "os_version",
+    "pretty",
+    "product",
+    "rollout_wariness",
+    "stream",
+    "wariness_tier",
+];
+
+/// Default aliases of basearch values, e.g. GOARCH-style names.
+pub static DEFAULT_BASEARCH_ALIASES: &[(&str, &str)] = &[
+    ("amd64", "x86_64"),
+    ("arm64", "aarch64"),
+    ("ppc64el", "ppc64le"),
+];
+
+/// Query parameters whose values are case-insensitive.
+static CASE_INSENSITIVE_PARAMS: &[&str] = &["basearch", "oci", "pretty", "product", "stream"];
+
+/// Format a UTC timestamp as an HTTP date, e.g. for `Last-Modified`.
+pub fn http_date(timestamp: i64) -> Option<String> {
+    use chrono::TimeZone;
+
+    let date = chrono::Utc.timestamp_opt(timestamp, 0).single()?;
+    Some(date.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+}
+
+/// Hash of some content (FNV-1a), identical across processes.
+fn content_hash(data: &[u8]) -> u64 {
+    crate::fnv::fnv1a64(data.iter().copied())
+}
+
+/// Compute a strong entity tag for a response body, identical across processes.
+pub fn entity_tag(body: &[u8]) -> String {
+    format!("\"{:016x}-{:x}\"", content_hash(body), body.len())
+}
+
+/// Identify the generation of a graph by its content and scrape time (UTC
+/// timestamp), e.g. `0123456789abcdef-1700000000`.
+pub fn graph_version(graph: &[u8], scraped: i64) -> String {
+    format!("{:016x}-{}", content_hash(graph), scraped)
+}
+
+/// Whether the `If-None-Match` header of a request matches an entity tag,
+/// i.e. whether the client already holds the current representation.
+pub fn if_none_match(req: &HttpRequest, etag: &str) -> bool {
+    req.headers()
+        .get(IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map(|tags| etag_list_matches(tags, etag))
+        .unwrap_or(false)
+}
+
+/// Whether a list of entity tags matches one, using weak comparison.
+fn etag_list_matches(tags: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    tags.split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Whether a request accepts a content encoding, per its `Accept-Encoding` header.
+pub fn accepts_encoding(req: &HttpRequest, encoding: &str) -> bool {
+    req.headers()
+        .get(ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .map(|accepted| encoding_accepted(accepted, encoding))
+        .unwrap_or(false)
+}
+
+/// Whether a list of accepted encodings (with optional weights) includes one.
+fn encoding_accepted(accepted: &str, encoding: &str) -> bool {
+    let mut wildcard = false;
+    for entry in accepted.split(',') {
+        let mut parts = entry.split(';');
+        let name = parts.next().unwrap_or_default().trim();
+        let weight = parts
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .map(|q| q.trim().parse::<f32>().unwrap_or(0.0))
+            .unwrap_or(1.0);
+        if name.eq_ignore_ascii_case(encoding) {
+            return weight > 0.0;
+        }
+        if name == "*" {
+            wildcard = weight > 0.0;
+        }
+    }
+    wildcard
+}
+
+/// Encoding of graph responses, negotiated per the `Accept` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// Plain JSON, the default.
+    Json,
+    /// JSON, labeled with the Cincinnati graph media type.
+    CincinnatiJson,
+    /// Compact protobuf encoding, see `protobuf::encode_graph`.
+    Protobuf,
+}
+
+impl GraphFormat {
+    /// Media type of responses in this format.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            GraphFormat::Json => "application/json",
+            GraphFormat::CincinnatiJson => "application/vnd.redhat.cincinnati.graph.v1+json",
+            GraphFormat::Protobuf => "application/vnd.redhat.cincinnati.graph.v1+protobuf",
+        }
+    }
+
+    /// Pick the format preferred by a request.
+    ///
+    /// This falls back to plain JSON if no supported media type is accepted,
+    /// as existing clients expect.
+    pub fn negotiate(req: &HttpRequest) -> Self {
+        req.headers()
+            .get(ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .map(Self::from_accept)
+            .unwrap_or(GraphFormat::Json)
+    }
+
+    /// Pick the format with the highest weight in a list of accepted media types.
+    fn from_accept(accepted: &str) -> Self {
+        let mut best = (GraphFormat::Json, 0.0);
+        for entry in accepted.split(',') {
+            let mut parts = entry.split(';');
+            let name = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+            let weight = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map(|q| q.trim().parse::<f32>().unwrap_or(0.0))
+                .unwrap_or(1.0);
+            let format = match name.as_str() {
+                "application/json" | "application/*" | "*/*" => GraphFormat::Json,
+                "application/vnd.redhat.cincinnati.graph.v1+json" => GraphFormat::CincinnatiJson,
+                "application/vnd.redhat.cincinnati.graph.v1+protobuf" => GraphFormat::Protobuf,
+                _ => continue,
+            };
+            if weight > best.1 {
+                best = (format, weight);
+            }
+        }
+        best.0
+    }
+}
+
+/// Build a `304 Not Modified` response for an entity tag.
+pub fn not_modified(etag: &str) -> HttpResponse {
+    HttpResponse::NotModified().header(ETAG, etag).finish()
+}
+
+/// Structured body for errors, as `application/problem+json` (RFC 7807).
+///
+/// `kind` and `value` predate problem details, and are kept for existing
+/// clients.
+#[derive(Clone, Debug, Serialize)]
+pub struct ClientError {
+    #[serde(rename = "type")]
+    pub problem_type: &'static str,
+    /// Reason phrase of the HTTP status.
+    pub title: &'static str,
+    pub status: u16,
+    /// Human-readable error details.
+    pub detail: String,
+    /// Machine-readable error kind.
+    pub kind: String,
+    /// Human-readable error details, same as `detail`.
+    pub value: String,
+}
+
+impl ClientError {
+    pub fn new(status: StatusCode, kind: &str, detail: impl ToString) -> Self {
+        let detail = detail.to_string();
+        Self {
+            problem_type: "about:blank",
+            title: status.canonical_reason().unwrap_or_default(),
+            status: status.as_u16(),
+            value: detail.clone(),
+            detail,
+            kind: kind.to_string(),
+        }
+    }
+}
+
+/// Build an error response with a structured `application/problem+json` body.
+pub fn problem(status: StatusCode, kind: &str, detail: impl ToString) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type("application/problem+json")
+        .json(ClientError::new(status, kind, detail))
+}
+
+/// Build a `400 Bad Request` response with a structured body.
+pub fn bad_request(kind: &str, detail: impl ToString) -> HttpResponse {
+    problem(StatusCode::BAD_REQUEST, kind, detail)
+}
+
+/// Build a `404 Not Found` response with a structured body.
+pub fn not_found(kind: &str, detail: impl ToString) -> HttpResponse {
+    problem(StatusCode::NOT_FOUND, kind, detail)
+}
+
+/// Build a `501 Not Implemented` response with a structured body.
+pub fn not_implemented(kind: &str, detail: impl ToString) -> HttpResponse {
+    problem(StatusCode::NOT_IMPLEMENTED, kind, detail)
+}
+
+/// Build a `504 Gateway Timeout` response with a structured body.
+pub fn gateway_timeout(kind: &str, detail: impl ToString) -> HttpResponse {
+    problem(StatusCode::GATEWAY_TIMEOUT, kind, detail)
+}
+
+/// Build a `503 Service Unavailable` response with a structured body,
+/// hinting clients on when to retry.
+pub fn service_unavailable(
+    kind: &str,
+    detail: impl ToString,
+    retry_after: std::time::Duration,
+) -> HttpResponse {
+    let mut resp = problem(StatusCode::SERVICE_UNAVAILABLE, kind, detail);
+    resp.headers_mut().insert(
+        RETRY_AFTER,
+        HeaderValue::from(retry_after_secs(retry_after)),
+    );
+    resp
+}
+
+/// Build a `429 Too Many Requests` response with a structured body,
+/// hinting clients on when to retry.
+pub fn too_many_requests(
+    kind: &str,
+    detail: impl ToString,
+    retry_after: std::time::Duration,
+) -> HttpResponse {
+    let mut resp = problem(StatusCode::TOO_MANY_REQUESTS, kind, detail);
+    resp.headers_mut().insert(
+        RETRY_AFTER,
+        HeaderValue::from(retry_after_secs(retry_after)),
+    );
+    resp
+}
+
+/// Whole seconds for a `Retry-After` header, rounded up so that clients
+/// never retry too early.
+fn retry_after_secs(retry_after: Duration) -> u64 {
+    let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    secs.max(1)
+}
+
+/// Error kind for requests without valid admin credentials.
+pub static UNAUTHORIZED: &str = "unauthorized";
+
+/// Build a `401 Unauthorized` response for admin endpoints.
+pub fn unauthorized() -> HttpResponse {
+    problem(
+        StatusCode::UNAUTHORIZED,
+        UNAUTHORIZED,
+        "missing or invalid admin token",
+    )
+}
+
+/// Error kind for unexpected failures while serving a request.
+pub static INTERNAL_ERROR: &str = "internal_error";
+
+/// Error returned by request handlers, redacted in responses.
+///
+/// Clients only get a `500 Internal Server Error` with a stable error kind,
+/// while full details (which may mention internal hosts) are logged.
+#[derive(Debug)]
+pub struct HandlerError {
+    kind: &'static str,
+    request_id: Option<String>,
+    error: failure::Error,
+}
+
+impl HandlerError {
+    /// Build an error with the given stable kind.
+    pub fn new(kind: &'static str, error: impl Into<failure::Error>) -> Self {
+        Self {
+            kind,
+            request_id: None,
+            error: error.into(),
+        }
+    }
+
+    /// Attach the ID of the failed request, for logs and responses.
+    pub fn with_request_id(mut self, request_id: &str) -> Self {
+        self.request_id = Some(request_id.to_string());
+        self
+    }
+
+    /// Stable error kind, as reported to clients.
+    pub fn kind(&self) -> &'static str {
+        self.kind
+    }
+}
+
+impl<E: Into<failure::Error>> From<E> for HandlerError {
+    fn from(error: E) -> Self {
+        Self::new(INTERNAL_ERROR, error)
+    }
+}
+
+impl std::fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.kind)?;
+        for cause in self.error.iter_chain() {
+            write!(f, ": {}", cause)?;
+        }
+        Ok(())
+    }
+}
+
+impl ResponseError for HandlerError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match &self.request_id {
+            Some(id) => log::error!("[{}] failed to serve request: {}", id, self),
+            None => log::error!("failed to serve request: {}", self),
+        }
+        let mut resp = problem(self.status_code(), self.kind, "internal server error");
+        if let Some(id) = &self.request_id {
+            set_request_id(&mut resp, id);
+        }
+        resp
+    }
+}
+
+/// Canonicalize a graph query string, e.g. for cache keys.
+///
+/// Parameter names are lowercased and sorted, values of scope parameters are
+/// lowercased, unknown parameters are dropped and only the first occurrence
+/// of repeated parameters is kept. Semantically identical queries thus map to
+/// the same string.
+pub fn canonical_query(query: &str) -> String {
+    let mut params = BTreeMap::new();
+    for (name, value) in url::form_urlencoded::parse(query.as_bytes()) {
+        let name = name.trim().to_ascii_lowercase();
+        let name = match GRAPH_QUERY_PARAMS.iter().find(|known| **known == name) {
+            Some(known) => *known,
+            None => continue,
+        };
+        let value = if CASE_INSENSITIVE_PARAMS.contains(&name) {
+            value.trim().to_ascii_lowercase()
+        } else {
+            value.into_owned()
+        };
+        params.entry(name).or_insert(value);
+    }
+    url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params)
+        .finish()
+}
+
+/// Names of query parameters not in `known`, sorted and deduplicated.
+///
+/// Names are compared like in `canonical_query`, i.e. trimmed and lowercased.
+pub fn unknown_query_params(query: &str, known: &[&str]) -> Vec<String> {
+    let unknown: BTreeSet<String> = url::form_urlencoded::parse(query.as_bytes())
+        .map(|(name, _)| name.trim().to_ascii_lowercase())
+        .filter(|name| !known.contains(&name.as_str()))
+        .collect();
+    unknown.into_iter().collect()
+}
+
+/// Whether a node ID looks like a UUID, either hyphenated or as 32 hex
+/// digits (e.g. an app-specific systemd machine ID, as sent by Zincati).
+pub fn is_node_uuid(value: &str) -> bool {
+    let digits: Vec<u8> = match value.len() {
+        32 => value.bytes().collect(),
+        36 => {
+            let hyphens = [8, 13, 18, 23];
+            if !hyphens.iter().all(|&i| value.as_bytes()[i] == b'-') {
+                return false;
+            }
+            value.bytes().filter(|&b| b != b'-').collect()
+        }
+        _ => return false,
+    };
+    digits.len() == 32 && digits.iter().all(u8::is_ascii_hexdigit)
+}
+
+/// Query extractor deserializing from the canonical form of the query string.
+///
+/// This ensures that services interpret a query exactly as caches keyed on
+/// `canonical_query` do.
+#[derive(Debug)]
+pub struct CanonicalQuery<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for CanonicalQuery<T> {
+    type Error = actix_web::Error;
+    type Future = std::future::Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let query = canonical_query(req.query_string());
+        let result = actix_web::web::Query::<T>::from_query(&query)
+            .map(|q| CanonicalQuery(q.into_inner()))
+            .map_err(|e| {
+                let resp = bad_request("invalid_query", &e);
+                actix_web::error::InternalError::from_response(e, resp).into()
+            });
+        std::future::ready(result)
+    }
+}
+
+/// Documented HTTP endpoint, as listed on `/admin/help`.
+#[derive(Clone, Debug, Serialize)]
+pub struct Endpoint {
+    pub method: &'static str,
+    pub path: &'static str,
+    pub description: &'static str,
+}
+
+impl Endpoint {
+    pub fn get(path: &'static str, description: &'static str) -> Self {
+        Self {
+            method: "GET",
+            path,
+            description,
+        }
+    }
+
+    pub fn post(path: &'static str, description: &'static str) -> Self {
+        Self {
+            method: "POST",
+            path,
+            description,
+        }
+    }
+}
+
+/// Route of a request among known paths, as a metrics label.
+///
+/// Unknown paths are grouped under `other`, as client input is untrusted.
+pub fn route_label(paths: &[&'static str], path: &str) -> &'static str {
+    paths
+        .iter()
+        .find(|known| **known == path)
+        .copied()
+        .unwrap_or("other")
+}
+
+/// Self-description of a running service, served on `/admin/help`.
+#[derive(Clone, Debug, Serialize)]
+pub struct ServiceHelp {
+    pub name: &'static str,
+    pub version: &'static str,
+    /// Endpoints, by server (`service` or `status`).
+    pub endpoints: BTreeMap<&'static str, Vec<Endpoint>>,
+    /// Served graph scopes (all scopes if unset).
+    pub scopes: Option<Vec<GraphScope>>,
+    /// Processing steps applied to served graphs, in order.
+    pub pipeline: Vec<String>,
+    pub features: BTreeMap<&'static str, bool>,
+}
+
+/// Graph scopes served by a deployment, listed on `/v1/streams`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StreamsIndex {
+    pub scopes: Vec<GraphScope>,
+}
 
 /// Build a CORS middleware.
 ///
@@ -22,22 +533,556 @@ pub fn build_cors_middleware(origin_allowlist: &Option<Vec<String>>) -> CorsFact
     builder.finish()
 }
 
+/// Check whether a request is authorized for admin actions.
+///
+/// Admin actions require an `Authorization: Bearer <token>` header matching
+/// the configured token. If no token is configured, admin actions are disabled.
+pub fn is_admin_authorized(req: &HttpRequest, admin_token: &Option<String>) -> bool {
+    let expected = match admin_token {
+        Some(token) => token.as_bytes(),
+        None => return false,
+    };
+    let provided = match req
+        .headers()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+    {
+        Some(token) => token.as_bytes(),
+        None => return false,
+    };
+
+    // Constant-time comparison, to avoid leaking token content via timing.
+    if provided.len() != expected.len() {
+        return false;
+    }
+    provided
+        .iter()
+        .zip(expected.iter())
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+        == 0
+}
+
+/// Return the ID of a request, as provided by the client or freshly generated.
+///
+/// Client-provided IDs are only honored if reasonably short and made of
+/// printable ASCII characters.
+pub fn request_id(req: &HttpRequest) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let provided = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| {
+            !v.is_empty()
+                && v.len() <= MAX_REQUEST_ID_LEN
+                && v.bytes().all(|b| b.is_ascii_graphic())
+        });
+    match provided {
+        Some(id) => id.to_string(),
+        None => {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or_default();
+            let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+            format!("{:016x}-{:08x}", now, count)
+        }
+    }
+}
+
+/// Report the ID of the request a response is for.
+pub fn set_request_id(resp: &mut HttpResponse, request_id: &str) {
+    let name = HeaderName::from_static("x-request-id");
+    if let Ok(value) = HeaderValue::from_str(request_id) {
+        resp.headers_mut().insert(name, value);
+    }
+}
+
+/// Return the address of the client which sent a request.
+///
+/// With `trusted_proxies` reverse proxies in front of the service, each one
+/// appending its peer to `X-Forwarded-For`, the client is the entry added by
+/// the outermost proxy. Entries before it are client-provided, and ignored.
+pub fn client_ip(req: &HttpRequest, trusted_proxies: usize) -> Option<IpAddr> {
+    if trusted_proxies > 0 {
+        let forwarded: Vec<&str> = req
+            .headers()
+            .get_all("x-forwarded-for")
+            .filter_map(|v| v.to_str().ok())
+            .collect();
+        if let Some(ip) = forwarded_client_ip(&forwarded.join(","), trusted_proxies) {
+            return Some(ip);
+        }
+    }
+    req.peer_addr().map(|addr| addr.ip())
+}
+
+/// Return the client address from an `X-Forwarded-For` list, skipping the
+/// entries of trusted proxies.
+fn forwarded_client_ip(forwarded: &str, trusted_proxies: usize) -> Option<IpAddr> {
+    let entries: Vec<&str> = forwarded
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .collect();
+    let index = entries.len().saturating_sub(trusted_proxies.max(1));
+    entries.get(index)?.parse().ok()
+}
+
+/// Config section for per-client rate limiting of graph requests.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Sustained graph requests per minute per client IP (unlimited if unset).
+    pub ip_per_minute: Option<u32>,
+    /// Sustained graph requests per minute per node ID (unlimited if unset).
+    pub node_per_minute: Option<u32>,
+    /// Requests allowed in a burst, per client (the per-minute rate if unset).
+    pub burst: Option<u32>,
+    /// Reverse proxies in front of the service, appending to `X-Forwarded-For`.
+    pub trusted_proxies: Option<usize>,
+}
+
+/// Runtime settings for per-client rate limiting.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RateLimitSettings {
+    pub ip_per_minute: Option<u32>,
+    pub node_per_minute: Option<u32>,
+    pub burst: Option<u32>,
+    pub trusted_proxies: usize,
+}
+
+impl RateLimitSettings {
+    /// Apply a configuration section on top of current settings.
+    pub fn apply_config(&mut self, cfg: RateLimitConfig) -> Fallible<()> {
+        for (name, value) in &[
+            ("ip_per_minute", cfg.ip_per_minute),
+            ("node_per_minute", cfg.node_per_minute),
+            ("burst", cfg.burst),
+        ] {
+            if *value == Some(0) {
+                bail!("invalid '{}': must be non-zero", name);
+            }
+        }
+        if let Some(rate) = cfg.ip_per_minute {
+            self.ip_per_minute = Some(rate);
+        }
+        if let Some(rate) = cfg.node_per_minute {
+            self.node_per_minute = Some(rate);
+        }
+        if let Some(burst) = cfg.burst {
+            self.burst = Some(burst);
+        }
+        if let Some(proxies) = cfg.trusted_proxies {
+            self.trusted_proxies = proxies;
+        }
+        Ok(())
+    }
+}
+
+/// Token buckets, by client.
+#[derive(Debug)]
+struct TokenBuckets {
+    /// Maximum tokens per bucket.
+    capacity: f64,
+    /// Tokens refilled per second.
+    refill_rate: f64,
+    /// (tokens, last refill), by client.
+    buckets: HashMap<String, (f64, Instant)>,
+}
+
+impl TokenBuckets {
+    fn new(per_minute: u32, burst: Option<u32>) -> Self {
+        Self {
+            capacity: f64::from(burst.unwrap_or(per_minute)),
+            refill_rate: f64::from(per_minute) / 60.0,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Take a token for a client, returning the time until the next token
+    /// if the bucket is empty.
+    fn take(&mut self, client: &str, now: Instant) -> Result<(), Duration> {
+        if !self.buckets.contains_key(client) && self.buckets.len() >= MAX_RATE_LIMITED_CLIENTS {
+            // Drop buckets refilled by now, which are equivalent to new ones.
+            let (capacity, refill_rate) = (self.capacity, self.refill_rate);
+            self.buckets.retain(|_, (tokens, last)| {
+                let elapsed = now.saturating_duration_since(*last).as_secs_f64();
+                *tokens + elapsed * refill_rate < capacity
+            });
+            if self.buckets.len() >= MAX_RATE_LIMITED_CLIENTS {
+                return Ok(());
+            }
+        }
+
+        let (tokens, last) = self
+            .buckets
+            .entry(client.to_string())
+            .or_insert((self.capacity, now));
+        let elapsed = now.saturating_duration_since(*last).as_secs_f64();
+        *tokens = (*tokens + elapsed * self.refill_rate).min(self.capacity);
+        *last = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - *tokens) / self.refill_rate))
+        }
+    }
+}
+
+/// Per-client rate limiter for graph requests, by client IP and node ID.
+#[derive(Debug)]
+pub struct RateLimiter {
+    trusted_proxies: usize,
+    by_ip: Option<Mutex<TokenBuckets>>,
+    by_node: Option<Mutex<TokenBuckets>>,
+    /// Throttled requests, by client key (`ip` or `node`).
+    throttled: IntCounterVec,
+}
+
+impl RateLimiter {
+    /// Create a rate limiter and register its metrics, with names prefixed by
+    /// `namespace`.
+    pub fn register(
+        settings: &RateLimitSettings,
+        namespace: &str,
+        registry: &Registry,
+    ) -> Fallible<Self> {
+        let throttled = IntCounterVec::new(
+            Opts::new(
+                "v1_graph_rate_limited_requests_total",
+                "Total number of graph requests rejected by per-client rate limiting.",
+            )
+            .namespace(namespace),
+            &["key"],
+        )?;
+        registry.register(Box::new(throttled.clone()))?;
+
+        let buckets = |per_minute: Option<u32>| {
+            per_minute.map(|rate| Mutex::new(TokenBuckets::new(rate, settings.burst)))
+        };
+        let limiter = Self {
+            trusted_proxies: settings.trusted_proxies,
+            by_ip: buckets(settings.ip_per_minute),
+            by_node: buckets(settings.node_per_minute),
+            throttled,
+        };
+        Ok(limiter)
+    }
+
+    /// Check a graph request against rate limits, returning a
+    /// `429 Too Many Requests` response if the client must back off.
+    pub fn check(&self, req: &HttpRequest, node_uuid: Option<&str>) -> Option<HttpResponse> {
+        self.check_at(req, node_uuid, Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        req: &HttpRequest,
+        node_uuid: Option<&str>,
+        now: Instant,
+    ) -> Option<HttpResponse> {
+        if let Some(by_ip) = &self.by_ip {
+            if let Some(ip) = client_ip(req, self.trusted_proxies) {
+                let taken = by_ip.lock().ok()?.take(&ip.to_string(), now);
+                if let Err(wait) = taken {
+                    self.throttled.with_label_values(&["ip"]).inc();
+                    return Some(too_many_requests(
+                        "rate_limited",
+                        "too many requests from this address",
+                        wait,
+                    ));
+                }
+            }
+        }
+        if let (Some(by_node), Some(uuid)) = (&self.by_node, node_uuid) {
+            let taken = by_node.lock().ok()?.take(&uuid.to_ascii_lowercase(), now);
+            if let Err(wait) = taken {
+                self.throttled.with_label_values(&["node"]).inc();
+                return Some(too_many_requests(
+                    "rate_limited",
+                    "too many requests from this node",
+                    wait,
+                ));
+            }
+        }
+        None
+    }
+}
+
+/// Limit on graph requests concurrently in flight, shedding overflow.
+///
+/// Beyond the limit, requests are rejected right away instead of piling up
+/// in workers while slow ones complete.
+#[derive(Clone, Debug)]
+pub struct InFlightLimit {
+    /// Maximum requests in flight (unlimited if unset).
+    max: Option<usize>,
+    current: Arc<AtomicUsize>,
+    in_flight: IntGauge,
+    shed: IntCounter,
+}
+
+impl InFlightLimit {
+    /// Create a limit and register its metrics, with names prefixed by
+    /// `namespace`.
+    pub fn register(max: Option<usize>, namespace: &str, registry: &Registry) -> Fallible<Self> {
+        let in_flight = IntGauge::with_opts(
+            Opts::new(
+                "v1_graph_in_flight_requests",
+                "Number of graph requests currently being served.",
+            )
+            .namespace(namespace),
+        )?;
+        let shed = IntCounter::with_opts(
+            Opts::new(
+                "v1_graph_shed_requests_total",
+                "Total number of graph requests shed beyond the in-flight limit.",
+            )
+            .namespace(namespace),
+        )?;
+        registry.register(Box::new(in_flight.clone()))?;
+        registry.register(Box::new(shed.clone()))?;
+
+        let limit = Self {
+            max,
+            current: Arc::new(AtomicUsize::new(0)),
+            in_flight,
+            shed,
+        };
+        Ok(limit)
+    }
+
+    /// Reserve a slot for a request, or return a `503 Service Unavailable`
+    /// response if at capacity.
+    ///
+    /// The slot is released when the returned permit is dropped.
+    pub fn try_acquire(&self) -> Result<InFlightPermit, HttpResponse> {
+        let max = self.max.unwrap_or(usize::MAX);
+        let reserved = self
+            .current
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                if current < max {
+                    Some(current + 1)
+                } else {
+                    None
+                }
+            });
+        if reserved.is_err() {
+            self.shed.inc();
+            return Err(service_unavailable(
+                "overloaded",
+                "too many graph requests in flight",
+                Duration::from_secs(1),
+            ));
+        }
+        self.in_flight.inc();
+        Ok(InFlightPermit {
+            current: Arc::clone(&self.current),
+            in_flight: self.in_flight.clone(),
+        })
+    }
+}
+
+/// Slot for a request in flight, released on drop.
+#[derive(Debug)]
+pub struct InFlightPermit {
+    current: Arc<AtomicUsize>,
+    in_flight: IntGauge,
+}
+
+impl Drop for InFlightPermit {
+    fn drop(&mut self) {
+        self.current.fetch_sub(1, Ordering::AcqRel);
+        self.in_flight.dec();
+    }
+}
+
+/// Sampled access logs of graph requests, as `key=value` pairs.
+#[derive(Clone, Debug)]
+pub struct AccessLog {
+    /// Log target, so that access logs can be filtered apart.
+    target: &'static str,
+    /// Fraction of requests logged, between 0.0 (disabled) and 1.0.
+    sample_rate: f64,
+    /// Reverse proxies in front of the service, for client addresses.
+    trusted_proxies: usize,
+    requests: Arc<AtomicU64>,
+}
+
+impl AccessLog {
+    pub fn new(target: &'static str, sample_rate: f64, trusted_proxies: usize) -> Self {
+        Self {
+            target,
+            sample_rate,
+            trusted_proxies,
+            requests: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Whether to log the next request, spreading samples evenly.
+    fn sampled(&self) -> bool {
+        let count = self.requests.fetch_add(1, Ordering::Relaxed) as f64;
+        ((count + 1.0) * self.sample_rate).floor() > (count * self.sample_rate).floor()
+    }
+
+    /// Log a served request, if sampled. Server errors are always logged,
+    /// unless access logs are disabled.
+    pub fn record(&self, req: &HttpRequest, status: StatusCode, latency: Duration) {
+        if self.sample_rate <= 0.0 || !(status.is_server_error() || self.sampled()) {
+            return;
+        }
+        let params: HashMap<String, String> =
+            url::form_urlencoded::parse(req.query_string().as_bytes())
+                .into_owned()
+                .collect();
+        // Client input is quoted and escaped, so entries can't be forged.
+        let param = |name: &str| match params.get(name) {
+            Some(value) => format!("{:?}", value),
+            None => "-".to_string(),
+        };
+        let client_ip = client_ip(req, self.trusted_proxies)
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        log::info!(
+            target: self.target,
+            "method={} path={:?} product={} basearch={} stream={} status={} latency_ms={} client_ip={}",
+            req.method(),
+            req.path(),
+            param("product"),
+            param("basearch"),
+            param("stream"),
+            status.as_u16(),
+            latency.as_millis(),
+            client_ip
+        );
+    }
+}
+
+/// Aliases of scope values in requests, mapped onto canonical values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScopeAliases {
+    /// Basearch alias -> canonical basearch.
+    pub basearch: HashMap<String, String>,
+    /// Stream alias -> canonical stream.
+    pub stream: HashMap<String, String>,
+}
+
+impl Default for ScopeAliases {
+    fn default() -> Self {
+        let basearch = DEFAULT_BASEARCH_ALIASES
+            .iter()
+            .map(|(alias, basearch)| (alias.to_string(), basearch.to_string()))
+            .collect();
+        Self {
+            basearch,
+            stream: HashMap::new(),
+        }
+    }
+}
+
+impl ScopeAliases {
+    /// Canonical basearch for a requested one.
+    pub fn resolve_basearch(&self, basearch: String) -> String {
+        match self.basearch.get(&basearch) {
+            Some(canonical) => canonical.clone(),
+            None => basearch,
+        }
+    }
+
+    /// Canonical stream for a requested one.
+    pub fn resolve_stream(&self, stream: String) -> String {
+        match self.stream.get(&stream) {
+            Some(canonical) => canonical.clone(),
+            None => stream,
+        }
+    }
+}
+
+/// Check and normalize configured aliases, onto canonical values.
+pub fn check_aliases(aliases: HashMap<String, String>) -> Fallible<HashMap<String, String>> {
+    let mut normalized = HashMap::with_capacity(aliases.len());
+    for (alias, canonical) in aliases {
+        let alias = alias.trim().to_ascii_lowercase();
+        let canonical = canonical.trim().to_ascii_lowercase();
+        if alias.is_empty() || canonical.is_empty() {
+            bail!("empty alias or canonical value");
+        }
+        if alias == canonical {
+            bail!("alias '{}' mapped onto itself", alias);
+        }
+        normalized.insert(alias, canonical);
+    }
+    if let Some(canonical) = normalized.values().find(|c| normalized.contains_key(*c)) {
+        bail!("'{}' is itself an alias", canonical);
+    }
+    Ok(normalized)
+}
+
+/// Invalid graph scope in a request.
+#[derive(Debug)]
+pub struct ScopeError {
+    /// Machine-readable error kind.
+    pub kind: &'static str,
+    detail: String,
+}
+
+impl ScopeError {
+    fn new(kind: &'static str, detail: impl ToString) -> Self {
+        Self {
+            kind,
+            detail: detail.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for ScopeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.detail)
+    }
+}
+
+impl std::error::Error for ScopeError {}
+
 /// Validate input query parameters into a valid graph scope.
+///
+/// A missing product defaults to `default_product`, and aliases of basearch
+/// and stream values are replaced by their canonical value.
 pub fn validate_scope(
+    product: Option<String>,
+    default_product: &str,
     basearch: Option<String>,
     stream: Option<String>,
+    aliases: &ScopeAliases,
     oci: Option<bool>,
     scope_allowlist: &Option<HashSet<GraphScope>>,
-) -> Result<GraphScope, failure::Error> {
-    let basearch = basearch.ok_or_else(|| err_msg("missing basearch"))?;
-    ensure!(!basearch.is_empty(), "empty basearch");
+) -> Result<GraphScope, ScopeError> {
+    let product = product.unwrap_or_else(|| default_product.to_string());
+    if product.is_empty() {
+        return Err(ScopeError::new("missing_product", "empty product"));
+    }
 
-    let stream = stream.ok_or_else(|| err_msg("missing stream"))?;
-    ensure!(!stream.is_empty(), "empty stream");
+    let basearch = match basearch {
+        Some(basearch) if !basearch.is_empty() => basearch,
+        Some(_) => return Err(ScopeError::new("missing_basearch", "empty basearch")),
+        None => return Err(ScopeError::new("missing_basearch", "missing basearch")),
+    };
+    let basearch = aliases.resolve_basearch(basearch);
+
+    let stream = match stream {
+        Some(stream) if !stream.is_empty() => stream,
+        Some(_) => return Err(ScopeError::new("missing_stream", "empty stream")),
+        None => return Err(ScopeError::new("missing_stream", "missing stream")),
+    };
+    let stream = aliases.resolve_stream(stream);
 
     let oci = oci.unwrap_or_default();
 
     let scope = GraphScope {
+        product,
         basearch,
         stream,
         oci,
@@ -46,12 +1091,13 @@ pub fn validate_scope(
     // Optionally filter out scope according to given allowlist, if any.
     if let Some(allowlist) = scope_allowlist {
         if !allowlist.contains(&scope) {
-            bail!(
-                "scope not allowed: basearch='{}', stream='{}', oci='{}'",
-                scope.basearch,
-                scope.stream,
-                scope.oci,
-            );
+            return Err(ScopeError::new(
+                "unknown_scope",
+                format!(
+                    "scope not allowed: product='{}', basearch='{}', stream='{}', oci='{}'",
+                    scope.product, scope.basearch, scope.stream, scope.oci,
+                ),
+            ));
         }
     }
 
@@ -61,45 +1107,416 @@ pub fn validate_scope(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::metadata::DEFAULT_PRODUCT;
+    use actix_web::http::header::CONTENT_TYPE;
+    use failure::err_msg;
+
+    #[test]
+    fn test_is_admin_authorized() {
+        use actix_web::test::TestRequest;
+
+        let token = Some("s3cr3t".to_string());
+        let req = TestRequest::default()
+            .header("Authorization", "Bearer s3cr3t")
+            .to_http_request();
+        assert!(is_admin_authorized(&req, &token));
+        assert!(!is_admin_authorized(&req, &None));
+
+        let req = TestRequest::default()
+            .header("Authorization", "Bearer wrong!")
+            .to_http_request();
+        assert!(!is_admin_authorized(&req, &token));
+
+        let req = TestRequest::default().to_http_request();
+        assert!(!is_admin_authorized(&req, &token));
+    }
+
+    #[test]
+    fn test_client_ip() {
+        use actix_web::test::TestRequest;
+
+        let peer = "192.0.2.1:41000".parse().unwrap();
+        let req = TestRequest::default()
+            .peer_addr(peer)
+            .header("X-Forwarded-For", "203.0.113.7, 198.51.100.2")
+            .to_http_request();
+        assert_eq!(client_ip(&req, 0), Some(peer.ip()));
+        assert_eq!(client_ip(&req, 1), "198.51.100.2".parse().ok());
+        assert_eq!(client_ip(&req, 2), "203.0.113.7".parse().ok());
+        assert_eq!(client_ip(&req, 3), "203.0.113.7".parse().ok());
+
+        assert_eq!(forwarded_client_ip("", 1), None);
+        assert_eq!(
+            forwarded_client_ip("2001:db8::1", 1),
+            "2001:db8::1".parse().ok()
+        );
+    }
+
+    #[test]
+    fn test_token_buckets() {
+        let start = Instant::now();
+        let mut buckets = TokenBuckets::new(60, Some(2));
+        assert_eq!(buckets.take("a", start), Ok(()));
+        assert_eq!(buckets.take("a", start), Ok(()));
+        assert_eq!(buckets.take("a", start), Err(Duration::from_secs(1)));
+        assert_eq!(buckets.take("b", start), Ok(()));
+
+        let later = start + Duration::from_millis(1500);
+        assert_eq!(buckets.take("a", later), Ok(()));
+        assert_eq!(buckets.take("a", later), Err(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        use actix_web::test::TestRequest;
+
+        let settings = RateLimitSettings {
+            node_per_minute: Some(1),
+            ..RateLimitSettings::default()
+        };
+        let limiter = RateLimiter::register(&settings, "test", &Registry::new()).unwrap();
+        let req = TestRequest::default().to_http_request();
+        let node = "b5a4d2b8c5e54e3d9b4bd3fa0a8e2f4c";
+        let start = Instant::now();
+        assert!(limiter.check_at(&req, Some(node), start).is_none());
+        assert!(limiter.check_at(&req, None, start).is_none());
+        let resp = limiter
+            .check_at(&req, Some(&node.to_uppercase()), start)
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers().get(RETRY_AFTER).unwrap(), "60");
+
+        let later = start + Duration::from_millis(500);
+        let resp = limiter.check_at(&req, Some(node), later).unwrap();
+        assert_eq!(resp.headers().get(RETRY_AFTER).unwrap(), "60");
+    }
+
+    #[test]
+    fn test_retry_after_secs() {
+        assert_eq!(retry_after_secs(Duration::from_secs(0)), 1);
+        assert_eq!(retry_after_secs(Duration::from_millis(200)), 1);
+        assert_eq!(retry_after_secs(Duration::from_secs(2)), 2);
+        assert_eq!(retry_after_secs(Duration::from_millis(59_500)), 60);
+    }
+
+    #[test]
+    fn test_in_flight_limit() {
+        let registry = Registry::new();
+        let limit = InFlightLimit::register(Some(1), "test", &registry).unwrap();
+        let permit = limit.try_acquire().unwrap();
+        assert_eq!(limit.in_flight.get(), 1);
+        let resp = limit.try_acquire().unwrap_err();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(limit.shed.get(), 1);
+
+        drop(permit);
+        assert_eq!(limit.in_flight.get(), 0);
+        assert!(limit.clone().try_acquire().is_ok());
+
+        let unlimited = InFlightLimit::register(None, "test", &Registry::new()).unwrap();
+        let _permits: Vec<_> = (0..10).map(|_| unlimited.try_acquire().unwrap()).collect();
+    }
+
+    #[test]
+    fn test_access_log_sampling() {
+        let quarter = AccessLog::new("test", 0.25, 0);
+        assert_eq!((0..100).filter(|_| quarter.sampled()).count(), 25);
+        let all = AccessLog::new("test", 1.0, 0);
+        assert!((0..100).all(|_| all.sampled()));
+    }
+
+    #[test]
+    fn test_request_id() {
+        use actix_web::test::TestRequest;
+
+        let req = TestRequest::default()
+            .header("X-Request-Id", "abc-123")
+            .to_http_request();
+        assert_eq!(request_id(&req), "abc-123");
+
+        // Invalid IDs are replaced, and generated ones are unique.
+        let req = TestRequest::default()
+            .header("X-Request-Id", "not valid")
+            .to_http_request();
+        let generated = request_id(&req);
+        assert_ne!(generated, "not valid");
+        assert_ne!(generated, request_id(&req));
+
+        let mut resp = HttpResponse::Ok().finish();
+        set_request_id(&mut resp, &generated);
+        assert_eq!(resp.headers().get(REQUEST_ID_HEADER).unwrap(), &generated);
+    }
 
     #[test]
     fn test_validate_scope() {
+        let aliases = ScopeAliases {
+            stream: maplit::hashmap! {"prod".to_string() => "stable".to_string()},
+            ..ScopeAliases::default()
+        };
         {
-            let r = validate_scope(None, None, None, &None);
-            assert!(r.is_err());
+            let r = validate_scope(None, DEFAULT_PRODUCT, None, None, &aliases, None, &None);
+            assert_eq!(r.unwrap_err().kind, "missing_basearch");
         }
         {
             let basearch = Some("test_empty".to_string());
             let stream = Some("".to_string());
             let oci = None;
-            let r = validate_scope(basearch, stream, oci, &None);
-            assert!(r.is_err());
+            let r = validate_scope(
+                None,
+                DEFAULT_PRODUCT,
+                basearch,
+                stream,
+                &aliases,
+                oci,
+                &None,
+            );
+            assert_eq!(r.unwrap_err().kind, "missing_stream");
         }
         {
             let basearch = Some("x86_64".to_string());
             let stream = Some("stable".to_string());
             let oci = Some(false);
-            let r = validate_scope(basearch, stream, oci, &None);
+            let r = validate_scope(
+                None,
+                DEFAULT_PRODUCT,
+                basearch,
+                stream,
+                &aliases,
+                oci,
+                &None,
+            );
             assert!(r.is_ok());
         }
         {
             let basearch = Some("x86_64".to_string());
             let stream = Some("stable".to_string());
             let filter_none_allowed = Some(HashSet::new());
-            let r = validate_scope(basearch, stream, None, &filter_none_allowed);
-            assert!(r.is_err());
+            let r = validate_scope(
+                None,
+                DEFAULT_PRODUCT,
+                basearch,
+                stream,
+                &aliases,
+                None,
+                &filter_none_allowed,
+            );
+            assert_eq!(r.unwrap_err().kind, "unknown_scope");
         }
         {
             let basearch = Some("x86_64".to_string());
             let stream = Some("stable".to_string());
             let allowed_scope = GraphScope {
+                product: DEFAULT_PRODUCT.to_string(),
                 basearch: "x86_64".to_string(),
                 stream: "stable".to_string(),
                 oci: false,
             };
             let filter = Some(maplit::hashset! {allowed_scope});
-            let r = validate_scope(basearch, stream, None, &filter);
+            let r = validate_scope(
+                None,
+                DEFAULT_PRODUCT,
+                basearch.clone(),
+                stream.clone(),
+                &aliases,
+                None,
+                &filter,
+            );
             assert!(r.is_ok());
+            let product = Some("other-os".to_string());
+            let r = validate_scope(
+                product,
+                DEFAULT_PRODUCT,
+                basearch,
+                stream,
+                &aliases,
+                None,
+                &filter,
+            );
+            assert!(r.is_err());
+        }
+        {
+            let basearch = Some("arm64".to_string());
+            let stream = Some("prod".to_string());
+            let r = validate_scope(
+                None,
+                DEFAULT_PRODUCT,
+                basearch,
+                stream,
+                &aliases,
+                None,
+                &None,
+            );
+            let scope = r.unwrap();
+            assert_eq!(scope.basearch, "aarch64");
+            assert_eq!(scope.stream, "stable");
         }
     }
+
+    #[test]
+    fn test_check_aliases() {
+        let aliases = maplit::hashmap! {
+            " AMD64".to_string() => "x86_64".to_string(),
+        };
+        let aliases = check_aliases(aliases).unwrap();
+        assert_eq!(aliases.get("amd64").unwrap(), "x86_64");
+
+        let empty = maplit::hashmap! {
+            "amd64".to_string() => "".to_string(),
+        };
+        check_aliases(empty).unwrap_err();
+        let chained = maplit::hashmap! {
+            "amd64".to_string() => "x64".to_string(),
+            "x64".to_string() => "x86_64".to_string(),
+        };
+        check_aliases(chained).unwrap_err();
+    }
+
+    #[test]
+    fn test_handler_error() {
+        let err = HandlerError::from(err_msg("http://internal.example.com/ unreachable"))
+            .with_request_id("abc-123");
+        assert_eq!(err.kind(), INTERNAL_ERROR);
+        assert!(err.to_string().contains("internal.example.com"));
+
+        let resp = err.error_response();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(resp.headers().get(REQUEST_ID_HEADER).unwrap(), "abc-123");
+        assert_eq!(
+            resp.headers().get(CONTENT_TYPE).unwrap(),
+            "application/problem+json"
+        );
+        let body = match resp.body().as_ref() {
+            Some(actix_web::body::Body::Bytes(b)) => b.clone(),
+            _ => panic!("unexpected response body"),
+        };
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(!body.contains("internal.example.com"));
+        assert!(body.contains(INTERNAL_ERROR));
+        assert!(body.contains(r#""status":500"#));
+    }
+
+    #[test]
+    fn test_route_label() {
+        let paths = ["/v1/graph", "/v1/streams"];
+        assert_eq!(route_label(&paths, "/v1/graph"), "/v1/graph");
+        assert_eq!(route_label(&paths, "/v1/graph/"), "other");
+        assert_eq!(route_label(&paths, "/wp-admin"), "other");
+    }
+
+    #[test]
+    fn test_canonical_query() {
+        let expected = "basearch=x86_64&oci=true&stream=stable";
+        assert_eq!(
+            canonical_query("stream=stable&basearch=x86_64&oci=true"),
+            expected
+        );
+        assert_eq!(
+            canonical_query("oci=True&Basearch=x86_64&STREAM=Stable"),
+            expected
+        );
+        assert_eq!(
+            canonical_query("stream=stable&basearch=x86_64&oci=true&stream=next&foo=bar"),
+            expected
+        );
+        assert_eq!(
+            canonical_query("basearch=x86%5F64&oci=true&stream=stable"),
+            expected
+        );
+        assert_eq!(
+            canonical_query("stream=stable&pretty=TRUE"),
+            "pretty=true&stream=stable"
+        );
+
+        // Opaque values keep their case, and get escaped.
+        assert_eq!(
+            canonical_query("node_uuid=ABC&os_version=a b&stream="),
+            "node_uuid=ABC&os_version=a+b&stream="
+        );
+        assert_eq!(canonical_query(""), "");
+    }
+
+    #[test]
+    fn test_is_node_uuid() {
+        assert!(is_node_uuid("b5a4d2b8c5e54e3d9b4bd3fa0a8e2f4c"));
+        assert!(is_node_uuid("B5A4D2B8-C5E5-4E3D-9B4B-D3FA0A8E2F4C"));
+        assert!(!is_node_uuid(""));
+        assert!(!is_node_uuid("not-a-uuid"));
+        assert!(!is_node_uuid("b5a4d2b8c5e54e3d9b4bd3fa0a8e2f4z"));
+        assert!(!is_node_uuid("b5a4d2b8-c5e54-e3d-9b4b-d3fa0a8e2f4c"));
+    }
+
+    #[test]
+    fn test_unknown_query_params() {
+        let known = ["basearch", "stream"];
+        assert!(unknown_query_params("Basearch=x86_64&stream=stable", &known).is_empty());
+        assert_eq!(
+            unknown_query_params("basearch=x86_64&steam=stable&steam=next&foo", &known),
+            vec!["foo".to_string(), "steam".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_entity_tag() {
+        let etag = entity_tag(b"{}");
+        assert_eq!(etag, entity_tag(b"{}"));
+        assert_ne!(etag, entity_tag(b"[]"));
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+
+        assert!(etag_list_matches(&etag, &etag));
+        assert!(etag_list_matches(&format!("\"other\", W/{}", etag), &etag));
+        assert!(etag_list_matches("*", &etag));
+        assert!(!etag_list_matches("\"other\"", &etag));
+    }
+
+    #[test]
+    fn test_graph_format() {
+        let protobuf = GraphFormat::Protobuf.content_type();
+        let cincinnati_json = GraphFormat::CincinnatiJson.content_type();
+        assert_eq!(
+            GraphFormat::from_accept("application/json"),
+            GraphFormat::Json
+        );
+        assert_eq!(GraphFormat::from_accept("*/*"), GraphFormat::Json);
+        assert_eq!(GraphFormat::from_accept("text/html"), GraphFormat::Json);
+        assert_eq!(GraphFormat::from_accept(protobuf), GraphFormat::Protobuf);
+        assert_eq!(
+            GraphFormat::from_accept(&format!("{};q=0.5, {}", protobuf, cincinnati_json)),
+            GraphFormat::CincinnatiJson
+        );
+        assert_eq!(
+            GraphFormat::from_accept(&format!("application/json;q=0.9, {}", protobuf)),
+            GraphFormat::Protobuf
+        );
+        assert_eq!(
+            GraphFormat::from_accept(&format!("{};q=0", protobuf)),
+            GraphFormat::Json
+        );
+    }
+
+    #[test]
+    fn test_graph_version() {
+        let version = graph_version(b"{}", 1_700_000_000);
+        assert!(version.ends_with("-1700000000"));
+        assert_eq!(version, graph_version(b"{}", 1_700_000_000));
+        assert_ne!(version, graph_version(b"[]", 1_700_000_000));
+    }
+
+    #[test]
+    fn test_encoding_accepted() {
+        assert!(encoding_accepted("gzip, deflate, br", "gzip"));
+        assert!(encoding_accepted("br;q=1.0, GZIP;q=0.5", "gzip"));
+        assert!(!encoding_accepted("gzip;q=0, *", "gzip"));
+        assert!(encoding_accepted("identity, *;q=0.1", "gzip"));
+        assert!(!encoding_accepted("deflate, br", "gzip"));
+        assert!(!encoding_accepted("", "gzip"));
+    }
+
+    #[test]
+    fn test_http_date() {
+        assert_eq!(
+            http_date(1698840000).unwrap(),
+            "Wed, 01 Nov 2023 12:00:00 GMT"
+        );
+        assert_eq!(http_date(i64::MAX), None);
+    }
 }
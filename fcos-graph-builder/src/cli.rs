@@ -1,4 +1,4 @@
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, Parser, Subcommand};
 use log::LevelFilter;
 use std::path::PathBuf;
 
@@ -12,6 +12,20 @@ pub(crate) struct CliOptions {
     /// Path to configuration file.
     #[clap(short = 'c')]
     pub config_path: PathBuf,
+
+    /// Validate configuration and print effective settings, then exit.
+    #[clap(long = "check-config")]
+    pub check_config: bool,
+
+    #[clap(subcommand)]
+    pub cmd: Option<CliCommand>,
+}
+
+/// CLI sub-commands.
+#[derive(Debug, Subcommand)]
+pub(crate) enum CliCommand {
+    /// Check health of a locally running instance, and exit.
+    Probe,
 }
 
 impl CliOptions {
@@ -1,14 +1,215 @@
-use failure::Fallible;
-use std::path::Path;
+use commons::config::HumanDuration;
+use commons::metrics::MetricsConfig;
+use commons::proxy::ProxyConfig;
+use commons::report::ReportingConfig;
+use commons::runtime::RuntimeConfig;
+use commons::shard::Shard;
+use commons::tls::TlsConfig;
+use commons::trace::TracingConfig;
+use commons::web::RateLimitConfig;
+use failure::{Fallible, ResultExt};
+use serde_derive::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 
 /// Configuration file.
-#[derive(Debug, Default)]
-pub struct FileConfig {}
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    /// Main service (graph endpoint) server.
+    pub service: Option<ServiceConfig>,
+    /// Status server.
+    pub status: Option<StatusConfig>,
+    /// Upstream metadata sources.
+    pub upstream: Option<UpstreamConfig>,
+    /// Upstream scrapers.
+    pub scraper: Option<ScraperConfig>,
+    /// Additional products, by name.
+    pub products: Option<BTreeMap<String, ProductConfig>>,
+    /// Fedora Messaging consumer, triggering scrapes on new releases.
+    pub messaging: Option<MessagingConfig>,
+    /// Async runtime sizing.
+    pub runtime: Option<RuntimeConfig>,
+    /// Distributed tracing.
+    pub tracing: Option<TracingConfig>,
+    /// Metrics export.
+    pub metrics: Option<MetricsConfig>,
+    /// Error reporting.
+    pub reporting: Option<ReportingConfig>,
+    /// Feature flags, by name.
+    pub features: Option<HashMap<String, bool>>,
+}
 
 impl FileConfig {
-    pub fn parse_file(_path: impl AsRef<Path>) -> Fallible<Self> {
-        // TODO(lucab): translate config entries.
-        let cfg = FileConfig::default();
+    /// Prefix for environment variables overriding configuration entries.
+    const ENV_PREFIX: &'static str = "FCOS_GB_";
+
+    pub fn parse_file(path: impl AsRef<Path>) -> Fallible<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|_| format!("failed to read config file '{}'", path.display()))?;
+        let cfg = toml::from_str(&content)
+            .with_context(|_| format!("failed to parse config file '{}'", path.display()))?;
+        Ok(cfg)
+    }
+
+    /// Collect configuration overrides from `FCOS_GB_*` environment variables.
+    pub fn from_env() -> Fallible<Self> {
+        let overrides = commons::config::env_overrides(Self::ENV_PREFIX, std::env::vars())?;
+        let cfg = overrides
+            .try_into()
+            .context("failed to parse configuration from environment")?;
         Ok(cfg)
     }
 }
+
+/// Config section for the main service.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceConfig {
+    pub address: Option<IpAddr>,
+    /// Listen addresses, instead of a single `address`.
+    pub addresses: Option<Vec<IpAddr>>,
+    pub port: Option<u16>,
+    pub origin_allowlist: Option<Vec<String>>,
+    /// Stream name to basearches.
+    pub streams: Option<BTreeMap<String, Vec<String>>>,
+    /// Subset of scopes owned by this instance (all if unset).
+    pub shard: Option<Shard>,
+    /// Static graphs served verbatim instead of scraped ones, for emergencies.
+    pub static_graphs: Option<Vec<StaticGraphConfig>>,
+    /// Aliases of basearch values in requests, mapped onto canonical
+    /// basearches (replaces the default aliases).
+    pub basearch_aliases: Option<HashMap<String, String>>,
+    /// Aliases of stream names in requests, mapped onto canonical streams.
+    pub stream_aliases: Option<HashMap<String, String>>,
+    /// Per-client rate limiting of graph requests.
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Maximum graph requests in flight, beyond which requests are shed
+    /// (unlimited if unset).
+    pub max_in_flight: Option<usize>,
+    /// Fraction of graph requests to log, between 0.0 and 1.0 (access logs
+    /// disabled if unset).
+    pub access_log_sample_rate: Option<f64>,
+}
+
+/// Config entry for a static override graph.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StaticGraphConfig {
+    /// Product (default product if unset).
+    pub product: Option<String>,
+    pub basearch: String,
+    pub stream: String,
+    #[serde(default)]
+    pub oci: bool,
+    /// Path to the graph JSON file.
+    pub path: PathBuf,
+}
+
+/// Config section for an additional product.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProductConfig {
+    /// Stream name to basearches.
+    pub streams: Option<BTreeMap<String, Vec<String>>>,
+    /// Templated URL for release index.
+    pub releases_url: Option<String>,
+    /// Templated URL for updates metadata.
+    pub updates_url: Option<String>,
+    /// Whether upstream only ships OCI payloads.
+    pub oci_only: Option<bool>,
+}
+
+/// Config section for the status server.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StatusConfig {
+    pub address: Option<IpAddr>,
+    /// Listen addresses, instead of a single `address`.
+    pub addresses: Option<Vec<IpAddr>>,
+    pub port: Option<u16>,
+    /// Bearer token for admin endpoints.
+    pub admin_token: Option<String>,
+    /// Mutual TLS, requiring client certificates (plain HTTP if unset).
+    pub tls: Option<TlsConfig>,
+}
+
+/// Config section for upstream metadata.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamConfig {
+    /// Product name, for URL templates.
+    pub product: Option<String>,
+    /// Templated URL for release index.
+    pub releases_url: Option<String>,
+    /// Templated URL for updates metadata.
+    pub updates_url: Option<String>,
+    /// Per-stream overrides, by stream name.
+    pub streams: Option<BTreeMap<String, UpstreamStreamConfig>>,
+    /// Outbound proxy for scraping.
+    pub proxy: Option<ProxyConfig>,
+}
+
+/// Config entry for per-stream upstream metadata overrides.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamStreamConfig {
+    /// Templated URL for release index.
+    pub releases_url: Option<String>,
+    /// Templated URL for updates metadata.
+    pub updates_url: Option<String>,
+}
+
+/// Config section for the Fedora Messaging consumer.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MessagingConfig {
+    /// AMQP broker URL, with credentials (disabled if unset).
+    pub url: Option<String>,
+    /// Exchange to bind to.
+    pub exchange: Option<String>,
+    /// Topics announcing new releases.
+    pub topics: Option<Vec<String>>,
+    /// Pause before reconnecting to the broker, after a failure.
+    pub reconnect_delay: Option<HumanDuration>,
+}
+
+/// Config section for upstream scrapers.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScraperConfig {
+    /// Maximum percentage of releases a refresh may drop from a cached graph.
+    pub max_release_loss_percent: Option<u8>,
+    /// Whether to accept any graph change, bypassing sanity checks.
+    pub allow_drastic_changes: Option<bool>,
+    /// How long to keep serving the previous graph after a significant change.
+    pub transition_window: Option<HumanDuration>,
+    /// Fraction of requests served from the previous graph during a transition.
+    pub transition_fraction: Option<f64>,
+    /// Pause between upstream scrapes.
+    pub interval: Option<HumanDuration>,
+    /// Maximum random delay added to each scheduled scrape.
+    pub jitter: Option<HumanDuration>,
+    /// Maximum delay between upstream scrapes, when backing off after failures.
+    pub max_backoff: Option<HumanDuration>,
+    /// Consecutive failures after which the circuit breaker opens.
+    pub circuit_breaker_threshold: Option<u32>,
+    /// Delay between upstream probes while the circuit breaker is open.
+    pub circuit_breaker_cooldown: Option<HumanDuration>,
+    /// Maximum age of a served graph, since its last successful refresh.
+    pub max_staleness: Option<HumanDuration>,
+    /// Maximum number of queued messages per scraper.
+    pub mailbox_capacity: Option<usize>,
+    /// Per-stream overrides, by stream name.
+    pub streams: Option<BTreeMap<String, ScraperStreamConfig>>,
+}
+
+/// Config entry for per-stream scraper overrides.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScraperStreamConfig {
+    /// Pause between upstream scrapes.
+    pub interval: Option<HumanDuration>,
+}
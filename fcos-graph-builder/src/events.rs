@@ -0,0 +1,115 @@
+//! Notifications of cached graph changes, streamed as server-sent events.
+
+use actix::prelude::*;
+use actix_web::web::Bytes;
+use futures::channel::mpsc;
+use serde_derive::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Maximum number of concurrent subscribers.
+const MAX_SUBSCRIBERS: usize = 256;
+/// Events buffered per subscriber, beyond which a slow subscriber is dropped.
+const SUBSCRIBER_BUFFER: usize = 64;
+/// Interval between keep-alive comments, so that idle streams are not
+/// closed by proxies, and gone subscribers are noticed.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Change of the cached graph for a scope.
+#[derive(Debug, Serialize)]
+pub(crate) struct GraphChange<'a> {
+    pub(crate) product: &'a str,
+    pub(crate) basearch: &'a str,
+    pub(crate) stream: &'a str,
+    pub(crate) oci: bool,
+    /// New graph generation, as served in the `X-Graph-Version` header.
+    pub(crate) version: &'a str,
+}
+
+/// Subscribers to graph change events.
+#[derive(Debug, Default)]
+pub(crate) struct GraphEvents {
+    subscribers: Mutex<Vec<mpsc::Sender<Bytes>>>,
+}
+
+impl GraphEvents {
+    /// Subscribe to graph change events, unless there are too many subscribers.
+    ///
+    /// The returned stream yields events already formatted for the wire.
+    pub(crate) fn subscribe(&self) -> Option<mpsc::Receiver<Bytes>> {
+        let mut subscribers = self.subscribers.lock().ok()?;
+        if subscribers.len() >= MAX_SUBSCRIBERS {
+            return None;
+        }
+        let (sender, receiver) = mpsc::channel(SUBSCRIBER_BUFFER);
+        subscribers.push(sender);
+        crate::GRAPH_EVENT_SUBSCRIBERS.set(subscribers.len() as i64);
+        Some(receiver)
+    }
+
+    /// Notify all subscribers of a graph change.
+    pub(crate) fn publish(&self, change: &GraphChange) {
+        match serde_json::to_string(change) {
+            Ok(data) => self.broadcast(format!("event: graph\ndata: {}\n\n", data)),
+            Err(e) => log::error!("failed to serialize graph change event: {}", e),
+        }
+    }
+
+    /// Send a message to all subscribers, dropping gone or lagging ones.
+    fn broadcast(&self, message: String) {
+        let message = Bytes::from(message);
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.retain_mut(|sender| sender.try_send(message.clone()).is_ok());
+            crate::GRAPH_EVENT_SUBSCRIBERS.set(subscribers.len() as i64);
+        }
+    }
+}
+
+/// Periodic keep-alive comments towards graph change subscribers.
+pub(crate) struct GraphEventsKeepalive {
+    pub(crate) events: Arc<GraphEvents>,
+}
+
+impl Actor for GraphEventsKeepalive {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(KEEPALIVE_INTERVAL, |actor, _ctx| {
+            actor.events.broadcast(": keepalive\n\n".to_string());
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[test]
+    fn test_graph_events() {
+        let events = GraphEvents::default();
+        let mut receiver = events.subscribe().unwrap();
+        let change = GraphChange {
+            product: "fedora-coreos",
+            basearch: "x86_64",
+            stream: "stable",
+            oci: false,
+            version: "0123456789abcdef-1700000000",
+        };
+        events.publish(&change);
+        let event = futures::executor::block_on(receiver.next()).unwrap();
+        assert_eq!(
+            event,
+            "event: graph\ndata: {\"product\":\"fedora-coreos\",\"basearch\":\"x86_64\",\"stream\":\"stable\",\"oci\":false,\"version\":\"0123456789abcdef-1700000000\"}\n\n"
+        );
+
+        drop(receiver);
+        events.publish(&change);
+        assert!(events.subscribers.lock().unwrap().is_empty());
+
+        let _receivers: Vec<_> = (0..MAX_SUBSCRIBERS)
+            .map(|_| events.subscribe().unwrap())
+            .collect();
+        assert!(events.subscribe().is_none());
+    }
+}
@@ -0,0 +1,78 @@
+//! Bounded queueing of requests towards actors, shedding overflow.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Limit on requests concurrently queued towards an actor.
+///
+/// Actix mailboxes apply backpressure when full, which would let waiting
+/// requests pile up behind a slow actor. This instead lets callers shed
+/// requests beyond capacity right away.
+#[derive(Clone, Debug)]
+pub(crate) struct MailboxGuard {
+    pending: Arc<AtomicUsize>,
+    capacity: usize,
+}
+
+impl MailboxGuard {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            pending: Arc::new(AtomicUsize::new(0)),
+            capacity,
+        }
+    }
+
+    /// Reserve a slot for a request, unless at capacity.
+    ///
+    /// The slot is released when the returned permit is dropped.
+    pub(crate) fn try_acquire(&self) -> Option<MailboxPermit> {
+        let capacity = self.capacity;
+        self.pending
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |pending| {
+                if pending < capacity {
+                    Some(pending + 1)
+                } else {
+                    None
+                }
+            })
+            .ok()?;
+        Some(MailboxPermit {
+            pending: Arc::clone(&self.pending),
+        })
+    }
+
+    /// Maximum number of queued requests.
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+/// Slot for a queued request, released on drop.
+#[derive(Debug)]
+pub(crate) struct MailboxPermit {
+    pending: Arc<AtomicUsize>,
+}
+
+impl Drop for MailboxPermit {
+    fn drop(&mut self) {
+        self.pending.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mailbox_guard() {
+        let guard = MailboxGuard::new(2);
+        let first = guard.try_acquire().unwrap();
+        let _second = guard.clone().try_acquire().unwrap();
+        assert!(guard.try_acquire().is_none());
+
+        drop(first);
+        let third = guard.try_acquire();
+        assert!(third.is_some());
+        assert!(guard.try_acquire().is_none());
+    }
+}
@@ -5,53 +5,178 @@ extern crate prometheus;
 
 mod cli;
 mod config;
+mod events;
+mod mailbox;
+mod messaging;
 mod scraper;
 mod settings;
+mod state;
 
 use actix::prelude::*;
-use actix_web::{web, App, HttpResponse};
+use actix_web::dev::{HttpResponseBuilder, Service};
+use actix_web::http::header::{CACHE_CONTROL, CONTENT_ENCODING, ETAG, LAST_MODIFIED, VARY};
+use actix_web::http::StatusCode;
+use actix_web::{web, App, HttpRequest, HttpResponse, ResponseError, Route};
 use clap::{crate_name, crate_version, Parser};
-use commons::{graph, metrics};
+use commons::build::BuildInfo;
+use commons::features::{Feature, FeatureFlags};
+use commons::openapi::{OpenApi, QueryParam};
+use commons::report::ErrorReporter;
+use commons::shard::Shard;
+use commons::trace::{SpanKind, TraceContext, Tracer};
+use commons::web::{
+    AccessLog, CanonicalQuery, Endpoint, GraphFormat, HandlerError, InFlightLimit, RateLimiter,
+    ScopeAliases, ServiceHelp,
+};
+use commons::{graph, metrics, policy};
 use failure::{Fallible, ResultExt};
-use prometheus::{IntCounterVec, IntGauge, IntGaugeVec};
-use serde::Deserialize;
-use std::collections::{HashMap, HashSet};
+use futures::{FutureExt, StreamExt};
+use prometheus::{GaugeVec, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /// Top-level log target for this application.
 static APP_LOG_TARGET: &str = "fcos_graph_builder";
 
+/// Log target for access logs, enabled independently of the log level.
+static ACCESS_LOG_TARGET: &str = "fcos_graph_builder::access";
+
+/// Namespace for metrics common to all services.
+static METRICS_NAMESPACE: &str = "fcos_cincinnati_gb";
+
+/// Interval between reminders about active static override graphs.
+const STATIC_GRAPH_REMINDER_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Interval for delivering queued error reports.
+const ERROR_REPORTS_FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
 lazy_static::lazy_static! {
     static ref CACHED_GRAPH_REQUESTS: IntCounterVec = register_int_counter_vec!(
         "fcos_cincinnati_gb_cache_graph_requests_total",
         "Total number of requests for a cached graph",
         &["basearch", "stream", "type"]
     ).unwrap();
+    static ref PREVIOUS_GRAPH_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "fcos_cincinnati_gb_cache_previous_graph_requests_total",
+        "Total number of requests served from the previous graph during a transition window",
+        &["product", "basearch", "stream", "type"]
+    ).unwrap();
     static ref GRAPH_FINAL_EDGES: IntGaugeVec = register_int_gauge_vec!(
         "fcos_cincinnati_gb_scraper_graph_final_edges",
         "Number of edges in the cached graph, after processing",
-        &["basearch", "stream", "type"]
+        &["product", "basearch", "stream", "type"]
     ).unwrap();
     static ref GRAPH_FINAL_RELEASES: IntGaugeVec = register_int_gauge_vec!(
         "fcos_cincinnati_gb_scraper_graph_final_releases",
         "Number of releases in the cached graph, after processing",
-        &["basearch", "stream", "type"]
+        &["product", "basearch", "stream", "type"]
     ).unwrap();
     static ref LAST_REFRESH: IntGaugeVec = register_int_gauge_vec!(
        "fcos_cincinnati_gb_scraper_graph_last_refresh_timestamp",
         "UTC timestamp of last graph refresh",
-        &["basearch", "stream", "type"]
+        &["product", "basearch", "stream", "type"]
+    ).unwrap();
+    static ref SHED_MESSAGES: IntCounterVec = register_int_counter_vec!(
+        "fcos_cincinnati_gb_scraper_shed_messages_total",
+        "Total number of messages shed due to a full scraper mailbox",
+        &["product", "stream", "message"]
+    ).unwrap();
+    static ref SCRAPER_PAUSED: IntGaugeVec = register_int_gauge_vec!(
+        "fcos_cincinnati_gb_scraper_paused",
+        "Whether scraping is paused for a stream",
+        &["product", "stream"]
+    ).unwrap();
+    static ref GRAPH_LAST_PUBLISHED: IntGaugeVec = register_int_gauge_vec!(
+        "fcos_cincinnati_gb_scraper_graph_last_published_timestamp",
+        "UTC timestamp of newest release publication in cached graph",
+        &["product", "basearch", "stream", "type"]
     ).unwrap();
+    static ref GRAPH_UPDATES_REJECTED: IntCounterVec = register_int_counter_vec!(
+        "fcos_cincinnati_gb_scraper_graph_updates_rejected_total",
+        "Total number of graph updates rejected by the rate-of-change guard",
+        &["product", "basearch", "stream", "type"]
+    ).unwrap();
+    static ref SCOPE_STATE: IntGaugeVec = register_int_gauge_vec!(
+        "fcos_cincinnati_gb_scraper_scope_state",
+        "Current state of each graph scope (1 for the active state, 0 otherwise)",
+        &["product", "basearch", "stream", "type", "state"]
+    ).unwrap();
+    static ref GRAPH_DIVERGENCES: IntGaugeVec = register_int_gauge_vec!(
+        "fcos_cincinnati_gb_scraper_graph_divergences",
+        "Number of differences between checksum and OCI graphs, at last audit",
+        &["product", "basearch", "stream"]
+    ).unwrap();
+    static ref GRAPH_STALENESS: IntGaugeVec = register_int_gauge_vec!(
+        "fcos_cincinnati_gb_scraper_graph_staleness_seconds",
+        "Seconds since the last successful refresh of each graph scope",
+        &["product", "basearch", "stream", "type"]
+    ).unwrap();
+    static ref STATIC_GRAPH_OVERRIDES: IntGaugeVec = register_int_gauge_vec!(
+        "fcos_cincinnati_gb_static_graph_override_active",
+        "Whether a static override graph is served for a scope, bypassing scrapers",
+        &["product", "basearch", "stream", "type"]
+    ).unwrap();
+    static ref STATIC_GRAPH_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "fcos_cincinnati_gb_static_graph_requests_total",
+        "Total number of requests served from a static override graph",
+        &["product", "basearch", "stream", "type"]
+    ).unwrap();
+    static ref GRAPH_EVENT_SUBSCRIBERS: IntGauge = register_int_gauge!(opts!(
+        "fcos_cincinnati_gb_graph_event_subscribers",
+        "Number of clients subscribed to graph change events"
+    )).unwrap();
     static ref UPSTREAM_SCRAPES: IntCounterVec = register_int_counter_vec!(
        "fcos_cincinnati_gb_scraper_upstream_scrapes_total",
        "Total number of upstream scrapes",
         &["stream"]
     ).unwrap();
-    // NOTE(lucab): alternatively this could come from the runtime library, see
-    // https://prometheus.io/docs/instrumenting/writing_clientlibs/#process-metrics
-    static ref PROCESS_START_TIME: IntGauge = register_int_gauge!(opts!(
-        "process_start_time_seconds",
-        "Start time of the process since unix epoch in seconds."
+    static ref UPSTREAM_BACKOFF: GaugeVec = register_gauge_vec!(
+        "fcos_cincinnati_gb_scraper_upstream_backoff_seconds",
+        "Current delay before the next upstream scrape, in seconds",
+        &["stream"]
+    ).unwrap();
+    static ref UPSTREAM_CIRCUIT_OPEN: IntGaugeVec = register_int_gauge_vec!(
+        "fcos_cincinnati_gb_scraper_upstream_circuit_open",
+        "Whether the upstream circuit breaker is open (1) or closed (0)",
+        &["stream"]
+    ).unwrap();
+    static ref UPSTREAM_LAST_STATUS: IntGaugeVec = register_int_gauge_vec!(
+        "fcos_cincinnati_gb_scraper_upstream_last_status_code",
+        "HTTP status code of the last upstream fetch (0 if no response was received)",
+        &["product", "source", "stream"]
+    ).unwrap();
+    static ref UPSTREAM_NOT_MODIFIED: IntCounterVec = register_int_counter_vec!(
+        "fcos_cincinnati_gb_scraper_upstream_not_modified_total",
+        "Total number of upstream fetches answered with 304 Not Modified",
+        &["product", "source", "stream"]
+    ).unwrap();
+    static ref UPSTREAM_FETCH_DURATION: GaugeVec = register_gauge_vec!(
+        "fcos_cincinnati_gb_scraper_upstream_last_fetch_duration_seconds",
+        "Duration of the last upstream fetch, in seconds",
+        &["product", "source", "stream"]
+    ).unwrap();
+    static ref UPSTREAM_FAILURES: IntCounterVec = register_int_counter_vec!(
+        "fcos_cincinnati_gb_scraper_upstream_failures_total",
+        "Total number of failed upstream fetches, by failure kind",
+        &["product", "source", "stream", "kind"]
+    ).unwrap();
+    static ref SCRAPE_DURATION: HistogramVec = register_histogram_vec!(
+        "fcos_cincinnati_gb_scraper_scrape_duration_seconds",
+        "Duration of upstream scrapes, including graph assembly",
+        &["product", "stream"],
+        prometheus::exponential_buckets(0.05, 2.0, 12).unwrap()
+    ).unwrap();
+    static ref MESSAGING_CONNECTED: IntGauge = register_int_gauge!(opts!(
+        "fcos_cincinnati_gb_messaging_connected",
+        "Whether the release announcements consumer is connected (1) or not (0)"
     )).unwrap();
+    static ref MESSAGING_REFRESHES: IntCounterVec = register_int_counter_vec!(
+        "fcos_cincinnati_gb_messaging_refreshes_total",
+        "Total number of scrapes triggered by release announcements",
+        &["product", "stream"]
+    ).unwrap();
 }
 
 fn main() -> Fallible<()> {
@@ -63,101 +188,816 @@ fn main() -> Fallible<()> {
         .format_timestamp_secs()
         .format_module_path(false)
         .filter(Some(APP_LOG_TARGET), cli_opts.loglevel())
+        .filter(Some(ACCESS_LOG_TARGET), log::LevelFilter::Info)
         .try_init()
         .context("failed to initialize logging")?;
 
+    // Parse config file and validate settings.
+    debug!("config file location: {}", cli_opts.config_path.display());
+    let settings = settings::GraphBuilderSettings::load(&cli_opts.config_path)?;
+
+    if cli_opts.check_config {
+        println!("{:#?}", settings);
+        return Ok(());
+    }
+
+    if let Some(cli::CliCommand::Probe) = cli_opts.cmd {
+        return run_probe(&settings);
+    }
+
+    settings.runtime.init_blocking_pool();
     let sys = actix::System::new("fcos_cincinnati_gb");
 
-    // Parse config file and validate settings.
-    let (service_settings, status_settings) = {
-        debug!("config file location: {}", cli_opts.config_path.display());
-        let cfg = config::FileConfig::parse_file(cli_opts.config_path)?;
-        let settings = settings::GraphBuilderSettings::validate_config(cfg)?;
-        (settings.service, settings.status)
-    };
-
-    let mut scrapers = HashMap::with_capacity(service_settings.streams.len());
-    for (&stream, &arches) in &service_settings.streams {
-        let addr = scraper::Scraper::new(
-            stream.to_string(),
-            arches.iter().map(|&arch| String::from(arch)).collect(),
+    let owned_scopes = settings.owned_scopes()?;
+    let static_graphs = load_static_graphs(&settings)?;
+    let help = build_help(&settings, &owned_scopes);
+    let settings::GraphBuilderSettings {
+        service: service_settings,
+        status: status_settings,
+        upstream: upstream_settings,
+        scraper: scraper_settings,
+        messaging: messaging_settings,
+        runtime: runtime_settings,
+        tracing: tracing_settings,
+        metrics: metrics_settings,
+        reporting: reporting_settings,
+        features,
+    } = settings;
+    debug!("feature flags: {:?}", features.to_named_map());
+    let reporter = ErrorReporter::new(crate_name!(), crate_version!(), &reporting_settings)
+        .context("failed to build error reporter")?;
+    reporter.install_panic_hook(deliver_reports);
+
+    if let Some(shard) = &service_settings.shard {
+        info!("serving shard {} of {}", shard.index, shard.count);
+    }
+    let tracer = Tracer::new(crate_name!(), &tracing_settings).context("failed to build tracer")?;
+    let collectors =
+        metrics::Collectors::register(METRICS_NAMESPACE, prometheus::default_registry())
+            .context("failed to register metrics")?;
+    let graph_events = Arc::new(events::GraphEvents::default());
+    let mut scrapers = HashMap::with_capacity(owned_scopes.len());
+    let mut mailboxes = HashMap::with_capacity(owned_scopes.len());
+    for ((product, stream), arches) in owned_scopes {
+        let oci_only = service_settings.oci_only_products.contains(&product);
+        let scraper = scraper::Scraper::new(
+            product.clone(),
+            stream.clone(),
+            arches,
+            oci_only,
+            &upstream_settings,
+            &scraper_settings,
+            &features,
         )?
-        .start();
-        scrapers.insert(stream.to_string(), addr);
+        .with_events(Arc::clone(&graph_events))
+        .with_tracer(tracer.clone())
+        .with_reporter(reporter.clone())
+        .with_metrics(collectors.clone());
+        mailboxes.insert((product.clone(), stream.clone()), scraper.mailbox());
+        scrapers.insert((product, stream), scraper.start());
     }
 
+    // Release announcements only cover the default product.
+    let announced_scrapers = scrapers
+        .iter()
+        .filter(|((product, _), _)| product == &upstream_settings.product)
+        .map(|((_, stream), addr)| (stream.clone(), addr.clone()))
+        .collect();
+    messaging::spawn_consumer(
+        messaging_settings,
+        upstream_settings.product.clone(),
+        announced_scrapers,
+    )
+    .context("failed to start release announcements consumer")?;
+
+    let rate_limiter = RateLimiter::register(
+        &service_settings.rate_limit,
+        METRICS_NAMESPACE,
+        prometheus::default_registry(),
+    )
+    .context("failed to register rate limiting metrics")?;
+    let in_flight = InFlightLimit::register(
+        service_settings.max_in_flight,
+        METRICS_NAMESPACE,
+        prometheus::default_registry(),
+    )
+    .context("failed to register in-flight metrics")?;
     // TODO(lucab): get allowed scopes from config file.
     let service_state = AppState {
         scope_filter: None,
+        default_product: upstream_settings.product.clone(),
+        scope_aliases: service_settings.scope_aliases.clone(),
+        rate_limiter: Arc::new(rate_limiter),
+        in_flight,
+        access_log: AccessLog::new(
+            ACCESS_LOG_TARGET,
+            service_settings.access_log_sample_rate,
+            service_settings.rate_limit.trusted_proxies,
+        ),
+        tracer: tracer.clone(),
+        oci_only_products: service_settings.oci_only_products.clone(),
         scrapers,
+        mailboxes: Arc::new(mailboxes),
+        shard: service_settings.shard,
+        features,
+        admin_token: status_settings.admin_token.clone(),
+        static_graphs: Arc::new(static_graphs),
+        graph_events,
+        help: Arc::new(help),
+        openapi: Arc::new(build_openapi()),
+        metrics: collectors,
     };
+    events::GraphEventsKeepalive {
+        events: Arc::clone(&service_state.graph_events),
+    }
+    .start();
+    if !service_state.static_graphs.is_empty() {
+        StaticGraphReminder {
+            static_graphs: Arc::clone(&service_state.static_graphs),
+        }
+        .start();
+    }
+    if tracer.is_enabled() {
+        info!(
+            "exporting traces every {:?}",
+            tracing_settings.export_interval
+        );
+        TraceExporter {
+            tracer,
+            interval: tracing_settings.export_interval,
+        }
+        .start();
+    }
+    if reporter.is_enabled() {
+        ErrorReportFlusher { reporter }.start();
+    }
 
     let start_timestamp = chrono::Utc::now();
-    PROCESS_START_TIME.set(start_timestamp.timestamp());
+    // NOTE(lucab): alternatively this could come from the runtime library, see
+    // https://prometheus.io/docs/instrumenting/writing_clientlibs/#process-metrics
+    service_state
+        .metrics
+        .process_start_time
+        .set(start_timestamp.timestamp());
+    service_state.metrics.set_build_info(
+        &BuildInfo::new(crate_name!(), crate_version!()),
+        &runtime_settings,
+    );
+    let metrics_exporter =
+        metrics::OtlpExporter::new(crate_name!(), &metrics_settings, start_timestamp.into())
+            .context("failed to build metrics exporter")?;
+    if let Some(exporter) = metrics_exporter {
+        info!(
+            "exporting metrics every {:?}",
+            metrics_settings.export_interval
+        );
+        MetricsExporter {
+            exporter,
+            interval: metrics_settings.export_interval,
+        }
+        .start();
+    }
     info!("starting server ({} {})", crate_name!(), crate_version!());
 
     // Graph-builder main service.
-    let service_socket = service_settings.socket_addr();
-    debug!("main service address: {}", service_socket);
+    let service_sockets = service_settings.socket_addrs();
     let gb_service = service_state.clone();
-    actix_web::HttpServer::new(move || {
-        App::new()
+    let mut service_server = actix_web::HttpServer::new(move || {
+        let routes = service_routes();
+        let paths: Vec<&'static str> = routes.iter().map(|(endpoint, _)| endpoint.path).collect();
+        let metrics = gb_service.metrics.clone();
+        let mut app = App::new()
             .wrap(commons::web::build_cors_middleware(
                 &service_settings.origin_allowlist,
             ))
-            .data(gb_service.clone())
-            .route("/v1/graph", web::get().to(gb_serve_graph))
+            .wrap_fn(move |req, srv| {
+                let route = commons::web::route_label(&paths, req.path());
+                let started = Instant::now();
+                let metrics = metrics.clone();
+                srv.call(req).map(move |res| {
+                    let status = match &res {
+                        Ok(res) => res.status(),
+                        Err(e) => e.as_response_error().status_code(),
+                    };
+                    metrics.record_request(route, status, started.elapsed());
+                    res
+                })
+            })
+            .data(gb_service.clone());
+        for (endpoint, route) in routes {
+            app = app.route(endpoint.path, route);
+        }
+        app
     })
-    .bind(service_socket)?
-    .run();
+    .workers(runtime_settings.workers);
+    for socket in service_sockets {
+        debug!("main service address: {}", socket);
+        service_server = service_server.listen(commons::listen::bind(socket)?)?;
+    }
+    service_server.run();
 
     // Graph-builder status service.
-    let status_socket = status_settings.socket_addr();
-    debug!("status service address: {}", status_socket);
+    let status_sockets = status_settings.socket_addrs();
+    let status_tls = status_settings.tls;
+    let prometheus = metrics_settings.prometheus;
+    let watchdog_scrapers = service_state.scrapers.clone();
     let gb_status = service_state;
-    actix_web::HttpServer::new(move || {
-        App::new()
-            .data(gb_status.clone())
-            .route("/metrics", web::get().to(metrics::serve_metrics))
-    })
-    .bind(status_socket)?
-    .run();
+    let mut status_server = actix_web::HttpServer::new(move || {
+        let mut app = App::new().data(gb_status.clone());
+        for (endpoint, route) in status_routes(prometheus) {
+            app = app.route(endpoint.path, route);
+        }
+        app
+    });
+    for socket in status_sockets {
+        debug!("status service address: {}", socket);
+        let listener = commons::listen::bind(socket)?;
+        status_server = match &status_tls {
+            Some(tls) => status_server.listen_openssl(listener, tls.acceptor()?)?,
+            None => status_server.listen(listener)?,
+        };
+    }
+    status_server.run();
+
+    // Listeners are bound and actors started, report readiness to systemd.
+    match commons::systemd::notify("READY=1") {
+        Ok(true) => debug!("notified systemd of readiness"),
+        Ok(false) => {}
+        Err(e) => warn!("failed to notify systemd of readiness: {}", e),
+    }
+    if let Some(interval) = commons::systemd::watchdog_interval() {
+        info!("sending systemd watchdog pings every {:?}", interval);
+        SystemdWatchdog {
+            scrapers: watchdog_scrapers,
+            interval,
+        }
+        .start();
+    }
 
     sys.run()?;
     Ok(())
 }
 
+/// Routes of the main service, with their documentation.
+fn service_routes() -> Vec<(Endpoint, Route)> {
+    vec![
+        (
+            Endpoint::get("/v1/graph", "Update graph for a scope"),
+            web::get().to(gb_serve_graph),
+        ),
+        (
+            Endpoint::get(
+                "/v1/manifest",
+                "Payloads referenced by the graph for a scope, for mirroring",
+            ),
+            web::get().to(gb_serve_manifest),
+        ),
+        (
+            Endpoint::get("/v1/version", "Build information of this instance"),
+            web::get().to(gb_serve_version),
+        ),
+        (
+            Endpoint::get("/openapi.json", "OpenAPI description of this service"),
+            web::get().to(gb_serve_openapi),
+        ),
+        (
+            Endpoint::get("/v1/streams", "Graph scopes served by this instance"),
+            web::get().to(gb_serve_streams),
+        ),
+        (
+            Endpoint::get(
+                "/v1/graph_meta",
+                "Freshness and size of cached graphs, by scope",
+            ),
+            web::get().to(gb_serve_graph_meta),
+        ),
+        (
+            Endpoint::get(
+                "/v1/graph/events",
+                "Stream of cached graph changes, as server-sent events",
+            ),
+            web::get().to(gb_serve_graph_events),
+        ),
+    ]
+}
+
+/// Routes of the status service, with their documentation.
+///
+/// The Prometheus metrics endpoint is only routed if enabled.
+fn status_routes(prometheus: bool) -> Vec<(Endpoint, Route)> {
+    let mut routes = vec![
+        (
+            Endpoint::get("/metrics", "Prometheus metrics"),
+            web::get().to(metrics::serve_metrics),
+        ),
+        (
+            Endpoint::get("/admin/features", "Feature flags"),
+            web::get().to(gb_serve_features),
+        ),
+        (
+            Endpoint::get("/admin/help", "Self-description of this instance"),
+            web::get().to(gb_serve_help),
+        ),
+        (
+            Endpoint::get("/livez", "Liveness, i.e. whether all scrapers are running"),
+            web::get().to(gb_serve_livez),
+        ),
+        (
+            Endpoint::get(
+                "/readyz",
+                "Readiness, i.e. whether all scopes serve a valid graph",
+            ),
+            web::get().to(gb_serve_readyz),
+        ),
+        (
+            Endpoint::get("/status", "Scraping status, by product and stream"),
+            web::get().to(gb_serve_status),
+        ),
+        (
+            Endpoint::post(
+                "/admin/evict",
+                "Drop and rebuild the cached graph for a scope",
+            ),
+            web::post().to(gb_admin_evict),
+        ),
+        (
+            Endpoint::post(
+                "/v1/refresh",
+                "Scrape upstream metadata for a stream immediately",
+            ),
+            web::post().to(gb_admin_refresh),
+        ),
+        (
+            Endpoint::post(
+                "/admin/pause",
+                "Stop scraping upstream for a stream, freezing cached graphs",
+            ),
+            web::post().to(gb_admin_pause),
+        ),
+        (
+            Endpoint::post("/admin/resume", "Resume scraping upstream for a stream"),
+            web::post().to(gb_admin_resume),
+        ),
+        (
+            Endpoint::post(
+                "/admin/refresh",
+                "Refresh graphs for a stream right away, reporting the outcome for a scope",
+            ),
+            web::post().to(gb_admin_force_refresh),
+        ),
+    ];
+    if !prometheus {
+        routes.retain(|(endpoint, _)| endpoint.path != "/metrics");
+    }
+    routes
+}
+
+/// Static graph, served verbatim for a scope.
+#[derive(Clone, Debug)]
+pub(crate) struct StaticGraph {
+    path: PathBuf,
+    body: scraper::GraphBody,
+}
+
+/// Periodically log active static override graphs.
+struct StaticGraphReminder {
+    static_graphs: Arc<HashMap<graph::GraphScope, StaticGraph>>,
+}
+
+impl Actor for StaticGraphReminder {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(STATIC_GRAPH_REMINDER_INTERVAL, |actor, _ctx| {
+            for (scope, graph) in actor.static_graphs.iter() {
+                warn!(
+                    "static override graph still active for {}/{}/{}/oci={} (from '{}')",
+                    scope.product,
+                    scope.basearch,
+                    scope.stream,
+                    scope.oci,
+                    graph.path.display()
+                );
+            }
+        });
+    }
+}
+
+/// Deliver queued error reports right away, from a dedicated runtime.
+///
+/// This is used while panicking, when the current runtime may be unusable.
+fn deliver_reports(reporter: &ErrorReporter) {
+    let reporter = reporter.clone();
+    let delivery = std::thread::spawn(move || {
+        let mut runner = actix::System::new("fcos_cincinnati_gb_report");
+        runner.block_on(async move { reporter.flush().await })
+    });
+    if let Ok(Err(e)) = delivery.join() {
+        error!("failed to deliver error reports: {}", e);
+    }
+}
+
+/// Periodic export of finished tracing spans.
+struct TraceExporter {
+    tracer: Tracer,
+    interval: Duration,
+}
+
+impl Actor for TraceExporter {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.interval, |actor, ctx| {
+            let tracer = actor.tracer.clone();
+            let export = async move {
+                if let Err(e) = tracer.export().await {
+                    warn!("failed to export traces: {}", e);
+                }
+            };
+            ctx.spawn(actix::fut::wrap_future::<_, Self>(export));
+        });
+    }
+}
+
+/// Periodic push of metrics to an OTLP endpoint.
+struct MetricsExporter {
+    exporter: metrics::OtlpExporter,
+    interval: Duration,
+}
+
+impl Actor for MetricsExporter {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.interval, |actor, ctx| {
+            let exporter = actor.exporter.clone();
+            let export = async move {
+                if let Err(e) = exporter.export().await {
+                    warn!("failed to export metrics: {}", e);
+                }
+            };
+            ctx.spawn(actix::fut::wrap_future::<_, Self>(export));
+        });
+    }
+}
+
+/// Periodic delivery of queued error reports.
+struct ErrorReportFlusher {
+    reporter: ErrorReporter,
+}
+
+impl Actor for ErrorReportFlusher {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(ERROR_REPORTS_FLUSH_INTERVAL, |actor, ctx| {
+            let reporter = actor.reporter.clone();
+            let flush = async move {
+                if let Err(e) = reporter.flush().await {
+                    warn!("failed to deliver error reports: {}", e);
+                }
+            };
+            ctx.spawn(actix::fut::wrap_future::<_, Self>(flush));
+        });
+    }
+}
+
+/// Periodic systemd watchdog pings, as long as all scrapers are responsive.
+///
+/// A wedged scraper stops pings, so that systemd restarts the process.
+struct SystemdWatchdog {
+    scrapers: HashMap<(String, String), Addr<scraper::Scraper>>,
+    interval: Duration,
+}
+
+impl Actor for SystemdWatchdog {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.interval, |actor, ctx| {
+            let checks = actor
+                .scrapers
+                .values()
+                .map(|addr| addr.send(scraper::GetStatus {}).timeout(actor.interval));
+            let checks = futures::future::join_all(checks);
+            let ping = actix::fut::wrap_future::<_, Self>(checks).map(|results, _actor, _ctx| {
+                if !results.iter().all(Result::is_ok) {
+                    warn!("unresponsive scrapers, skipping systemd watchdog ping");
+                    return;
+                }
+                if let Err(e) = commons::systemd::notify("WATCHDOG=1") {
+                    warn!("failed to send systemd watchdog ping: {}", e);
+                }
+            });
+            ctx.spawn(ping);
+        });
+    }
+}
+
+/// Load and validate all configured static override graphs.
+fn load_static_graphs(
+    settings: &settings::GraphBuilderSettings,
+) -> Fallible<HashMap<graph::GraphScope, StaticGraph>> {
+    let mut static_graphs = HashMap::new();
+    for (scope, path) in settings.static_graphs()? {
+        let content = std::fs::read(&path)
+            .with_context(|_| format!("failed to read static graph '{}'", path.display()))?;
+        let graph = serde_json::from_slice::<graph::Graph>(&content)
+            .with_context(|_| format!("invalid static graph '{}'", path.display()))?;
+
+        let graph_type = if scope.oci { "oci" } else { "checksum" };
+        warn!(
+            "serving static override graph for {}/{}/{}/oci={} from '{}', bypassing scrapers",
+            scope.product,
+            scope.basearch,
+            scope.stream,
+            scope.oci,
+            path.display()
+        );
+        STATIC_GRAPH_OVERRIDES
+            .with_label_values(&[&scope.product, &scope.basearch, &scope.stream, graph_type])
+            .set(1);
+        let body = scraper::GraphBody::new(content, chrono::Utc::now().timestamp())?
+            .with_encodings(&graph)?;
+        static_graphs.insert(scope, StaticGraph { path, body });
+    }
+    Ok(static_graphs)
+}
+
+/// Describe endpoints, scopes and graph processing of this instance.
+fn build_help(
+    settings: &settings::GraphBuilderSettings,
+    owned_scopes: &BTreeMap<(String, String), Vec<String>>,
+) -> ServiceHelp {
+    let mut scopes = vec![];
+    for ((product, stream), arches) in owned_scopes {
+        let oci_only = settings.service.oci_only_products.contains(product);
+        let oci_enabled = settings.features.is_enabled(Feature::OciGraphs);
+        for arch in arches {
+            for &oci in &[false, true] {
+                if (oci && !oci_enabled) || (!oci && oci_only) {
+                    continue;
+                }
+                scopes.push(graph::GraphScope {
+                    product: product.clone(),
+                    basearch: arch.clone(),
+                    stream: stream.clone(),
+                    oci,
+                });
+            }
+        }
+    }
+
+    let endpoints = maplit::btreemap! {
+        "service" => service_routes().into_iter().map(|(e, _)| e).collect(),
+        "status" => status_routes(settings.metrics.prometheus)
+            .into_iter()
+            .map(|(e, _)| e)
+            .collect(),
+    };
+    ServiceHelp {
+        name: crate_name!(),
+        version: crate_version!(),
+        endpoints,
+        scopes: Some(scopes),
+        pipeline: settings.scraper.pipeline(&settings.features),
+        features: settings.features.to_named_map(),
+    }
+}
+
+/// Describe the main service, per the OpenAPI specification.
+fn build_openapi() -> OpenApi {
+    let endpoints: Vec<Endpoint> = service_routes().into_iter().map(|(e, _)| e).collect();
+    OpenApi::new(
+        crate_name!(),
+        crate_version!(),
+        &endpoints,
+        &GraphQuery::openapi_params(),
+        &[
+            ("400", "Invalid scope or query"),
+            ("404", "Scope not served by this instance"),
+            ("429", "Too many requests, see `Retry-After`"),
+            ("500", "Internal error"),
+            ("503", "Graph temporarily unavailable, see `Retry-After`"),
+        ],
+    )
+}
+
+/// Check health of a locally running graph-builder.
+fn run_probe(settings: &settings::GraphBuilderSettings) -> Fallible<()> {
+    let mut targets = vec![];
+    if settings.status.tls.is_none() {
+        let status_base = commons::probe::local_base_url(settings.status.socket_addr())?;
+        targets.push(status_base.join("livez")?);
+        targets.push(status_base.join("readyz")?);
+        targets.push(status_base.join("metrics")?);
+    } else {
+        warn!("status server requires client certificates, skipping its checks");
+    }
+
+    // Canary request for the first owned scope, if any.
+    let owned_scopes = settings.owned_scopes()?;
+    let canary = owned_scopes
+        .iter()
+        .find_map(|((product, stream), arches)| arches.first().map(|arch| (product, stream, arch)));
+    if let Some((product, stream, basearch)) = canary {
+        let service_base = commons::probe::local_base_url(settings.service.socket_addr())?;
+        let mut graph_url = service_base.join("v1/graph")?;
+        graph_url
+            .query_pairs_mut()
+            .append_pair("product", product)
+            .append_pair("basearch", basearch)
+            .append_pair("stream", stream);
+        if settings.service.oci_only_products.contains(product) {
+            graph_url.query_pairs_mut().append_pair("oci", "true");
+        }
+        targets.push(graph_url);
+    }
+
+    let mut runner = actix::System::new("fcos_cincinnati_gb_probe");
+    runner.block_on(async move { commons::probe::check_endpoints(&targets).await })?;
+    info!("probe succeeded");
+    Ok(())
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct AppState {
     scope_filter: Option<HashSet<graph::GraphScope>>,
-    scrapers: HashMap<String, Addr<scraper::Scraper>>,
+    /// Product for requests without an explicit one.
+    default_product: String,
+    /// Aliases of basearch and stream values.
+    scope_aliases: ScopeAliases,
+    /// Per-client rate limiting of graph requests.
+    rate_limiter: Arc<RateLimiter>,
+    /// Limit on graph requests in flight.
+    in_flight: InFlightLimit,
+    /// Sampled access logs of graph requests.
+    access_log: AccessLog,
+    /// Source of tracing spans.
+    tracer: Tracer,
+    /// Products without checksum graphs.
+    oci_only_products: BTreeSet<String>,
+    /// (product, stream) -> scraper
+    scrapers: HashMap<(String, String), Addr<scraper::Scraper>>,
+    /// (product, stream) -> bound on graph requests queued towards the scraper
+    mailboxes: Arc<HashMap<(String, String), mailbox::MailboxGuard>>,
+    shard: Option<Shard>,
+    features: FeatureFlags,
+    admin_token: Option<String>,
+    /// Static override graphs, by scope.
+    static_graphs: Arc<HashMap<graph::GraphScope, StaticGraph>>,
+    /// Subscribers to cached graph changes.
+    graph_events: Arc<events::GraphEvents>,
+    help: Arc<ServiceHelp>,
+    openapi: Arc<OpenApi>,
+    metrics: metrics::Collectors,
 }
 
 /// Mandatory parameters for querying a graph from graph-builder.
 #[derive(Deserialize)]
 struct GraphQuery {
+    product: Option<String>,
     basearch: Option<String>,
     stream: Option<String>,
     oci: Option<bool>,
+    /// Rollout wariness tier, for a pre-built graph variant.
+    wariness_tier: Option<u8>,
+    /// Requesting node, for consistent graphs during transition windows.
+    node_uuid: Option<String>,
+    /// Pretty-print the graph, for debugging.
+    pretty: Option<bool>,
+}
+
+impl GraphQuery {
+    /// Query parameters, for the OpenAPI description.
+    fn openapi_params() -> Vec<QueryParam> {
+        // Fail to build until new fields are documented here.
+        let _ = |query: GraphQuery| {
+            let GraphQuery {
+                product: _,
+                basearch: _,
+                stream: _,
+                oci: _,
+                wariness_tier: _,
+                node_uuid: _,
+                pretty: _,
+            } = query;
+        };
+        vec![
+            QueryParam::string("product", "Product (default product if unset)"),
+            QueryParam::string("basearch", "Base architecture, e.g. `x86_64`"),
+            QueryParam::string("stream", "Update stream, e.g. `stable`"),
+            QueryParam::boolean("oci", "Whether to serve OCI payloads"),
+            QueryParam::integer(
+                "wariness_tier",
+                "Rollout wariness tier, for a pre-built throttled graph",
+            ),
+            QueryParam::string("node_uuid", "Unique identifier of the node"),
+            QueryParam::boolean("pretty", "Pretty-print the graph, for debugging"),
+        ]
+    }
+}
+
+/// Reject queries with unknown parameters, if strict queries are enabled.
+fn check_query_params(req: &HttpRequest, data: &AppState) -> Option<HttpResponse> {
+    if !data.features.is_enabled(Feature::StrictQueries) {
+        return None;
+    }
+    let params = GraphQuery::openapi_params();
+    let known: Vec<&str> = params.iter().map(|param| param.name).collect();
+    let unknown = commons::web::unknown_query_params(req.query_string(), &known);
+    if unknown.is_empty() {
+        return None;
+    }
+    log::error!(
+        "request with unknown query parameters: {}",
+        unknown.join(", ")
+    );
+    Some(commons::web::bad_request(
+        "unknown_parameter",
+        format!(
+            "unknown query parameters: {} (known: {})",
+            unknown.join(", "),
+            known.join(", ")
+        ),
+    ))
 }
 
 pub(crate) async fn gb_serve_graph(
+    req: HttpRequest,
     data: web::Data<AppState>,
-    web::Query(query): web::Query<GraphQuery>,
+    CanonicalQuery(query): CanonicalQuery<GraphQuery>,
+) -> Result<HttpResponse, HandlerError> {
+    data.metrics.graph_requests.inc();
+    let started = Instant::now();
+    let request_id = commons::web::request_id(&req);
+    let mut span = data.tracer.start_span(
+        "GET /v1/graph",
+        SpanKind::Server,
+        TraceContext::from_request(&req),
+    );
+    span.set_attribute("request_id", &request_id);
+    let access_log = data.access_log.clone();
+    let result = gb_serve_graph_for_request(&req, data, query, &request_id)
+        .await
+        .map_err(|e| HandlerError::from(e).with_request_id(&request_id));
+    let status = match &result {
+        Ok(resp) => resp.status(),
+        Err(e) => e.status_code(),
+    };
+    access_log.record(&req, status, started.elapsed());
+    span.set_attribute("http.status_code", status.as_u16());
+    if status.is_server_error() {
+        span.set_error();
+    }
+    let mut resp = result?;
+    commons::web::set_request_id(&mut resp, &request_id);
+    Ok(resp)
+}
+
+/// Serve a graph, for the request with the given ID.
+async fn gb_serve_graph_for_request(
+    req: &HttpRequest,
+    data: web::Data<AppState>,
+    query: GraphQuery,
+    request_id: &str,
 ) -> Result<HttpResponse, failure::Error> {
+    let _permit = match data.in_flight.try_acquire() {
+        Ok(permit) => permit,
+        Err(resp) => {
+            log::warn!(
+                "[{}] too many graph requests in flight, shedding",
+                request_id
+            );
+            return Ok(resp);
+        }
+    };
+    if let Some(resp) = check_query_params(req, &data) {
+        return Ok(resp);
+    }
+    if let Some(resp) = data.rate_limiter.check(req, None) {
+        log::debug!("[{}] rate limiting graph request", request_id);
+        return Ok(resp);
+    }
+    let pretty = query.pretty.unwrap_or(false);
     let scope = match commons::web::validate_scope(
+        query.product,
+        &data.default_product,
         query.basearch,
         query.stream,
+        &data.scope_aliases,
         query.oci,
         &data.scope_filter,
     ) {
         Err(e) => {
-            log::error!("graph request with invalid scope: {}", e);
-            return Ok(HttpResponse::BadRequest().finish());
+            log::error!("[{}] graph request with invalid scope: {}", request_id, e);
+            return Ok(commons::web::bad_request(e.kind, e));
         }
         Ok(s) => {
             log::trace!(
-                "serving request for valid scope: basearch='{}', stream='{}', oci='{}'",
+                "[{}] serving request for valid scope: product='{}', basearch='{}', stream='{}', oci='{}'",
+                request_id,
+                s.product,
                 s.basearch,
                 s.stream,
                 s.oci,
@@ -166,22 +1006,606 @@ pub(crate) async fn gb_serve_graph(
         }
     };
 
-    let addr = match data.scrapers.get(&scope.stream) {
+    if scope.oci && !data.features.is_enabled(Feature::OciGraphs) {
+        log::error!(
+            "[{}] graph request for OCI scope, but OCI graphs are disabled",
+            request_id
+        );
+        return Ok(commons::web::bad_request(
+            "oci_disabled",
+            "OCI graphs are disabled",
+        ));
+    }
+
+    if !scope.oci && data.oci_only_products.contains(&scope.product) {
+        log::error!(
+            "[{}] graph request for checksum scope, but product '{}' only ships OCI payloads",
+            request_id,
+            scope.product
+        );
+        return Ok(commons::web::bad_request(
+            "oci_only_product",
+            format!("product '{}' only ships OCI payloads", scope.product),
+        ));
+    }
+
+    if let Some(static_graph) = data.static_graphs.get(&scope) {
+        let graph_type = if scope.oci { "oci" } else { "checksum" };
+        STATIC_GRAPH_REQUESTS
+            .with_label_values(&[&scope.product, &scope.basearch, &scope.stream, graph_type])
+            .inc();
+        let mut resp = HttpResponse::Ok();
+        resp.header(
+            commons::web::GRAPH_SOURCE_HEADER,
+            format!("static={}", static_graph.path.display()),
+        );
+        resp.header(commons::web::GRAPH_OCI_HEADER, scope.oci.to_string());
+        return graph_response(req, &data, resp, &static_graph.body, pretty);
+    }
+
+    if let Some(shard) = &data.shard {
+        if !shard.owns(&scope.product, &scope.stream, &scope.basearch) {
+            log::error!(
+                "[{}] scope not owned by this shard: product='{}', basearch='{}', stream='{}'",
+                request_id,
+                scope.product,
+                scope.basearch,
+                scope.stream,
+            );
+            return Ok(commons::web::not_found(
+                "scope_not_owned",
+                "scope not served by this shard",
+            ));
+        }
+    }
+
+    let scraper_key = (scope.product.clone(), scope.stream.clone());
+    let addr = match data.scrapers.get(&scraper_key) {
         None => {
             log::error!(
-                "no scraper configured for scope: basearch='{}', stream='{}'",
+                "[{}] no scraper configured for scope: product='{}', basearch='{}', stream='{}'",
+                request_id,
+                scope.product,
                 scope.basearch,
                 scope.stream,
             );
-            return Ok(HttpResponse::NotFound().finish());
+            return Ok(unknown_stream(&scope.product, &scope.stream));
+        }
+        Some(addr) => addr,
+    };
+    if !serves_basearch(&data, &scope) {
+        log::error!(
+            "[{}] no graph for basearch '{}' on {}/{}",
+            request_id,
+            scope.basearch,
+            scope.product,
+            scope.stream,
+        );
+        return Ok(commons::web::not_found(
+            "unknown_basearch",
+            format!("unknown basearch '{}'", scope.basearch),
+        ));
+    }
+
+    if let Some(tier) = query.wariness_tier {
+        if !data.features.is_enabled(Feature::WarinessTiers) {
+            log::error!(
+                "[{}] graph request for wariness tier, but tiered graphs are disabled",
+                request_id
+            );
+            return Ok(commons::web::bad_request(
+                "tiers_disabled",
+                "tiered graphs are disabled",
+            ));
+        }
+        if tier > policy::WARINESS_TIERS {
+            log::error!(
+                "[{}] graph request with invalid wariness tier: {}",
+                request_id,
+                tier
+            );
+            return Ok(commons::web::bad_request(
+                "invalid_wariness_tier",
+                format!(
+                    "wariness tier {} out of range 0..={}",
+                    tier,
+                    policy::WARINESS_TIERS
+                ),
+            ));
+        }
+    }
+
+    // Shed requests instead of queueing them behind a stalled scraper.
+    let _permit = match data
+        .mailboxes
+        .get(&scraper_key)
+        .and_then(|mailbox| mailbox.try_acquire())
+    {
+        Some(permit) => permit,
+        None => {
+            log::warn!(
+                "[{}] too many pending graph requests for {}/{}, shedding",
+                request_id,
+                scope.product,
+                scope.stream
+            );
+            SHED_MESSAGES
+                .with_label_values(&[&scope.product, &scope.stream, "get_cached_graph"])
+                .inc();
+            return Ok(commons::web::service_unavailable(
+                "overloaded",
+                "too many pending graph requests",
+                Duration::from_secs(1),
+            ));
+        }
+    };
+
+    let oci = scope.oci;
+    let cached = addr
+        .send(scraper::GetCachedGraph {
+            scope: scope.clone(),
+            tier: query.wariness_tier,
+            node_uuid: query.node_uuid.clone(),
+        })
+        .await??;
+    let graph_type = if oci { "oci" } else { "checksum" };
+    let result = if cached.body.is_some() { "hit" } else { "miss" };
+    data.metrics
+        .cache_lookups
+        .with_label_values(&[
+            &scope.product,
+            &scope.basearch,
+            &scope.stream,
+            graph_type,
+            result,
+        ])
+        .inc();
+
+    if let Some(retry_after) = cached.retry_after {
+        let (kind, detail) = if cached.stale {
+            ("stale_graph", "graph too stale to be served")
+        } else {
+            ("graph_unavailable", "graph not built yet")
+        };
+        return Ok(commons::web::service_unavailable(kind, detail, retry_after));
+    }
+
+    let body = cached
+        .body
+        .ok_or_else(|| failure::err_msg("missing cached graph"))?;
+
+    let mut resp = HttpResponse::Ok();
+    resp.header(commons::web::GRAPH_OCI_HEADER, oci.to_string());
+    if let Some(source) = &cached.source {
+        resp.header(commons::web::GRAPH_SOURCE_HEADER, source.header_value());
+    }
+    if let Some(date) = cached.last_published.and_then(commons::web::http_date) {
+        resp.header(LAST_MODIFIED, date);
+    }
+    graph_response(req, &data, resp, &body, pretty)
+}
+
+/// Build a `404 Not Found` response for a stream without a scraper.
+fn unknown_stream(product: &str, stream: &str) -> HttpResponse {
+    commons::web::not_found(
+        "unknown_stream",
+        format!("unknown stream '{}' for product '{}'", stream, product),
+    )
+}
+
+/// Build a `409 Conflict` response for a stream with scraping paused.
+fn scraping_paused() -> HttpResponse {
+    commons::web::problem(
+        StatusCode::CONFLICT,
+        "scraping_paused",
+        "scraping is paused for this stream",
+    )
+}
+
+/// Whether graphs are built for the basearch of a scope.
+fn serves_basearch(data: &AppState, scope: &graph::GraphScope) -> bool {
+    match &data.help.scopes {
+        Some(scopes) => scopes.iter().any(|s| {
+            s.product == scope.product && s.stream == scope.stream && s.basearch == scope.basearch
+        }),
+        None => true,
+    }
+}
+
+/// Finish a graph response, honoring `If-None-Match` and `Accept`.
+///
+/// The precompressed body is served to clients accepting gzip, while
+/// pretty-printed and protobuf bodies are built on demand.
+fn graph_response(
+    req: &HttpRequest,
+    data: &AppState,
+    mut resp: HttpResponseBuilder,
+    body: &scraper::GraphBody,
+    pretty: bool,
+) -> Fallible<HttpResponse> {
+    let format = GraphFormat::negotiate(req);
+    let body = if format == GraphFormat::Protobuf {
+        body.protobuf()
+            .ok_or_else(|| failure::format_err!("missing protobuf encoding for graph"))?
+    } else if pretty {
+        body.pretty()
+            .ok_or_else(|| failure::format_err!("missing pretty encoding for graph"))?
+    } else {
+        body
+    };
+    let gzip = commons::web::accepts_encoding(req, "gzip");
+    let (content, etag) = if gzip {
+        (&body.gzip, &body.gzip_etag)
+    } else {
+        (&body.data, &body.etag)
+    };
+    if commons::web::if_none_match(req, etag) {
+        return Ok(commons::web::not_modified(etag));
+    }
+
+    resp.content_type(format.content_type());
+    resp.header(ETAG, etag.as_str());
+    resp.header(commons::web::GRAPH_VERSION_HEADER, body.version.as_str());
+    resp.header(VARY, "Accept, Accept-Encoding");
+    if gzip {
+        resp.header(CONTENT_ENCODING, "gzip");
+    }
+    data.metrics
+        .graph_response_size
+        .observe(content.len() as f64);
+    Ok(resp.body(content.clone()))
+}
+
+/// Stream cached graph changes as server-sent events, until the client
+/// disconnects.
+pub(crate) async fn gb_serve_graph_events(data: web::Data<AppState>) -> HttpResponse {
+    match data.graph_events.subscribe() {
+        Some(receiver) => HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .header(CACHE_CONTROL, "no-cache")
+            .streaming(receiver.map(Ok::<_, actix_web::Error>)),
+        None => {
+            log::warn!("too many graph event subscribers, rejecting");
+            commons::web::service_unavailable(
+                "too_many_subscribers",
+                "too many graph event subscribers",
+                Duration::from_secs(30),
+            )
+        }
+    }
+}
+
+/// Payloads referenced by the graph for a scope.
+#[derive(Serialize)]
+struct PayloadManifest {
+    #[serde(flatten)]
+    scope: graph::GraphScope,
+    artifacts: BTreeSet<graph::PayloadArtifact>,
+}
+
+/// Serve a manifest of all payloads which the graph for a scope may
+/// instruct nodes to fetch, so that mirrors can sync exactly those.
+pub(crate) async fn gb_serve_manifest(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    CanonicalQuery(query): CanonicalQuery<GraphQuery>,
+) -> Result<HttpResponse, HandlerError> {
+    if let Some(resp) = check_query_params(&req, &data) {
+        return Ok(resp);
+    }
+    let scope = match commons::web::validate_scope(
+        query.product,
+        &data.default_product,
+        query.basearch,
+        query.stream,
+        &data.scope_aliases,
+        query.oci,
+        &data.scope_filter,
+    ) {
+        Err(e) => {
+            log::error!("manifest request with invalid scope: {}", e);
+            return Ok(commons::web::bad_request(e.kind, e));
+        }
+        Ok(s) => s,
+    };
+    if scope.oci && !data.features.is_enabled(Feature::OciGraphs) {
+        log::error!("manifest request for OCI scope, but OCI graphs are disabled");
+        return Ok(commons::web::bad_request(
+            "oci_disabled",
+            "OCI graphs are disabled",
+        ));
+    }
+
+    let artifacts = if let Some(static_graph) = data.static_graphs.get(&scope) {
+        let graph: graph::Graph = serde_json::from_slice(&static_graph.body.data)?;
+        graph.payload_artifacts()
+    } else {
+        let scraper_key = (scope.product.clone(), scope.stream.clone());
+        let addr = match data.scrapers.get(&scraper_key) {
+            None => return Ok(unknown_stream(&scope.product, &scope.stream)),
+            Some(addr) => addr,
+        };
+        match addr
+            .send(scraper::GetManifest {
+                scope: scope.clone(),
+            })
+            .await?
+        {
+            Ok(artifacts) => artifacts,
+            Err(e) => {
+                log::error!("failed to collect manifest: {}", e);
+                return Ok(commons::web::not_found("unknown_basearch", e));
+            }
+        }
+    };
+
+    Ok(HttpResponse::Ok().json(PayloadManifest { scope, artifacts }))
+}
+
+pub(crate) async fn gb_serve_features(data: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(data.features.to_named_map())
+}
+
+pub(crate) async fn gb_serve_help(data: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(data.help.as_ref())
+}
+
+/// Report liveness, i.e. whether all scraper actors are still running.
+pub(crate) async fn gb_serve_livez(data: web::Data<AppState>) -> HttpResponse {
+    for ((product, stream), addr) in &data.scrapers {
+        if !addr.connected() {
+            log::error!("scraper for {}/{} is not running", product, stream);
+            return commons::web::problem(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "scraper_stopped",
+                format!("scraper for {}/{} is not running", product, stream),
+            );
+        }
+    }
+    HttpResponse::Ok().finish()
+}
+
+/// Report readiness, i.e. whether all scopes serve a graph built from upstream data.
+pub(crate) async fn gb_serve_readyz(
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, HandlerError> {
+    for addr in data.scrapers.values() {
+        let scraper_status = addr.send(scraper::GetStatus {}).await?;
+        let ready = scraper_status
+            .scopes
+            .iter()
+            .all(|s| s.populated && s.state.is_ready());
+        if !ready {
+            return Ok(commons::web::problem(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "not_ready",
+                "not all scopes serve a graph built from upstream data",
+            ));
+        }
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Serve a JSON summary of all scrapers status.
+pub(crate) async fn gb_serve_status(
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, HandlerError> {
+    let mut status = BTreeMap::new();
+    for ((product, stream), addr) in &data.scrapers {
+        let scraper_status = addr.send(scraper::GetStatus {}).await?;
+        status.insert(format!("{}/{}", product, stream), scraper_status);
+    }
+    Ok(HttpResponse::Ok().json(status))
+}
+
+pub(crate) async fn gb_serve_openapi(data: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(data.openapi.as_ref())
+}
+
+pub(crate) async fn gb_serve_version() -> HttpResponse {
+    HttpResponse::Ok().json(BuildInfo::new(crate_name!(), crate_version!()))
+}
+
+/// List the graph scopes served by this instance.
+pub(crate) async fn gb_serve_streams(data: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(commons::web::StreamsIndex {
+        scopes: data.help.scopes.clone().unwrap_or_default(),
+    })
+}
+
+/// Serve freshness and size of all cached graphs, by scope.
+pub(crate) async fn gb_serve_graph_meta(
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, HandlerError> {
+    let mut metas = vec![];
+    for addr in data.scrapers.values() {
+        metas.extend(addr.send(scraper::GetGraphMeta {}).await?);
+    }
+    metas.sort_by(|a, b| a.scope.cmp(&b.scope));
+    Ok(HttpResponse::Ok().json(metas))
+}
+
+/// Drop the cached graph for a scope, and trigger an immediate rebuild.
+pub(crate) async fn gb_admin_evict(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    web::Query(query): web::Query<GraphQuery>,
+) -> Result<HttpResponse, HandlerError> {
+    if !commons::web::is_admin_authorized(&req, &data.admin_token) {
+        return Ok(commons::web::unauthorized());
+    }
+
+    let scope = match commons::web::validate_scope(
+        query.product,
+        &data.default_product,
+        query.basearch,
+        query.stream,
+        &data.scope_aliases,
+        query.oci,
+        &data.scope_filter,
+    ) {
+        Err(e) => {
+            log::error!("evict request with invalid scope: {}", e);
+            return Ok(commons::web::bad_request(e.kind, e));
         }
+        Ok(s) => s,
+    };
+
+    let scraper_key = (scope.product.clone(), scope.stream.clone());
+    let addr = match data.scrapers.get(&scraper_key) {
+        None => return Ok(unknown_stream(&scope.product, &scope.stream)),
         Some(addr) => addr,
     };
+    match addr.send(scraper::EvictGraph { scope }).await? {
+        Err(e) => {
+            log::error!("failed to evict cached graph: {}", e);
+            Ok(commons::web::not_found("unknown_basearch", e))
+        }
+        Ok(false) => Ok(scraping_paused()),
+        Ok(true) => Ok(HttpResponse::Accepted().finish()),
+    }
+}
+
+/// Parameters selecting a stream, for per-stream scraping endpoints.
+#[derive(Deserialize)]
+struct StreamQuery {
+    /// Product (default product if unset).
+    product: Option<String>,
+    stream: Option<String>,
+}
 
-    let graph_json_bytes = addr.send(scraper::GetCachedGraph { scope }).await??;
+/// Trigger an immediate upstream scrape for a stream, e.g. right after a release.
+pub(crate) async fn gb_admin_refresh(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    web::Query(query): web::Query<StreamQuery>,
+) -> Result<HttpResponse, HandlerError> {
+    if !commons::web::is_admin_authorized(&req, &data.admin_token) {
+        return Ok(commons::web::unauthorized());
+    }
 
-    let resp = HttpResponse::Ok()
-        .content_type("application/json")
-        .body(graph_json_bytes);
-    Ok(resp)
+    let product = query
+        .product
+        .unwrap_or_else(|| data.default_product.clone());
+    let stream = match query.stream {
+        Some(stream) if !stream.is_empty() => data.scope_aliases.resolve_stream(stream),
+        _ => {
+            log::error!("refresh request without stream");
+            return Ok(commons::web::bad_request(
+                "missing_stream",
+                "missing stream",
+            ));
+        }
+    };
+
+    let addr = match data.scrapers.get(&(product.clone(), stream.clone())) {
+        None => return Ok(unknown_stream(&product, &stream)),
+        Some(addr) => addr,
+    };
+    log::info!("refresh requested for {}/{}", product, stream);
+    addr.do_send(scraper::RefreshTick {});
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Stop scraping upstream for a stream, e.g. during upstream maintenance.
+pub(crate) async fn gb_admin_pause(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    query: web::Query<StreamQuery>,
+) -> Result<HttpResponse, HandlerError> {
+    gb_admin_set_paused(req, data, query, true).await
+}
+
+/// Resume scraping upstream for a stream.
+pub(crate) async fn gb_admin_resume(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    query: web::Query<StreamQuery>,
+) -> Result<HttpResponse, HandlerError> {
+    gb_admin_set_paused(req, data, query, false).await
+}
+
+async fn gb_admin_set_paused(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    web::Query(query): web::Query<StreamQuery>,
+    paused: bool,
+) -> Result<HttpResponse, HandlerError> {
+    if !commons::web::is_admin_authorized(&req, &data.admin_token) {
+        return Ok(commons::web::unauthorized());
+    }
+
+    let product = query
+        .product
+        .unwrap_or_else(|| data.default_product.clone());
+    let stream = match query.stream {
+        Some(stream) if !stream.is_empty() => data.scope_aliases.resolve_stream(stream),
+        _ => {
+            log::error!("pause/resume request without stream");
+            return Ok(commons::web::bad_request(
+                "missing_stream",
+                "missing stream",
+            ));
+        }
+    };
+
+    let addr = match data.scrapers.get(&(product.clone(), stream.clone())) {
+        None => return Ok(unknown_stream(&product, &stream)),
+        Some(addr) => addr,
+    };
+    if paused {
+        addr.send(scraper::PauseScraping {}).await?;
+    } else {
+        addr.send(scraper::ResumeScraping {}).await?;
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Refresh graphs for the stream of a scope right away, and report the
+/// outcome for the scope.
+pub(crate) async fn gb_admin_force_refresh(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    web::Query(query): web::Query<GraphQuery>,
+) -> Result<HttpResponse, HandlerError> {
+    if !commons::web::is_admin_authorized(&req, &data.admin_token) {
+        return Ok(commons::web::unauthorized());
+    }
+
+    let scope = match commons::web::validate_scope(
+        query.product,
+        &data.default_product,
+        query.basearch,
+        query.stream,
+        &data.scope_aliases,
+        query.oci,
+        &data.scope_filter,
+    ) {
+        Err(e) => {
+            log::error!("force-refresh request with invalid scope: {}", e);
+            return Ok(commons::web::bad_request(e.kind, e));
+        }
+        Ok(s) => s,
+    };
+
+    let scraper_key = (scope.product.clone(), scope.stream.clone());
+    let addr = match data.scrapers.get(&scraper_key) {
+        None => return Ok(unknown_stream(&scope.product, &scope.stream)),
+        Some(addr) => addr,
+    };
+    match addr.send(scraper::ForceRefresh { scope }).await? {
+        Err(e) => {
+            log::error!("failed to force refresh: {}", e);
+            Ok(commons::web::not_found("unknown_basearch", e))
+        }
+        Ok(scraper::ForceRefreshResult::Paused) => Ok(scraping_paused()),
+        Ok(scraper::ForceRefreshResult::InProgress) => Ok(commons::web::problem(
+            StatusCode::CONFLICT,
+            "refresh_in_progress",
+            "a refresh is already in progress",
+        )),
+        Ok(scraper::ForceRefreshResult::Refreshed(outcome)) => Ok(HttpResponse::Ok().json(outcome)),
+    }
 }
@@ -0,0 +1,138 @@
+//! Fedora Messaging consumer, triggering scrapes when new releases land.
+//!
+//! This only speeds up the pickup of new releases: scrapers keep polling
+//! upstream on their own schedule, so graphs stay fresh while the message
+//! bus is unavailable.
+
+use crate::scraper::{RefreshTick, Scraper};
+use crate::settings::MessagingSettings;
+use actix::Addr;
+use failure::Fallible;
+use futures::StreamExt;
+use lapin::options::{BasicConsumeOptions, QueueBindOptions, QueueDeclareOptions};
+use lapin::types::FieldTable;
+use lapin::{Connection, ConnectionProperties};
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+
+/// Release announcement, as far as scrapes are concerned.
+#[derive(Debug, Default, Deserialize)]
+struct ReleaseMessage {
+    /// Stream the release belongs to (all streams if unset).
+    #[serde(default)]
+    stream: Option<String>,
+}
+
+/// Scrapers for the default product, by stream.
+type StreamScrapers = HashMap<String, Addr<Scraper>>;
+
+/// Start consuming release announcements in a dedicated thread, if enabled.
+pub(crate) fn spawn_consumer(
+    settings: MessagingSettings,
+    product: String,
+    scrapers: StreamScrapers,
+) -> Fallible<()> {
+    let url = match settings.url.clone() {
+        Some(url) => url,
+        None => return Ok(()),
+    };
+    std::thread::Builder::new()
+        .name("fedora-messaging".to_string())
+        .spawn(move || loop {
+            let res = futures::executor::block_on(consume(&url, &settings, &product, &scrapers));
+            crate::MESSAGING_CONNECTED.set(0);
+            match res {
+                Ok(()) => log::warn!("message bus connection closed"),
+                Err(e) => log::error!("message bus failure: {}", e),
+            }
+            std::thread::sleep(settings.reconnect_delay);
+        })?;
+    Ok(())
+}
+
+/// Consume release announcements, until the connection fails.
+async fn consume(
+    url: &str,
+    settings: &MessagingSettings,
+    product: &str,
+    scrapers: &StreamScrapers,
+) -> Fallible<()> {
+    let conn = Connection::connect(url, ConnectionProperties::default()).await?;
+    let channel = conn.create_channel().await?;
+    let options = QueueDeclareOptions {
+        exclusive: true,
+        auto_delete: true,
+        ..QueueDeclareOptions::default()
+    };
+    let queue = channel
+        .queue_declare("", options, FieldTable::default())
+        .await?;
+    for topic in &settings.topics {
+        channel
+            .queue_bind(
+                queue.name().as_str(),
+                &settings.exchange,
+                topic,
+                QueueBindOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+    }
+    let options = BasicConsumeOptions {
+        no_ack: true,
+        ..BasicConsumeOptions::default()
+    };
+    let mut consumer = channel
+        .basic_consume(queue.name().as_str(), "", options, FieldTable::default())
+        .await?;
+    log::info!(
+        "listening for release announcements on {:?}",
+        settings.topics
+    );
+    crate::MESSAGING_CONNECTED.set(1);
+
+    while let Some(delivery) = consumer.next().await {
+        let delivery = delivery?;
+        let announced = match parse_announcement(&delivery.data) {
+            Ok(announced) => announced,
+            Err(e) => {
+                log::warn!("ignoring malformed release announcement: {}", e);
+                continue;
+            }
+        };
+        for (stream, addr) in scrapers {
+            if matches!(&announced, Some(s) if s != stream) {
+                continue;
+            }
+            log::info!("release announced for stream '{}', refreshing", stream);
+            crate::MESSAGING_REFRESHES
+                .with_label_values(&[product, stream])
+                .inc();
+            addr.do_send(RefreshTick {});
+        }
+    }
+    Ok(())
+}
+
+/// Parse a release announcement, returning its stream (if any).
+fn parse_announcement(body: &[u8]) -> Fallible<Option<String>> {
+    let msg: ReleaseMessage = serde_json::from_slice(body)?;
+    Ok(msg.stream.filter(|s| !s.is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_announcement() {
+        let body = br#"{"build_id": "39.20231101.3.0", "stream": "stable"}"#;
+        assert_eq!(
+            parse_announcement(body).unwrap(),
+            Some("stable".to_string())
+        );
+        assert_eq!(parse_announcement(b"{}").unwrap(), None);
+        assert_eq!(parse_announcement(br#"{"stream": ""}"#).unwrap(), None);
+        parse_announcement(b"not json").unwrap_err();
+    }
+}
@@ -1,51 +1,369 @@
+use crate::events::{GraphChange, GraphEvents};
+use crate::mailbox::MailboxGuard;
+use crate::settings::{ScraperSettings, TransitionSettings, UpstreamSettings};
+use crate::state::{Backoff, ScopeState};
 use actix::prelude::*;
 use actix_web::web::Bytes;
-use commons::{graph, metadata};
+use commons::features::{Feature, FeatureFlags};
+use commons::metrics::Collectors;
+use commons::report::{ErrorReport, ErrorReporter};
+use commons::trace::{SpanKind, Tracer};
+use commons::{graph, metadata, policy};
 use failure::{Error, Fallible};
-use reqwest::Method;
-use std::collections::HashMap;
-use std::num::NonZeroU64;
-use std::time::Duration;
+use reqwest::header::{HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{Method, StatusCode};
+use serde_derive::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /// Default timeout for HTTP requests (30 minutes).
 const DEFAULT_HTTP_REQ_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// Pause between consistency audits of checksum and OCI graphs.
+const GRAPH_AUDIT_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Assembled graphs, as (arch, oci, graph, provenance) entries.
+type AssembledGraphs = Vec<(String, bool, graph::Graph, GraphSource)>;
+
+/// Upstream metadata document which graphs have been built from.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct SourceArtifact {
+    pub(crate) url: String,
+    pub(crate) sha256: String,
+}
+
+impl SourceArtifact {
+    fn new(url: &reqwest::Url, content: &[u8]) -> Self {
+        Self {
+            url: url.to_string(),
+            sha256: format!("{:x}", Sha256::digest(content)),
+        }
+    }
+}
+
+/// Provenance of cached graphs, i.e. the exact upstream artifacts they were built from.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct GraphSource {
+    pub(crate) releases: SourceArtifact,
+    pub(crate) updates: SourceArtifact,
+    /// UTC timestamp of graphs assembly.
+    pub(crate) built_at: i64,
+}
+
+impl GraphSource {
+    /// Render provenance as a value for the `X-Graph-Source` header.
+    pub(crate) fn header_value(&self) -> String {
+        format!(
+            "releases={};sha256={}, updates={};sha256={}, built_at={}",
+            self.releases.url,
+            self.releases.sha256,
+            self.updates.url,
+            self.updates.sha256,
+            self.built_at
+        )
+    }
+}
+
+/// Sanity guard against drastic changes in cached graphs.
+#[derive(Clone, Copy, Debug)]
+struct ChangeGuard {
+    max_release_loss_percent: u8,
+    allow_drastic_changes: bool,
+}
+
+impl ChangeGuard {
+    /// Check whether a graph with `next` (releases, edges) counts can
+    /// replace a cached one with `previous` counts.
+    fn check(&self, previous: (usize, usize), next: (usize, usize)) -> Fallible<()> {
+        use failure::bail;
+
+        if self.allow_drastic_changes {
+            return Ok(());
+        }
+        let (prev_releases, prev_edges) = previous;
+        let (next_releases, next_edges) = next;
+
+        let min_releases = prev_releases
+            .saturating_mul(100 - usize::from(self.max_release_loss_percent.min(100)))
+            / 100;
+        if next_releases < min_releases {
+            bail!(
+                "refusing graph update, releases dropped from {} to {} (more than {}%)",
+                prev_releases,
+                next_releases,
+                self.max_release_loss_percent
+            );
+        }
+        if prev_edges > 0 && next_edges == 0 {
+            bail!(
+                "refusing graph update, all {} edges would be dropped",
+                prev_edges
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Upstream metadata documents, shared by a set of arches.
+#[derive(Clone, Debug)]
+struct UpstreamSource {
+    releases_url: reqwest::Url,
+    updates_url: reqwest::Url,
+    arches: Vec<String>,
+}
+
+/// Upstream metadata document, with validators for conditional requests.
+#[derive(Clone, Debug)]
+struct CachedDocument {
+    body: Bytes,
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+}
+
+/// Upstream metadata document, as fetched during a refresh.
+#[derive(Clone, Debug)]
+struct FetchedDocument {
+    url: reqwest::Url,
+    document: CachedDocument,
+    /// Whether the document changed since the last processed one.
+    modified: bool,
+}
+
+/// Outcome of a refresh from a single upstream source.
+#[derive(Debug)]
+struct UpstreamRefresh {
+    arches: Vec<String>,
+    documents: Vec<FetchedDocument>,
+    /// Assembled graphs, unless upstream metadata did not change.
+    graphs: Option<AssembledGraphs>,
+}
+
+/// Serialized graph, with a precompressed copy and entity tags identifying them.
+#[derive(Clone, Debug)]
+pub(crate) struct GraphBody {
+    pub(crate) data: Bytes,
+    pub(crate) etag: String,
+    /// Gzip-compressed copy of `data`, so that it is not compressed per request.
+    pub(crate) gzip: Bytes,
+    pub(crate) gzip_etag: String,
+    /// Graph generation, served as the `X-Graph-Version` header.
+    pub(crate) version: String,
+    /// Other encodings of the same graph, so that they are not encoded per request.
+    encodings: Option<Arc<GraphEncodings>>,
+}
+
+/// Pretty-printed and protobuf encodings of a serialized graph.
+#[derive(Debug)]
+struct GraphEncodings {
+    pretty: GraphBody,
+    protobuf: GraphBody,
+}
+
+impl GraphBody {
+    /// Serialized graph, scraped at the given time (UTC timestamp).
+    pub(crate) fn new(data: Vec<u8>, scraped: i64) -> Fallible<Self> {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let mut encoder = GzEncoder::new(
+            Vec::with_capacity(data.len() / 8),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(&data)?;
+        let gzip = encoder.finish()?;
+        Ok(Self {
+            version: commons::web::graph_version(&data, scraped),
+            etag: commons::web::entity_tag(&data),
+            data: Bytes::from(data),
+            gzip_etag: commons::web::entity_tag(&gzip),
+            gzip: Bytes::from(gzip),
+            encodings: None,
+        })
+    }
+
+    /// Serialized graph, with its other encodings, scraped at the given time
+    /// (UTC timestamp).
+    pub(crate) fn from_graph(graph: &graph::Graph, scraped: i64) -> Fallible<Self> {
+        let data = serde_json::to_vec(graph).map_err(|e| failure::format_err!("{}", e))?;
+        Self::new(data, scraped)?.with_encodings(graph)
+    }
+
+    /// Attach other encodings of the graph this body was serialized from.
+    pub(crate) fn with_encodings(mut self, graph: &graph::Graph) -> Fallible<Self> {
+        let encodings = GraphEncodings {
+            pretty: self.reencoded(serde_json::to_vec_pretty(graph)?)?,
+            protobuf: self.reencoded(commons::protobuf::encode_graph(graph))?,
+        };
+        self.encodings = Some(Arc::new(encodings));
+        Ok(self)
+    }
+
+    /// Pretty-printed copy of this body, for debugging.
+    pub(crate) fn pretty(&self) -> Option<&Self> {
+        self.encodings.as_ref().map(|encodings| &encodings.pretty)
+    }
+
+    /// Copy of this body in the compact protobuf encoding.
+    pub(crate) fn protobuf(&self) -> Option<&Self> {
+        self.encodings.as_ref().map(|encodings| &encodings.protobuf)
+    }
+
+    /// Copy of this body with another encoding of the same graph generation,
+    /// thus keeping the same version.
+    fn reencoded(&self, data: Vec<u8>) -> Fallible<Self> {
+        let mut body = Self::new(data, 0)?;
+        body.version = self.version.clone();
+        Ok(body)
+    }
+}
+
+/// Previous generation of a cached graph, served during a transition window.
+#[derive(Clone, Debug)]
+struct PreviousGraph {
+    data: GraphBody,
+    tiers: Vec<GraphBody>,
+    source: Option<GraphSource>,
+    last_published: Option<i64>,
+    /// UTC timestamp of the end of the transition window.
+    until: i64,
+}
 
 /// Release scraper.
 #[derive(Clone, Debug)]
 pub struct Scraper {
+    product: String,
     stream: String,
+    /// Whether upstream only ships OCI payloads (no checksum graphs).
+    oci_only: bool,
     /// arch -> graph
-    graphs: HashMap<String, Bytes>,
+    graphs: HashMap<String, GraphBody>,
     /// arch -> graph
-    oci_graphs: HashMap<String, Bytes>,
+    oci_graphs: HashMap<String, GraphBody>,
     hclient: reqwest::Client,
-    pause_secs: NonZeroU64,
-    release_index_url: reqwest::Url,
-    updates_url: reqwest::Url,
+    /// Schedule of upstream scrapes.
+    backoff: Backoff,
+    /// Maximum random delay added to scheduled scrapes.
+    jitter: Duration,
+    upstreams: Vec<UpstreamSource>,
+    /// url -> last upstream document which graphs have been built from
+    documents: HashMap<reqwest::Url, CachedDocument>,
+    /// (arch, oci) -> provenance of the currently cached graph
+    sources: HashMap<(String, bool), GraphSource>,
+    /// (arch, oci) -> state
+    states: HashMap<(String, bool), ScopeState>,
+    /// (arch, oci) of scopes with a graph built from upstream data, i.e.
+    /// not the empty placeholder served before the first scrape
+    populated: HashSet<(String, bool)>,
+    /// (arch, oci) -> time of last successful refresh (or scraper creation)
+    refreshed_at: HashMap<(String, bool), i64>,
+    /// Maximum age of a served graph, since its last successful refresh.
+    max_staleness: Option<Duration>,
+    /// Bound on graph requests queued towards this actor.
+    mailbox: MailboxGuard,
+    /// Whether scraping is paused, freezing cached graphs.
+    paused: bool,
+    /// Whether a refresh is currently in progress.
+    refreshing: bool,
+    /// Whether another refresh was requested while one was in progress.
+    refresh_again: bool,
+    /// Next scheduled refresh, if any.
+    next_tick: Option<actix::SpawnHandle>,
+    /// (arch, oci) -> (releases, edges) in cached graph
+    graph_counts: HashMap<(String, bool), (usize, usize)>,
+    /// (arch, oci) -> publication time of newest release in cached graph
+    last_published: HashMap<(String, bool), i64>,
+    guard: ChangeGuard,
+    /// (arch, oci) -> barriers and rollouts in cached graph
+    transition_signatures: HashMap<(String, bool), BTreeMap<String, String>>,
+    /// (arch, oci) -> previous graph, still served during a transition window
+    previous_graphs: HashMap<(String, bool), PreviousGraph>,
+    transition: TransitionSettings,
+    /// Whether to pre-build graph variants per rollout wariness tier.
+    wariness_tiers: bool,
+    /// Whether to keep experimental payload variants in graphs.
+    payload_variants: bool,
+    /// (arch, oci) -> pre-built graph variants, by wariness tier
+    tiered_graphs: HashMap<(String, bool), Vec<GraphBody>>,
+    /// (arch, oci) -> cached graph with rollouts, for throttling tiered variants
+    rollout_graphs: HashMap<(String, bool), graph::Graph>,
+    /// Subscribers to cached graph changes.
+    events: Arc<GraphEvents>,
+    /// Source of tracing spans, for refresh cycles.
+    tracer: Tracer,
+    /// Sink of reports, for scopes which keep failing.
+    reporter: ErrorReporter,
+    /// Collectors shared with other services, if exported.
+    metrics: Option<Collectors>,
 }
 
 impl Scraper {
-    pub(crate) fn new(stream: String, arches: Vec<String>) -> Fallible<Self> {
-        let empty = {
-            let empty_graph = graph::Graph::default();
-            let data = serde_json::to_vec(&empty_graph)?;
-            Bytes::from(data)
+    pub(crate) fn new(
+        product: String,
+        stream: String,
+        arches: Vec<String>,
+        oci_only: bool,
+        upstream: &UpstreamSettings,
+        scraper_settings: &ScraperSettings,
+        features: &FeatureFlags,
+    ) -> Fallible<Self> {
+        let empty = Self::empty_graph()?;
+        let graphs = if oci_only {
+            HashMap::new()
+        } else {
+            arches
+                .iter()
+                .map(|arch| (arch.clone(), empty.clone()))
+                .collect()
         };
-        let graphs = arches
+        let now = chrono::Utc::now().timestamp();
+        let refreshed_at = arches
             .iter()
-            .map(|arch| (arch.clone(), empty.clone()))
+            .flat_map(|arch| {
+                Self::graph_kinds(oci_only)
+                    .iter()
+                    .map(move |&oci| ((arch.clone(), oci), now))
+            })
+            .collect();
+        let states = arches
+            .iter()
+            .flat_map(|arch| {
+                Self::graph_kinds(oci_only)
+                    .iter()
+                    .map(move |&oci| ((arch.clone(), oci), ScopeState::Initializing))
+            })
             .collect();
+        // Group arches by upstream documents, to fetch each of them only once.
+        let mut upstreams: Vec<UpstreamSource> = vec![];
+        for arch in &arches {
+            let releases_url = upstream.releases_url(&product, &stream, arch)?;
+            let updates_url = upstream.updates_url(&product, &stream, arch)?;
+            match upstreams
+                .iter_mut()
+                .find(|u| u.releases_url == releases_url && u.updates_url == updates_url)
+            {
+                Some(existing) => existing.arches.push(arch.clone()),
+                None => upstreams.push(UpstreamSource {
+                    releases_url,
+                    updates_url,
+                    arches: vec![arch.clone()],
+                }),
+            }
+        }
         let oci_graphs = arches
             .into_iter()
             .map(|arch| (arch, empty.clone()))
             .collect();
 
-        let vars = maplit::hashmap! {
-            "stream".to_string() => stream.clone(),
-        };
-        let releases_json = envsubst::substitute(metadata::RELEASES_JSON, &vars)?;
-        let updates_json = envsubst::substitute(metadata::UPDATES_JSON, &vars)?;
-        let hclient = reqwest::ClientBuilder::new()
+        let backoff = Backoff::new(
+            scraper_settings.interval_for(&stream),
+            scraper_settings.backoff.max_delay,
+            scraper_settings.backoff.breaker_threshold,
+            scraper_settings.backoff.breaker_cooldown,
+        );
+        let hclient = upstream
+            .proxy
+            .client_builder()
             .pool_idle_timeout(Some(Duration::from_secs(10)))
             .timeout(DEFAULT_HTTP_REQ_TIMEOUT)
             .build()?;
@@ -54,14 +372,85 @@ impl Scraper {
             graphs,
             oci_graphs,
             hclient,
-            pause_secs: NonZeroU64::new(30).expect("non-zero pause"),
+            backoff,
+            jitter: scraper_settings.jitter,
+            product,
             stream,
-            release_index_url: reqwest::Url::parse(&releases_json)?,
-            updates_url: reqwest::Url::parse(&updates_json)?,
+            oci_only,
+            upstreams,
+            documents: HashMap::new(),
+            sources: HashMap::new(),
+            states,
+            refreshed_at,
+            populated: HashSet::new(),
+            max_staleness: scraper_settings.max_staleness,
+            mailbox: MailboxGuard::new(scraper_settings.mailbox_capacity),
+            paused: false,
+            refreshing: false,
+            refresh_again: false,
+            next_tick: None,
+            graph_counts: HashMap::new(),
+            last_published: HashMap::new(),
+            guard: ChangeGuard {
+                max_release_loss_percent: scraper_settings.max_release_loss_percent,
+                allow_drastic_changes: scraper_settings.allow_drastic_changes,
+            },
+            transition_signatures: HashMap::new(),
+            previous_graphs: HashMap::new(),
+            transition: scraper_settings.transition.clone(),
+            wariness_tiers: features.is_enabled(Feature::WarinessTiers),
+            payload_variants: features.is_enabled(Feature::PayloadVariants),
+            tiered_graphs: HashMap::new(),
+            rollout_graphs: HashMap::new(),
+            events: Arc::new(GraphEvents::default()),
+            tracer: Tracer::default(),
+            reporter: ErrorReporter::default(),
+            metrics: None,
         };
+        for ((arch, oci), state) in &scraper.states {
+            scraper.export_state(arch, *oci, state);
+        }
         Ok(scraper)
     }
 
+    /// Notify graph changes to the given subscribers.
+    pub(crate) fn with_events(mut self, events: Arc<GraphEvents>) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Trace refresh cycles with the given tracer.
+    pub(crate) fn with_tracer(mut self, tracer: Tracer) -> Self {
+        self.tracer = tracer;
+        self
+    }
+
+    /// Report scopes which keep failing to the given reporter.
+    pub(crate) fn with_reporter(mut self, reporter: ErrorReporter) -> Self {
+        self.reporter = reporter;
+        self
+    }
+
+    /// Export shared upstream metrics to the given collectors.
+    pub(crate) fn with_metrics(mut self, metrics: Collectors) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Kinds of graphs (by `oci` flag) built for each arch.
+    fn graph_kinds(oci_only: bool) -> &'static [bool] {
+        if oci_only {
+            &[true]
+        } else {
+            &[false, true]
+        }
+    }
+
+    /// Serialize an empty graph, used as placeholder until real data is available.
+    fn empty_graph() -> Fallible<GraphBody> {
+        GraphBody::from_graph(&graph::Graph::default(), 0)
+    }
+
     /// Return a request builder with base URL and parameters set.
     fn new_request(
         &self,
@@ -73,81 +462,466 @@ impl Scraper {
         Ok(builder)
     }
 
-    /// Fetch releases from release-index.
-    fn fetch_releases(&self) -> impl Future<Output = Result<Vec<metadata::Release>, Error>> {
-        let target = self.release_index_url.clone();
-        let req = self.new_request(Method::GET, target);
-
-        async {
-            let resp = req?.send().await?;
-            let content = resp.error_for_status()?;
-            let json = content.json::<metadata::ReleasesJSON>().await?;
-            Ok(json.releases)
-        }
-    }
+    /// Fetch an upstream metadata document, unless it did not change since
+    /// the last successfully processed one.
+    ///
+    /// `kind` labels the upstream source (e.g. `releases`) in metrics.
+    fn fetch_document(
+        &self,
+        url: reqwest::Url,
+        kind: &'static str,
+    ) -> impl Future<Output = Result<FetchedDocument, Error>> {
+        let cached = self.documents.get(&url).cloned();
+        let req = self
+            .new_request(Method::GET, url.clone())
+            .map(|mut builder| {
+                if let Some(cached) = &cached {
+                    if let Some(etag) = &cached.etag {
+                        builder = builder.header(IF_NONE_MATCH, etag.clone());
+                    }
+                    if let Some(last_modified) = &cached.last_modified {
+                        builder = builder.header(IF_MODIFIED_SINCE, last_modified.clone());
+                    }
+                }
+                builder
+            });
+        let product = self.product.clone();
+        let stream = self.stream.clone();
 
-    /// Fetch updates metadata.
-    fn fetch_updates(&self) -> impl Future<Output = Result<metadata::UpdatesJSON, Error>> {
-        let target = self.updates_url.clone();
-        let req = self.new_request(Method::GET, target);
+        async move {
+            let started = Instant::now();
+            let resp = req?.send().await;
+            // Status code is reported as 0 if no response was received at all.
+            let status_code = resp.as_ref().map_or(0, |r| i64::from(r.status().as_u16()));
+            crate::UPSTREAM_LAST_STATUS
+                .with_label_values(&[&product, kind, &stream])
+                .set(status_code);
+            let fetched = match resp.and_then(|r| r.error_for_status()) {
+                Ok(content) => match cached {
+                    Some(cached) if content.status() == StatusCode::NOT_MODIFIED => {
+                        Ok((cached, false))
+                    }
+                    _ => {
+                        let etag = content.headers().get(ETAG).cloned();
+                        let last_modified = content.headers().get(LAST_MODIFIED).cloned();
+                        content.bytes().await.map(|body| {
+                            let document = CachedDocument {
+                                body,
+                                etag,
+                                last_modified,
+                            };
+                            (document, true)
+                        })
+                    }
+                },
+                Err(e) => Err(e),
+            };
+            crate::UPSTREAM_FETCH_DURATION
+                .with_label_values(&[&product, kind, &stream])
+                .set(started.elapsed().as_secs_f64());
 
-        async {
-            let resp = req?.send().await?;
-            let content = resp.error_for_status()?;
-            let json = content.json::<metadata::UpdatesJSON>().await?;
-            Ok(json)
+            if let Err(e) = &fetched {
+                crate::UPSTREAM_FAILURES
+                    .with_label_values(&[&product, kind, &stream, fetch_failure_kind(e)])
+                    .inc();
+            }
+            let (document, modified) = fetched?;
+            if !modified {
+                crate::UPSTREAM_NOT_MODIFIED
+                    .with_label_values(&[&product, kind, &stream])
+                    .inc();
+            }
+            Ok(FetchedDocument {
+                url,
+                document,
+                modified,
+            })
         }
     }
 
-    /// Combine release-index and updates metadata.
-    fn assemble_graphs(
-        &self,
-    ) -> impl Future<
-        Output = Result<(HashMap<String, graph::Graph>, HashMap<String, graph::Graph>), Error>,
-    > {
-        let stream_releases = self.fetch_releases();
-        let stream_updates = self.fetch_updates();
+    /// Combine release-index and updates metadata, for all upstream sources.
+    ///
+    /// Each distinct upstream document is fetched only once per refresh, even
+    /// if shared by several upstream sources.
+    fn assemble_graphs(&self) -> impl Future<Output = Result<Vec<UpstreamRefresh>, Error>> {
+        let mut urls: Vec<(reqwest::Url, &'static str)> = vec![];
+        for upstream in &self.upstreams {
+            for (url, kind) in [
+                (&upstream.releases_url, "releases"),
+                (&upstream.updates_url, "updates"),
+            ] {
+                if !urls.iter().any(|(u, _)| u == url) {
+                    urls.push((url.clone(), kind));
+                }
+            }
+        }
+        let fetches: Vec<_> = urls
+            .into_iter()
+            .map(|(url, kind)| self.fetch_document(url, kind))
+            .collect();
 
         // yuck... we clone a bunch here to keep the async closure 'static
+        let upstreams = self.upstreams.clone();
+        let product = self.product.clone();
         let stream = self.stream.clone();
-        let arches: Vec<String> = self.graphs.keys().cloned().collect();
+        let graph_kinds = Self::graph_kinds(self.oci_only);
 
         async move {
-            let (graph, updates) =
-                futures::future::try_join(stream_releases, stream_updates).await?;
-            // first the legacy graphs
-            let mut map = HashMap::with_capacity(arches.len());
-            for arch in &arches {
-                map.insert(
-                    arch.clone(),
-                    graph::Graph::from_metadata(
-                        graph.clone(),
-                        updates.clone(),
-                        graph::GraphScope {
-                            basearch: arch.clone(),
-                            stream: stream.clone(),
-                            oci: false,
-                        },
-                    )?,
+            let documents: HashMap<reqwest::Url, FetchedDocument> =
+                futures::future::try_join_all(fetches)
+                    .await?
+                    .into_iter()
+                    .map(|fetched| (fetched.url.clone(), fetched))
+                    .collect();
+            upstreams
+                .into_iter()
+                .map(|upstream| {
+                    Self::assemble_upstream_graphs(
+                        upstream,
+                        &documents,
+                        &product,
+                        &stream,
+                        graph_kinds,
+                    )
+                })
+                .collect()
+        }
+    }
+
+    /// Combine release-index and updates metadata, for arches sharing an upstream source.
+    ///
+    /// Graphs are not assembled again if upstream metadata did not change.
+    fn assemble_upstream_graphs(
+        upstream: UpstreamSource,
+        documents: &HashMap<reqwest::Url, FetchedDocument>,
+        product: &str,
+        stream: &str,
+        graph_kinds: &[bool],
+    ) -> Result<UpstreamRefresh, Error> {
+        let document = |url: &reqwest::Url| {
+            documents
+                .get(url)
+                .cloned()
+                .ok_or_else(|| failure::format_err!("missing upstream document '{}'", url))
+        };
+        let releases_doc = document(&upstream.releases_url)?;
+        let updates_doc = document(&upstream.updates_url)?;
+
+        let unchanged = !releases_doc.modified && !updates_doc.modified;
+        let graphs = if unchanged {
+            None
+        } else {
+            let releases_body = &releases_doc.document.body;
+            let updates_body = &updates_doc.document.body;
+            let releases = decode_document::<metadata::ReleasesJSON>(
+                releases_body,
+                "releases",
+                product,
+                stream,
+            )?
+            .releases;
+            let updates =
+                decode_document::<metadata::UpdatesJSON>(updates_body, "updates", product, stream)?;
+            let source = GraphSource {
+                releases: SourceArtifact::new(&releases_doc.url, releases_body),
+                updates: SourceArtifact::new(&updates_doc.url, updates_body),
+                built_at: chrono::Utc::now().timestamp(),
+            };
+            // legacy (unless OCI-only) and OCI graphs, for each arch
+            let mut graphs = Vec::with_capacity(upstream.arches.len() * graph_kinds.len());
+            for arch in &upstream.arches {
+                for &oci in graph_kinds {
+                    let scope = graph::GraphScope {
+                        product: product.to_string(),
+                        basearch: arch.clone(),
+                        stream: stream.to_string(),
+                        oci,
+                    };
+                    let graph = graph::Graph::from_metadata(&releases, &updates, &scope)?;
+                    graphs.push((arch.clone(), oci, graph, source.clone()));
+                }
+            }
+            Some(graphs)
+        };
+        Ok(UpstreamRefresh {
+            arches: upstream.arches,
+            documents: vec![releases_doc, updates_doc],
+            graphs,
+        })
+    }
+
+    /// Cache graphs refreshed from an upstream source.
+    fn apply_upstream_refresh(&mut self, refresh: UpstreamRefresh) {
+        let graphs = match refresh.graphs {
+            Some(graphs) => graphs,
+            None => {
+                log::trace!(
+                    "upstream metadata for {}/{} not modified, keeping cached graphs",
+                    self.product,
+                    self.stream
                 );
+                let now = chrono::Utc::now().timestamp();
+                self.keep_unchanged_graphs(&refresh.arches, now);
+                return;
             }
-            // now the OCI graphs
-            let mut oci_map = HashMap::with_capacity(arches.len());
-            for arch in &arches {
-                oci_map.insert(
-                    arch.clone(),
-                    graph::Graph::from_metadata(
-                        graph.clone(),
-                        updates.clone(),
-                        graph::GraphScope {
-                            basearch: arch.clone(),
-                            stream: stream.clone(),
-                            oci: true,
-                        },
-                    )?,
+        };
+
+        // Provenance is only updated for graphs which made it into the cache.
+        let mut all_cached = true;
+        for (arch, oci, graph, source) in graphs {
+            let res = self.update_cached_graph(arch.clone(), oci, graph);
+            if let Err(e) = &res {
+                log::error!(
+                    "failed to cache graph for {}/{}/oci={}: {}",
+                    arch,
+                    self.stream,
+                    oci,
+                    e
                 );
             }
-            Ok((map, oci_map))
+            self.record_refresh(&arch, oci, res.is_ok());
+            match &res {
+                Ok(()) => {
+                    self.sources.insert((arch, oci), source);
+                }
+                Err(e) => {
+                    self.report_failure(&arch, oci, e);
+                    all_cached = false;
+                }
+            }
+        }
+
+        // Only documents which made it into cached graphs are used for
+        // conditional requests, so that failed updates are retried in full.
+        for fetched in refresh.documents {
+            if all_cached {
+                self.documents.insert(fetched.url, fetched.document);
+            } else {
+                self.documents.remove(&fetched.url);
+            }
+        }
+    }
+
+    /// Keep cached graphs for arches with unchanged upstream metadata, as
+    /// refreshed at the given time (UTC timestamp).
+    ///
+    /// Rollouts progress over time, so tiered variants are still throttled
+    /// again from the cached graph.
+    fn keep_unchanged_graphs(&mut self, arches: &[String], now: i64) {
+        for arch in arches {
+            for &oci in Self::graph_kinds(self.oci_only) {
+                let key = (arch.clone(), oci);
+                if let Some(graph) = self.rollout_graphs.get(&key) {
+                    match Self::tiered_variants(graph, now) {
+                        Ok(tiers) => {
+                            self.tiered_graphs.insert(key, tiers);
+                        }
+                        Err(e) => log::error!(
+                            "failed to throttle graph variants for {}/{}/oci={}: {}",
+                            arch,
+                            self.stream,
+                            oci,
+                            e
+                        ),
+                    }
+                }
+                let graph_type = if oci { "oci" } else { "checksum" };
+                crate::LAST_REFRESH
+                    .with_label_values(&[&self.product, arch, &self.stream, graph_type])
+                    .set(now);
+                self.record_refresh(arch, oci, true);
+            }
+        }
+    }
+
+    /// Check that checksum and OCI graphs agree, apart from payloads.
+    ///
+    /// Divergences are not expected, as both graphs are built from the same
+    /// metadata, and would point to a bug in graph assembly.
+    fn audit_graphs(&self) {
+        for (arch, body) in &self.graphs {
+            let oci_body = match self.oci_graphs.get(arch) {
+                Some(body) => body,
+                None => continue,
+            };
+            let parsed = serde_json::from_slice::<graph::Graph>(&body.data).and_then(|graph| {
+                serde_json::from_slice::<graph::Graph>(&oci_body.data).map(|oci| (graph, oci))
+            });
+            let (graph, oci_graph) = match parsed {
+                Ok(graphs) => graphs,
+                Err(e) => {
+                    log::error!(
+                        "failed to parse cached graphs for {}/{}: {}",
+                        arch,
+                        self.stream,
+                        e
+                    );
+                    continue;
+                }
+            };
+
+            let divergences = graph.divergences(&oci_graph);
+            for divergence in &divergences {
+                log::warn!(
+                    "checksum and OCI graphs for {}/{} diverge: {}",
+                    arch,
+                    self.stream,
+                    divergence
+                );
+            }
+            crate::GRAPH_DIVERGENCES
+                .with_label_values(&[&self.product, arch, &self.stream])
+                .set(divergences.len() as i64);
+        }
+    }
+
+    /// Random delay for the next scrape, so that replicas do not scrape in lockstep.
+    fn random_jitter(&self) -> Duration {
+        let max_millis = self.jitter.as_millis() as u64;
+        if max_millis == 0 {
+            return Duration::from_secs(0);
+        }
+        Duration::from_millis(rand::random::<u64>() % (max_millis + 1))
+    }
+
+    /// Record the outcome of an upstream scrape, updating the backoff schedule.
+    fn record_scrape(&mut self, success: bool) {
+        let was_open = self.backoff.is_open();
+        if success {
+            self.backoff.on_success();
+        } else {
+            self.backoff.on_failure();
+        }
+
+        let is_open = self.backoff.is_open();
+        if is_open && !was_open {
+            log::error!(
+                "upstream for {}/{} keeps failing, circuit breaker open",
+                self.product,
+                self.stream
+            );
+        } else if was_open && !is_open {
+            log::info!(
+                "upstream for {}/{} recovered, circuit breaker closed",
+                self.product,
+                self.stream
+            );
+        }
+        crate::UPSTREAM_BACKOFF
+            .with_label_values(&[&self.stream])
+            .set(self.backoff.next_delay().as_secs_f64());
+        crate::UPSTREAM_CIRCUIT_OPEN
+            .with_label_values(&[&self.stream])
+            .set(is_open as i64);
+        if let Some(metrics) = &self.metrics {
+            let labels = [self.product.as_str(), self.stream.as_str()];
+            let result = if success { "success" } else { "failure" };
+            metrics
+                .upstream_requests
+                .with_label_values(&[labels[0], labels[1], result])
+                .inc();
+            metrics
+                .upstream_backoff
+                .with_label_values(&labels)
+                .set(self.backoff.next_delay().as_secs_f64());
+            metrics
+                .upstream_circuit_open
+                .with_label_values(&labels)
+                .set(is_open as i64);
+        }
+    }
+
+    /// Record the outcome of a refresh for a scope, advancing its state.
+    fn record_refresh(&mut self, arch: &str, oci: bool, success: bool) {
+        let now = chrono::Utc::now().timestamp();
+        let key = (arch.to_string(), oci);
+        let previous = self
+            .states
+            .get(&key)
+            .copied()
+            .unwrap_or(ScopeState::Initializing);
+        let next = if success {
+            previous.on_success(now)
+        } else {
+            previous.on_failure(now)
+        };
+        if success {
+            self.refreshed_at.insert(key.clone(), now);
+        }
+        let refreshed_at = self.refreshed_at.get(&key).copied().unwrap_or(now);
+        let graph_type = if oci { "oci" } else { "checksum" };
+        crate::GRAPH_STALENESS
+            .with_label_values(&[&self.product, arch, &self.stream, graph_type])
+            .set(now.saturating_sub(refreshed_at));
+
+        if previous.label() != next.label() {
+            match next {
+                ScopeState::Healthy { .. } => log::info!(
+                    "scope {}/{}/oci={} is now {} (was {})",
+                    arch,
+                    self.stream,
+                    oci,
+                    next.label(),
+                    previous.label()
+                ),
+                _ => log::warn!(
+                    "scope {}/{}/oci={} is now {} (was {})",
+                    arch,
+                    self.stream,
+                    oci,
+                    next.label(),
+                    previous.label()
+                ),
+            };
+            self.export_state(arch, oci, &next);
+        }
+        self.states.insert(key, next);
+    }
+
+    /// Report a scope once its refreshes reach the configured number of
+    /// consecutive failures.
+    fn report_failure(&self, arch: &str, oci: bool, error: &Error) {
+        let threshold = match self.reporter.failure_threshold() {
+            Some(threshold) => threshold,
+            None => return,
+        };
+        let failures = match self.states.get(&(arch.to_string(), oci)) {
+            Some(ScopeState::Degraded { failures, .. })
+            | Some(ScopeState::Failed { failures, .. }) => *failures,
+            _ => return,
+        };
+        if failures != threshold {
+            return;
+        }
+        let mut report = ErrorReport::new(
+            "scrape_failure",
+            format!("graph refreshes keep failing: {}", error),
+        )
+        .with("product", &self.product)
+        .with("stream", &self.stream)
+        .with("basearch", arch)
+        .with("oci", oci)
+        .with("failures", failures);
+        let upstream = self
+            .upstreams
+            .iter()
+            .find(|upstream| upstream.arches.iter().any(|a| a == arch));
+        if let Some(upstream) = upstream {
+            report = report
+                .with("releases_url", &upstream.releases_url)
+                .with("updates_url", &upstream.updates_url);
+        }
+        self.reporter.report(report);
+    }
+
+    /// Export the current state of a scope as metrics.
+    fn export_state(&self, arch: &str, oci: bool, state: &ScopeState) {
+        let graph_type = if oci { "oci" } else { "checksum" };
+        for label in ScopeState::LABELS.iter() {
+            let active = (*label == state.label()) as i64;
+            crate::SCOPE_STATE
+                .with_label_values(&[&self.product, arch, &self.stream, graph_type, label])
+                .set(active);
         }
     }
 
@@ -158,19 +932,53 @@ impl Scraper {
         oci: bool,
         graph: graph::Graph,
     ) -> Result<(), Error> {
-        let data = serde_json::to_vec_pretty(&graph).map_err(|e| failure::format_err!("{}", e))?;
         let graph_type = if oci { "oci" } else { "checksum" };
+        let key = (arch.clone(), oci);
+        let graph = if self.payload_variants {
+            graph
+        } else {
+            policy::resolve_payload_variants(graph, None)
+        };
+        let counts = (graph.nodes.len(), graph.edges.len());
+        if let Some(&previous) = self.graph_counts.get(&key) {
+            if let Err(e) = self.guard.check(previous, counts) {
+                crate::GRAPH_UPDATES_REJECTED
+                    .with_label_values(&[&self.product, &arch, &self.stream, graph_type])
+                    .inc();
+                return Err(e);
+            }
+        }
 
         let refresh_timestamp = chrono::Utc::now();
+        let body = GraphBody::from_graph(&graph, refresh_timestamp.timestamp())?;
+        let tiers = if self.wariness_tiers {
+            Self::tiered_variants(&graph, refresh_timestamp.timestamp())?
+        } else {
+            vec![]
+        };
+        // Graphs with rollouts are kept around, to throttle tiered variants
+        // again when upstream metadata does not change.
+        let has_rollouts = graph
+            .nodes
+            .iter()
+            .any(|release| release.metadata.contains_key(metadata::ROLLOUT));
+        let signature = Self::transition_signature(&graph);
+        let last_published = graph.last_published();
+
         crate::LAST_REFRESH
-            .with_label_values(&[&arch, &self.stream, graph_type])
+            .with_label_values(&[&self.product, &arch, &self.stream, graph_type])
             .set(refresh_timestamp.timestamp());
         crate::GRAPH_FINAL_EDGES
-            .with_label_values(&[&arch, &self.stream, graph_type])
+            .with_label_values(&[&self.product, &arch, &self.stream, graph_type])
             .set(graph.edges.len() as i64);
         crate::GRAPH_FINAL_RELEASES
-            .with_label_values(&[&arch, &self.stream, graph_type])
+            .with_label_values(&[&self.product, &arch, &self.stream, graph_type])
             .set(graph.nodes.len() as i64);
+        if let Some(timestamp) = last_published {
+            crate::GRAPH_LAST_PUBLISHED
+                .with_label_values(&[&self.product, &arch, &self.stream, graph_type])
+                .set(timestamp);
+        }
 
         log::trace!(
             "cached graph for {}/{}/oci={}: releases={}, edges={}",
@@ -181,21 +989,152 @@ impl Scraper {
             graph.edges.len()
         );
 
-        if oci {
-            self.oci_graphs.insert(arch, Bytes::from(data));
+        // Keep serving the previous graph to some clients, if this update
+        // significantly changes the graph.
+        let significant_change = self.graph_counts.contains_key(&key)
+            && self.transition_signatures.get(&key) != Some(&signature);
+        if significant_change && self.transition.is_enabled() {
+            let target_graphmap = if oci { &self.oci_graphs } else { &self.graphs };
+            if let Some(previous) = target_graphmap.get(&arch) {
+                let until =
+                    chrono::Utc::now().timestamp() + self.transition.window.as_secs() as i64;
+                log::info!(
+                    "significant change in graph for {}/{}/oci={}, serving previous graph to {:.0}% of requests until {}",
+                    &arch,
+                    self.stream,
+                    oci,
+                    self.transition.fraction * 100.0,
+                    until
+                );
+                let previous = PreviousGraph {
+                    data: previous.clone(),
+                    tiers: self.tiered_graphs.get(&key).cloned().unwrap_or_default(),
+                    source: self.sources.get(&key).cloned(),
+                    last_published: self.last_published.get(&key).cloned(),
+                    until,
+                };
+                self.previous_graphs.insert(key.clone(), previous);
+            }
+        }
+
+        self.graph_counts.insert(key.clone(), counts);
+        match last_published {
+            Some(timestamp) => self.last_published.insert(key.clone(), timestamp),
+            None => self.last_published.remove(&key),
+        };
+        self.tiered_graphs.insert(key.clone(), tiers);
+        if self.wariness_tiers && has_rollouts {
+            self.rollout_graphs.insert(key.clone(), graph);
         } else {
-            self.graphs.insert(arch, Bytes::from(data));
+            self.rollout_graphs.remove(&key);
         }
+        self.populated.insert(key.clone());
+        self.transition_signatures.insert(key, signature);
+        let target_graphmap = if oci {
+            &mut self.oci_graphs
+        } else {
+            &mut self.graphs
+        };
+        let changed = match target_graphmap.get(&arch) {
+            Some(previous) => previous.etag != body.etag,
+            None => true,
+        };
+        if changed {
+            self.events.publish(&GraphChange {
+                product: &self.product,
+                basearch: &arch,
+                stream: &self.stream,
+                oci,
+                version: &body.version,
+            });
+        }
+        target_graphmap.insert(arch, body);
         Ok(())
     }
+
+    /// Serialize throttled graph variants, for each rollout wariness tier.
+    ///
+    /// Throttling depends on the refresh time, thus variants are only as
+    /// fresh as the latest refresh.
+    fn tiered_variants(graph: &graph::Graph, scraped: i64) -> Fallible<Vec<GraphBody>> {
+        (0..=policy::WARINESS_TIERS)
+            .map(|tier| {
+                let throttled = policy::throttle_rollouts_at(
+                    graph.clone(),
+                    policy::tier_wariness(tier),
+                    scraped,
+                );
+                GraphBody::from_graph(&throttled, scraped)
+            })
+            .collect()
+    }
+
+    /// Summarize barriers and rollouts in a graph, for detecting significant changes.
+    fn transition_signature(graph: &graph::Graph) -> BTreeMap<String, String> {
+        let mut signature = BTreeMap::new();
+        for release in &graph.nodes {
+            let mut entry = vec![];
+            if release.metadata.contains_key(metadata::BARRIER) {
+                entry.push("barrier".to_string());
+            }
+            if release.metadata.contains_key(metadata::ROLLOUT) {
+                for key in &[
+                    metadata::START_EPOCH,
+                    metadata::START_VALUE,
+                    metadata::DURATION,
+                ] {
+                    let value = release.metadata.get(*key).cloned().unwrap_or_default();
+                    entry.push(format!("{}={}", key, value));
+                }
+            }
+            if !entry.is_empty() {
+                signature.insert(release.version.clone(), entry.join(","));
+            }
+        }
+        signature
+    }
+
+    /// Return the previous graph for a scope, if the current request should
+    /// still be served from it.
+    ///
+    /// Nodes are consistently assigned to either graph for the whole window,
+    /// while anonymous requests always get the current graph.
+    fn previous_graph_for_request(
+        &mut self,
+        arch: &str,
+        oci: bool,
+        node_uuid: Option<&str>,
+    ) -> Option<&PreviousGraph> {
+        let key = (arch.to_string(), oci);
+        let now = chrono::Utc::now().timestamp();
+        let expired = match self.previous_graphs.get(&key) {
+            Some(previous) => previous.until < now,
+            None => return None,
+        };
+        if expired {
+            self.previous_graphs.remove(&key);
+            return None;
+        }
+        let previous = self.previous_graphs.get(&key)?;
+        if !serves_previous_graph(node_uuid?, previous.until, self.transition.fraction) {
+            return None;
+        }
+        Some(previous)
+    }
 }
 
 impl Actor for Scraper {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.set_mailbox_capacity(self.mailbox.capacity());
+        self.set_paused(self.paused);
         // Kick-start the state machine.
         Self::tick_now(ctx);
+
+        if !self.oci_only {
+            ctx.run_interval(GRAPH_AUDIT_INTERVAL, |act, _ctx| act.audit_graphs());
+        }
     }
 }
 
@@ -208,54 +1147,143 @@ impl Message for RefreshTick {
 impl Handler<RefreshTick> for Scraper {
     type Result = ResponseActFuture<Self, Result<(), failure::Error>>;
 
-    fn handle(&mut self, _msg: RefreshTick, _ctx: &mut Self::Context) -> Self::Result {
-        crate::UPSTREAM_SCRAPES
-            .with_label_values(&[&self.stream])
-            .inc();
+    fn handle(&mut self, _msg: RefreshTick, ctx: &mut Self::Context) -> Self::Result {
+        // Ticks are dropped while paused, resuming schedules a new one.
+        if self.paused {
+            log::debug!(
+                "scraping paused for {}/{}, skipping refresh",
+                self.product,
+                self.stream
+            );
+            return Box::new(actix::fut::ok(()));
+        }
+        // Coalesce with an in-progress refresh, re-running right after it.
+        if self.refreshing {
+            self.refresh_again = true;
+            return Box::new(actix::fut::ok(()));
+        }
+        let refresh = self
+            .refresh(ctx)
+            .map(|res, _actor, _ctx| res.map(|_success| ()));
+        Box::new(refresh)
+    }
+}
 
-        let latest_graphs = self.assemble_graphs();
-        let update_graphs = actix::fut::wrap_future::<_, Self>(latest_graphs)
-            .map(|graphs, actor, _ctx| {
-                let res: Result<(), Error> = graphs.and_then(|(g, oci_g)| {
-                    g.into_iter()
-                        .map(|(arch, graph)| (arch, false, graph))
-                        .chain(oci_g.into_iter().map(|(arch, graph)| (arch, true, graph)))
-                        .try_for_each(|(arch, oci, graph)| {
-                            actor.update_cached_graph(arch, oci, graph)
-                        })
-                });
-                if let Err(e) = res {
-                    log::error!("transient scraping failure: {}", e);
-                };
-            })
-            .then(|_r, actor, ctx| {
-                let pause = Duration::from_secs(actor.pause_secs.get());
-                Self::tick_later(ctx, pause);
-                actix::fut::ok(())
-            });
+/// Refresh graphs for a stream right away, reporting the outcome for an arch.
+pub(crate) struct ForceRefresh {
+    pub(crate) scope: graph::GraphScope,
+}
 
-        Box::new(update_graphs)
+/// Outcome of a forced refresh.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct ForcedRefresh {
+    /// Whether upstream was successfully scraped.
+    pub(crate) success: bool,
+    /// Status of the refreshed scopes, for all graph kinds.
+    pub(crate) scopes: Vec<ScopeStatus>,
+}
+
+/// Result of a forced refresh request.
+#[derive(Clone, Debug)]
+pub(crate) enum ForceRefreshResult {
+    Refreshed(ForcedRefresh),
+    /// Scraping is paused.
+    Paused,
+    /// Another refresh is already in progress.
+    InProgress,
+}
+
+impl Message for ForceRefresh {
+    type Result = Result<ForceRefreshResult, Error>;
+}
+
+impl Handler<ForceRefresh> for Scraper {
+    type Result = ResponseActFuture<Self, Result<ForceRefreshResult, Error>>;
+
+    fn handle(&mut self, msg: ForceRefresh, ctx: &mut Self::Context) -> Self::Result {
+        use failure::format_err;
+
+        if msg.scope.product != self.product || msg.scope.stream != self.stream {
+            return Box::new(actix::fut::err(format_err!(
+                "unexpected product stream '{}/{}'",
+                msg.scope.product,
+                msg.scope.stream
+            )));
+        }
+        let basearch = msg.scope.basearch;
+        if !self.states.keys().any(|(arch, _)| arch == &basearch) {
+            return Box::new(actix::fut::err(format_err!(
+                "unexpected basearch '{}'",
+                basearch
+            )));
+        }
+        if self.paused {
+            return Box::new(actix::fut::ok(ForceRefreshResult::Paused));
+        }
+        if self.refreshing {
+            return Box::new(actix::fut::ok(ForceRefreshResult::InProgress));
+        }
+
+        log::info!(
+            "forcing refresh for {}/{}/{}",
+            self.product,
+            self.stream,
+            basearch
+        );
+        let refresh = self.refresh(ctx).map(move |res, actor, _ctx| {
+            let success = res?;
+            let scopes = actor
+                .scope_statuses()
+                .into_iter()
+                .filter(|s| s.basearch == basearch)
+                .collect();
+            Ok(ForceRefreshResult::Refreshed(ForcedRefresh {
+                success,
+                scopes,
+            }))
+        });
+        Box::new(refresh)
     }
 }
 
 pub(crate) struct GetCachedGraph {
     pub(crate) scope: graph::GraphScope,
+    /// Rollout wariness tier of a pre-built variant, if requested.
+    pub(crate) tier: Option<u8>,
+    /// Requesting node, for picking a graph during a transition window.
+    pub(crate) node_uuid: Option<String>,
+}
+
+/// Cached graph for a scope, with its provenance.
+pub(crate) struct CachedGraph {
+    /// Serialized graph, unset for placeholders.
+    pub(crate) body: Option<GraphBody>,
+    pub(crate) source: Option<GraphSource>,
+    /// Set if the graph is not built yet or too stale to be served, as a hint
+    /// on when to retry.
+    pub(crate) retry_after: Option<Duration>,
+    /// Whether the graph is withheld for being too stale, rather than for
+    /// not being built yet.
+    pub(crate) stale: bool,
+    /// UTC timestamp of the newest release publication, if known.
+    pub(crate) last_published: Option<i64>,
 }
 
 impl Message for GetCachedGraph {
-    type Result = Result<Bytes, Error>;
+    type Result = Result<CachedGraph, Error>;
 }
 
 impl Handler<GetCachedGraph> for Scraper {
-    type Result = ResponseActFuture<Self, Result<Bytes, Error>>;
+    type Result = ResponseActFuture<Self, Result<CachedGraph, Error>>;
 
     fn handle(&mut self, msg: GetCachedGraph, _ctx: &mut Self::Context) -> Self::Result {
         use failure::format_err;
         let graph_type = if msg.scope.oci { "oci" } else { "checksum" };
 
-        if msg.scope.stream != self.stream {
+        if msg.scope.product != self.product || msg.scope.stream != self.stream {
             return Box::new(actix::fut::err(format_err!(
-                "unexpected stream '{}'",
+                "unexpected product stream '{}/{}'",
+                msg.scope.product,
                 msg.scope.stream
             )));
         }
@@ -264,22 +1292,481 @@ impl Handler<GetCachedGraph> for Scraper {
         } else {
             &self.graphs
         };
-        if let Some(graph) = target_graphmap.get(&msg.scope.basearch) {
-            crate::CACHED_GRAPH_REQUESTS
-                .with_label_values(&[&msg.scope.basearch, &msg.scope.stream, graph_type])
+        let graph = match target_graphmap.get(&msg.scope.basearch) {
+            Some(graph) => graph,
+            None => {
+                return Box::new(actix::fut::err(format_err!(
+                    "unexpected basearch '{}'",
+                    msg.scope.basearch
+                )))
+            }
+        };
+        crate::CACHED_GRAPH_REQUESTS
+            .with_label_values(&[&msg.scope.basearch, &msg.scope.stream, graph_type])
+            .inc();
+
+        let key = (msg.scope.basearch.clone(), msg.scope.oci);
+        // The placeholder graph would tell clients that there are no updates.
+        if !self.populated.contains(&key) {
+            log::warn!(
+                "graph for {}/{}/oci={} not built yet, refusing to serve it",
+                msg.scope.basearch,
+                self.stream,
+                msg.scope.oci
+            );
+            return Box::new(actix::fut::ok(CachedGraph {
+                body: None,
+                source: None,
+                retry_after: Some(self.backoff.next_delay()),
+                stale: false,
+                last_published: None,
+            }));
+        }
+        // Graphs are deliberately frozen while paused.
+        let max_staleness = self.max_staleness.filter(|_| !self.paused);
+        if let (Some(max_staleness), Some(refreshed_at)) =
+            (max_staleness, self.refreshed_at.get(&key))
+        {
+            let age = chrono::Utc::now().timestamp().saturating_sub(*refreshed_at);
+            if age > max_staleness.as_secs() as i64 {
+                log::warn!(
+                    "graph for {}/{}/oci={} not refreshed for {}s, refusing to serve it",
+                    msg.scope.basearch,
+                    self.stream,
+                    msg.scope.oci,
+                    age
+                );
+                return Box::new(actix::fut::ok(CachedGraph {
+                    body: None,
+                    source: None,
+                    retry_after: Some(self.backoff.next_delay()),
+                    stale: true,
+                    last_published: None,
+                }));
+            }
+        }
+
+        let mut cached = CachedGraph {
+            body: Some(graph.clone()),
+            source: self.sources.get(&key).cloned(),
+            retry_after: None,
+            stale: false,
+            last_published: self.last_published.get(&key).cloned(),
+        };
+        let mut tiers = self.tiered_graphs.get(&key).cloned().unwrap_or_default();
+        let previous = self.previous_graph_for_request(
+            &msg.scope.basearch,
+            msg.scope.oci,
+            msg.node_uuid.as_deref(),
+        );
+        if let Some(previous) = previous {
+            crate::PREVIOUS_GRAPH_REQUESTS
+                .with_label_values(&[
+                    &msg.scope.product,
+                    &msg.scope.basearch,
+                    &msg.scope.stream,
+                    graph_type,
+                ])
                 .inc();
+            cached = CachedGraph {
+                body: Some(previous.data.clone()),
+                source: previous.source.clone(),
+                retry_after: None,
+                stale: false,
+                last_published: previous.last_published,
+            };
+            tiers = previous.tiers.clone();
+        }
 
-            Box::new(actix::fut::ok(graph.clone()))
+        if let Some(tier) = msg.tier {
+            match tiers.get(usize::from(tier)) {
+                Some(body) => cached.body = Some(body.clone()),
+                None => {
+                    return Box::new(actix::fut::err(format_err!(
+                        "no graph variant for wariness tier {}",
+                        tier
+                    )))
+                }
+            }
+        }
+        Box::new(actix::fut::ok(cached))
+    }
+}
+
+/// Collect payloads which cached graphs for a scope may instruct nodes to fetch.
+///
+/// This covers the previous graph too, while still served during a transition.
+pub(crate) struct GetManifest {
+    pub(crate) scope: graph::GraphScope,
+}
+
+impl Message for GetManifest {
+    type Result = Result<BTreeSet<graph::PayloadArtifact>, Error>;
+}
+
+impl Handler<GetManifest> for Scraper {
+    type Result = Result<BTreeSet<graph::PayloadArtifact>, Error>;
+
+    fn handle(&mut self, msg: GetManifest, _ctx: &mut Self::Context) -> Self::Result {
+        use failure::bail;
+
+        let scope = msg.scope;
+        if scope.product != self.product || scope.stream != self.stream {
+            bail!(
+                "unexpected product stream '{}/{}'",
+                scope.product,
+                scope.stream
+            );
+        }
+        let target_graphmap = if scope.oci {
+            &self.oci_graphs
         } else {
-            Box::new(actix::fut::err(format_err!(
-                "unexpected basearch '{}'",
-                msg.scope.basearch
-            )))
+            &self.graphs
+        };
+        let current = match target_graphmap.get(&scope.basearch) {
+            Some(graph) => graph,
+            None => bail!("unexpected basearch '{}'", scope.basearch),
+        };
+
+        let key = (scope.basearch.clone(), scope.oci);
+        let now = chrono::Utc::now().timestamp();
+        let previous = self
+            .previous_graphs
+            .get(&key)
+            .filter(|previous| previous.until >= now)
+            .map(|previous| &previous.data);
+
+        let mut artifacts = BTreeSet::new();
+        for body in Some(current).into_iter().chain(previous) {
+            let graph: graph::Graph = serde_json::from_slice(&body.data)?;
+            artifacts.extend(graph.payload_artifacts());
+        }
+        Ok(artifacts)
+    }
+}
+
+/// Drop the cached graph for a scope, and rebuild it as soon as possible.
+pub(crate) struct EvictGraph {
+    pub(crate) scope: graph::GraphScope,
+}
+
+impl Message for EvictGraph {
+    /// Whether the graph was evicted, i.e. scraping is not paused.
+    type Result = Result<bool, Error>;
+}
+
+impl Handler<EvictGraph> for Scraper {
+    type Result = Result<bool, Error>;
+
+    fn handle(&mut self, msg: EvictGraph, ctx: &mut Self::Context) -> Self::Result {
+        use failure::bail;
+
+        let scope = msg.scope;
+        if scope.product != self.product || scope.stream != self.stream {
+            bail!(
+                "unexpected product stream '{}/{}'",
+                scope.product,
+                scope.stream
+            );
+        }
+        let target_graphmap = if scope.oci {
+            &mut self.oci_graphs
+        } else {
+            &mut self.graphs
+        };
+        let graph = match target_graphmap.get_mut(&scope.basearch) {
+            Some(graph) => graph,
+            None => bail!("unexpected basearch '{}'", scope.basearch),
+        };
+        // Nothing would rebuild the graph until scraping is resumed.
+        if self.paused {
+            return Ok(false);
+        }
+        *graph = Self::empty_graph()?;
+
+        log::warn!(
+            "evicted cached graph for {}/{}/oci={}, rebuilding",
+            scope.basearch,
+            scope.stream,
+            scope.oci
+        );
+        let state = ScopeState::Initializing;
+        self.export_state(&scope.basearch, scope.oci, &state);
+        let key = (scope.basearch, scope.oci);
+        // Rebuilt graph is not compared against the evicted one.
+        self.graph_counts.remove(&key);
+        self.last_published.remove(&key);
+        self.transition_signatures.remove(&key);
+        self.previous_graphs.remove(&key);
+        self.tiered_graphs.remove(&key);
+        self.rollout_graphs.remove(&key);
+        self.populated.remove(&key);
+        self.sources.remove(&key);
+        // Rebuild even if upstream metadata did not change.
+        self.documents.clear();
+        self.states.insert(key, state);
+        Self::tick_now(ctx);
+
+        Ok(true)
+    }
+}
+
+/// Stop scraping upstream, freezing cached graphs.
+pub(crate) struct PauseScraping {}
+
+impl Message for PauseScraping {
+    type Result = ();
+}
+
+impl Handler<PauseScraping> for Scraper {
+    type Result = ();
+
+    fn handle(&mut self, _msg: PauseScraping, ctx: &mut Self::Context) -> Self::Result {
+        if self.paused {
+            return;
+        }
+        log::warn!("pausing scraping for {}/{}", self.product, self.stream);
+        self.set_paused(true);
+        // An in-progress refresh still completes.
+        if let Some(handle) = self.next_tick.take() {
+            ctx.cancel_future(handle);
+        }
+        self.refresh_again = false;
+    }
+}
+
+/// Resume scraping upstream, with an immediate refresh.
+pub(crate) struct ResumeScraping {}
+
+impl Message for ResumeScraping {
+    type Result = ();
+}
+
+impl Handler<ResumeScraping> for Scraper {
+    type Result = ();
+
+    fn handle(&mut self, _msg: ResumeScraping, ctx: &mut Self::Context) -> Self::Result {
+        if !self.paused {
+            return;
         }
+        log::warn!("resuming scraping for {}/{}", self.product, self.stream);
+        self.set_paused(false);
+        // Graphs may be stale by now, refresh them right away.
+        Self::tick_now(ctx);
+    }
+}
+
+pub(crate) struct GetStatus {}
+
+/// Current status of a scraper.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct ScraperStatus {
+    pub(crate) product: String,
+    pub(crate) stream: String,
+    pub(crate) paused: bool,
+    pub(crate) scopes: Vec<ScopeStatus>,
+}
+
+/// Current status of a single graph scope.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct ScopeStatus {
+    pub(crate) basearch: String,
+    pub(crate) oci: bool,
+    pub(crate) source: Option<GraphSource>,
+    /// Whether a graph has been built from upstream data.
+    pub(crate) populated: bool,
+    #[serde(flatten)]
+    pub(crate) state: ScopeState,
+}
+
+impl Message for GetStatus {
+    type Result = ScraperStatus;
+}
+
+impl Handler<GetStatus> for Scraper {
+    type Result = MessageResult<GetStatus>;
+
+    fn handle(&mut self, _msg: GetStatus, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(ScraperStatus {
+            product: self.product.clone(),
+            stream: self.stream.clone(),
+            paused: self.paused,
+            scopes: self.scope_statuses(),
+        })
+    }
+}
+
+/// Request freshness and size of all cached graphs.
+pub(crate) struct GetGraphMeta {}
+
+/// Freshness and size of the cached graph for a scope.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct GraphMeta {
+    #[serde(flatten)]
+    pub(crate) scope: graph::GraphScope,
+    /// UTC timestamp of the last successful refresh, if any.
+    pub(crate) last_refresh: Option<i64>,
+    pub(crate) releases: usize,
+    pub(crate) edges: usize,
+    /// Graph generation, as served in the `X-Graph-Version` header.
+    pub(crate) version: Option<String>,
+    /// url -> ETag of the upstream documents the graph is built from
+    pub(crate) upstream_etags: BTreeMap<String, String>,
+    /// Whether the last scrape succeeded, unless none completed yet.
+    pub(crate) last_scrape_ok: Option<bool>,
+}
+
+impl Message for GetGraphMeta {
+    type Result = Vec<GraphMeta>;
+}
+
+impl Handler<GetGraphMeta> for Scraper {
+    type Result = MessageResult<GetGraphMeta>;
+
+    fn handle(&mut self, _msg: GetGraphMeta, _ctx: &mut Self::Context) -> Self::Result {
+        let metas = self
+            .states
+            .iter()
+            .map(|((arch, oci), state)| self.graph_meta(arch, *oci, state))
+            .collect();
+        MessageResult(metas)
     }
 }
 
 impl Scraper {
+    /// Scrape upstream and refresh all cached graphs, then schedule the next
+    /// refresh. This resolves to whether upstream was successfully scraped.
+    fn refresh(
+        &mut self,
+        ctx: &mut Context<Self>,
+    ) -> impl ActorFuture<Output = Result<bool, Error>, Actor = Self> {
+        // Supersede any already scheduled refresh.
+        if let Some(handle) = self.next_tick.take() {
+            ctx.cancel_future(handle);
+        }
+        self.refreshing = true;
+
+        crate::UPSTREAM_SCRAPES
+            .with_label_values(&[&self.stream])
+            .inc();
+        let mut span = self
+            .tracer
+            .start_span("scraper refresh", SpanKind::Internal, None);
+        span.set_attribute("product", &self.product);
+        span.set_attribute("stream", &self.stream);
+
+        let started = Instant::now();
+        let latest_graphs = self.assemble_graphs();
+        actix::fut::wrap_future::<_, Self>(latest_graphs)
+            .map(move |refreshes, actor, _ctx| {
+                crate::SCRAPE_DURATION
+                    .with_label_values(&[&actor.product, &actor.stream])
+                    .observe(started.elapsed().as_secs_f64());
+                refreshes
+            })
+            .map(move |refreshes, actor, _ctx| match refreshes {
+                Ok(refreshes) => {
+                    actor.record_scrape(true);
+                    for refresh in refreshes {
+                        actor.apply_upstream_refresh(refresh);
+                    }
+                    true
+                }
+                Err(e) => {
+                    log::error!("transient scraping failure: {}", e);
+                    span.set_error();
+                    actor.record_scrape(false);
+                    let scopes: Vec<(String, bool)> = actor.states.keys().cloned().collect();
+                    for (arch, oci) in scopes {
+                        actor.record_refresh(&arch, oci, false);
+                        actor.report_failure(&arch, oci, &e);
+                    }
+                    false
+                }
+            })
+            .then(|success, actor, ctx| {
+                actor.refreshing = false;
+                if actor.refresh_again {
+                    actor.refresh_again = false;
+                    Self::tick_now(ctx);
+                } else {
+                    let delay = actor.backoff.next_delay() + actor.random_jitter();
+                    actor.next_tick = Some(Self::tick_later(ctx, delay));
+                }
+                actix::fut::ok(success)
+            })
+    }
+
+    /// Current status of all graph scopes, sorted.
+    fn scope_statuses(&self) -> Vec<ScopeStatus> {
+        let mut scopes: Vec<ScopeStatus> = self
+            .states
+            .iter()
+            .map(|((arch, oci), state)| ScopeStatus {
+                basearch: arch.clone(),
+                oci: *oci,
+                source: self.sources.get(&(arch.clone(), *oci)).cloned(),
+                populated: self.populated.contains(&(arch.clone(), *oci)),
+                state: *state,
+            })
+            .collect();
+        scopes.sort_by(|a, b| (&a.basearch, a.oci).cmp(&(&b.basearch, b.oci)));
+        scopes
+    }
+
+    /// Describe freshness and size of the cached graph for a scope.
+    fn graph_meta(&self, arch: &str, oci: bool, state: &ScopeState) -> GraphMeta {
+        let key = (arch.to_string(), oci);
+        let populated = self.populated.contains(&key);
+        let (releases, edges) = self.graph_counts.get(&key).copied().unwrap_or_default();
+        let graphs = if oci { &self.oci_graphs } else { &self.graphs };
+        let upstream_etags = self
+            .upstreams
+            .iter()
+            .filter(|upstream| upstream.arches.iter().any(|a| a == arch))
+            .flat_map(|upstream| [&upstream.releases_url, &upstream.updates_url])
+            .filter_map(|url| {
+                let etag = self.documents.get(url)?.etag.as_ref()?.to_str().ok()?;
+                Some((url.to_string(), etag.to_string()))
+            })
+            .collect();
+        let last_scrape_ok = match state {
+            ScopeState::Initializing => None,
+            ScopeState::Healthy { .. } => Some(true),
+            ScopeState::Degraded { .. } | ScopeState::Failed { .. } => Some(false),
+        };
+        GraphMeta {
+            scope: graph::GraphScope {
+                product: self.product.clone(),
+                basearch: arch.to_string(),
+                stream: self.stream.clone(),
+                oci,
+            },
+            last_refresh: self.refreshed_at.get(&key).copied().filter(|_| populated),
+            releases,
+            edges,
+            version: graphs
+                .get(arch)
+                .filter(|_| populated)
+                .map(|body| body.version.clone()),
+            upstream_etags,
+            last_scrape_ok,
+        }
+    }
+
+    /// Bound on graph requests queued towards this actor, for callers to enforce.
+    pub(crate) fn mailbox(&self) -> MailboxGuard {
+        self.mailbox.clone()
+    }
+
+    /// Toggle scraping, exporting the new state as a metric.
+    fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        crate::SCRAPER_PAUSED
+            .with_label_values(&[&self.product, &self.stream])
+            .set(paused as i64);
+    }
+
     /// Schedule an immediate refresh of the state machine.
     pub fn tick_now(ctx: &mut Context<Self>) {
         ctx.notify(RefreshTick {})
@@ -290,3 +1777,311 @@ impl Scraper {
         ctx.notify_later(RefreshTick {}, after)
     }
 }
+
+/// Whether a node is served the previous graph during a transition window,
+/// for a `fraction` of nodes.
+///
+/// Salting with the end of the window picks different nodes for each transition.
+fn serves_previous_graph(node_uuid: &str, until: i64, fraction: f64) -> bool {
+    let node_uuid = node_uuid.to_ascii_lowercase();
+    let input = node_uuid.bytes().chain(Some(0)).chain(until.to_le_bytes());
+    let percentile = (commons::fnv::fnv1a64(input) % 10_000) as f64 / 10_000.0;
+    percentile < fraction
+}
+
+/// Categorize a failed upstream fetch, as reported in metrics.
+fn fetch_failure_kind(err: &reqwest::Error) -> &'static str {
+    if let Some(status) = err.status() {
+        return if status.is_client_error() {
+            "http_4xx"
+        } else if status.is_server_error() {
+            "http_5xx"
+        } else {
+            "http_other"
+        };
+    }
+    if err.is_timeout() {
+        return if err.is_connect() {
+            "connect_timeout"
+        } else {
+            "timeout"
+        };
+    }
+    if err.is_connect() {
+        // Resolution failures are only distinguishable by their message.
+        let mut source = std::error::Error::source(err);
+        while let Some(cause) = source {
+            if cause.to_string().starts_with("dns error") {
+                return "dns";
+            }
+            source = cause.source();
+        }
+        return "connect";
+    }
+    if err.is_body() || err.is_decode() {
+        return "body";
+    }
+    "other"
+}
+
+/// Decode an upstream metadata document, recording decoding failures.
+fn decode_document<T: serde::de::DeserializeOwned>(
+    body: &[u8],
+    kind: &'static str,
+    product: &str,
+    stream: &str,
+) -> Fallible<T> {
+    serde_json::from_slice(body).map_err(|e| {
+        crate::UPSTREAM_FAILURES
+            .with_label_values(&[product, kind, stream, "decode"])
+            .inc();
+        failure::format_err!("failed to decode {} metadata: {}", kind, e)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::GraphBuilderSettings;
+
+    fn scraper(features: &FeatureFlags) -> Scraper {
+        let settings = GraphBuilderSettings::default();
+        Scraper::new(
+            "fedora-coreos".to_string(),
+            "stable".to_string(),
+            vec!["x86_64".to_string()],
+            false,
+            &settings.upstream,
+            &settings.scraper,
+            features,
+        )
+        .unwrap()
+    }
+
+    /// Linear graph with the given number of releases.
+    fn linear_graph(releases: u64) -> graph::Graph {
+        let nodes = (0..releases)
+            .map(|i| graph::CincinnatiPayload {
+                version: format!("3{}.20200101.3.0", i),
+                metadata: HashMap::new(),
+                payload: format!("sha256:{}", i),
+            })
+            .collect();
+        let edges = (1..releases).map(|i| (i - 1, i)).collect();
+        graph::Graph { nodes, edges }
+    }
+
+    fn source(content: &str) -> GraphSource {
+        let url = reqwest::Url::parse("https://example.com/releases.json").unwrap();
+        GraphSource {
+            releases: SourceArtifact::new(&url, content.as_bytes()),
+            updates: SourceArtifact::new(&url, content.as_bytes()),
+            built_at: 0,
+        }
+    }
+
+    fn refresh(graphs: Vec<(bool, graph::Graph, GraphSource)>) -> UpstreamRefresh {
+        UpstreamRefresh {
+            arches: vec!["x86_64".to_string()],
+            documents: vec![],
+            graphs: Some(
+                graphs
+                    .into_iter()
+                    .map(|(oci, graph, source)| ("x86_64".to_string(), oci, graph, source))
+                    .collect(),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_graph_body_encodings() {
+        let graph = linear_graph(3);
+        let body = GraphBody::from_graph(&graph, 1000).unwrap();
+        assert_eq!(body.data, serde_json::to_vec(&graph).unwrap());
+
+        let pretty = body.pretty().unwrap();
+        assert_eq!(pretty.data, serde_json::to_vec_pretty(&graph).unwrap());
+        assert_eq!(pretty.version, body.version);
+        assert_ne!(pretty.etag, body.etag);
+        let protobuf = body.protobuf().unwrap();
+        assert_eq!(protobuf.data, commons::protobuf::encode_graph(&graph));
+        assert_eq!(protobuf.version, body.version);
+
+        let raw = GraphBody::new(body.data.to_vec(), 1000).unwrap();
+        assert!(raw.pretty().is_none());
+        assert_eq!(raw.with_encodings(&graph).unwrap().version, body.version);
+    }
+
+    #[test]
+    fn test_change_guard() {
+        let guard = ChangeGuard {
+            max_release_loss_percent: 50,
+            allow_drastic_changes: false,
+        };
+        guard.check((10, 9), (10, 9)).unwrap();
+        guard.check((10, 9), (5, 4)).unwrap();
+        guard.check((10, 9), (20, 19)).unwrap();
+        guard.check((10, 9), (4, 3)).unwrap_err();
+        guard.check((10, 9), (10, 0)).unwrap_err();
+        guard.check((1, 0), (1, 0)).unwrap();
+
+        let permissive = ChangeGuard {
+            allow_drastic_changes: true,
+            ..guard
+        };
+        permissive.check((10, 9), (0, 0)).unwrap();
+    }
+
+    #[test]
+    fn test_change_guard_first_graph() {
+        let mut scraper = scraper(&FeatureFlags::default());
+        let key = ("x86_64".to_string(), false);
+        assert!(!scraper.graph_counts.contains_key(&key));
+
+        // No baseline, even a tiny graph replaces the empty placeholder.
+        scraper
+            .update_cached_graph("x86_64".to_string(), false, linear_graph(1))
+            .unwrap();
+        assert_eq!(scraper.graph_counts[&key], (1, 0));
+
+        scraper
+            .update_cached_graph("x86_64".to_string(), false, linear_graph(10))
+            .unwrap();
+        scraper
+            .update_cached_graph("x86_64".to_string(), false, linear_graph(2))
+            .unwrap_err();
+        assert_eq!(scraper.graph_counts[&key], (10, 9));
+    }
+
+    #[test]
+    fn test_serves_previous_graph() {
+        let nodes: Vec<String> = (0..1000).map(|i| format!("{:032x}", i)).collect();
+        let previous = nodes
+            .iter()
+            .filter(|node| serves_previous_graph(node, 1000, 0.3))
+            .count();
+        assert!(previous > 250 && previous < 350, "{}", previous);
+
+        for node in &nodes {
+            let picked = serves_previous_graph(node, 1000, 0.3);
+            assert_eq!(serves_previous_graph(node, 1000, 0.3), picked);
+            assert_eq!(
+                serves_previous_graph(&node.to_uppercase(), 1000, 0.3),
+                picked
+            );
+            assert!(!serves_previous_graph(node, 1000, 0.0));
+            assert!(serves_previous_graph(node, 1000, 1.0));
+        }
+    }
+
+    #[test]
+    fn test_previous_graph_stable_per_node() {
+        let mut scraper = scraper(&FeatureFlags::default());
+        scraper.transition = TransitionSettings {
+            window: Duration::from_secs(60 * 60),
+            fraction: 0.5,
+        };
+        scraper
+            .update_cached_graph("x86_64".to_string(), false, linear_graph(3))
+            .unwrap();
+        let mut barrier = linear_graph(4);
+        barrier.nodes[1]
+            .metadata
+            .insert(metadata::BARRIER.to_string(), "true".to_string());
+        scraper
+            .update_cached_graph("x86_64".to_string(), false, barrier)
+            .unwrap();
+
+        assert!(scraper
+            .previous_graph_for_request("x86_64", false, None)
+            .is_none());
+        let mut served = [0, 0];
+        for i in 0..100 {
+            let node = format!("{:032x}", i);
+            let first = scraper
+                .previous_graph_for_request("x86_64", false, Some(&node))
+                .map(|previous| previous.data.etag.clone());
+            for _ in 0..5 {
+                let again = scraper
+                    .previous_graph_for_request("x86_64", false, Some(&node))
+                    .map(|previous| previous.data.etag.clone());
+                assert_eq!(again, first);
+            }
+            served[first.is_some() as usize] += 1;
+        }
+        assert!(served[0] > 0 && served[1] > 0);
+    }
+
+    #[test]
+    fn test_unchanged_upstream_rethrottles_tiers() {
+        let mut features = FeatureFlags::default();
+        features.set(Feature::WarinessTiers, true);
+        let mut scraper = scraper(&features);
+        let key = ("x86_64".to_string(), false);
+
+        // Halfway through a 10 minutes rollout.
+        let now = chrono::Utc::now().timestamp();
+        let mut graph = linear_graph(3);
+        let rollout = &mut graph.nodes[2].metadata;
+        rollout.insert(metadata::ROLLOUT.to_string(), "true".to_string());
+        rollout.insert(
+            metadata::START_EPOCH.to_string(),
+            (now - 5 * 60).to_string(),
+        );
+        rollout.insert(metadata::START_VALUE.to_string(), "0".to_string());
+        rollout.insert(metadata::DURATION.to_string(), "10".to_string());
+        scraper
+            .update_cached_graph("x86_64".to_string(), false, graph)
+            .unwrap();
+        let tiered_edges = |scraper: &Scraper, tier: u8| {
+            let body = &scraper.tiered_graphs[&key][usize::from(tier)];
+            serde_json::from_slice::<graph::Graph>(&body.data)
+                .unwrap()
+                .edges
+                .len()
+        };
+        assert_eq!(tiered_edges(&scraper, 0), 2);
+        assert_eq!(tiered_edges(&scraper, policy::WARINESS_TIERS), 1);
+
+        // Upstream answers with 304 once the rollout is complete.
+        scraper.keep_unchanged_graphs(&["x86_64".to_string()], now + 20 * 60);
+        assert_eq!(tiered_edges(&scraper, 0), 2);
+        assert_eq!(tiered_edges(&scraper, policy::WARINESS_TIERS), 2);
+    }
+
+    #[test]
+    fn test_rejected_update_keeps_source() {
+        let mut scraper = scraper(&FeatureFlags::default());
+        let first = source("first");
+        scraper.apply_upstream_refresh(refresh(vec![
+            (false, linear_graph(4), first.clone()),
+            (true, linear_graph(4), first.clone()),
+        ]));
+        let checksum_key = ("x86_64".to_string(), false);
+        let oci_key = ("x86_64".to_string(), true);
+        assert_eq!(
+            scraper.sources[&checksum_key].releases.sha256,
+            first.releases.sha256
+        );
+        assert_eq!(
+            scraper.sources[&oci_key].releases.sha256,
+            first.releases.sha256
+        );
+
+        // The OCI graph loses too many releases and is rejected.
+        let second = source("second");
+        scraper.apply_upstream_refresh(refresh(vec![
+            (false, linear_graph(5), second.clone()),
+            (true, linear_graph(1), second.clone()),
+        ]));
+        assert_eq!(
+            scraper.sources[&checksum_key].releases.sha256,
+            second.releases.sha256
+        );
+        assert_eq!(
+            scraper.sources[&oci_key].releases.sha256,
+            first.releases.sha256
+        );
+        assert_eq!(scraper.graph_counts[&oci_key], (4, 3));
+    }
+}
@@ -1,31 +1,289 @@
-use crate::config::FileConfig;
-use failure::Fallible;
-use std::collections::BTreeMap;
+use crate::config::{
+    FileConfig, MessagingConfig, ProductConfig, ScraperConfig, ServiceConfig, StatusConfig,
+    UpstreamConfig, UpstreamStreamConfig,
+};
+use commons::config::HumanDuration;
+use commons::features::{Feature, FeatureFlags};
+use commons::graph::GraphScope;
+use commons::metrics::MetricsSettings;
+use commons::proxy::ProxySettings;
+use commons::report::ReportingSettings;
+use commons::runtime::RuntimeSettings;
+use commons::shard::Shard;
+use commons::tls::TlsSettings;
+use commons::trace::TracingSettings;
+use commons::web::{RateLimitSettings, ScopeAliases};
+use commons::{metadata, policy};
+use failure::{bail, Fallible, ResultExt};
+use std::collections::{BTreeMap, BTreeSet};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 /// Runtime settings for the graph-builder.
 #[derive(Clone, Debug, Default)]
 pub struct GraphBuilderSettings {
     pub(crate) service: ServiceSettings,
     pub(crate) status: StatusSettings,
+    pub(crate) upstream: UpstreamSettings,
+    pub(crate) scraper: ScraperSettings,
+    pub(crate) messaging: MessagingSettings,
+    pub(crate) runtime: RuntimeSettings,
+    pub(crate) tracing: TracingSettings,
+    pub(crate) metrics: MetricsSettings,
+    pub(crate) reporting: ReportingSettings,
+    pub(crate) features: FeatureFlags,
 }
 
 impl GraphBuilderSettings {
-    pub fn validate_config(_cfg: FileConfig) -> Fallible<Self> {
-        // TODO(lucab): translate config entries.
-        let settings = GraphBuilderSettings::default();
+    pub fn validate_config(cfg: FileConfig) -> Fallible<Self> {
+        let mut settings = GraphBuilderSettings::default();
+        settings.apply_config(cfg)?;
         Ok(settings)
     }
+
+    /// Load settings from a config file, its drop-in fragments, and environment.
+    pub fn load(config_path: &Path) -> Fallible<Self> {
+        let cfg = FileConfig::parse_file(config_path)?;
+        let mut settings = Self::validate_config(cfg)?;
+        for fragment in commons::config::dropin_fragments(config_path)? {
+            log::debug!("config fragment: {}", fragment.display());
+            let fragment_cfg = FileConfig::parse_file(&fragment)?;
+            settings.apply_config(fragment_cfg).with_context(|_| {
+                format!("invalid configuration fragment '{}'", fragment.display())
+            })?;
+        }
+        let env_cfg = FileConfig::from_env()?;
+        settings
+            .apply_config(env_cfg)
+            .context("invalid configuration from environment")?;
+        settings.check_consistency()?;
+        Ok(settings)
+    }
+
+    /// Check consistency across configuration sections, once all layers are applied.
+    fn check_consistency(&self) -> Fallible<()> {
+        for stream in self.upstream.stream_overrides.keys() {
+            if !self.service.streams.contains_key(stream) {
+                bail!("upstream overrides for unknown stream '{}'", stream);
+            }
+        }
+        for stream in self.scraper.stream_intervals.keys() {
+            if !self.service.streams.contains_key(stream) {
+                bail!("scraper overrides for unknown stream '{}'", stream);
+            }
+        }
+        for (product, streams) in &self.service.product_streams {
+            if product == &self.upstream.product {
+                bail!("product '{}' is already the default product", product);
+            }
+            if streams.is_empty() {
+                bail!("no streams configured for product '{}'", product);
+            }
+        }
+        for product in self
+            .upstream
+            .product_overrides
+            .keys()
+            .chain(&self.service.oci_only_products)
+        {
+            if !self.service.product_streams.contains_key(product) {
+                bail!("no streams configured for product '{}'", product);
+            }
+        }
+        if !self.service.oci_only_products.is_empty()
+            && !self.features.is_enabled(Feature::OciGraphs)
+        {
+            bail!("OCI-only products are configured, but OCI graphs are disabled");
+        }
+        if self.messaging.url.is_some() && self.messaging.topics.is_empty() {
+            bail!("messaging is enabled, but no topics are configured");
+        }
+        self.static_graphs()?;
+        Ok(())
+    }
+
+    /// Static override graphs, by scope.
+    pub(crate) fn static_graphs(&self) -> Fallible<BTreeMap<GraphScope, PathBuf>> {
+        let mut graphs = BTreeMap::new();
+        for entry in &self.service.static_graphs {
+            let scope = GraphScope {
+                product: entry
+                    .product
+                    .clone()
+                    .unwrap_or_else(|| self.upstream.product.clone()),
+                basearch: entry.basearch.clone(),
+                stream: entry.stream.clone(),
+                oci: entry.oci,
+            };
+            if graphs.contains_key(&scope) {
+                bail!(
+                    "duplicate static graph for scope: product='{}', basearch='{}', stream='{}', oci='{}'",
+                    scope.product,
+                    scope.basearch,
+                    scope.stream,
+                    scope.oci
+                );
+            }
+            graphs.insert(scope, entry.path.clone());
+        }
+        Ok(graphs)
+    }
+
+    /// Streams and basearches served by this instance, by (product, stream).
+    ///
+    /// Each (product, stream) pair must be configured only once, as it maps
+    /// to a single scraper.
+    pub(crate) fn owned_scopes(&self) -> Fallible<BTreeMap<(String, String), Vec<String>>> {
+        let default_product = (&self.upstream.product, &self.service.streams);
+        let products = std::iter::once(default_product).chain(&self.service.product_streams);
+
+        let mut scopes = BTreeMap::new();
+        for (product, streams) in products {
+            for (stream, arches) in streams {
+                let owned: Vec<String> = arches
+                    .iter()
+                    .filter(|arch| self.service.owns(product, stream, arch))
+                    .cloned()
+                    .collect();
+                if owned.is_empty() {
+                    continue;
+                }
+                let key = (product.clone(), stream.clone());
+                if scopes.insert(key, owned).is_some() {
+                    bail!(
+                        "duplicate scope for product '{}', stream '{}'",
+                        product,
+                        stream
+                    );
+                }
+            }
+        }
+        Ok(scopes)
+    }
+
+    fn apply_product_config(&mut self, product: String, cfg: ProductConfig) -> Fallible<()> {
+        if let Some(streams) = cfg.streams {
+            ServiceSettings::check_streams(&streams)?;
+            self.service
+                .product_streams
+                .insert(product.clone(), streams);
+        }
+        if let Some(oci_only) = cfg.oci_only {
+            if oci_only {
+                self.service.oci_only_products.insert(product.clone());
+            } else {
+                self.service.oci_only_products.remove(&product);
+            }
+        }
+        let upstream_cfg = UpstreamStreamConfig {
+            releases_url: cfg.releases_url,
+            updates_url: cfg.updates_url,
+        };
+        let overrides = self.upstream.product_overrides.entry(product).or_default();
+        UpstreamSettings::apply_stream_config(overrides, upstream_cfg)
+    }
+
+    /// Apply a configuration layer on top of current settings.
+    pub fn apply_config(&mut self, cfg: FileConfig) -> Fallible<()> {
+        if let Some(service) = cfg.service {
+            self.service
+                .apply_config(service)
+                .context("invalid 'service' configuration")?;
+        }
+        if let Some(status) = cfg.status {
+            self.status
+                .apply_config(status)
+                .context("invalid 'status' configuration")?;
+        }
+        if let Some(upstream) = cfg.upstream {
+            self.upstream
+                .apply_config(upstream)
+                .context("invalid 'upstream' configuration")?;
+        }
+        if let Some(scraper) = cfg.scraper {
+            self.scraper
+                .apply_config(scraper)
+                .context("invalid 'scraper' configuration")?;
+        }
+        if let Some(messaging) = cfg.messaging {
+            self.messaging
+                .apply_config(messaging)
+                .context("invalid 'messaging' configuration")?;
+        }
+        if let Some(products) = cfg.products {
+            for (product, product_cfg) in products {
+                if product.trim().is_empty() {
+                    bail!("invalid 'products' configuration: empty product name");
+                }
+                self.apply_product_config(product.clone(), product_cfg)
+                    .with_context(|_| format!("invalid 'products.{}' configuration", product))?;
+            }
+        }
+        if let Some(runtime) = cfg.runtime {
+            self.runtime
+                .apply_config(runtime)
+                .context("invalid 'runtime' configuration")?;
+        }
+        if let Some(tracing) = cfg.tracing {
+            self.tracing
+                .apply_config(tracing)
+                .context("invalid 'tracing' configuration")?;
+        }
+        if let Some(metrics) = cfg.metrics {
+            self.metrics
+                .apply_config(metrics)
+                .context("invalid 'metrics' configuration")?;
+        }
+        if let Some(reporting) = cfg.reporting {
+            self.reporting
+                .apply_config(reporting)
+                .context("invalid 'reporting' configuration")?;
+        }
+        if let Some(features) = cfg.features {
+            self.features
+                .apply_overrides(&features)
+                .context("invalid 'features' configuration")?;
+        }
+        Ok(())
+    }
 }
 
 /// Runtime settings for the main service (graph endpoint) server.
 #[derive(Clone, Debug)]
 pub struct ServiceSettings {
     pub(crate) origin_allowlist: Option<Vec<String>>,
-    pub(crate) ip_addr: IpAddr,
+    pub(crate) ip_addrs: Vec<IpAddr>,
     pub(crate) port: u16,
     // stream --> set of valid arches for it
-    pub(crate) streams: BTreeMap<&'static str, &'static [&'static str]>,
+    pub(crate) streams: BTreeMap<String, Vec<String>>,
+    // additional product --> stream --> set of valid arches for it
+    pub(crate) product_streams: BTreeMap<String, BTreeMap<String, Vec<String>>>,
+    /// Products only shipping OCI payloads, without checksum graphs.
+    pub(crate) oci_only_products: BTreeSet<String>,
+    /// Subset of scopes owned by this instance, if sharded.
+    pub(crate) shard: Option<Shard>,
+    /// Static override graphs.
+    pub(crate) static_graphs: Vec<StaticGraphSettings>,
+    /// Aliases of basearch and stream values.
+    pub(crate) scope_aliases: ScopeAliases,
+    /// Per-client rate limiting of graph requests.
+    pub(crate) rate_limit: RateLimitSettings,
+    /// Maximum graph requests in flight (unlimited if unset).
+    pub(crate) max_in_flight: Option<usize>,
+    /// Fraction of graph requests to log (access logs disabled if zero).
+    pub(crate) access_log_sample_rate: f64,
+}
+
+/// Static graph served verbatim for a scope, bypassing scrapers.
+#[derive(Clone, Debug)]
+pub struct StaticGraphSettings {
+    /// Product (default product if unset).
+    pub(crate) product: Option<String>,
+    pub(crate) basearch: String,
+    pub(crate) stream: String,
+    pub(crate) oci: bool,
+    pub(crate) path: PathBuf,
 }
 
 impl ServiceSettings {
@@ -40,8 +298,119 @@ impl ServiceSettings {
         ("next", &["x86_64", "aarch64", "s390x", "ppc64le"]),
     ];
 
+    /// First listen address, e.g. for local probes.
     pub fn socket_addr(&self) -> SocketAddr {
-        SocketAddr::new(self.ip_addr, self.port)
+        SocketAddr::new(self.ip_addrs[0], self.port)
+    }
+
+    /// All listen addresses.
+    pub fn socket_addrs(&self) -> Vec<SocketAddr> {
+        self.ip_addrs
+            .iter()
+            .map(|addr| SocketAddr::new(*addr, self.port))
+            .collect()
+    }
+
+    /// Whether a scope is served by this instance.
+    pub(crate) fn owns(&self, product: &str, stream: &str, basearch: &str) -> bool {
+        self.shard
+            .map(|shard| shard.owns(product, stream, basearch))
+            .unwrap_or(true)
+    }
+
+    /// Check that a streams configuration is well-formed.
+    fn check_streams(streams: &BTreeMap<String, Vec<String>>) -> Fallible<()> {
+        if streams.is_empty() {
+            bail!("no streams configured");
+        }
+        for (stream, arches) in streams {
+            if stream.trim().is_empty() {
+                bail!("empty stream name");
+            }
+            if arches.is_empty() {
+                bail!("no basearches configured for stream '{}'", stream);
+            }
+            if arches.iter().any(|arch| arch.trim().is_empty()) {
+                bail!("empty basearch for stream '{}'", stream);
+            }
+            let mut seen = BTreeSet::new();
+            if let Some(arch) = arches.iter().find(|arch| !seen.insert(arch.as_str())) {
+                bail!("duplicate basearch '{}' for stream '{}'", arch, stream);
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_config(&mut self, cfg: ServiceConfig) -> Fallible<()> {
+        if let Some(addrs) = commons::listen::listen_addresses(cfg.address, cfg.addresses)? {
+            self.ip_addrs = addrs;
+        }
+        if let Some(port) = cfg.port {
+            self.port = port;
+        }
+        if let Some(allowlist) = cfg.origin_allowlist {
+            if allowlist.iter().any(|origin| origin.trim().is_empty()) {
+                bail!("empty entry in 'origin_allowlist'");
+            }
+            self.origin_allowlist = Some(allowlist);
+        }
+        if let Some(shard) = cfg.shard {
+            let shard = Shard::new(shard.index, shard.count).context("invalid 'shard'")?;
+            self.shard = Some(shard);
+        }
+        if let Some(streams) = cfg.streams {
+            Self::check_streams(&streams)?;
+            self.streams = streams;
+        }
+        if let Some(static_graphs) = cfg.static_graphs {
+            let mut entries = Vec::with_capacity(static_graphs.len());
+            for entry in static_graphs {
+                let empty_product = matches!(&entry.product, Some(p) if p.trim().is_empty());
+                if empty_product
+                    || entry.basearch.trim().is_empty()
+                    || entry.stream.trim().is_empty()
+                {
+                    bail!("invalid 'static_graphs' entry: empty product, basearch or stream");
+                }
+                if entry.path.as_os_str().is_empty() {
+                    bail!("invalid 'static_graphs' entry: empty path");
+                }
+                entries.push(StaticGraphSettings {
+                    product: entry.product,
+                    basearch: entry.basearch,
+                    stream: entry.stream,
+                    oci: entry.oci,
+                    path: entry.path,
+                });
+            }
+            self.static_graphs = entries;
+        }
+        if let Some(aliases) = cfg.basearch_aliases {
+            self.scope_aliases.basearch =
+                commons::web::check_aliases(aliases).context("invalid 'basearch_aliases'")?;
+        }
+        if let Some(aliases) = cfg.stream_aliases {
+            self.scope_aliases.stream =
+                commons::web::check_aliases(aliases).context("invalid 'stream_aliases'")?;
+        }
+        if let Some(rate_limit) = cfg.rate_limit {
+            self.rate_limit
+                .apply_config(rate_limit)
+                .context("invalid 'rate_limit' configuration")?;
+        }
+        if let Some(max) = cfg.max_in_flight {
+            if max == 0 {
+                bail!("invalid 'max_in_flight': must be non-zero");
+            }
+            self.max_in_flight = Some(max);
+        }
+        if let Some(rate) = cfg.access_log_sample_rate {
+            if !(0.0..=1.0).contains(&rate) {
+                bail!("invalid 'access_log_sample_rate': must be between 0.0 and 1.0");
+            }
+            self.access_log_sample_rate = rate;
+        }
+        Ok(())
     }
 }
 
@@ -49,18 +418,36 @@ impl Default for ServiceSettings {
     fn default() -> Self {
         Self {
             origin_allowlist: None,
-            ip_addr: Self::DEFAULT_GB_SERVICE_ADDR.into(),
+            ip_addrs: vec![Self::DEFAULT_GB_SERVICE_ADDR.into()],
             port: Self::DEFAULT_GB_SERVICE_PORT,
-            streams: Self::DEFAULT_STREAMS.iter().copied().collect(),
+            streams: Self::DEFAULT_STREAMS
+                .iter()
+                .map(|(stream, arches)| {
+                    let arches = arches.iter().map(|arch| arch.to_string()).collect();
+                    (stream.to_string(), arches)
+                })
+                .collect(),
+            product_streams: BTreeMap::new(),
+            oci_only_products: BTreeSet::new(),
+            shard: None,
+            static_graphs: vec![],
+            scope_aliases: ScopeAliases::default(),
+            rate_limit: RateLimitSettings::default(),
+            max_in_flight: None,
+            access_log_sample_rate: 0.0,
         }
     }
 }
 
 /// Runtime settings for the status server.
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct StatusSettings {
-    pub(crate) ip_addr: IpAddr,
+    pub(crate) ip_addrs: Vec<IpAddr>,
     pub(crate) port: u16,
+    /// Bearer token for admin endpoints (disabled if unset).
+    pub(crate) admin_token: Option<String>,
+    /// Mutual TLS (plain HTTP if unset).
+    pub(crate) tls: Option<TlsSettings>,
 }
 
 impl StatusSettings {
@@ -69,16 +456,779 @@ impl StatusSettings {
     /// Default TCP port for graph-builder status.
     const DEFAULT_GB_STATUS_PORT: u16 = 9080;
 
+    /// First listen address, e.g. for local probes.
     pub fn socket_addr(&self) -> SocketAddr {
-        SocketAddr::new(self.ip_addr, self.port)
+        SocketAddr::new(self.ip_addrs[0], self.port)
+    }
+
+    /// All listen addresses.
+    pub fn socket_addrs(&self) -> Vec<SocketAddr> {
+        self.ip_addrs
+            .iter()
+            .map(|addr| SocketAddr::new(*addr, self.port))
+            .collect()
+    }
+
+    fn apply_config(&mut self, cfg: StatusConfig) -> Fallible<()> {
+        if let Some(addrs) = commons::listen::listen_addresses(cfg.address, cfg.addresses)? {
+            self.ip_addrs = addrs;
+        }
+        if let Some(port) = cfg.port {
+            self.port = port;
+        }
+        if let Some(token) = cfg.admin_token {
+            if token.trim().is_empty() {
+                bail!("empty 'admin_token'");
+            }
+            self.admin_token = Some(token);
+        }
+        if let Some(tls) = cfg.tls {
+            let tls = TlsSettings::from_config(tls).context("invalid 'tls'")?;
+            self.tls = Some(tls);
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for StatusSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never leak the admin token, e.g. when printing effective settings.
+        f.debug_struct("StatusSettings")
+            .field("ip_addrs", &self.ip_addrs)
+            .field("port", &self.port)
+            .field(
+                "admin_token",
+                &self.admin_token.as_ref().map(|_| "<redacted>"),
+            )
+            .field("tls", &self.tls)
+            .finish()
     }
 }
 
 impl Default for StatusSettings {
     fn default() -> Self {
         Self {
-            ip_addr: Self::DEFAULT_GB_SERVICE_ADDR.into(),
+            ip_addrs: vec![Self::DEFAULT_GB_SERVICE_ADDR.into()],
             port: Self::DEFAULT_GB_STATUS_PORT,
+            admin_token: None,
+            tls: None,
+        }
+    }
+}
+
+/// Runtime settings for upstream metadata sources.
+#[derive(Clone, Debug)]
+pub struct UpstreamSettings {
+    /// Product name, available as `${product}` in URL templates.
+    pub(crate) product: String,
+    /// Templated URL for release index.
+    pub(crate) releases_url: String,
+    /// Templated URL for updates metadata.
+    pub(crate) updates_url: String,
+    /// Per-stream URL templates overrides, for the default product.
+    pub(crate) stream_overrides: BTreeMap<String, StreamUpstreamSettings>,
+    /// Per-product URL templates overrides, for additional products.
+    pub(crate) product_overrides: BTreeMap<String, StreamUpstreamSettings>,
+    /// Outbound proxy for scraping.
+    pub(crate) proxy: ProxySettings,
+}
+
+/// Per-stream (or per-product) overrides for upstream metadata sources.
+#[derive(Clone, Debug, Default)]
+pub struct StreamUpstreamSettings {
+    /// Templated URL for release index.
+    pub(crate) releases_url: Option<String>,
+    /// Templated URL for updates metadata.
+    pub(crate) updates_url: Option<String>,
+}
+
+impl UpstreamSettings {
+    /// Default product name.
+    const DEFAULT_PRODUCT: &'static str = "fedora-coreos";
+
+    fn apply_config(&mut self, cfg: UpstreamConfig) -> Fallible<()> {
+        if let Some(product) = cfg.product {
+            if product.trim().is_empty() {
+                bail!("invalid 'product': must be non-empty");
+            }
+            self.product = product;
+        }
+        if let Some(url) = cfg.releases_url {
+            Self::check_template(&url).context("invalid 'releases_url'")?;
+            self.releases_url = url;
+        }
+        if let Some(url) = cfg.updates_url {
+            Self::check_template(&url).context("invalid 'updates_url'")?;
+            self.updates_url = url;
+        }
+        if let Some(streams) = cfg.streams {
+            for (stream, stream_cfg) in streams {
+                let overrides = self.stream_overrides.entry(stream.clone()).or_default();
+                Self::apply_stream_config(overrides, stream_cfg)
+                    .with_context(|_| format!("invalid 'streams.{}' entry", stream))?;
+            }
+        }
+        if let Some(proxy) = cfg.proxy {
+            self.proxy
+                .apply_config(proxy)
+                .context("invalid 'proxy' configuration")?;
+        }
+        Ok(())
+    }
+
+    fn apply_stream_config(
+        overrides: &mut StreamUpstreamSettings,
+        cfg: UpstreamStreamConfig,
+    ) -> Fallible<()> {
+        if let Some(url) = cfg.releases_url {
+            Self::check_template(&url).context("invalid 'releases_url'")?;
+            overrides.releases_url = Some(url);
+        }
+        if let Some(url) = cfg.updates_url {
+            Self::check_template(&url).context("invalid 'updates_url'")?;
+            overrides.updates_url = Some(url);
+        }
+        Ok(())
+    }
+
+    /// Return the overrides applying to a product stream, if any.
+    fn overrides_for(&self, product: &str, stream: &str) -> Option<&StreamUpstreamSettings> {
+        if product == self.product {
+            self.stream_overrides.get(stream)
+        } else {
+            self.product_overrides.get(product)
+        }
+    }
+
+    /// Render the release index URL for a scope.
+    pub(crate) fn releases_url(
+        &self,
+        product: &str,
+        stream: &str,
+        basearch: &str,
+    ) -> Fallible<reqwest::Url> {
+        let template = self
+            .overrides_for(product, stream)
+            .and_then(|o| o.releases_url.as_ref())
+            .unwrap_or(&self.releases_url);
+        Self::render(template, product, stream, basearch)
+    }
+
+    /// Render the updates metadata URL for a scope.
+    pub(crate) fn updates_url(
+        &self,
+        product: &str,
+        stream: &str,
+        basearch: &str,
+    ) -> Fallible<reqwest::Url> {
+        let template = self
+            .overrides_for(product, stream)
+            .and_then(|o| o.updates_url.as_ref())
+            .unwrap_or(&self.updates_url);
+        Self::render(template, product, stream, basearch)
+    }
+
+    /// Substitute all variables in a URL template.
+    fn render(
+        template: &str,
+        product: &str,
+        stream: &str,
+        basearch: &str,
+    ) -> Fallible<reqwest::Url> {
+        let vars = maplit::hashmap! {
+            "product".to_string() => product.to_string(),
+            "stream".to_string() => stream.to_string(),
+            "basearch".to_string() => basearch.to_string(),
+        };
+        let rendered = envsubst::substitute(template, &vars)?;
+        if envsubst::is_templated(&rendered) {
+            bail!("unknown variable in template '{}'", template);
+        }
+        let url = reqwest::Url::parse(&rendered)?;
+        Ok(url)
+    }
+
+    /// Check that a URL template renders to a valid URL.
+    fn check_template(template: &str) -> Fallible<()> {
+        Self::render(template, Self::DEFAULT_PRODUCT, "stable", "x86_64")?;
+        Ok(())
+    }
+}
+
+impl Default for UpstreamSettings {
+    fn default() -> Self {
+        Self {
+            product: Self::DEFAULT_PRODUCT.to_string(),
+            releases_url: metadata::RELEASES_JSON.to_string(),
+            updates_url: metadata::UPDATES_JSON.to_string(),
+            stream_overrides: BTreeMap::new(),
+            product_overrides: BTreeMap::new(),
+            proxy: ProxySettings::default(),
+        }
+    }
+}
+
+/// Runtime settings for upstream scrapers.
+#[derive(Clone, Debug)]
+pub struct ScraperSettings {
+    /// Maximum percentage of releases a refresh may drop from a cached graph.
+    pub(crate) max_release_loss_percent: u8,
+    /// Whether to accept any graph change, bypassing sanity checks.
+    pub(crate) allow_drastic_changes: bool,
+    pub(crate) transition: TransitionSettings,
+    pub(crate) backoff: BackoffSettings,
+    /// Pause between upstream scrapes.
+    pub(crate) interval: Duration,
+    /// Per-stream pause between upstream scrapes.
+    pub(crate) stream_intervals: BTreeMap<String, Duration>,
+    /// Maximum random delay added to each scheduled scrape (disabled if zero).
+    pub(crate) jitter: Duration,
+    /// Maximum age of a served graph, since its last successful refresh
+    /// (unlimited if unset).
+    pub(crate) max_staleness: Option<Duration>,
+    /// Maximum number of queued messages per scraper, beyond which graph
+    /// requests are shed.
+    pub(crate) mailbox_capacity: usize,
+}
+
+impl ScraperSettings {
+    /// Default maximum percentage of releases a refresh may drop.
+    const DEFAULT_MAX_RELEASE_LOSS_PERCENT: u8 = 50;
+    /// Default pause between upstream scrapes.
+    const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+    /// Default maximum number of queued messages per scraper.
+    const DEFAULT_MAILBOX_CAPACITY: usize = 256;
+
+    /// Processing steps applied to scraped graphs, in order.
+    pub(crate) fn pipeline(&self, features: &FeatureFlags) -> Vec<String> {
+        let mut steps = vec!["assemble_graph".to_string(), "filter_deadends".to_string()];
+        if !self.allow_drastic_changes {
+            steps.push(format!(
+                "change_guard (max_release_loss_percent={})",
+                self.max_release_loss_percent
+            ));
+        }
+        if self.transition.is_enabled() {
+            steps.push(format!(
+                "transition_window (window={}s, fraction={})",
+                self.transition.window.as_secs(),
+                self.transition.fraction
+            ));
+        }
+        if features.is_enabled(Feature::WarinessTiers) {
+            steps.push(format!("wariness_tiers (tiers={})", policy::WARINESS_TIERS));
+        }
+        steps
+    }
+
+    /// Pause between upstream scrapes for a stream.
+    pub(crate) fn interval_for(&self, stream: &str) -> Duration {
+        self.stream_intervals
+            .get(stream)
+            .copied()
+            .unwrap_or(self.interval)
+    }
+
+    fn check_interval(interval: HumanDuration) -> Fallible<Duration> {
+        if interval.0 == Duration::from_secs(0) {
+            bail!("invalid 'interval': must be non-zero");
+        }
+        Ok(interval.0)
+    }
+
+    fn apply_config(&mut self, cfg: ScraperConfig) -> Fallible<()> {
+        if let Some(percent) = cfg.max_release_loss_percent {
+            if percent > 100 {
+                bail!("invalid 'max_release_loss_percent': must be at most 100");
+            }
+            self.max_release_loss_percent = percent;
+        }
+        if let Some(allow) = cfg.allow_drastic_changes {
+            self.allow_drastic_changes = allow;
+        }
+        if let Some(window) = cfg.transition_window {
+            self.transition.window = window.0;
+        }
+        if let Some(fraction) = cfg.transition_fraction {
+            if !(0.0..=1.0).contains(&fraction) {
+                bail!("invalid 'transition_fraction': must be between 0.0 and 1.0");
+            }
+            self.transition.fraction = fraction;
+        }
+        if let Some(interval) = cfg.interval {
+            self.interval = Self::check_interval(interval)?;
+        }
+        if let Some(jitter) = cfg.jitter {
+            self.jitter = jitter.0;
+        }
+        if let Some(max_backoff) = cfg.max_backoff {
+            if max_backoff.0 == Duration::from_secs(0) {
+                bail!("invalid 'max_backoff': must be non-zero");
+            }
+            self.backoff.max_delay = max_backoff.0;
+        }
+        if let Some(threshold) = cfg.circuit_breaker_threshold {
+            if threshold == 0 {
+                bail!("invalid 'circuit_breaker_threshold': must be non-zero");
+            }
+            self.backoff.breaker_threshold = threshold;
+        }
+        if let Some(cooldown) = cfg.circuit_breaker_cooldown {
+            if cooldown.0 == Duration::from_secs(0) {
+                bail!("invalid 'circuit_breaker_cooldown': must be non-zero");
+            }
+            self.backoff.breaker_cooldown = cooldown.0;
+        }
+        if let Some(max_staleness) = cfg.max_staleness {
+            if max_staleness.0 == Duration::from_secs(0) {
+                bail!("invalid 'max_staleness': must be non-zero");
+            }
+            self.max_staleness = Some(max_staleness.0);
+        }
+        if let Some(capacity) = cfg.mailbox_capacity {
+            if capacity == 0 {
+                bail!("invalid 'mailbox_capacity': must be non-zero");
+            }
+            self.mailbox_capacity = capacity;
+        }
+        if let Some(streams) = cfg.streams {
+            for (stream, stream_cfg) in streams {
+                if let Some(interval) = stream_cfg.interval {
+                    let interval = Self::check_interval(interval)
+                        .with_context(|_| format!("invalid 'streams.{}' entry", stream))?;
+                    self.stream_intervals.insert(stream, interval);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for ScraperSettings {
+    fn default() -> Self {
+        Self {
+            max_release_loss_percent: Self::DEFAULT_MAX_RELEASE_LOSS_PERCENT,
+            allow_drastic_changes: false,
+            transition: TransitionSettings::default(),
+            backoff: BackoffSettings::default(),
+            interval: Self::DEFAULT_INTERVAL,
+            stream_intervals: BTreeMap::new(),
+            jitter: Duration::from_secs(0),
+            max_staleness: None,
+            mailbox_capacity: Self::DEFAULT_MAILBOX_CAPACITY,
+        }
+    }
+}
+
+/// Runtime settings for dual-serving graphs after significant changes.
+#[derive(Clone, Debug)]
+pub struct TransitionSettings {
+    /// How long to keep serving the previous graph (disabled if zero).
+    pub(crate) window: Duration,
+    /// Fraction of requests served from the previous graph.
+    pub(crate) fraction: f64,
+}
+
+impl TransitionSettings {
+    /// Default fraction of requests served from the previous graph.
+    const DEFAULT_FRACTION: f64 = 0.5;
+
+    /// Whether dual-serving is enabled.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.window > Duration::from_secs(0) && self.fraction > 0.0
+    }
+}
+
+impl Default for TransitionSettings {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(0),
+            fraction: Self::DEFAULT_FRACTION,
         }
     }
 }
+
+/// Runtime settings for backing off from a failing upstream.
+#[derive(Clone, Debug)]
+pub struct BackoffSettings {
+    /// Maximum delay between scrapes while backing off.
+    pub(crate) max_delay: Duration,
+    /// Consecutive failures after which the circuit breaker opens.
+    pub(crate) breaker_threshold: u32,
+    /// Delay between scrapes while the circuit breaker is open.
+    pub(crate) breaker_cooldown: Duration,
+}
+
+impl BackoffSettings {
+    /// Default maximum delay between scrapes while backing off (10 minutes).
+    const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(10 * 60);
+    /// Default consecutive failures after which the circuit breaker opens.
+    const DEFAULT_BREAKER_THRESHOLD: u32 = 10;
+    /// Default delay between scrapes while the circuit breaker is open (15 minutes).
+    const DEFAULT_BREAKER_COOLDOWN: Duration = Duration::from_secs(15 * 60);
+}
+
+impl Default for BackoffSettings {
+    fn default() -> Self {
+        Self {
+            max_delay: Self::DEFAULT_MAX_DELAY,
+            breaker_threshold: Self::DEFAULT_BREAKER_THRESHOLD,
+            breaker_cooldown: Self::DEFAULT_BREAKER_COOLDOWN,
+        }
+    }
+}
+
+/// Runtime settings for the Fedora Messaging consumer.
+#[derive(Clone, Debug)]
+pub struct MessagingSettings {
+    /// AMQP broker URL, with credentials (disabled if unset).
+    pub(crate) url: Option<String>,
+    /// Exchange to bind to.
+    pub(crate) exchange: String,
+    /// Topics announcing new releases.
+    pub(crate) topics: Vec<String>,
+    /// Pause before reconnecting to the broker, after a failure.
+    pub(crate) reconnect_delay: Duration,
+}
+
+impl MessagingSettings {
+    /// Default exchange for Fedora Messaging topics.
+    const DEFAULT_EXCHANGE: &'static str = "amq.topic";
+    /// Default pause before reconnecting to the broker (1 minute).
+    const DEFAULT_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+    fn apply_config(&mut self, cfg: MessagingConfig) -> Fallible<()> {
+        if let Some(url) = cfg.url {
+            if url.trim().is_empty() {
+                bail!("invalid 'url': must be non-empty");
+            }
+            self.url = Some(url);
+        }
+        if let Some(exchange) = cfg.exchange {
+            self.exchange = exchange;
+        }
+        if let Some(topics) = cfg.topics {
+            if topics.iter().any(|t| t.trim().is_empty()) {
+                bail!("invalid 'topics': empty topic");
+            }
+            self.topics = topics;
+        }
+        if let Some(delay) = cfg.reconnect_delay {
+            if delay.0 == Duration::from_secs(0) {
+                bail!("invalid 'reconnect_delay': must be non-zero");
+            }
+            self.reconnect_delay = delay.0;
+        }
+        Ok(())
+    }
+}
+
+impl Default for MessagingSettings {
+    fn default() -> Self {
+        Self {
+            url: None,
+            exchange: Self::DEFAULT_EXCHANGE.to_string(),
+            topics: vec![],
+            reconnect_delay: Self::DEFAULT_RECONNECT_DELAY,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_config() {
+        let input = r#"
+            [service]
+            address = "127.0.0.1"
+            port = 8090
+            origin_allowlist = ["https://example.com"]
+            max_in_flight = 64
+            access_log_sample_rate = 0.5
+
+            [service.streams]
+            stable = ["x86_64", "aarch64"]
+
+            [service.rate_limit]
+            ip_per_minute = 600
+            trusted_proxies = 1
+
+            [status]
+            addresses = ["0.0.0.0", "::"]
+            port = 9090
+
+            [upstream]
+            product = "example-os"
+            releases_url = "https://example.com/${product}/${stream}/releases.json"
+
+            [upstream.streams.stable]
+            updates_url = "https://example.com/custom/${stream}-${basearch}.json"
+
+            [scraper]
+            interval = "1m"
+            jitter = "5s"
+            max_backoff = "5m"
+            circuit_breaker_threshold = 3
+            max_staleness = "6h"
+            mailbox_capacity = 64
+
+            [scraper.streams.stable]
+            interval = "10s"
+
+            [messaging]
+            url = "amqps://fedora:@rabbitmq.example.com/%2Fpublic_pubsub"
+            topics = ["org.example.coreos.release"]
+
+            [tracing]
+            otlp_endpoint = "http://otel.example.com:4318/v1/traces"
+            export_interval = "10s"
+
+            [metrics]
+            prometheus = false
+            otlp_endpoint = "http://otel.example.com:4318/v1/metrics"
+
+            [reporting]
+            sentry_dsn = "https://abc123@sentry.example.com/42"
+            failure_threshold = 5
+
+            [features]
+            oci_graphs = false
+        "#;
+        let cfg: FileConfig = toml::from_str(input).unwrap();
+        let settings = GraphBuilderSettings::validate_config(cfg).unwrap();
+        assert_eq!(settings.service.port, 8090);
+        assert_eq!(settings.service.streams.len(), 1);
+        assert_eq!(settings.service.rate_limit.ip_per_minute, Some(600));
+        assert_eq!(settings.service.rate_limit.trusted_proxies, 1);
+        assert_eq!(settings.service.max_in_flight, Some(64));
+        assert_eq!(settings.service.access_log_sample_rate, 0.5);
+        assert_eq!(settings.status.port, 9090);
+        assert_eq!(settings.status.socket_addrs().len(), 2);
+        assert!(settings.tracing.otlp_endpoint.is_some());
+        assert_eq!(settings.tracing.export_interval, Duration::from_secs(10));
+        assert!(!settings.metrics.prometheus);
+        assert!(settings.metrics.otlp_endpoint.is_some());
+        assert_eq!(settings.metrics.export_interval, Duration::from_secs(60));
+        assert!(settings.reporting.sentry.is_some());
+        assert!(settings.reporting.webhook_url.is_none());
+        assert_eq!(settings.reporting.failure_threshold, 5);
+        assert_eq!(
+            settings
+                .upstream
+                .releases_url("example-os", "stable", "x86_64")
+                .unwrap()
+                .as_str(),
+            "https://example.com/example-os/stable/releases.json"
+        );
+        assert_eq!(settings.upstream.updates_url, metadata::UPDATES_JSON);
+        assert_eq!(
+            settings
+                .upstream
+                .updates_url("example-os", "stable", "aarch64")
+                .unwrap()
+                .as_str(),
+            "https://example.com/custom/stable-aarch64.json"
+        );
+        assert_eq!(
+            settings.scraper.interval_for("stable"),
+            Duration::from_secs(10)
+        );
+        assert_eq!(
+            settings.scraper.interval_for("next"),
+            Duration::from_secs(60)
+        );
+        assert_eq!(settings.scraper.jitter, Duration::from_secs(5));
+        assert_eq!(settings.scraper.backoff.max_delay, Duration::from_secs(300));
+        assert_eq!(settings.scraper.backoff.breaker_threshold, 3);
+        assert_eq!(settings.messaging.exchange, "amq.topic");
+        assert_eq!(
+            settings.messaging.topics,
+            vec!["org.example.coreos.release"]
+        );
+        assert_eq!(
+            settings.scraper.max_staleness,
+            Some(Duration::from_secs(6 * 60 * 60))
+        );
+        assert_eq!(settings.scraper.mailbox_capacity, 64);
+        settings.check_consistency().unwrap();
+        let mut no_topics = settings.clone();
+        no_topics.messaging.topics.clear();
+        no_topics.check_consistency().unwrap_err();
+
+        let products = r#"
+            [service.streams]
+            stable = ["x86_64"]
+
+            [products.other-os]
+            releases_url = "https://example.com/other/${stream}/releases.json"
+
+            [products.other-os.streams]
+            stable = ["x86_64"]
+            rawhide = ["x86_64", "aarch64"]
+        "#;
+        let cfg: FileConfig = toml::from_str(products).unwrap();
+        let settings = GraphBuilderSettings::validate_config(cfg).unwrap();
+        settings.check_consistency().unwrap();
+        let scopes = settings.owned_scopes().unwrap();
+        assert_eq!(scopes.len(), 3);
+        assert_eq!(
+            scopes[&("other-os".to_string(), "rawhide".to_string())],
+            vec!["x86_64", "aarch64"]
+        );
+        assert_eq!(
+            settings
+                .upstream
+                .releases_url("other-os", "rawhide", "aarch64")
+                .unwrap()
+                .as_str(),
+            "https://example.com/other/rawhide/releases.json"
+        );
+        assert_eq!(
+            settings
+                .upstream
+                .releases_url("fedora-coreos", "stable", "x86_64")
+                .unwrap()
+                .as_str(),
+            "https://builds.coreos.fedoraproject.org/prod/streams/stable/releases.json"
+        );
+
+        let product_without_streams = r#"
+            [products.other-os]
+            releases_url = "https://example.com/other/${stream}/releases.json"
+        "#;
+        let cfg: FileConfig = toml::from_str(product_without_streams).unwrap();
+        let settings = GraphBuilderSettings::validate_config(cfg).unwrap();
+        settings.check_consistency().unwrap_err();
+
+        let bad_streams = r#"
+            [service.streams]
+            stable = []
+        "#;
+        let cfg: FileConfig = toml::from_str(bad_streams).unwrap();
+        GraphBuilderSettings::validate_config(cfg).unwrap_err();
+
+        let bad_url = r#"
+            [upstream]
+            updates_url = "not a url"
+        "#;
+        let cfg: FileConfig = toml::from_str(bad_url).unwrap();
+        GraphBuilderSettings::validate_config(cfg).unwrap_err();
+
+        let unknown_var = r#"
+            [upstream]
+            updates_url = "https://example.com/${flavor}/updates.json"
+        "#;
+        let cfg: FileConfig = toml::from_str(unknown_var).unwrap();
+        GraphBuilderSettings::validate_config(cfg).unwrap_err();
+
+        let unknown_key = r#"
+            [service]
+            prot = 8090
+        "#;
+        toml::from_str::<FileConfig>(unknown_key).unwrap_err();
+    }
+
+    #[test]
+    fn test_oci_only_product() {
+        let input = r#"
+            [products.fedora-silverblue]
+            releases_url = "https://example.com/${product}/${stream}/releases.json"
+            oci_only = true
+
+            [products.fedora-silverblue.streams]
+            "42" = ["x86_64", "aarch64"]
+        "#;
+        let cfg: FileConfig = toml::from_str(input).unwrap();
+        let settings = GraphBuilderSettings::validate_config(cfg).unwrap();
+        settings.check_consistency().unwrap();
+        assert!(settings
+            .service
+            .oci_only_products
+            .contains("fedora-silverblue"));
+        assert!(!settings
+            .service
+            .oci_only_products
+            .contains(metadata::DEFAULT_PRODUCT));
+
+        let oci_disabled = r#"
+            [products.fedora-silverblue]
+            oci_only = true
+
+            [products.fedora-silverblue.streams]
+            "42" = ["x86_64"]
+
+            [features]
+            oci_graphs = false
+        "#;
+        let cfg: FileConfig = toml::from_str(oci_disabled).unwrap();
+        let settings = GraphBuilderSettings::validate_config(cfg).unwrap();
+        settings.check_consistency().unwrap_err();
+    }
+
+    #[test]
+    fn test_static_graphs() {
+        let input = r#"
+            [service]
+            static_graphs = [
+                { basearch = "x86_64", stream = "stable", path = "/etc/graphs/stable.json" },
+                { basearch = "x86_64", stream = "stable", oci = true, path = "/etc/graphs/stable-oci.json" },
+            ]
+        "#;
+        let cfg: FileConfig = toml::from_str(input).unwrap();
+        let settings = GraphBuilderSettings::validate_config(cfg).unwrap();
+        settings.check_consistency().unwrap();
+        let graphs = settings.static_graphs().unwrap();
+        assert_eq!(graphs.len(), 2);
+        let scope = GraphScope {
+            product: metadata::DEFAULT_PRODUCT.to_string(),
+            basearch: "x86_64".to_string(),
+            stream: "stable".to_string(),
+            oci: false,
+        };
+        assert_eq!(graphs[&scope], PathBuf::from("/etc/graphs/stable.json"));
+
+        // Explicit default product is the same scope as an implicit one.
+        let duplicate = r#"
+            [service]
+            static_graphs = [
+                { basearch = "x86_64", stream = "stable", path = "/etc/graphs/a.json" },
+                { product = "fedora-coreos", basearch = "x86_64", stream = "stable", path = "/etc/graphs/b.json" },
+            ]
+        "#;
+        let cfg: FileConfig = toml::from_str(duplicate).unwrap();
+        let settings = GraphBuilderSettings::validate_config(cfg).unwrap();
+        settings.check_consistency().unwrap_err();
+    }
+
+    #[test]
+    fn test_duplicate_scopes() {
+        let duplicate_arch = r#"
+            [service.streams]
+            stable = ["x86_64", "aarch64", "x86_64"]
+        "#;
+        let cfg: FileConfig = toml::from_str(duplicate_arch).unwrap();
+        let err = GraphBuilderSettings::validate_config(cfg).unwrap_err();
+        assert!(format!("{}", err.find_root_cause()).contains("duplicate basearch 'x86_64'"));
+
+        // Duplicate basearch in an additional product.
+        let duplicate_product_arch = r#"
+            [products.other-os.streams]
+            rawhide = ["aarch64", "aarch64"]
+        "#;
+        let cfg: FileConfig = toml::from_str(duplicate_product_arch).unwrap();
+        let err = GraphBuilderSettings::validate_config(cfg).unwrap_err();
+        assert!(format!("{}", err.find_root_cause()).contains("duplicate basearch 'aarch64'"));
+
+        // Additional product colliding with the default one.
+        let colliding_product = r#"
+            [products.fedora-coreos.streams]
+            stable = ["x86_64"]
+        "#;
+        let cfg: FileConfig = toml::from_str(colliding_product).unwrap();
+        let settings = GraphBuilderSettings::validate_config(cfg).unwrap();
+        settings.check_consistency().unwrap_err();
+        settings.owned_scopes().unwrap_err();
+    }
+}
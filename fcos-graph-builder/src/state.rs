@@ -0,0 +1,215 @@
+//! Per-scope scraping state machine.
+
+use serde_derive::Serialize;
+use std::time::Duration;
+
+/// Number of consecutive failures after which a degraded scope is considered failed.
+const FAILED_THRESHOLD: u32 = 10;
+
+/// State of a cached graph scope.
+///
+/// ```text
+/// Initializing --ok--> Healthy --err--> Degraded --err (xN)--> Failed
+///      |                  ^                |                      |
+///      +------err---------|----------------|------> Failed        |
+///                         +------ok--------+----------------------+
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub(crate) enum ScopeState {
+    /// No graph has been built yet.
+    Initializing,
+    /// Latest refresh succeeded.
+    Healthy { since: i64 },
+    /// Latest refresh failed, a stale graph is being served.
+    Degraded { since: i64, failures: u32 },
+    /// No valid graph available, or refresh has been failing for too long.
+    Failed { since: i64, failures: u32 },
+}
+
+impl ScopeState {
+    /// All state labels, as used in metrics.
+    pub(crate) const LABELS: [&'static str; 4] = ["initializing", "healthy", "degraded", "failed"];
+
+    /// Label for this state, as used in metrics and logs.
+    pub(crate) fn label(&self) -> &'static str {
+        match self {
+            ScopeState::Initializing => "initializing",
+            ScopeState::Healthy { .. } => "healthy",
+            ScopeState::Degraded { .. } => "degraded",
+            ScopeState::Failed { .. } => "failed",
+        }
+    }
+
+    /// Whether this scope is serving a reasonably fresh graph.
+    pub(crate) fn is_ready(&self) -> bool {
+        match self {
+            ScopeState::Healthy { .. } | ScopeState::Degraded { .. } => true,
+            ScopeState::Initializing | ScopeState::Failed { .. } => false,
+        }
+    }
+
+    /// Compute next state after a successful refresh.
+    pub(crate) fn on_success(self, now: i64) -> Self {
+        match self {
+            ScopeState::Healthy { .. } => self,
+            _ => ScopeState::Healthy { since: now },
+        }
+    }
+
+    /// Compute next state after a failed refresh.
+    pub(crate) fn on_failure(self, now: i64) -> Self {
+        match self {
+            ScopeState::Initializing => ScopeState::Failed {
+                since: now,
+                failures: 1,
+            },
+            ScopeState::Healthy { .. } => ScopeState::Degraded {
+                since: now,
+                failures: 1,
+            },
+            ScopeState::Degraded { since, failures } => {
+                let failures = failures.saturating_add(1);
+                if failures >= FAILED_THRESHOLD {
+                    ScopeState::Failed { since, failures }
+                } else {
+                    ScopeState::Degraded { since, failures }
+                }
+            }
+            ScopeState::Failed { since, failures } => ScopeState::Failed {
+                since,
+                failures: failures.saturating_add(1),
+            },
+        }
+    }
+}
+
+/// Schedule of upstream scrapes, with exponential backoff on failures.
+///
+/// After `breaker_threshold` consecutive failures the circuit breaker opens,
+/// and upstream is only probed again once per `breaker_cooldown`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Backoff {
+    pause: Duration,
+    max_delay: Duration,
+    breaker_threshold: u32,
+    breaker_cooldown: Duration,
+    failures: u32,
+}
+
+impl Backoff {
+    pub(crate) fn new(
+        pause: Duration,
+        max_delay: Duration,
+        breaker_threshold: u32,
+        breaker_cooldown: Duration,
+    ) -> Self {
+        Self {
+            pause,
+            max_delay,
+            breaker_threshold,
+            breaker_cooldown,
+            failures: 0,
+        }
+    }
+
+    /// Record a successful scrape, resetting the schedule.
+    pub(crate) fn on_success(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Record a failed scrape.
+    pub(crate) fn on_failure(&mut self) {
+        self.failures = self.failures.saturating_add(1);
+    }
+
+    /// Whether the circuit breaker is open, i.e. upstream is considered down.
+    pub(crate) fn is_open(&self) -> bool {
+        self.failures >= self.breaker_threshold
+    }
+
+    /// Delay before the next scrape.
+    pub(crate) fn next_delay(&self) -> Duration {
+        if self.failures == 0 {
+            return self.pause;
+        }
+        if self.is_open() {
+            return self.breaker_cooldown;
+        }
+        let factor = 2u32.saturating_pow(self.failures);
+        let delay = self.pause.checked_mul(factor).unwrap_or(self.max_delay);
+        delay.min(self.max_delay).max(self.pause)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scope_state_transitions() {
+        let initial = ScopeState::Initializing;
+        assert_eq!(initial.label(), "initializing");
+        assert!(!initial.is_ready());
+
+        let failed = initial.on_failure(10);
+        assert_eq!(
+            failed,
+            ScopeState::Failed {
+                since: 10,
+                failures: 1
+            }
+        );
+
+        let healthy = failed.on_success(20);
+        assert_eq!(healthy, ScopeState::Healthy { since: 20 });
+        assert_eq!(healthy.on_success(30), healthy);
+
+        let mut state = healthy.on_failure(40);
+        assert_eq!(
+            state,
+            ScopeState::Degraded {
+                since: 40,
+                failures: 1
+            }
+        );
+        assert!(state.is_ready());
+        for step in 1..FAILED_THRESHOLD {
+            state = state.on_failure(40 + i64::from(step));
+        }
+        assert_eq!(
+            state,
+            ScopeState::Failed {
+                since: 40,
+                failures: FAILED_THRESHOLD
+            }
+        );
+        assert_eq!(state.on_success(100), ScopeState::Healthy { since: 100 });
+    }
+
+    #[test]
+    fn test_backoff() {
+        let pause = Duration::from_secs(30);
+        let mut backoff =
+            Backoff::new(pause, Duration::from_secs(300), 5, Duration::from_secs(900));
+        assert_eq!(backoff.next_delay(), pause);
+
+        backoff.on_failure();
+        assert_eq!(backoff.next_delay(), Duration::from_secs(60));
+        backoff.on_failure();
+        assert_eq!(backoff.next_delay(), Duration::from_secs(120));
+        backoff.on_failure();
+        backoff.on_failure();
+        // Capped at the maximum delay.
+        assert_eq!(backoff.next_delay(), Duration::from_secs(300));
+        assert!(!backoff.is_open());
+
+        backoff.on_failure();
+        assert!(backoff.is_open());
+        assert_eq!(backoff.next_delay(), Duration::from_secs(900));
+
+        backoff.on_success();
+        assert!(!backoff.is_open());
+        assert_eq!(backoff.next_delay(), pause);
+    }
+}
@@ -0,0 +1,147 @@
+//! Short-lived in-memory cache of upstream graphs.
+
+use crate::utils::UpstreamGraph;
+use commons::graph::GraphScope;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Identity of an upstream graph.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct GraphKey {
+    pub(crate) upstream: reqwest::Url,
+    pub(crate) scope: GraphScope,
+    /// Rollout wariness tier, for pre-built graph variants.
+    pub(crate) tier: Option<u8>,
+}
+
+/// Upstream graphs, by key, kept for a fixed time-to-live.
+///
+/// Graphs are retained beyond their time-to-live, up to a maximum staleness,
+/// as last-known-good fallbacks for upstream outages.
+#[derive(Debug)]
+pub(crate) struct GraphCache {
+    ttl: Duration,
+    max_stale: Duration,
+    /// key -> (fetch time, graph)
+    entries: HashMap<GraphKey, (Instant, UpstreamGraph)>,
+}
+
+impl GraphCache {
+    /// Create a cache; a zero time-to-live (or maximum staleness) disables
+    /// caching (or fallbacks).
+    pub(crate) fn new(ttl: Duration, max_stale: Duration) -> Self {
+        Self {
+            ttl,
+            max_stale,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Look up a graph fetched less than a time-to-live ago.
+    pub(crate) fn get(&self, key: &GraphKey, now: Instant) -> Option<UpstreamGraph> {
+        self.entries
+            .get(key)
+            .filter(|(fetched, _)| now.saturating_duration_since(*fetched) < self.ttl)
+            .map(|(_, graph)| graph.clone())
+    }
+
+    /// Look up the last fetched graph, up to the maximum staleness, with its age.
+    pub(crate) fn get_stale(
+        &self,
+        key: &GraphKey,
+        now: Instant,
+    ) -> Option<(UpstreamGraph, Duration)> {
+        self.entries
+            .get(key)
+            .map(|(fetched, graph)| (graph, now.saturating_duration_since(*fetched)))
+            .filter(|(_, age)| *age <= self.max_stale)
+            .map(|(graph, age)| (graph.clone(), age))
+    }
+
+    /// Store a freshly fetched graph, dropping expired entries.
+    pub(crate) fn insert(&mut self, key: GraphKey, graph: UpstreamGraph, now: Instant) {
+        let retention = self.ttl.max(self.max_stale);
+        if retention == Duration::from_secs(0) {
+            return;
+        }
+        self.entries
+            .retain(|_, (fetched, _)| now.saturating_duration_since(*fetched) < retention);
+        self.entries.insert(key, (now, graph));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::web::Bytes;
+
+    fn graph(body: &'static str) -> UpstreamGraph {
+        UpstreamGraph {
+            body: Bytes::from_static(body.as_bytes()),
+            source: None,
+            oci: None,
+            last_modified: None,
+            version: None,
+        }
+    }
+
+    fn key(stream: &str, tier: Option<u8>) -> GraphKey {
+        GraphKey {
+            upstream: reqwest::Url::parse("http://127.0.0.1:8080/v1/graph").unwrap(),
+            scope: GraphScope {
+                product: "fedora-coreos".to_string(),
+                basearch: "x86_64".to_string(),
+                stream: stream.to_string(),
+                oci: false,
+            },
+            tier,
+        }
+    }
+
+    #[test]
+    fn test_graph_cache() {
+        let start = Instant::now();
+        let mut cache = GraphCache::new(Duration::from_secs(10), Duration::from_secs(0));
+        assert!(cache.get(&key("stable", None), start).is_none());
+
+        cache.insert(key("stable", None), graph("stable"), start);
+        cache.insert(key("stable", Some(3)), graph("tier"), start);
+        let later = start + Duration::from_secs(5);
+        assert_eq!(
+            cache.get(&key("stable", None), later).unwrap().body,
+            "stable"
+        );
+        assert_eq!(
+            cache.get(&key("stable", Some(3)), later).unwrap().body,
+            "tier"
+        );
+        assert!(cache.get(&key("next", None), later).is_none());
+
+        let expired = start + Duration::from_secs(10);
+        assert!(cache.get(&key("stable", None), expired).is_none());
+        cache.insert(key("next", None), graph("next"), expired);
+        assert_eq!(cache.entries.len(), 1);
+
+        let mut disabled = GraphCache::new(Duration::from_secs(0), Duration::from_secs(0));
+        disabled.insert(key("stable", None), graph("stable"), start);
+        assert!(disabled.get(&key("stable", None), start).is_none());
+        assert!(disabled.get_stale(&key("stable", None), start).is_none());
+    }
+
+    #[test]
+    fn test_graph_cache_stale() {
+        let start = Instant::now();
+        let mut cache = GraphCache::new(Duration::from_secs(0), Duration::from_secs(600));
+        cache.insert(key("stable", None), graph("stable"), start);
+        assert!(cache.get(&key("stable", None), start).is_none());
+
+        let outage = start + Duration::from_secs(120);
+        let (stale, age) = cache.get_stale(&key("stable", None), outage).unwrap();
+        assert_eq!(stale.body, "stable");
+        assert_eq!(age, Duration::from_secs(120));
+        assert!(cache.get_stale(&key("next", None), outage).is_none());
+
+        let too_old = start + Duration::from_secs(601);
+        assert!(cache.get_stale(&key("stable", None), too_old).is_none());
+    }
+}
@@ -1,14 +1,160 @@
-use failure::Fallible;
-use std::path::Path;
+use commons::config::HumanDuration;
+use commons::metrics::MetricsConfig;
+use commons::proxy::ProxyConfig;
+use commons::report::ReportingConfig;
+use commons::runtime::RuntimeConfig;
+use commons::tls::TlsConfig;
+use commons::trace::TracingConfig;
+use commons::web::RateLimitConfig;
+use failure::{Fallible, ResultExt};
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 
 /// Configuration file.
-#[derive(Debug, Default)]
-pub struct FileConfig {}
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    /// Main service (graph endpoint) server.
+    pub service: Option<ServiceConfig>,
+    /// Status server.
+    pub status: Option<StatusConfig>,
+    /// Async runtime sizing.
+    pub runtime: Option<RuntimeConfig>,
+    /// Distributed tracing.
+    pub tracing: Option<TracingConfig>,
+    /// Metrics export.
+    pub metrics: Option<MetricsConfig>,
+    /// Error reporting.
+    pub reporting: Option<ReportingConfig>,
+    /// Feature flags, by name.
+    pub features: Option<HashMap<String, bool>>,
+}
 
 impl FileConfig {
-    pub fn parse_file(_path: impl AsRef<Path>) -> Fallible<Self> {
-        // TODO(lucab): translate config entries.
-        let cfg = FileConfig::default();
+    /// Prefix for environment variables overriding configuration entries.
+    const ENV_PREFIX: &'static str = "FCOS_PE_";
+
+    pub fn parse_file(path: impl AsRef<Path>) -> Fallible<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|_| format!("failed to read config file '{}'", path.display()))?;
+        let cfg = toml::from_str(&content)
+            .with_context(|_| format!("failed to parse config file '{}'", path.display()))?;
+        Ok(cfg)
+    }
+
+    /// Collect configuration overrides from `FCOS_PE_*` environment variables.
+    pub fn from_env() -> Fallible<Self> {
+        let overrides = commons::config::env_overrides(Self::ENV_PREFIX, std::env::vars())?;
+        let cfg = overrides
+            .try_into()
+            .context("failed to parse configuration from environment")?;
         Ok(cfg)
     }
 }
+
+/// Config section for the main service.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceConfig {
+    pub address: Option<IpAddr>,
+    /// Listen addresses, instead of a single `address`.
+    pub addresses: Option<Vec<IpAddr>>,
+    pub port: Option<u16>,
+    pub origin_allowlist: Option<Vec<String>>,
+    /// Upstream graph-builder endpoint.
+    pub upstream_base: Option<String>,
+    /// Interchangeable upstream graph-builder endpoints, with failover
+    /// (instead of `upstream_base`).
+    pub upstream_replicas: Option<Vec<String>>,
+    /// Upstream graph-builder endpoints, by shard index (overrides `upstream_base`).
+    pub upstream_shards: Option<Vec<String>>,
+    /// Timeout for upstream requests.
+    pub upstream_timeout: Option<HumanDuration>,
+    /// Outbound proxy for upstream requests.
+    pub upstream_proxy: Option<ProxyConfig>,
+    /// Time-to-live of cached upstream graphs (caching disabled if zero).
+    pub upstream_cache_ttl: Option<HumanDuration>,
+    /// Maximum age of last-known-good graphs served while upstream is
+    /// unreachable (disabled if zero).
+    pub upstream_stale_max_age: Option<HumanDuration>,
+    /// Interval between background refreshes of upstream graphs for all
+    /// configured scopes (disabled if unset).
+    pub upstream_prefetch_interval: Option<HumanDuration>,
+    /// Graph scopes served by this instance (all if unset).
+    pub scopes: Option<Vec<ScopeConfig>>,
+    /// Minimum number of releases a client must be behind to be routed
+    /// through barriers only (disabled if unset).
+    pub old_client_release_lag: Option<u64>,
+    /// Window of the requests by client version summary, in hours.
+    pub version_heatmap_hours: Option<usize>,
+    /// File for snapshots of unique nodes estimates, restored on startup
+    /// (disabled if unset).
+    pub population_snapshot_path: Option<PathBuf>,
+    /// Interval between snapshots of unique nodes estimates.
+    pub population_snapshot_interval: Option<HumanDuration>,
+    /// Content for `/robots.txt`.
+    pub robots_txt: Option<String>,
+    /// Content for `/.well-known/security.txt`.
+    pub security_txt: Option<String>,
+    /// Aliases of basearch values in requests, mapped onto canonical
+    /// basearches (replaces the default aliases).
+    pub basearch_aliases: Option<HashMap<String, String>>,
+    /// Aliases of stream names in requests, mapped onto canonical streams.
+    pub stream_aliases: Option<HashMap<String, String>>,
+    /// Product assumed for requests and scopes without one (should match
+    /// the graph-builder `upstream.product`).
+    pub default_product: Option<String>,
+    /// Basearch assumed for requests without one (rejected if unset).
+    pub default_basearch: Option<String>,
+    /// Stream assumed for requests without one (rejected if unset).
+    pub default_stream: Option<String>,
+    /// Key for deriving rollout wariness from node IDs, as 32 hex digits.
+    pub wariness_key: Option<String>,
+    /// Canary node IDs (or prefixes, ending with `*`), always served
+    /// rollouts first.
+    pub canary_nodes: Option<Vec<String>>,
+    /// Node IDs (or prefixes, ending with `*`) denied graph requests.
+    pub denied_nodes: Option<Vec<String>>,
+    /// Maximum graph requests per minute from a single node, beyond which
+    /// it is temporarily banned (disabled if unset).
+    pub node_ban_rate: Option<u32>,
+    /// Duration of temporary bans of nodes.
+    pub node_ban_duration: Option<HumanDuration>,
+    /// Per-client rate limiting of graph requests.
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Maximum graph requests in flight, beyond which requests are shed
+    /// (unlimited if unset).
+    pub max_in_flight: Option<usize>,
+    /// Fraction of graph requests to log, between 0.0 and 1.0 (access logs
+    /// disabled if unset).
+    pub access_log_sample_rate: Option<f64>,
+    /// Deadline for serving graph requests, beyond which they are aborted
+    /// (disabled if unset).
+    pub request_timeout: Option<HumanDuration>,
+}
+
+/// Config entry for an allowed graph scope.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScopeConfig {
+    pub product: Option<String>,
+    pub basearch: String,
+    pub stream: String,
+    #[serde(default)]
+    pub oci: bool,
+}
+
+/// Config section for the status server.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StatusConfig {
+    pub address: Option<IpAddr>,
+    /// Listen addresses, instead of a single `address`.
+    pub addresses: Option<Vec<IpAddr>>,
+    pub port: Option<u16>,
+    /// Mutual TLS, requiring client certificates (plain HTTP if unset).
+    pub tls: Option<TlsConfig>,
+}
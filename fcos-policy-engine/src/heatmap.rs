@@ -0,0 +1,195 @@
+//! In-memory summary of graph requests, by client version.
+
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::RwLock;
+
+/// Length of a single bucket, in seconds.
+const BUCKET_SECS: i64 = 60 * 60;
+/// Maximum distinct versions tracked per bucket, as client input is untrusted.
+const MAX_VERSIONS_PER_BUCKET: usize = 1000;
+/// Label for requests without a reported version.
+const UNKNOWN_VERSION: &str = "unknown";
+/// Label for versions beyond the per-bucket limit.
+const OTHER_VERSIONS: &str = "other";
+
+/// Request counts by client version, over a sliding window of hourly buckets.
+///
+/// Buckets are reused in a ring, and only locked for writing when rotated or
+/// when a new version shows up, so that recording requests does not contend
+/// on a global lock.
+#[derive(Debug)]
+pub(crate) struct VersionHeatmap {
+    /// One bucket per hour in the window, by hour modulo window length.
+    buckets: Vec<HourBucket>,
+}
+
+/// Request counts by client version, for a single hour.
+#[derive(Debug, Default)]
+struct HourBucket {
+    /// Bucket start (UTC timestamp), or zero if never used.
+    start: AtomicI64,
+    /// version -> count
+    counts: RwLock<HashMap<String, AtomicU64>>,
+}
+
+/// Summary of requests by client version, over recent hours.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct VersionSummary {
+    pub(crate) hours: usize,
+    pub(crate) total: u64,
+    pub(crate) versions: BTreeMap<String, u64>,
+}
+
+impl VersionHeatmap {
+    pub(crate) fn new(hours: usize) -> Self {
+        Self {
+            buckets: (0..hours.max(1)).map(|_| HourBucket::default()).collect(),
+        }
+    }
+
+    /// Window length, in hours.
+    pub(crate) fn hours(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// Record a request from a client, at the given UTC timestamp.
+    pub(crate) fn record(&self, version: Option<&str>, now: i64) {
+        let start = now - now.rem_euclid(BUCKET_SECS);
+        let bucket = &self.buckets[(start / BUCKET_SECS).rem_euclid(self.hours() as i64) as usize];
+        if bucket.start.load(Ordering::Acquire) != start {
+            let mut counts = match bucket.counts.write() {
+                Ok(counts) => counts,
+                Err(_) => return,
+            };
+            let current = bucket.start.load(Ordering::Acquire);
+            // Requests racing across an hour boundary may be late, and must
+            // not rotate a newer bucket away.
+            if current > start {
+                return;
+            }
+            if current < start {
+                counts.clear();
+                bucket.start.store(start, Ordering::Release);
+            }
+        }
+
+        let version = version.filter(|v| !v.is_empty()).unwrap_or(UNKNOWN_VERSION);
+        if let Ok(counts) = bucket.counts.read() {
+            if let Some(count) = counts.get(version) {
+                count.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        }
+        if let Ok(mut counts) = bucket.counts.write() {
+            let key = if counts.contains_key(version) || counts.len() < MAX_VERSIONS_PER_BUCKET {
+                version
+            } else {
+                OTHER_VERSIONS
+            };
+            counts
+                .entry(key.to_string())
+                .or_default()
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Summarize requests over the last `hours` hours (capped to the window).
+    pub(crate) fn summary(&self, hours: usize, now: i64) -> VersionSummary {
+        let hours = hours.min(self.hours());
+        let current = now - now.rem_euclid(BUCKET_SECS);
+        let oldest = current - (hours as i64 - 1) * BUCKET_SECS;
+
+        let mut versions = BTreeMap::new();
+        for bucket in &self.buckets {
+            let counts = match bucket.counts.read() {
+                Ok(counts) => counts,
+                Err(_) => continue,
+            };
+            let start = bucket.start.load(Ordering::Acquire);
+            if start < oldest || start > current {
+                continue;
+            }
+            for (version, count) in counts.iter() {
+                *versions.entry(version.clone()).or_default() += count.load(Ordering::Relaxed);
+            }
+        }
+        VersionSummary {
+            hours,
+            total: versions.values().sum(),
+            versions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_heatmap() {
+        let heatmap = VersionHeatmap::new(3);
+        let hour = BUCKET_SECS;
+
+        heatmap.record(Some("38.20230806.3.0"), 0);
+        heatmap.record(Some("38.20230806.3.0"), 10);
+        heatmap.record(None, 20);
+        heatmap.record(Some("39.20231101.3.0"), hour + 5);
+        heatmap.record(Some("39.20231101.3.0"), 2 * hour + 5);
+
+        let all = heatmap.summary(24, 2 * hour + 10);
+        assert_eq!(all.hours, 3);
+        assert_eq!(all.total, 5);
+        assert_eq!(all.versions["38.20230806.3.0"], 2);
+        assert_eq!(all.versions[UNKNOWN_VERSION], 1);
+
+        let last_hour = heatmap.summary(1, 2 * hour + 10);
+        assert_eq!(last_hour.total, 1);
+        assert_eq!(last_hour.versions["39.20231101.3.0"], 1);
+
+        // Oldest bucket falls out of the window.
+        heatmap.record(Some("39.20231101.3.0"), 3 * hour);
+        let all = heatmap.summary(3, 3 * hour);
+        assert_eq!(all.total, 3);
+        assert!(!all.versions.contains_key("38.20230806.3.0"));
+
+        // Distinct versions are capped per bucket.
+        let heatmap = VersionHeatmap::new(1);
+        for n in 0..=MAX_VERSIONS_PER_BUCKET {
+            heatmap.record(Some(&format!("v{}", n)), 0);
+        }
+        let summary = heatmap.summary(1, 0);
+        assert_eq!(summary.versions[OTHER_VERSIONS], 1);
+        assert_eq!(summary.total, MAX_VERSIONS_PER_BUCKET as u64 + 1);
+
+        // Late requests do not rotate a newer bucket away.
+        let heatmap = VersionHeatmap::new(1);
+        heatmap.record(Some("39.20231101.3.0"), hour);
+        heatmap.record(Some("38.20230806.3.0"), hour - 1);
+        let summary = heatmap.summary(1, hour);
+        assert_eq!(summary.total, 1);
+        assert_eq!(summary.versions["39.20231101.3.0"], 1);
+    }
+
+    #[test]
+    fn test_version_heatmap_concurrent() {
+        let heatmap = std::sync::Arc::new(VersionHeatmap::new(24));
+        let threads: Vec<_> = (0..4)
+            .map(|n| {
+                let heatmap = std::sync::Arc::clone(&heatmap);
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        heatmap.record(Some(&format!("v{}", n % 2)), 10);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let summary = heatmap.summary(24, 10);
+        assert_eq!(summary.total, 4000);
+        assert_eq!(summary.versions["v0"], 2000);
+    }
+}
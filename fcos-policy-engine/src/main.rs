@@ -3,34 +3,83 @@ extern crate log;
 #[macro_use]
 extern crate prometheus;
 
+mod cache;
 mod cli;
 mod config;
+mod heatmap;
+mod nodes;
+mod population;
 mod settings;
+mod throttled;
+mod upstream;
 mod utils;
 
-use actix_web::{web, App, HttpResponse};
+use actix::fut::ActorFuture;
+use actix::{Actor, AsyncContext, Context};
+use actix_web::dev::Service;
+use actix_web::http::header::{ETAG, LAST_MODIFIED, VARY};
+use actix_web::http::StatusCode;
+use actix_web::{web, App, HttpRequest, HttpResponse, ResponseError, Route};
 use clap::{crate_name, crate_version, Parser};
-use commons::{graph, metrics, policy};
+use commons::build::BuildInfo;
+use commons::features::{Feature, FeatureFlags};
+use commons::openapi::{OpenApi, QueryParam};
+use commons::report::ErrorReporter;
+use commons::trace::{SpanKind, TraceContext, Tracer};
+use commons::web::{
+    AccessLog, CanonicalQuery, Endpoint, GraphFormat, HandlerError, InFlightLimit, RateLimiter,
+    ScopeAliases, ServiceHelp,
+};
+use commons::{graph, metrics, policy, shard};
 use failure::{Error, Fallible, ResultExt};
-use prometheus::{Histogram, IntCounter, IntGauge};
+use futures::future::Either;
+use futures::FutureExt;
+use prometheus::{Gauge, GaugeVec, Histogram, IntCounter, IntCounterVec, IntGaugeVec};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
-use std::sync::Arc;
-use std::time::Duration;
+use std::collections::{BTreeSet, HashSet};
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 /// Top-level log target for this application.
 static APP_LOG_TARGET: &str = "fcos_policy_engine";
 
+/// Log target for access logs, enabled independently of the log level.
+static ACCESS_LOG_TARGET: &str = "fcos_policy_engine::access";
+
+/// Namespace for metrics common to all services.
+static METRICS_NAMESPACE: &str = "fcos_cincinnati_pe";
+
+/// Interval between health checks of upstream replicas, also bounding each check.
+const UPSTREAM_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Interval between updates of time-windowed unique nodes estimates.
+const POPULATION_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
 lazy_static::lazy_static! {
-    static ref V1_GRAPH_INCOMING_REQS: IntCounter = register_int_counter!(opts!(
-        "fcos_cincinnati_pe_v1_graph_incoming_requests_total",
-        "Total number of incoming HTTP client request to /v1/graph"
+    static ref UNIQUE_IDS: Gauge = register_gauge!(opts!(
+        "fcos_cincinnati_pe_v1_graph_unique_uuids",
+        "Estimated number of unique node UUIDs (per-instance HyperLogLog)."
     ))
     .unwrap();
-    static ref UNIQUE_IDS: IntCounter = register_int_counter!(opts!(
-        "fcos_cincinnati_pe_v1_graph_unique_uuids_total",
-        "Total number of unique node UUIDs (per-instance Bloom filter)."
-    ))
+    static ref SCOPE_UNIQUE_IDS: GaugeVec = register_gauge_vec!(
+        "fcos_cincinnati_pe_v1_graph_scope_unique_uuids",
+        "Estimated number of unique node UUIDs per scope (per-instance HyperLogLog).",
+        &["product", "basearch", "stream"]
+    )
+    .unwrap();
+    static ref WINDOW_UNIQUE_IDS: GaugeVec = register_gauge_vec!(
+        "fcos_cincinnati_pe_v1_graph_window_unique_uuids",
+        "Estimated number of unique node UUIDs over a time window (per-instance HyperLogLog).",
+        &["window"]
+    )
+    .unwrap();
+    static ref SCOPE_WINDOW_UNIQUE_IDS: GaugeVec = register_gauge_vec!(
+        "fcos_cincinnati_pe_v1_graph_scope_window_unique_uuids",
+        "Estimated number of unique node UUIDs per scope over a time window (per-instance HyperLogLog).",
+        &["product", "basearch", "stream", "window"]
+    )
     .unwrap();
     static ref ROLLOUT_WARINESS: Histogram = register_histogram!(
         "fcos_cincinnati_pe_v1_graph_rollout_wariness",
@@ -38,12 +87,70 @@ lazy_static::lazy_static! {
         prometheus::linear_buckets(0.0, 0.1, 11).unwrap()
     )
     .unwrap();
-    // NOTE(lucab): alternatively this could come from the runtime library, see
-    // https://prometheus.io/docs/instrumenting/writing_clientlibs/#process-metrics
-    static ref PROCESS_START_TIME: IntGauge = register_int_gauge!(opts!(
-        "process_start_time_seconds",
-        "Start time of the process since unix epoch in seconds."
-    )).unwrap();
+    static ref GRAPH_SERIALIZATION_DURATION: Histogram = register_histogram!(
+        "fcos_cincinnati_pe_v1_graph_serialization_duration_seconds",
+        "Time spent serializing response graphs.",
+        prometheus::exponential_buckets(0.000_05, 2.0, 14).unwrap()
+    )
+    .unwrap();
+    static ref GRAPH_PASSTHROUGH: IntCounter = register_int_counter!(opts!(
+        "fcos_cincinnati_pe_v1_graph_passthrough_total",
+        "Total number of upstream graphs served as-is, without rewriting."
+    ))
+    .unwrap();
+    static ref UPSTREAM_REPLICA_UP: IntGaugeVec = register_int_gauge_vec!(
+        "fcos_cincinnati_pe_upstream_replica_up",
+        "Whether an upstream graph-builder replica is healthy.",
+        &["replica"]
+    )
+    .unwrap();
+    static ref UPSTREAM_REPLICA_FAILURES: IntCounter = register_int_counter!(opts!(
+        "fcos_cincinnati_pe_upstream_replica_failures_total",
+        "Total number of failed upstream graph requests, retried on other replicas."
+    ))
+    .unwrap();
+    static ref UPSTREAM_STALE_GRAPHS: IntCounter = register_int_counter!(opts!(
+        "fcos_cincinnati_pe_upstream_stale_graphs_total",
+        "Total number of last-known-good graphs served while upstream is unreachable."
+    ))
+    .unwrap();
+    static ref DEFAULTED_SCOPE_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "fcos_cincinnati_pe_v1_graph_defaulted_scope_requests_total",
+        "Total number of graph requests missing a scope parameter, served with its configured default.",
+        &["param"]
+    )
+    .unwrap();
+    static ref MALFORMED_NODE_UUIDS: IntCounter = register_int_counter!(opts!(
+        "fcos_cincinnati_pe_v1_graph_malformed_node_uuids_total",
+        "Total number of graph requests with a malformed node UUID, ignored."
+    ))
+    .unwrap();
+    static ref INVALID_WARINESS: IntCounter = register_int_counter!(opts!(
+        "fcos_cincinnati_pe_v1_graph_invalid_rollout_wariness_total",
+        "Total number of graph requests with a malformed or out of range rollout wariness."
+    ))
+    .unwrap();
+    static ref CANARY_REQUESTS: IntCounter = register_int_counter!(opts!(
+        "fcos_cincinnati_pe_v1_graph_canary_requests_total",
+        "Total number of graph requests from canary nodes, served with zero wariness."
+    ))
+    .unwrap();
+    static ref REJECTED_NODE_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "fcos_cincinnati_pe_v1_graph_rejected_node_requests_total",
+        "Total number of graph requests rejected from denied or banned nodes.",
+        &["reason"]
+    )
+    .unwrap();
+    static ref REQUEST_TIMEOUTS: IntCounter = register_int_counter!(opts!(
+        "fcos_cincinnati_pe_v1_graph_request_timeouts_total",
+        "Total number of graph requests aborted at the request deadline."
+    ))
+    .unwrap();
+    static ref UPSTREAM_OCI_UNSUPPORTED: IntCounter = register_int_counter!(opts!(
+        "fcos_cincinnati_pe_upstream_oci_unsupported_total",
+        "Total number of OCI graph requests rejected as unsupported by upstream."
+    ))
+    .unwrap();
 }
 
 fn main() -> Fallible<()> {
@@ -55,134 +162,1449 @@ fn main() -> Fallible<()> {
         .format_timestamp_secs()
         .format_module_path(false)
         .filter(Some(APP_LOG_TARGET), cli_opts.loglevel())
+        .filter(Some(ACCESS_LOG_TARGET), log::LevelFilter::Info)
         .try_init()
         .context("failed to initialize logging")?;
 
     // Parse config file and validate settings.
-    let (service_settings, status_settings) = {
-        debug!("config file location: {}", cli_opts.config_path.display());
-        let cfg = config::FileConfig::parse_file(cli_opts.config_path)?;
-        let settings = settings::PolicyEngineSettings::validate_config(cfg)?;
-        (settings.service, settings.status)
-    };
+    debug!("config file location: {}", cli_opts.config_path.display());
+    let settings = settings::PolicyEngineSettings::load(&cli_opts.config_path)?;
+
+    if cli_opts.check_config {
+        println!("{:#?}", settings);
+        return Ok(());
+    }
+
+    if let Some(cli::CliCommand::Probe) = cli_opts.cmd {
+        return run_probe(&settings);
+    }
 
+    let help = build_help(&settings);
+    let settings::PolicyEngineSettings {
+        service: service_settings,
+        status: status_settings,
+        runtime: runtime_settings,
+        tracing: tracing_settings,
+        metrics: metrics_settings,
+        reporting: reporting_settings,
+        features,
+    } = settings;
+    debug!("feature flags: {:?}", features.to_named_map());
+    let reporter = ErrorReporter::new(crate_name!(), crate_version!(), &reporting_settings)
+        .context("failed to build error reporter")?;
+    reporter.install_panic_hook(deliver_reports);
+
+    runtime_settings.init_blocking_pool();
     let sys = actix::System::new("fcos_cincinnati_pe");
 
-    let node_population = Arc::new(cbloom::Filter::new(
-        service_settings.bloom_size,
-        service_settings.bloom_max_population,
-    ));
+    let node_population = match &service_settings.population_snapshot_path {
+        Some(path) => match population::NodePopulation::load(path) {
+            Ok(Some(population)) => {
+                info!("restored unique nodes estimates from {}", path.display());
+                population
+            }
+            Ok(None) => population::NodePopulation::default(),
+            Err(e) => {
+                warn!("starting with empty unique nodes estimates: {}", e);
+                population::NodePopulation::default()
+            }
+        },
+        None => population::NodePopulation::default(),
+    };
+    let node_population = Arc::new(node_population);
+    let collectors =
+        metrics::Collectors::register(METRICS_NAMESPACE, prometheus::default_registry())
+            .context("failed to register metrics")?;
+    let rate_limiter = RateLimiter::register(
+        &service_settings.rate_limit,
+        METRICS_NAMESPACE,
+        prometheus::default_registry(),
+    )
+    .context("failed to register rate limiting metrics")?;
+    let in_flight = InFlightLimit::register(
+        service_settings.max_in_flight,
+        METRICS_NAMESPACE,
+        prometheus::default_registry(),
+    )
+    .context("failed to register in-flight metrics")?;
+    let tracer = Tracer::new(crate_name!(), &tracing_settings).context("failed to build tracer")?;
+    let upstream_client = service_settings
+        .upstream_proxy
+        .client_builder()
+        .timeout(service_settings.upstream_req_timeout)
+        .build()
+        .context("failed to build upstream HTTP client")?;
     let service_state = AppState {
-        // TODO(lucab): get allowed scopes from config file.
-        scope_filter: None,
+        scope_filter: service_settings.scope_allowlist.clone(),
+        scope_aliases: service_settings.scope_aliases.clone(),
+        default_product: service_settings.default_product.clone(),
+        default_basearch: service_settings.default_basearch.clone(),
+        default_stream: service_settings.default_stream.clone(),
+        wariness_key: service_settings.wariness_key,
+        canary_nodes: Arc::new(service_settings.canary_nodes.clone()),
+        denied_nodes: Arc::new(service_settings.denied_nodes.clone()),
+        node_bans: service_settings.node_ban_rate.map(|rate| {
+            Arc::new(Mutex::new(nodes::NodeBans::new(
+                rate,
+                service_settings.node_ban_duration,
+                Instant::now(),
+            )))
+        }),
+        rate_limiter: Arc::new(rate_limiter),
+        in_flight,
+        access_log: AccessLog::new(
+            ACCESS_LOG_TARGET,
+            service_settings.access_log_sample_rate,
+            service_settings.rate_limit.trusted_proxies,
+        ),
+        request_timeout: service_settings.request_timeout,
+        tracer: tracer.clone(),
         population: Arc::clone(&node_population),
-        upstream_endpoint: service_settings.upstream_base.clone(),
-        upstream_req_timeout: service_settings.upstream_req_timeout,
+        upstream_replicas: Arc::new(upstream::UpstreamPool::new(
+            service_settings.upstream_endpoints(),
+        )),
+        upstream_shards: service_settings.upstream_shards.clone(),
+        upstream_client,
+        robots_txt: service_settings.robots_txt.clone(),
+        security_txt: service_settings.security_txt.clone(),
+        old_client_release_lag: service_settings.old_client_release_lag,
+        features,
+        help: Arc::new(help),
+        openapi: Arc::new(build_openapi()),
+        metrics: collectors,
+        version_heatmap: Arc::new(heatmap::VersionHeatmap::new(
+            service_settings.version_heatmap_hours,
+        )),
+        upstream_cache: Arc::new(Mutex::new(cache::GraphCache::new(
+            service_settings.upstream_cache_ttl,
+            service_settings.upstream_stale_max_age,
+        ))),
+        throttled_graphs: Arc::new(Mutex::new(throttled::ThrottledGraphs::default())),
     };
+    let upstream_endpoints: Vec<String> = service_settings
+        .upstream_endpoints()
+        .iter()
+        .map(ToString::to_string)
+        .collect();
     debug!(
-        "upstream graph endpoint: {}",
-        service_settings.upstream_base
+        "upstream graph endpoints: {}",
+        upstream_endpoints.join(", ")
     );
+    let prefetcher = match (
+        service_settings.upstream_prefetch_interval,
+        &service_settings.scope_allowlist,
+    ) {
+        (Some(interval), Some(scopes)) => {
+            let tiers: Vec<Option<u8>> =
+                if service_state.features.is_enabled(Feature::WarinessTiers) {
+                    (0..=policy::WARINESS_TIERS).map(Some).collect()
+                } else {
+                    vec![None]
+                };
+            let mut keys = vec![];
+            for scope in scopes {
+                for tier in &tiers {
+                    keys.push(graph_key(&service_state, scope, *tier));
+                }
+            }
+            info!("prefetching upstream graphs every {:?}", interval);
+            Some(GraphPrefetcher {
+                state: service_state.clone(),
+                keys,
+                interval,
+                running: false,
+            })
+        }
+        (Some(_), None) => {
+            warn!("no 'scopes' configured, upstream graphs prefetching disabled");
+            None
+        }
+        (None, _) => None,
+    };
+    let upstream_health_check = if service_settings.upstream_shards.is_none()
+        && service_settings.upstream_replicas.is_some()
+    {
+        let client = service_settings
+            .upstream_proxy
+            .client_builder()
+            .timeout(UPSTREAM_HEALTH_CHECK_INTERVAL)
+            .build()
+            .context("failed to build upstream health check client")?;
+        Some(UpstreamHealthCheck {
+            replicas: Arc::clone(&service_state.upstream_replicas),
+            client,
+        })
+    } else {
+        None
+    };
 
     let start_timestamp = chrono::Utc::now();
-    PROCESS_START_TIME.set(start_timestamp.timestamp());
+    // NOTE(lucab): alternatively this could come from the runtime library, see
+    // https://prometheus.io/docs/instrumenting/writing_clientlibs/#process-metrics
+    service_state
+        .metrics
+        .process_start_time
+        .set(start_timestamp.timestamp());
+    service_state.metrics.set_build_info(
+        &BuildInfo::new(crate_name!(), crate_version!()),
+        &runtime_settings,
+    );
+    let metrics_exporter =
+        metrics::OtlpExporter::new(crate_name!(), &metrics_settings, start_timestamp.into())
+            .context("failed to build metrics exporter")?;
     info!("starting server ({} {})", crate_name!(), crate_version!());
 
     // Policy-engine main service.
-    let service_socket = service_settings.socket_addr();
-    debug!("main service address: {}", service_socket);
-    actix_web::HttpServer::new(move || {
-        App::new()
+    let service_sockets = service_settings.socket_addrs();
+    let population_snapshot_path = service_settings.population_snapshot_path.clone();
+    let population_snapshot_interval = service_settings.population_snapshot_interval;
+    let pe_service = service_state.clone();
+    let mut service_server = actix_web::HttpServer::new(move || {
+        let routes = service_routes();
+        let paths: Vec<&'static str> = routes.iter().map(|(endpoint, _)| endpoint.path).collect();
+        let metrics = pe_service.metrics.clone();
+        let mut app = App::new()
+            .wrap(actix_web::middleware::Compress::default())
             .wrap(commons::web::build_cors_middleware(
                 &service_settings.origin_allowlist,
             ))
-            .data(service_state.clone())
-            .route("/v1/graph", web::get().to(pe_serve_graph))
+            .wrap_fn(move |req, srv| {
+                let route = commons::web::route_label(&paths, req.path());
+                let started = Instant::now();
+                let metrics = metrics.clone();
+                srv.call(req).map(move |res| {
+                    let status = match &res {
+                        Ok(res) => res.status(),
+                        Err(e) => e.as_response_error().status_code(),
+                    };
+                    metrics.record_request(route, status, started.elapsed());
+                    res
+                })
+            })
+            .data(pe_service.clone());
+        for (endpoint, route) in routes {
+            app = app.route(endpoint.path, route);
+        }
+        app
     })
-    .bind(service_socket)?
-    .run();
+    .workers(runtime_settings.workers);
+    for socket in service_sockets {
+        debug!("main service address: {}", socket);
+        service_server = service_server.listen(commons::listen::bind(socket)?)?;
+    }
+    service_server.run();
 
     // Policy-engine status service.
-    let status_socket = status_settings.socket_addr();
-    debug!("status service address: {}", status_socket);
-    actix_web::HttpServer::new(move || {
-        App::new().route("/metrics", web::get().to(metrics::serve_metrics))
-    })
-    .bind(status_socket)?
-    .run();
+    let status_sockets = status_settings.socket_addrs();
+    let status_tls = status_settings.tls;
+    let prometheus = metrics_settings.prometheus;
+    let pe_status = service_state;
+    let mut status_server = actix_web::HttpServer::new(move || {
+        let mut app = App::new().data(pe_status.clone());
+        for (endpoint, route) in status_routes(prometheus) {
+            app = app.route(endpoint.path, route);
+        }
+        app
+    });
+    for socket in status_sockets {
+        debug!("status service address: {}", socket);
+        let listener = commons::listen::bind(socket)?;
+        status_server = match &status_tls {
+            Some(tls) => status_server.listen_openssl(listener, tls.acceptor()?)?,
+            None => status_server.listen(listener)?,
+        };
+    }
+    status_server.run();
+
+    // Listeners are bound and actors started, report readiness to systemd.
+    match commons::systemd::notify("READY=1") {
+        Ok(true) => debug!("notified systemd of readiness"),
+        Ok(false) => {}
+        Err(e) => warn!("failed to notify systemd of readiness: {}", e),
+    }
+    if let Some(interval) = commons::systemd::watchdog_interval() {
+        info!("sending systemd watchdog pings every {:?}", interval);
+        SystemdWatchdog { interval }.start();
+    }
+    if let Some(health_check) = upstream_health_check {
+        health_check.start();
+    }
+    if let Some(prefetcher) = prefetcher {
+        prefetcher.start();
+    }
+    if let Some(path) = population_snapshot_path {
+        info!(
+            "snapshotting unique nodes estimates every {:?}",
+            population_snapshot_interval
+        );
+        PopulationSnapshotter {
+            population: Arc::clone(&node_population),
+            path,
+            interval: population_snapshot_interval,
+        }
+        .start();
+    }
+    PopulationReporter {
+        population: node_population,
+    }
+    .start();
+    if tracer.is_enabled() {
+        info!(
+            "exporting traces every {:?}",
+            tracing_settings.export_interval
+        );
+        TraceExporter {
+            tracer,
+            interval: tracing_settings.export_interval,
+        }
+        .start();
+    }
+    if let Some(exporter) = metrics_exporter {
+        info!(
+            "exporting metrics every {:?}",
+            metrics_settings.export_interval
+        );
+        MetricsExporter {
+            exporter,
+            interval: metrics_settings.export_interval,
+        }
+        .start();
+    }
 
     sys.run()?;
     Ok(())
 }
 
+/// Periodic systemd watchdog pings, as long as the event loop is responsive.
+struct SystemdWatchdog {
+    interval: Duration,
+}
+
+impl Actor for SystemdWatchdog {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.interval, |_actor, _ctx| {
+            if let Err(e) = commons::systemd::notify("WATCHDOG=1") {
+                warn!("failed to send systemd watchdog ping: {}", e);
+            }
+        });
+    }
+}
+
+/// Deliver queued error reports right away, from a dedicated runtime.
+///
+/// This is used while panicking, when the current runtime may be unusable.
+fn deliver_reports(reporter: &ErrorReporter) {
+    let reporter = reporter.clone();
+    let delivery = std::thread::spawn(move || {
+        let mut runner = actix::System::new("fcos_cincinnati_pe_report");
+        runner.block_on(async move { reporter.flush().await })
+    });
+    if let Ok(Err(e)) = delivery.join() {
+        error!("failed to deliver error reports: {}", e);
+    }
+}
+
+/// Periodic export of finished tracing spans.
+struct TraceExporter {
+    tracer: Tracer,
+    interval: Duration,
+}
+
+impl Actor for TraceExporter {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.interval, |actor, ctx| {
+            let tracer = actor.tracer.clone();
+            let export = async move {
+                if let Err(e) = tracer.export().await {
+                    warn!("failed to export traces: {}", e);
+                }
+            };
+            ctx.spawn(actix::fut::wrap_future::<_, Self>(export));
+        });
+    }
+}
+
+/// Periodic push of metrics to an OTLP endpoint.
+struct MetricsExporter {
+    exporter: metrics::OtlpExporter,
+    interval: Duration,
+}
+
+impl Actor for MetricsExporter {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.interval, |actor, ctx| {
+            let exporter = actor.exporter.clone();
+            let export = async move {
+                if let Err(e) = exporter.export().await {
+                    warn!("failed to export metrics: {}", e);
+                }
+            };
+            ctx.spawn(actix::fut::wrap_future::<_, Self>(export));
+        });
+    }
+}
+
+/// Periodic health checks of upstream replicas, so that failed ones are
+/// used again once they recover.
+struct UpstreamHealthCheck {
+    replicas: Arc<upstream::UpstreamPool>,
+    /// HTTP client for health checks, with a short timeout.
+    client: reqwest::Client,
+}
+
+impl Actor for UpstreamHealthCheck {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(UPSTREAM_HEALTH_CHECK_INTERVAL, |actor, ctx| {
+            let checks = actor.replicas.urls().cloned().map(|url| {
+                let client = actor.client.clone();
+                async move {
+                    let result = utils::check_upstream(&client, url.clone()).await;
+                    (url, result.is_ok())
+                }
+            });
+            let checks = futures::future::join_all(checks);
+            let update = actix::fut::wrap_future::<_, Self>(checks).map(|results, actor, _ctx| {
+                for (url, healthy) in results {
+                    actor.replicas.set_healthy(&url, healthy);
+                }
+            });
+            ctx.spawn(update);
+        });
+    }
+}
+
+/// Periodic update of unique nodes estimates, as time windows slide even
+/// without requests.
+struct PopulationReporter {
+    population: Arc<population::NodePopulation>,
+}
+
+impl PopulationReporter {
+    fn report(population: &population::NodePopulation) {
+        let now = chrono::Utc::now().timestamp();
+        for entry in population.estimates(now) {
+            match (&entry.scope, entry.window) {
+                (Some((product, basearch, stream)), Some(window)) => SCOPE_WINDOW_UNIQUE_IDS
+                    .with_label_values(&[product, basearch, stream, window])
+                    .set(entry.estimate),
+                (Some((product, basearch, stream)), None) => SCOPE_UNIQUE_IDS
+                    .with_label_values(&[product, basearch, stream])
+                    .set(entry.estimate),
+                (None, Some(window)) => WINDOW_UNIQUE_IDS
+                    .with_label_values(&[window])
+                    .set(entry.estimate),
+                (None, None) => UNIQUE_IDS.set(entry.estimate),
+            }
+        }
+    }
+}
+
+impl Actor for PopulationReporter {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        // Estimates may have been restored from a snapshot.
+        Self::report(&self.population);
+        ctx.run_interval(POPULATION_REPORT_INTERVAL, |actor, _ctx| {
+            Self::report(&actor.population);
+        });
+    }
+}
+
+/// Periodic snapshots of unique nodes estimates, restored on startup.
+struct PopulationSnapshotter {
+    population: Arc<population::NodePopulation>,
+    path: PathBuf,
+    interval: Duration,
+}
+
+impl Actor for PopulationSnapshotter {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.interval, |actor, ctx| {
+            let population = Arc::clone(&actor.population);
+            let path = actor.path.clone();
+            let save = async move {
+                if let Err(e) = web::block(move || population.save(&path)).await {
+                    warn!("failed to snapshot unique nodes estimates: {}", e);
+                }
+            };
+            ctx.spawn(actix::fut::wrap_future::<_, Self>(save));
+        });
+    }
+}
+
+/// Periodic prefetching of upstream graphs for all configured scopes, so
+/// that requests are served from cache.
+struct GraphPrefetcher {
+    state: AppState,
+    keys: Vec<cache::GraphKey>,
+    interval: Duration,
+    /// Whether a prefetch round is in progress.
+    running: bool,
+}
+
+impl GraphPrefetcher {
+    /// Fetch all graphs into cache, unless the previous round is still running.
+    fn prefetch(&mut self, ctx: &mut Context<Self>) {
+        if self.running {
+            warn!("previous upstream graphs prefetching still running, skipping");
+            return;
+        }
+        self.running = true;
+
+        let state = self.state.clone();
+        let keys = self.keys.clone();
+        let prefetch = async move {
+            for key in keys {
+                let product = Some(key.scope.product.clone());
+                match fetch_graph_with_failover(&state, &key, product, "prefetch", None).await {
+                    Ok(upstream) => {
+                        if let Ok(mut cache) = state.upstream_cache.lock() {
+                            cache.insert(key, upstream, Instant::now());
+                        }
+                    }
+                    Err(e) => warn!(
+                        "failed to prefetch graph for {}/{}/{}/oci={}: {}",
+                        key.scope.product, key.scope.basearch, key.scope.stream, key.scope.oci, e
+                    ),
+                }
+            }
+        };
+        let done = actix::fut::wrap_future::<_, Self>(prefetch).map(|_, actor, _ctx| {
+            actor.running = false;
+        });
+        ctx.spawn(done);
+    }
+}
+
+impl Actor for GraphPrefetcher {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.prefetch(ctx);
+        ctx.run_interval(self.interval, |actor, ctx| actor.prefetch(ctx));
+    }
+}
+
+/// Routes of the main service, with their documentation.
+fn service_routes() -> Vec<(Endpoint, Route)> {
+    vec![
+        (
+            Endpoint::get("/v1/graph", "Update graph for a client"),
+            web::get().to(pe_serve_graph),
+        ),
+        (
+            Endpoint::get("/v1/version", "Build information of this instance"),
+            web::get().to(pe_serve_version),
+        ),
+        (
+            Endpoint::get("/openapi.json", "OpenAPI description of this service"),
+            web::get().to(pe_serve_openapi),
+        ),
+        (
+            Endpoint::get("/v1/streams", "Graph scopes served by this deployment"),
+            web::get().to(pe_serve_streams),
+        ),
+        (
+            Endpoint::get("/robots.txt", "Crawlers policy"),
+            web::get().to(pe_serve_robots_txt),
+        ),
+        (
+            Endpoint::get("/.well-known/security.txt", "Security contact information"),
+            web::get().to(pe_serve_security_txt),
+        ),
+    ]
+}
+
+/// Routes of the status service, with their documentation.
+///
+/// The Prometheus metrics endpoint is only routed if enabled.
+fn status_routes(prometheus: bool) -> Vec<(Endpoint, Route)> {
+    let mut routes = vec![
+        (
+            Endpoint::get("/metrics", "Prometheus metrics"),
+            web::get().to(metrics::serve_metrics),
+        ),
+        (
+            Endpoint::get("/livez", "Liveness"),
+            web::get().to(pe_serve_livez),
+        ),
+        (
+            Endpoint::get("/readyz", "Readiness, i.e. whether upstream is reachable"),
+            web::get().to(pe_serve_readyz),
+        ),
+        (
+            Endpoint::get("/admin/features", "Feature flags"),
+            web::get().to(pe_serve_features),
+        ),
+        (
+            Endpoint::get("/admin/help", "Self-description of this instance"),
+            web::get().to(pe_serve_help),
+        ),
+        (
+            Endpoint::get(
+                "/admin/versions",
+                "Graph requests by client version, over the last `hours` hours",
+            ),
+            web::get().to(pe_serve_versions),
+        ),
+    ];
+    if !prometheus {
+        routes.retain(|(endpoint, _)| endpoint.path != "/metrics");
+    }
+    routes
+}
+
+/// Describe endpoints, scopes and graph processing of this instance.
+fn build_help(settings: &settings::PolicyEngineSettings) -> ServiceHelp {
+    let scopes = settings.service.scope_allowlist.as_ref().map(|allowlist| {
+        let mut scopes: Vec<graph::GraphScope> = allowlist.iter().cloned().collect();
+        scopes.sort();
+        scopes
+    });
+    let endpoints = maplit::btreemap! {
+        "service" => service_routes().into_iter().map(|(e, _)| e).collect(),
+        "status" => status_routes(settings.metrics.prometheus)
+            .into_iter()
+            .map(|(e, _)| e)
+            .collect(),
+    };
+    ServiceHelp {
+        name: crate_name!(),
+        version: crate_version!(),
+        endpoints,
+        scopes,
+        pipeline: settings.service.policy_pipeline(&settings.features),
+        features: settings.features.to_named_map(),
+    }
+}
+
+/// Describe the main service, per the OpenAPI specification.
+fn build_openapi() -> OpenApi {
+    let endpoints: Vec<Endpoint> = service_routes().into_iter().map(|(e, _)| e).collect();
+    OpenApi::new(
+        crate_name!(),
+        crate_version!(),
+        &endpoints,
+        &GraphQuery::openapi_params(),
+        &[
+            ("400", "Invalid scope or query"),
+            ("429", "Too many requests, see `Retry-After`"),
+            ("500", "Internal error"),
+            ("501", "OCI graphs unsupported by upstream"),
+            ("503", "Service unavailable, see `Retry-After`"),
+            ("504", "Request timed out"),
+        ],
+    )
+}
+
+/// Check health of a locally running policy-engine.
+fn run_probe(settings: &settings::PolicyEngineSettings) -> Fallible<()> {
+    let service_base = commons::probe::local_base_url(settings.service.socket_addr())?;
+
+    // Canary request for an allowed scope, defaulting to the most common one.
+    let canary = settings
+        .service
+        .scope_allowlist
+        .as_ref()
+        .and_then(|scopes| scopes.iter().min_by_key(|s| s.oci));
+    let (product, basearch, stream, oci) = canary
+        .map(|s| {
+            (
+                s.product.as_str(),
+                s.basearch.as_str(),
+                s.stream.as_str(),
+                s.oci,
+            )
+        })
+        .unwrap_or((
+            settings.service.default_product.as_str(),
+            "x86_64",
+            "stable",
+            false,
+        ));
+    let mut graph_url = service_base.join("v1/graph")?;
+    graph_url
+        .query_pairs_mut()
+        .append_pair("product", product)
+        .append_pair("basearch", basearch)
+        .append_pair("stream", stream)
+        .append_pair("oci", &oci.to_string())
+        .append_pair("rollout_wariness", "0");
+
+    let mut targets = vec![];
+    if settings.status.tls.is_none() {
+        let status_base = commons::probe::local_base_url(settings.status.socket_addr())?;
+        targets.push(status_base.join("livez")?);
+        targets.push(status_base.join("readyz")?);
+        targets.push(status_base.join("metrics")?);
+    } else {
+        warn!("status server requires client certificates, skipping its checks");
+    }
+    targets.push(graph_url);
+    let mut runner = actix::System::new("fcos_cincinnati_pe_probe");
+    runner.block_on(async move { commons::probe::check_endpoints(&targets).await })?;
+    info!("probe succeeded");
+    Ok(())
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct AppState {
     scope_filter: Option<HashSet<graph::GraphScope>>,
-    population: Arc<cbloom::Filter>,
-    upstream_endpoint: reqwest::Url,
-    upstream_req_timeout: Duration,
+    /// Aliases of basearch and stream values.
+    scope_aliases: ScopeAliases,
+    /// Product assumed for requests without one.
+    default_product: String,
+    /// Basearch assumed for requests without one.
+    default_basearch: Option<String>,
+    /// Stream assumed for requests without one.
+    default_stream: Option<String>,
+    /// Key for deriving rollout wariness from node IDs.
+    wariness_key: [u8; 16],
+    /// Canary nodes, with a rollout wariness pinned to zero.
+    canary_nodes: Arc<nodes::NodeSet>,
+    /// Nodes denied graph requests.
+    denied_nodes: Arc<nodes::NodeSet>,
+    /// Temporary bans of nodes polling too often, if enabled.
+    node_bans: Option<Arc<Mutex<nodes::NodeBans>>>,
+    /// Per-client rate limiting of graph requests.
+    rate_limiter: Arc<RateLimiter>,
+    /// Limit on graph requests in flight.
+    in_flight: InFlightLimit,
+    /// Sampled access logs of graph requests.
+    access_log: AccessLog,
+    /// Deadline for serving graph requests.
+    request_timeout: Option<Duration>,
+    /// Source of tracing spans.
+    tracer: Tracer,
+    /// Unique nodes, overall and per scope.
+    population: Arc<population::NodePopulation>,
+    /// Upstream graph-builder replicas, unless sharded.
+    upstream_replicas: Arc<upstream::UpstreamPool>,
+    upstream_shards: Option<Vec<reqwest::Url>>,
+    /// HTTP client for upstream requests, shared so that keep-alive
+    /// connections to the graph-builder are pooled and reused.
+    upstream_client: reqwest::Client,
+    robots_txt: String,
+    security_txt: Option<String>,
+    old_client_release_lag: Option<u64>,
+    features: FeatureFlags,
+    help: Arc<ServiceHelp>,
+    openapi: Arc<OpenApi>,
+    metrics: metrics::Collectors,
+    version_heatmap: Arc<heatmap::VersionHeatmap>,
+    /// Recently fetched upstream graphs.
+    upstream_cache: Arc<Mutex<cache::GraphCache>>,
+    /// Throttled graphs, precomputed per wariness bucket.
+    throttled_graphs: Arc<Mutex<throttled::ThrottledGraphs>>,
 }
 
 /// Mandatory parameters for querying a graph from policy-engine.
 #[derive(Serialize, Deserialize)]
 pub struct GraphQuery {
+    product: Option<String>,
     basearch: Option<String>,
     stream: Option<String>,
     rollout_wariness: Option<String>,
     node_uuid: Option<String>,
+    os_version: Option<String>,
     oci: Option<bool>,
+    /// Rollout wariness tier, only used for upstream requests.
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    wariness_tier: Option<u8>,
+    /// Pretty-print the graph, for debugging. Never forwarded upstream.
+    #[serde(skip_serializing)]
+    pretty: Option<bool>,
+}
+
+impl GraphQuery {
+    /// Query parameters accepted from clients, for the OpenAPI description.
+    fn openapi_params() -> Vec<QueryParam> {
+        // Fail to build until new fields are documented (or skipped) here.
+        let _ = |query: GraphQuery| {
+            let GraphQuery {
+                product: _,
+                basearch: _,
+                stream: _,
+                rollout_wariness: _,
+                node_uuid: _,
+                os_version: _,
+                oci: _,
+                wariness_tier: _,
+                pretty: _,
+            } = query;
+        };
+        vec![
+            QueryParam::string("product", "Product (`fedora-coreos` by default)"),
+            QueryParam::string("basearch", "Base architecture, e.g. `x86_64`"),
+            QueryParam::string("stream", "Update stream, e.g. `stable`"),
+            QueryParam::string(
+                "rollout_wariness",
+                "Rollout wariness of the node, between 0.0 (eager) and 1.0 (wary)",
+            ),
+            QueryParam::string("node_uuid", "Unique identifier of the node"),
+            QueryParam::string("os_version", "Current OS version of the node"),
+            QueryParam::boolean("oci", "Whether to serve OCI payloads"),
+            QueryParam::boolean("pretty", "Pretty-print the graph, for debugging"),
+        ]
+    }
+}
+
+/// Assume the configured default for a missing scope parameter, if any.
+fn default_scope_param(
+    value: Option<String>,
+    default: &Option<String>,
+    param: &str,
+) -> Option<String> {
+    match (value, default) {
+        (None, Some(default)) => {
+            DEFAULTED_SCOPE_REQUESTS.with_label_values(&[param]).inc();
+            Some(default.clone())
+        }
+        (value, _) => value,
+    }
+}
+
+/// Reject queries with unknown parameters, if strict queries are enabled.
+fn check_query_params(req: &HttpRequest, data: &AppState) -> Option<HttpResponse> {
+    if !data.features.is_enabled(Feature::StrictQueries) {
+        return None;
+    }
+    let params = GraphQuery::openapi_params();
+    let known: Vec<&str> = params.iter().map(|param| param.name).collect();
+    let unknown = commons::web::unknown_query_params(req.query_string(), &known);
+    if unknown.is_empty() {
+        return None;
+    }
+    log::error!(
+        "request with unknown query parameters: {}",
+        unknown.join(", ")
+    );
+    Some(commons::web::bad_request(
+        "unknown_parameter",
+        format!(
+            "unknown query parameters: {} (known: {})",
+            unknown.join(", "),
+            known.join(", ")
+        ),
+    ))
+}
+
+/// Reject requests from denied nodes, and from nodes polling too often.
+fn check_node_allowed(data: &AppState, node_uuid: &str, request_id: &str) -> Option<HttpResponse> {
+    if data.denied_nodes.contains(node_uuid) {
+        log::debug!("[{}] rejecting request from denied node", request_id);
+        REJECTED_NODE_REQUESTS.with_label_values(&["denied"]).inc();
+        return Some(commons::web::too_many_requests(
+            "node_denied",
+            "requests from this node are denied",
+            Duration::from_secs(60 * 60),
+        ));
+    }
+    let banned = data
+        .node_bans
+        .as_ref()
+        .and_then(|bans| bans.lock().ok()?.check(node_uuid, Instant::now()));
+    if let Some(remaining) = banned {
+        log::debug!("[{}] rejecting request from banned node", request_id);
+        REJECTED_NODE_REQUESTS.with_label_values(&["banned"]).inc();
+        return Some(commons::web::too_many_requests(
+            "node_banned",
+            "too many requests from this node, temporarily banned",
+            remaining,
+        ));
+    }
+    None
 }
 
 pub(crate) async fn pe_serve_graph(
+    req: HttpRequest,
     data: web::Data<AppState>,
-    web::Query(query): web::Query<GraphQuery>,
+    CanonicalQuery(query): CanonicalQuery<GraphQuery>,
+) -> Result<HttpResponse, HandlerError> {
+    let started = Instant::now();
+    let request_id = commons::web::request_id(&req);
+    let mut span = data.tracer.start_span(
+        "GET /v1/graph",
+        SpanKind::Server,
+        TraceContext::from_request(&req),
+    );
+    span.set_attribute("request_id", &request_id);
+    let request_timeout = data.request_timeout;
+    let access_log = data.access_log.clone();
+    let handled = with_timeout(
+        pe_serve_graph_for_request(&req, data, query, &request_id, span.context()),
+        request_timeout,
+    )
+    .await;
+    let result = match handled {
+        Some(result) => result.map_err(|e| HandlerError::from(e).with_request_id(&request_id)),
+        None => {
+            log::warn!("[{}] graph request timed out, aborting", request_id);
+            REQUEST_TIMEOUTS.inc();
+            Ok(commons::web::gateway_timeout(
+                "request_timeout",
+                "graph request timed out",
+            ))
+        }
+    };
+    let status = match &result {
+        Ok(resp) => resp.status(),
+        Err(e) => e.status_code(),
+    };
+    access_log.record(&req, status, started.elapsed());
+    span.set_attribute("http.status_code", status.as_u16());
+    if status.is_server_error() {
+        span.set_error();
+    }
+    let mut resp = result?;
+    commons::web::set_request_id(&mut resp, &request_id);
+    Ok(resp)
+}
+
+/// Await a future, giving up after a timeout (if any).
+async fn with_timeout<F: Future>(fut: F, timeout: Option<Duration>) -> Option<F::Output> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return Some(fut.await),
+    };
+    let deadline = Box::pin(actix::clock::delay_for(timeout));
+    match futures::future::select(Box::pin(fut), deadline).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
+
+/// Serve a graph, for the request with the given ID and trace context.
+async fn pe_serve_graph_for_request(
+    req: &HttpRequest,
+    data: web::Data<AppState>,
+    mut query: GraphQuery,
+    request_id: &str,
+    trace: TraceContext,
 ) -> Result<HttpResponse, Error> {
+    let _permit = match data.in_flight.try_acquire() {
+        Ok(permit) => permit,
+        Err(resp) => {
+            log::warn!(
+                "[{}] too many graph requests in flight, shedding",
+                request_id
+            );
+            return Ok(resp);
+        }
+    };
+    if let Some(resp) = check_query_params(req, &data) {
+        return Ok(resp);
+    }
+    // Garbage IDs would skew unique nodes metrics and rollout wariness.
+    if let Some(uuid) = &query.node_uuid {
+        if !commons::web::is_node_uuid(uuid) {
+            log::debug!("[{}] ignoring malformed node UUID", request_id);
+            MALFORMED_NODE_UUIDS.inc();
+            query.node_uuid = None;
+        }
+    }
+    if let Some(uuid) = &query.node_uuid {
+        if let Some(resp) = check_node_allowed(&data, uuid, request_id) {
+            return Ok(resp);
+        }
+    }
+    if let Some(resp) = data.rate_limiter.check(req, query.node_uuid.as_deref()) {
+        log::debug!("[{}] rate limiting graph request", request_id);
+        return Ok(resp);
+    }
     pe_record_metrics(&data, &query);
 
     let scope = match commons::web::validate_scope(
-        query.basearch.clone(),
-        query.stream.clone(),
+        query.product.clone(),
+        &data.default_product,
+        default_scope_param(query.basearch.clone(), &data.default_basearch, "basearch"),
+        default_scope_param(query.stream.clone(), &data.default_stream, "stream"),
+        &data.scope_aliases,
         query.oci,
         &data.scope_filter,
     ) {
         Err(e) => {
-            log::error!("graph request with invalid scope: {}", e);
-            return Ok(HttpResponse::BadRequest().finish());
+            log::error!("[{}] graph request with invalid scope: {}", request_id, e);
+            return Ok(commons::web::bad_request(e.kind, e));
         }
         Ok(s) => {
-            log::trace!("graph query stream: {:#?}", s);
+            log::trace!("[{}] graph query stream: {:#?}", request_id, s);
             s
         }
     };
 
-    let wariness = compute_wariness(&query);
+    if scope.oci && !data.features.is_enabled(Feature::OciGraphs) {
+        log::error!(
+            "[{}] graph request for OCI scope, but OCI graphs are disabled",
+            request_id
+        );
+        return Ok(commons::web::bad_request(
+            "oci_disabled",
+            "OCI graphs are disabled",
+        ));
+    }
+
+    if let Some(input) = &query.rollout_wariness {
+        if !is_valid_wariness(input) {
+            INVALID_WARINESS.inc();
+            if data.features.is_enabled(Feature::StrictWariness) {
+                log::error!(
+                    "[{}] graph request with invalid rollout wariness: {}",
+                    request_id,
+                    input
+                );
+                return Ok(commons::web::bad_request(
+                    "invalid_rollout_wariness",
+                    format!(
+                        "invalid rollout_wariness '{}': must be a number between 0.0 and 1.0",
+                        input
+                    ),
+                ));
+            }
+        }
+    }
+
+    let canary = matches!(&query.node_uuid, Some(uuid) if data.canary_nodes.contains(uuid));
+    let wariness = if canary {
+        CANARY_REQUESTS.inc();
+        0.0
+    } else {
+        compute_wariness(&query, &data.wariness_key)
+    };
     ROLLOUT_WARINESS.observe(wariness);
+    // Node-derived wariness salted per release, so that early adopters rotate.
+    let salted_uuid = query.node_uuid.as_deref().filter(|_| {
+        data.features.is_enabled(Feature::SaltedWariness)
+            && !has_explicit_wariness(&query)
+            && !canary
+    });
+
+    // Serve a pre-built graph variant, unless an exact wariness was requested.
+    let wariness_tier = if data.features.is_enabled(Feature::WarinessTiers)
+        && !has_explicit_wariness(&query)
+        && salted_uuid.is_none()
+    {
+        Some(policy::wariness_tier(wariness))
+    } else {
+        None
+    };
+    // Otherwise, serve a precomputed throttled graph for the wariness bucket.
+    let wariness_bucket = if wariness_tier.is_none()
+        && data.features.is_enabled(Feature::WarinessBuckets)
+        && !has_explicit_wariness(&query)
+        && salted_uuid.is_none()
+    {
+        Some(policy::wariness_bucket(wariness))
+    } else {
+        None
+    };
 
-    let cached_graph = utils::fetch_graph_from_gb(
-        data.upstream_endpoint.clone(),
-        scope.stream,
-        scope.basearch,
-        scope.oci,
-        data.upstream_req_timeout,
+    let cache_key = graph_key(&data, &scope, wariness_tier);
+    let cached = data
+        .upstream_cache
+        .lock()
+        .ok()
+        .and_then(|cache| cache.get(&cache_key, Instant::now()));
+    // Age of a last-known-good graph, served during an upstream outage.
+    let mut stale_age = None;
+    let graph_type = if scope.oci { "oci" } else { "checksum" };
+    let cache_lookups = |result| {
+        data.metrics
+            .cache_lookups
+            .with_label_values(&[
+                &scope.product,
+                &scope.basearch,
+                &scope.stream,
+                graph_type,
+                result,
+            ])
+            .inc()
+    };
+    let upstream = match cached {
+        Some(upstream) => {
+            cache_lookups("hit");
+            upstream
+        }
+        None => {
+            cache_lookups("miss");
+            let fetched = fetch_graph_with_failover(
+                &data,
+                &cache_key,
+                query.product.clone(),
+                request_id,
+                Some(trace),
+            )
+            .await;
+            match fetched {
+                Ok(upstream) => {
+                    if let Ok(mut cache) = data.upstream_cache.lock() {
+                        cache.insert(cache_key.clone(), upstream.clone(), Instant::now());
+                    }
+                    upstream
+                }
+                Err(e) if upstream::is_replica_failure(&e) => {
+                    let stale = data
+                        .upstream_cache
+                        .lock()
+                        .ok()
+                        .and_then(|cache| cache.get_stale(&cache_key, Instant::now()));
+                    let (upstream, age) = stale.ok_or(e)?;
+                    log::warn!(
+                        "[{}] upstream unreachable, serving last-known-good graph ({}s old)",
+                        request_id,
+                        age.as_secs()
+                    );
+                    UPSTREAM_STALE_GRAPHS.inc();
+                    stale_age = Some(age);
+                    upstream
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    };
+
+    // Older graph-builders silently serve checksum graphs for OCI scopes.
+    if scope.oci && upstream.oci != Some(true) {
+        log::error!(
+            "[{}] graph request for OCI scope, but upstream does not support OCI graphs",
+            request_id
+        );
+        UPSTREAM_OCI_UNSUPPORTED.inc();
+        return Ok(commons::web::not_implemented(
+            "oci_unsupported",
+            "OCI graphs unsupported by upstream",
+        ));
+    }
+    pe_record_scope_population(&data, &scope, query.node_uuid.as_deref());
+
+    let old_client = match (data.old_client_release_lag, &query.os_version) {
+        (Some(lag), Some(version)) => Some((lag, version)),
+        _ => None,
+    };
+    // Payload variants are resolved per node, so graphs must be rewritten.
+    let payload_variants = data.features.is_enabled(Feature::PayloadVariants);
+    let pretty = query.pretty.unwrap_or(false);
+    let format = GraphFormat::negotiate(req);
+    // Graphs without anything for policies to act upon are left untouched:
+    // pass them through.
+    let passthrough = old_client.is_none()
+        && !pretty
+        && format != GraphFormat::Protobuf
+        && !policy::may_rewrite(&upstream.body);
+    let body = if passthrough {
+        GRAPH_PASSTHROUGH.inc();
+        upstream.body
+    } else {
+        let mut graph = match wariness_bucket {
+            Some(bucket) => bucket_graph(&data, &cache_key, &upstream, bucket)?,
+            None => upstream.parse()?,
+        };
+        if let Some(uuid) = salted_uuid {
+            graph = policy::throttle_rollouts_per_release(graph, |version| {
+                derived_wariness(uuid, Some(version), &data.wariness_key)
+            });
+        } else if wariness_tier.is_none() && wariness_bucket.is_none() {
+            graph = policy::throttle_rollouts(graph, wariness);
+        }
+        if let Some((lag, version)) = old_client {
+            graph = policy::prune_for_old_client(graph, version, lag);
+        }
+        let node_uuid = query
+            .node_uuid
+            .as_deref()
+            .filter(|uuid| payload_variants && !uuid.is_empty());
+        graph = policy::resolve_payload_variants(graph, node_uuid);
+        let final_graph = policy::filter_deadends(graph);
+        match format {
+            GraphFormat::Protobuf => commons::protobuf::encode_graph(&final_graph).into(),
+            _ => utils::serialize_graph(&final_graph, pretty)?,
+        }
+    };
+    let etag = commons::web::entity_tag(&body);
+    if commons::web::if_none_match(req, &etag) {
+        return Ok(commons::web::not_modified(&etag));
+    }
+    data.metrics.graph_response_size.observe(body.len() as f64);
+
+    let mut resp = HttpResponse::Ok();
+    resp.content_type(format.content_type());
+    resp.header(ETAG, etag);
+    resp.header(VARY, "Accept");
+    if let Some(source) = upstream.source {
+        resp.header(commons::web::GRAPH_SOURCE_HEADER, source);
+    }
+    if let Some(last_modified) = upstream.last_modified {
+        resp.header(LAST_MODIFIED, last_modified);
+    }
+    if let Some(version) = upstream.version {
+        resp.header(commons::web::GRAPH_VERSION_HEADER, version);
+    }
+    if let Some(age) = stale_age {
+        resp.header(commons::web::GRAPH_STALE_HEADER, age.as_secs().to_string());
+    }
+    Ok(resp.body(body))
+}
+
+/// Cache key of the upstream graph for a scope, identifying its upstream
+/// shard or replicas.
+fn graph_key(data: &AppState, scope: &graph::GraphScope, tier: Option<u8>) -> cache::GraphKey {
+    let upstream = match &data.upstream_shards {
+        Some(shards) => {
+            let index = shard::shard_for(
+                &scope.product,
+                &scope.stream,
+                &scope.basearch,
+                shards.len() as u32,
+            );
+            shards[index as usize].clone()
+        }
+        None => data.upstream_replicas.primary().clone(),
+    };
+    cache::GraphKey {
+        upstream,
+        scope: scope.clone(),
+        tier,
+    }
+}
+
+/// Fetch a graph from the first upstream endpoint able to serve it.
+///
+/// Replicas failing with transport or server errors are marked unhealthy,
+/// and the next one is tried. Each attempt is traced as a child span of
+/// the given trace context, if any.
+async fn fetch_graph_with_failover(
+    data: &AppState,
+    key: &cache::GraphKey,
+    product: Option<String>,
+    request_id: &str,
+    trace: Option<TraceContext>,
+) -> Fallible<utils::UpstreamGraph> {
+    let endpoints = match data.upstream_shards {
+        Some(_) => vec![key.upstream.clone()],
+        None => data.upstream_replicas.candidates(),
+    };
+    let mut last_error = None;
+    for endpoint in endpoints {
+        let mut span = data
+            .tracer
+            .start_span("GET upstream graph", SpanKind::Client, trace);
+        span.set_attribute("http.url", &endpoint);
+        let result = utils::fetch_graph_from_gb(
+            endpoint.clone(),
+            product.clone(),
+            key.scope.stream.clone(),
+            key.scope.basearch.clone(),
+            key.scope.oci,
+            key.tier,
+            &data.upstream_client,
+            request_id,
+            &span.context().to_header(),
+        )
+        .await;
+        if result.is_err() {
+            span.set_error();
+        }
+        let outcome = if result.is_ok() { "success" } else { "failure" };
+        data.metrics
+            .upstream_requests
+            .with_label_values(&[&key.scope.product, &key.scope.stream, outcome])
+            .inc();
+        match result {
+            Err(e) if upstream::is_replica_failure(&e) => {
+                log::warn!(
+                    "[{}] upstream request to '{}' failed: {}",
+                    request_id,
+                    endpoint,
+                    e
+                );
+                UPSTREAM_REPLICA_FAILURES.inc();
+                data.upstream_replicas.set_healthy(&endpoint, false);
+                last_error = Some(e);
+            }
+            result => return result,
+        }
+    }
+    Err(last_error.unwrap_or_else(|| failure::err_msg("no upstream endpoints")))
+}
+
+/// Throttled graph for a wariness bucket, precomputing all buckets if needed.
+fn bucket_graph(
+    data: &AppState,
+    key: &cache::GraphKey,
+    upstream: &utils::UpstreamGraph,
+    bucket: u8,
+) -> Fallible<graph::Graph> {
+    let now = chrono::Utc::now().timestamp();
+    let precomputed = data
+        .throttled_graphs
+        .lock()
+        .ok()
+        .and_then(|graphs| graphs.get(key, upstream, bucket, now));
+    if let Some(graph) = precomputed {
+        return Ok(graph::Graph::clone(&graph));
+    }
+
+    let buckets = policy::throttle_rollouts_buckets(&upstream.parse()?);
+    let graph = graph::Graph::clone(&buckets[usize::from(bucket)]);
+    if let Ok(mut graphs) = data.throttled_graphs.lock() {
+        graphs.insert(key.clone(), upstream.clone(), buckets, now);
+    }
+    Ok(graph)
+}
+
+pub(crate) async fn pe_serve_openapi(data: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(data.openapi.as_ref())
+}
+
+pub(crate) async fn pe_serve_version() -> HttpResponse {
+    HttpResponse::Ok().json(BuildInfo::new(crate_name!(), crate_version!()))
+}
+
+/// List the graph scopes served by this deployment, i.e. the configured
+/// scopes, or else all scopes served upstream.
+pub(crate) async fn pe_serve_streams(
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, HandlerError> {
+    let scopes = match &data.help.scopes {
+        Some(scopes) => scopes.clone(),
+        None => upstream_scopes(&data).await?,
+    };
+    Ok(HttpResponse::Ok().json(commons::web::StreamsIndex { scopes }))
+}
+
+/// Fetch the graph scopes served upstream, from all shards or any replica.
+async fn upstream_scopes(data: &AppState) -> Fallible<Vec<graph::GraphScope>> {
+    let mut scopes = BTreeSet::new();
+    if let Some(shards) = &data.upstream_shards {
+        for upstream in shards {
+            let index = utils::fetch_streams_from_gb(&data.upstream_client, upstream).await?;
+            scopes.extend(index.scopes);
+        }
+        return Ok(scopes.into_iter().collect());
+    }
+
+    let mut last_error = None;
+    for upstream in data.upstream_replicas.candidates() {
+        match utils::fetch_streams_from_gb(&data.upstream_client, &upstream).await {
+            Ok(index) => {
+                scopes.extend(index.scopes);
+                return Ok(scopes.into_iter().collect());
+            }
+            Err(e) => {
+                log::warn!("failed to list scopes from upstream '{}': {}", upstream, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| failure::err_msg("no upstream endpoints")))
+}
+
+pub(crate) async fn pe_serve_robots_txt(data: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(data.robots_txt.clone())
+}
+
+pub(crate) async fn pe_serve_security_txt(data: web::Data<AppState>) -> HttpResponse {
+    match &data.security_txt {
+        Some(content) => HttpResponse::Ok()
+            .content_type("text/plain; charset=utf-8")
+            .body(content.clone()),
+        None => commons::web::not_found("not_found", "no security.txt configured"),
+    }
+}
+
+/// Report liveness, i.e. whether the service is up and serving requests.
+pub(crate) async fn pe_serve_livez() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+/// Report readiness, i.e. whether all upstream shards, or any upstream
+/// replica, are reachable.
+pub(crate) async fn pe_serve_readyz(data: web::Data<AppState>) -> HttpResponse {
+    if let Some(shards) = &data.upstream_shards {
+        for upstream in shards {
+            if let Err(e) = utils::check_upstream(&data.upstream_client, upstream.clone()).await {
+                log::warn!("upstream '{}' unreachable: {}", upstream, e);
+                return upstream_unreachable();
+            }
+        }
+        return HttpResponse::Ok().finish();
+    }
+
+    for upstream in data.upstream_replicas.candidates() {
+        match utils::check_upstream(&data.upstream_client, upstream.clone()).await {
+            Ok(()) => return HttpResponse::Ok().finish(),
+            Err(e) => log::warn!("upstream '{}' unreachable: {}", upstream, e),
+        }
+    }
+    upstream_unreachable()
+}
+
+/// Build a `503 Service Unavailable` response for unreachable upstreams.
+fn upstream_unreachable() -> HttpResponse {
+    commons::web::problem(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "upstream_unreachable",
+        "upstream graph-builder unreachable",
     )
-    .await?;
+}
 
-    let throttled_graph = policy::throttle_rollouts(cached_graph, wariness);
-    let final_graph = policy::filter_deadends(throttled_graph);
+pub(crate) async fn pe_serve_features(data: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(data.features.to_named_map())
+}
 
-    let json =
-        serde_json::to_string_pretty(&final_graph).map_err(|e| failure::format_err!("{}", e))?;
-    let resp = HttpResponse::Ok()
-        .content_type("application/json")
-        .body(json);
-    Ok(resp)
+pub(crate) async fn pe_serve_help(data: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(data.help.as_ref())
 }
 
-#[allow(clippy::let_and_return)]
-fn compute_wariness(params: &GraphQuery) -> f64 {
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
+/// Parameters for the requests by client version summary.
+#[derive(Deserialize)]
+struct VersionsQuery {
+    /// Summary window, in hours (whole tracked window if unset).
+    hours: Option<usize>,
+}
 
+/// Serve a summary of graph requests by client version.
+pub(crate) async fn pe_serve_versions(
+    data: web::Data<AppState>,
+    web::Query(query): web::Query<VersionsQuery>,
+) -> HttpResponse {
+    let now = chrono::Utc::now().timestamp();
+    let heatmap = &data.version_heatmap;
+    let summary = heatmap.summary(query.hours.unwrap_or_else(|| heatmap.hours()), now);
+    HttpResponse::Ok().json(summary)
+}
+
+/// Whether the client explicitly requested a rollout wariness.
+fn has_explicit_wariness(params: &GraphQuery) -> bool {
+    matches!(
+        params.rollout_wariness.as_ref().map(|w| w.parse::<f64>()),
+        Some(Ok(_))
+    )
+}
+
+/// Whether a requested rollout wariness is a number within `[0.0, 1.0]`.
+fn is_valid_wariness(input: &str) -> bool {
+    matches!(input.parse::<f64>(), Ok(wariness) if (0.0..=1.0).contains(&wariness))
+}
+
+/// Rollout wariness of a client, as requested or derived from its node ID.
+///
+/// Derived wariness is keyed, so that it is stable across replicas sharing
+/// the same key, and across builds.
+fn compute_wariness(params: &GraphQuery, key: &[u8; 16]) -> f64 {
     if let Ok(input) = params
         .rollout_wariness
         .as_ref()
@@ -194,18 +1616,24 @@ fn compute_wariness(params: &GraphQuery) -> f64 {
         return wariness;
     }
 
-    let uuid = params
-        .node_uuid
-        .as_ref()
-        .map(String::from)
-        .unwrap_or_default();
+    let uuid = params.node_uuid.as_deref().unwrap_or_default();
+    derived_wariness(uuid, None, key)
+}
+
+/// Rollout wariness derived from a node ID, optionally salted (e.g. with a
+/// release version).
+#[allow(clippy::let_and_return)]
+fn derived_wariness(uuid: &str, salt: Option<&str>, key: &[u8; 16]) -> f64 {
+    let mut input = uuid.as_bytes().to_vec();
+    if let Some(salt) = salt {
+        input.push(0);
+        input.extend_from_slice(salt.as_bytes());
+    }
     let wariness = {
         // Left limit not included in range.
         const COMPUTED_MIN: f64 = 0.0 + 0.000_001;
         const COMPUTED_MAX: f64 = 1.0;
-        let mut hasher = DefaultHasher::new();
-        uuid.hash(&mut hasher);
-        let digest = hasher.finish();
+        let digest = commons::siphash::siphash24(key, &input);
         // Scale down.
         let scaled = (digest as f64) / (u64::MAX as f64);
         // Clamp within limits.
@@ -215,19 +1643,36 @@ fn compute_wariness(params: &GraphQuery) -> f64 {
     wariness
 }
 
+/// Count unique nodes per scope.
+///
+/// Scopes come from clients, so this is only done once upstream served a
+/// graph for the scope, bounding labels to existing scopes.
+fn pe_record_scope_population(data: &AppState, scope: &graph::GraphScope, node_uuid: Option<&str>) {
+    let uuid = match node_uuid {
+        Some(uuid) => uuid,
+        None => return,
+    };
+    let now = chrono::Utc::now().timestamp();
+    if let Some(estimate) =
+        data.population
+            .insert_scoped(&scope.product, &scope.basearch, &scope.stream, uuid, now)
+    {
+        SCOPE_UNIQUE_IDS
+            .with_label_values(&[&scope.product, &scope.basearch, &scope.stream])
+            .set(estimate);
+    }
+}
+
 pub(crate) fn pe_record_metrics(data: &AppState, query: &GraphQuery) {
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
+    data.metrics.graph_requests.inc();
 
-    V1_GRAPH_INCOMING_REQS.inc();
+    let now = chrono::Utc::now().timestamp();
+    data.version_heatmap
+        .record(query.os_version.as_deref(), now);
 
     if let Some(uuid) = &query.node_uuid {
-        let mut hasher = DefaultHasher::default();
-        uuid.hash(&mut hasher);
-        let client_uuid = hasher.finish();
-        if !data.population.maybe_contains(client_uuid) {
-            data.population.insert(client_uuid);
-            UNIQUE_IDS.inc();
+        if let Some(estimate) = data.population.insert(uuid, now) {
+            UNIQUE_IDS.set(estimate);
         }
     }
 }
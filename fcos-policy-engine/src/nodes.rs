@@ -0,0 +1,143 @@
+//! Tracking of node IDs, for canary fleets and abusive clients.
+
+use failure::{bail, Fallible};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// Window for counting requests per node.
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+/// Maximum distinct nodes counted per window, as client input is untrusted.
+const MAX_NODES_PER_WINDOW: usize = 500_000;
+
+/// Set of node IDs, matched case-insensitively, either exactly or by prefix.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct NodeSet {
+    exact: HashSet<String>,
+    prefixes: Vec<String>,
+}
+
+impl NodeSet {
+    /// Build a set from config entries, where entries ending with `*` are
+    /// prefixes.
+    pub(crate) fn parse(entries: &[String]) -> Fallible<Self> {
+        let mut set = Self::default();
+        for entry in entries {
+            let entry = entry.trim().to_ascii_lowercase();
+            match entry.strip_suffix('*') {
+                Some("") => bail!("prefix entry matching all nodes"),
+                Some(prefix) => set.prefixes.push(prefix.to_string()),
+                None if entry.is_empty() => bail!("empty entry"),
+                None => {
+                    set.exact.insert(entry);
+                }
+            }
+        }
+        Ok(set)
+    }
+
+    /// Whether a node ID belongs to this set.
+    pub(crate) fn contains(&self, node_uuid: &str) -> bool {
+        if self.exact.is_empty() && self.prefixes.is_empty() {
+            return false;
+        }
+        let node_uuid = node_uuid.to_ascii_lowercase();
+        self.exact.contains(&node_uuid)
+            || self
+                .prefixes
+                .iter()
+                .any(|prefix| node_uuid.starts_with(prefix.as_str()))
+    }
+}
+
+/// Per-node request counters, temporarily banning nodes which poll too often.
+#[derive(Debug)]
+pub(crate) struct NodeBans {
+    /// Maximum requests per node, within a window.
+    max_requests: u32,
+    ban_duration: Duration,
+    /// Start of the current window.
+    window_start: Instant,
+    /// Requests per node, within the current window.
+    requests: HashMap<String, u32>,
+    /// Expiry of bans, by node.
+    banned: HashMap<String, Instant>,
+}
+
+impl NodeBans {
+    pub(crate) fn new(max_requests: u32, ban_duration: Duration, now: Instant) -> Self {
+        Self {
+            max_requests,
+            ban_duration,
+            window_start: now,
+            requests: HashMap::new(),
+            banned: HashMap::new(),
+        }
+    }
+
+    /// Record a request from a node, returning the remaining ban time if the
+    /// node is banned.
+    pub(crate) fn check(&mut self, node_uuid: &str, now: Instant) -> Option<Duration> {
+        if now.saturating_duration_since(self.window_start) >= RATE_WINDOW {
+            self.window_start = now;
+            self.requests.clear();
+            self.banned.retain(|_, expiry| *expiry > now);
+        }
+
+        let node_uuid = node_uuid.to_ascii_lowercase();
+        if let Some(expiry) = self.banned.get(&node_uuid) {
+            if *expiry > now {
+                return Some(expiry.saturating_duration_since(now));
+            }
+        }
+        if !self.requests.contains_key(&node_uuid) && self.requests.len() >= MAX_NODES_PER_WINDOW {
+            return None;
+        }
+        let count = self.requests.entry(node_uuid.clone()).or_insert(0);
+        *count += 1;
+        if *count <= self.max_requests {
+            return None;
+        }
+        self.requests.remove(&node_uuid);
+        self.banned.insert(node_uuid, now + self.ban_duration);
+        Some(self.ban_duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_set() {
+        let entries = vec![
+            "B5A4D2B8C5E54E3D9B4BD3FA0A8E2F4C".to_string(),
+            "0000*".to_string(),
+        ];
+        let set = NodeSet::parse(&entries).unwrap();
+        assert!(set.contains("b5a4d2b8c5e54e3d9b4bd3fa0a8e2f4c"));
+        assert!(set.contains("0000d2b8c5e54e3d9b4bd3fa0a8e2f4c"));
+        assert!(!set.contains("1111d2b8c5e54e3d9b4bd3fa0a8e2f4c"));
+        assert!(!NodeSet::default().contains("b5a4d2b8c5e54e3d9b4bd3fa0a8e2f4c"));
+
+        NodeSet::parse(&["*".to_string()]).unwrap_err();
+        NodeSet::parse(&[" ".to_string()]).unwrap_err();
+    }
+
+    #[test]
+    fn test_node_bans() {
+        let start = Instant::now();
+        let ban = Duration::from_secs(600);
+        let mut bans = NodeBans::new(2, ban, start);
+        let node = "b5a4d2b8c5e54e3d9b4bd3fa0a8e2f4c";
+        assert_eq!(bans.check(node, start), None);
+        assert_eq!(bans.check(node, start), None);
+        assert_eq!(bans.check("00000000000000000000000000000000", start), None);
+        assert_eq!(bans.check(&node.to_uppercase(), start), Some(ban));
+
+        // Bans outlive windows, until they expire.
+        let later = start + Duration::from_secs(300);
+        assert_eq!(bans.check(node, later), Some(Duration::from_secs(300)));
+        let after = start + ban;
+        assert_eq!(bans.check(node, after), None);
+    }
+}
@@ -0,0 +1,479 @@
+//! Tracking of the node population, i.e. unique node IDs seen.
+
+use failure::{bail, format_err, Fallible, ResultExt};
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Bits of hashes indexing sketch registers.
+///
+/// 2^14 registers take 16 KiB per sketch, for a standard error of 0.8%.
+const PRECISION: u32 = 14;
+/// Number of registers per sketch.
+const REGISTERS: usize = 1 << PRECISION;
+/// Hourly sketches retained, covering the longest window.
+const RETAINED_HOURS: i64 = 24;
+
+/// Windows over which unique nodes are estimated, with their length in hours.
+///
+/// Windows end with the current hour, so that the `1h` window covers nodes
+/// seen since the start of the current hour.
+pub(crate) const WINDOWS: [(&str, i64); 2] = [("1h", 1), ("24h", RETAINED_HOURS)];
+
+/// Header of snapshot files, including the format version.
+const SNAPSHOT_MAGIC: &[u8; 8] = b"FCOSPOP2";
+
+/// Key for hashing node UUIDs, which must not change for snapshots to remain
+/// valid.
+const NODE_HASH_KEY: [u8; 16] = *b"fcos-population0";
+
+/// HyperLogLog sketch, estimating the number of distinct hashes inserted.
+#[derive(Clone, Debug)]
+pub(crate) struct HyperLogLog {
+    /// Maximum rank seen, per register.
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self {
+            registers: vec![0; REGISTERS],
+        }
+    }
+}
+
+impl HyperLogLog {
+    /// Insert a hash, returning whether the estimate may have changed.
+    pub(crate) fn insert(&mut self, hash: u64) -> bool {
+        let index = (hash >> (64 - PRECISION)) as usize;
+        // Guard bit, bounding the rank of remaining bits.
+        let rest = (hash << PRECISION) | (1 << (PRECISION - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        if rank <= self.registers[index] {
+            return false;
+        }
+        self.registers[index] = rank;
+        true
+    }
+
+    /// Merge another sketch into this one, estimating their union.
+    pub(crate) fn merge(&mut self, other: &Self) {
+        for (rank, other) in self.registers.iter_mut().zip(&other.registers) {
+            *rank = (*rank).max(*other);
+        }
+    }
+
+    /// Estimated number of distinct hashes inserted.
+    pub(crate) fn estimate(&self) -> f64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|rank| (-f64::from(*rank)).exp2())
+            .sum();
+        let estimate = alpha * m * m / sum;
+        // Linear counting is more accurate for small cardinalities.
+        let zeros = self.registers.iter().filter(|rank| **rank == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            estimate
+        }
+    }
+}
+
+/// Sketches of nodes since process start and per hour.
+#[derive(Clone, Debug, Default)]
+struct Sketches {
+    since_start: HyperLogLog,
+    /// Hourly sketches, oldest first, by hours since the epoch.
+    hourly: VecDeque<(i64, HyperLogLog)>,
+}
+
+impl Sketches {
+    /// Insert a hash at a given hour, returning whether the estimate since
+    /// process start may have changed.
+    fn insert(&mut self, hash: u64, hour: i64) -> bool {
+        match self.hourly.back_mut() {
+            Some((last, sketch)) if *last >= hour => {
+                sketch.insert(hash);
+            }
+            _ => {
+                let mut sketch = HyperLogLog::default();
+                sketch.insert(hash);
+                self.hourly.push_back((hour, sketch));
+                while let Some((first, _)) = self.hourly.front() {
+                    if *first > hour - RETAINED_HOURS {
+                        break;
+                    }
+                    self.hourly.pop_front();
+                }
+            }
+        }
+        self.since_start.insert(hash)
+    }
+
+    /// Estimate unique hashes over the last `hours`, up to a given hour.
+    fn window_estimate(&self, hours: i64, hour: i64) -> f64 {
+        let mut window = HyperLogLog::default();
+        for (_, sketch) in self
+            .hourly
+            .iter()
+            .filter(|(start, _)| *start > hour - hours && *start <= hour)
+        {
+            window.merge(sketch);
+        }
+        window.estimate()
+    }
+}
+
+/// Estimate of unique nodes.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct PopulationEstimate {
+    /// Product, basearch and stream, unless overall.
+    pub(crate) scope: Option<(String, String, String)>,
+    /// Time window, unless since process start (or since the first restored
+    /// snapshot).
+    pub(crate) window: Option<&'static str>,
+    pub(crate) estimate: f64,
+}
+
+/// Estimates of unique nodes, overall and per scope.
+#[derive(Debug, Default)]
+pub(crate) struct NodePopulation {
+    total: Mutex<Sketches>,
+    /// Sketches by product, basearch and stream.
+    scopes: Mutex<HashMap<(String, String, String), Sketches>>,
+}
+
+impl NodePopulation {
+    /// Record a node at a given UTC timestamp, returning the updated overall
+    /// estimate since process start if it changed.
+    pub(crate) fn insert(&self, node_uuid: &str, now: i64) -> Option<f64> {
+        let mut total = self.total.lock().ok()?;
+        if total.insert(node_hash(node_uuid), hour_of(now)) {
+            Some(total.since_start.estimate())
+        } else {
+            None
+        }
+    }
+
+    /// Record a node for a scope at a given UTC timestamp, returning the
+    /// updated scope estimate since process start if it changed.
+    pub(crate) fn insert_scoped(
+        &self,
+        product: &str,
+        basearch: &str,
+        stream: &str,
+        node_uuid: &str,
+        now: i64,
+    ) -> Option<f64> {
+        let mut scopes = self.scopes.lock().ok()?;
+        let sketches = scopes
+            .entry((
+                product.to_string(),
+                basearch.to_string(),
+                stream.to_string(),
+            ))
+            .or_default();
+        if sketches.insert(node_hash(node_uuid), hour_of(now)) {
+            Some(sketches.since_start.estimate())
+        } else {
+            None
+        }
+    }
+
+    /// Estimate unique nodes since process start and over all `WINDOWS`,
+    /// ending at a given UTC timestamp.
+    pub(crate) fn estimates(&self, now: i64) -> Vec<PopulationEstimate> {
+        let hour = hour_of(now);
+        let mut estimates = vec![];
+        let mut add = |scope: Option<(String, String, String)>, sketches: &Sketches| {
+            estimates.push(PopulationEstimate {
+                scope: scope.clone(),
+                window: None,
+                estimate: sketches.since_start.estimate(),
+            });
+            for (window, hours) in WINDOWS.iter() {
+                estimates.push(PopulationEstimate {
+                    scope: scope.clone(),
+                    window: Some(window),
+                    estimate: sketches.window_estimate(*hours, hour),
+                });
+            }
+        };
+        if let Ok(total) = self.total.lock() {
+            add(None, &total);
+        }
+        if let Ok(scopes) = self.scopes.lock() {
+            for (scope, sketches) in scopes.iter() {
+                add(Some(scope.clone()), sketches);
+            }
+        }
+        estimates
+    }
+
+    /// Serialize all sketches, as a snapshot.
+    pub(crate) fn snapshot(&self) -> Fallible<Vec<u8>> {
+        let poisoned = || format_err!("poisoned population lock");
+        let mut buf = SNAPSHOT_MAGIC.to_vec();
+        encode_sketches(&mut buf, &*self.total.lock().map_err(|_| poisoned())?);
+        let scopes = self.scopes.lock().map_err(|_| poisoned())?;
+        buf.extend_from_slice(&(scopes.len() as u32).to_le_bytes());
+        for ((product, basearch, stream), sketches) in scopes.iter() {
+            encode_str(&mut buf, product);
+            encode_str(&mut buf, basearch);
+            encode_str(&mut buf, stream);
+            encode_sketches(&mut buf, sketches);
+        }
+        Ok(buf)
+    }
+
+    /// Restore sketches from a snapshot.
+    pub(crate) fn from_snapshot(data: &[u8]) -> Fallible<Self> {
+        let mut reader = SnapshotReader { data };
+        if reader.take(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+            bail!("unknown snapshot format");
+        }
+        let total = reader.sketches()?;
+        let mut scopes = HashMap::new();
+        for _ in 0..reader.u32()? {
+            let product = reader.string()?;
+            let basearch = reader.string()?;
+            let stream = reader.string()?;
+            scopes.insert((product, basearch, stream), reader.sketches()?);
+        }
+        if !reader.data.is_empty() {
+            bail!("trailing data in snapshot");
+        }
+        let population = Self {
+            total: Mutex::new(total),
+            scopes: Mutex::new(scopes),
+        };
+        Ok(population)
+    }
+
+    /// Write a snapshot to a file, replacing it atomically.
+    pub(crate) fn save(&self, path: &Path) -> Fallible<()> {
+        let snapshot = self.snapshot()?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, snapshot)
+            .with_context(|_| format!("failed to write '{}'", Path::new(&tmp).display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|_| format!("failed to replace '{}'", path.display()))?;
+        Ok(())
+    }
+
+    /// Restore sketches from a snapshot file, if it exists.
+    pub(crate) fn load(path: &Path) -> Fallible<Option<Self>> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|_| format!("failed to read '{}'", path.display()))
+                    .map_err(Into::into)
+            }
+        };
+        let population = Self::from_snapshot(&data)
+            .with_context(|_| format!("invalid snapshot '{}'", path.display()))?;
+        Ok(Some(population))
+    }
+}
+
+fn encode_str(buf: &mut Vec<u8>, value: &str) {
+    buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    buf.extend_from_slice(value.as_bytes());
+}
+
+fn encode_sketches(buf: &mut Vec<u8>, sketches: &Sketches) {
+    buf.extend_from_slice(&sketches.since_start.registers);
+    buf.extend_from_slice(&(sketches.hourly.len() as u32).to_le_bytes());
+    for (hour, sketch) in &sketches.hourly {
+        buf.extend_from_slice(&hour.to_le_bytes());
+        buf.extend_from_slice(&sketch.registers);
+    }
+}
+
+/// Decoder of snapshots, consuming its input.
+struct SnapshotReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> SnapshotReader<'a> {
+    fn take(&mut self, len: usize) -> Fallible<&'a [u8]> {
+        if self.data.len() < len {
+            bail!("truncated snapshot");
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Fallible<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn i64(&mut self) -> Fallible<i64> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn string(&mut self) -> Fallible<String> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8(self.take(len)?.to_vec())?)
+    }
+
+    fn sketch(&mut self) -> Fallible<HyperLogLog> {
+        let registers = self.take(REGISTERS)?;
+        // Ranks are bounded by the guard bit.
+        if registers
+            .iter()
+            .any(|rank| u32::from(*rank) > 64 - PRECISION + 1)
+        {
+            bail!("invalid sketch register");
+        }
+        Ok(HyperLogLog {
+            registers: registers.to_vec(),
+        })
+    }
+
+    fn sketches(&mut self) -> Fallible<Sketches> {
+        let since_start = self.sketch()?;
+        let count = self.u32()?;
+        if i64::from(count) > RETAINED_HOURS {
+            bail!("too many hourly sketches");
+        }
+        let mut hourly = VecDeque::with_capacity(count as usize);
+        for _ in 0..count {
+            let hour = self.i64()?;
+            hourly.push_back((hour, self.sketch()?));
+        }
+        Ok(Sketches {
+            since_start,
+            hourly,
+        })
+    }
+}
+
+/// Hours since the epoch, for a UTC timestamp.
+fn hour_of(timestamp: i64) -> i64 {
+    timestamp.div_euclid(3600)
+}
+
+/// Hash a node UUID, case-insensitively.
+///
+/// Hashes are persisted in snapshots, so they must be stable across Rust
+/// releases (unlike `DefaultHasher`).
+fn node_hash(node_uuid: &str) -> u64 {
+    commons::siphash::siphash24(&NODE_HASH_KEY, node_uuid.to_ascii_lowercase().as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hyperloglog() {
+        let mut sketch = HyperLogLog::default();
+        assert_eq!(sketch.estimate(), 0.0);
+        for count in &[100, 10_000, 200_000] {
+            for node in 0..*count {
+                sketch.insert(node_hash(&format!("{:032x}", node)));
+            }
+            // Repeated nodes are not counted again.
+            assert!(!sketch.insert(node_hash(&format!("{:032x}", 0))));
+            let error = (sketch.estimate() - *count as f64).abs() / *count as f64;
+            assert!(error < 0.03, "{} for {}", sketch.estimate(), count);
+        }
+    }
+
+    #[test]
+    fn test_node_population() {
+        let population = NodePopulation::default();
+        let now = 1_700_000_000;
+        let node = "b5a4d2b8c5e54e3d9b4bd3fa0a8e2f4c";
+        assert!(population.insert(node, now).is_some());
+        assert!(population.insert(&node.to_uppercase(), now).is_none());
+        assert!(population
+            .insert_scoped("fedora-coreos", "x86_64", "stable", node, now)
+            .is_some());
+        assert!(population
+            .insert_scoped("fedora-coreos", "x86_64", "stable", node, now)
+            .is_none());
+        assert!(population
+            .insert_scoped("fedora-coreos", "x86_64", "next", node, now)
+            .is_some());
+        assert!(population
+            .insert_scoped("other-os", "x86_64", "stable", node, now)
+            .is_some());
+    }
+
+    #[test]
+    fn test_estimates() {
+        let population = NodePopulation::default();
+        let start = 1_700_000_000;
+        for hour in 0..30 {
+            let now = start + hour * 3600;
+            population.insert(&format!("{:032x}", hour), now);
+            population.insert_scoped(
+                "fedora-coreos",
+                "x86_64",
+                "stable",
+                &format!("{:032x}", hour),
+                now,
+            );
+        }
+        let now = start + 29 * 3600;
+        let estimates: Vec<_> = population
+            .estimates(now)
+            .into_iter()
+            .map(|e| (e.scope.is_some(), e.window, e.estimate.round()))
+            .collect();
+        // One node per hour, with sketches of older hours rotated out.
+        assert_eq!(
+            estimates,
+            vec![
+                (false, None, 30.0),
+                (false, Some("1h"), 1.0),
+                (false, Some("24h"), 24.0),
+                (true, None, 30.0),
+                (true, Some("1h"), 1.0),
+                (true, Some("24h"), 24.0),
+            ]
+        );
+        assert_eq!(population.total.lock().unwrap().hourly.len(), 24);
+
+        // Windows slide even without new nodes.
+        let later = population.estimates(now + 12 * 3600);
+        assert_eq!(later[1].estimate.round(), 0.0);
+        assert_eq!(later[2].estimate.round(), 12.0);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let population = NodePopulation::default();
+        let now = 1_700_000_000;
+        for node in 0..1000 {
+            let node = format!("{:032x}", node);
+            population.insert(&node, now - 3600);
+            population.insert_scoped("fedora-coreos", "x86_64", "stable", &node, now);
+        }
+        let snapshot = population.snapshot().unwrap();
+        let restored = NodePopulation::from_snapshot(&snapshot).unwrap();
+        assert_eq!(restored.estimates(now), population.estimates(now));
+        assert_eq!(restored.snapshot().unwrap(), snapshot);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("population");
+        assert!(NodePopulation::load(&path).unwrap().is_none());
+        population.save(&path).unwrap();
+        let loaded = NodePopulation::load(&path).unwrap().unwrap();
+        assert_eq!(loaded.estimates(now), population.estimates(now));
+
+        NodePopulation::from_snapshot(&snapshot[..snapshot.len() - 1]).unwrap_err();
+        NodePopulation::from_snapshot(b"FCOSPOP1").unwrap_err();
+    }
+}
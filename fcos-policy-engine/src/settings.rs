@@ -1,6 +1,19 @@
-use super::config::FileConfig;
-use failure::Fallible;
+use super::config::{FileConfig, ServiceConfig, StatusConfig};
+use crate::nodes::NodeSet;
+use commons::features::{Feature, FeatureFlags};
+use commons::graph::GraphScope;
+use commons::metrics::MetricsSettings;
+use commons::policy;
+use commons::proxy::ProxySettings;
+use commons::report::ReportingSettings;
+use commons::runtime::RuntimeSettings;
+use commons::tls::TlsSettings;
+use commons::trace::TracingSettings;
+use commons::web::{RateLimitSettings, ScopeAliases};
+use failure::{bail, format_err, Fallible, ResultExt};
+use std::collections::HashSet;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 /// Runtime settings for the policy-engine.
@@ -8,33 +21,138 @@ use std::time::Duration;
 pub struct PolicyEngineSettings {
     pub(crate) service: ServiceSettings,
     pub(crate) status: StatusSettings,
+    pub(crate) runtime: RuntimeSettings,
+    pub(crate) tracing: TracingSettings,
+    pub(crate) metrics: MetricsSettings,
+    pub(crate) reporting: ReportingSettings,
+    pub(crate) features: FeatureFlags,
 }
 
 impl PolicyEngineSettings {
-    pub fn validate_config(_cfg: FileConfig) -> Fallible<Self> {
-        // TODO(lucab): translate config entries.
-        let settings = PolicyEngineSettings::default();
+    pub fn validate_config(cfg: FileConfig) -> Fallible<Self> {
+        let mut settings = PolicyEngineSettings::default();
+        settings.apply_config(cfg)?;
         Ok(settings)
     }
+
+    /// Load settings from a config file, its drop-in fragments, and environment.
+    pub fn load(config_path: &Path) -> Fallible<Self> {
+        let cfg = FileConfig::parse_file(config_path)?;
+        let mut settings = Self::validate_config(cfg)?;
+        for fragment in commons::config::dropin_fragments(config_path)? {
+            log::debug!("config fragment: {}", fragment.display());
+            let fragment_cfg = FileConfig::parse_file(&fragment)?;
+            settings.apply_config(fragment_cfg).with_context(|_| {
+                format!("invalid configuration fragment '{}'", fragment.display())
+            })?;
+        }
+        let env_cfg = FileConfig::from_env()?;
+        settings
+            .apply_config(env_cfg)
+            .context("invalid configuration from environment")?;
+        Ok(settings)
+    }
+
+    /// Apply a configuration layer on top of current settings.
+    pub fn apply_config(&mut self, cfg: FileConfig) -> Fallible<()> {
+        if let Some(service) = cfg.service {
+            self.service
+                .apply_config(service)
+                .context("invalid 'service' configuration")?;
+        }
+        if let Some(status) = cfg.status {
+            self.status
+                .apply_config(status)
+                .context("invalid 'status' configuration")?;
+        }
+        if let Some(runtime) = cfg.runtime {
+            self.runtime
+                .apply_config(runtime)
+                .context("invalid 'runtime' configuration")?;
+        }
+        if let Some(tracing) = cfg.tracing {
+            self.tracing
+                .apply_config(tracing)
+                .context("invalid 'tracing' configuration")?;
+        }
+        if let Some(metrics) = cfg.metrics {
+            self.metrics
+                .apply_config(metrics)
+                .context("invalid 'metrics' configuration")?;
+        }
+        if let Some(reporting) = cfg.reporting {
+            self.reporting
+                .apply_config(reporting)
+                .context("invalid 'reporting' configuration")?;
+        }
+        if let Some(features) = cfg.features {
+            self.features
+                .apply_overrides(&features)
+                .context("invalid 'features' configuration")?;
+        }
+        Ok(())
+    }
 }
 
 /// Runtime settings for the main service (graph endpoint) server.
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct ServiceSettings {
     pub(crate) origin_allowlist: Option<Vec<String>>,
-    pub(crate) bloom_max_population: usize,
-    pub(crate) bloom_size: usize,
-    pub(crate) ip_addr: IpAddr,
+    pub(crate) ip_addrs: Vec<IpAddr>,
     pub(crate) port: u16,
     pub(crate) upstream_base: reqwest::Url,
+    /// Interchangeable upstream endpoints, overriding `upstream_base`.
+    pub(crate) upstream_replicas: Option<Vec<reqwest::Url>>,
+    /// Sharded upstream endpoints, by shard index.
+    pub(crate) upstream_shards: Option<Vec<reqwest::Url>>,
     pub(crate) upstream_req_timeout: Duration,
+    /// Outbound proxy for upstream requests.
+    pub(crate) upstream_proxy: ProxySettings,
+    /// Time-to-live of cached upstream graphs (caching disabled if zero).
+    pub(crate) upstream_cache_ttl: Duration,
+    /// Maximum age of last-known-good graphs (disabled if zero).
+    pub(crate) upstream_stale_max_age: Duration,
+    /// Interval between background refreshes of upstream graphs.
+    pub(crate) upstream_prefetch_interval: Option<Duration>,
+    pub(crate) robots_txt: String,
+    pub(crate) security_txt: Option<String>,
+    pub(crate) scope_allowlist: Option<HashSet<GraphScope>>,
+    /// Aliases of basearch and stream values.
+    pub(crate) scope_aliases: ScopeAliases,
+    /// Product assumed for requests and scopes without one.
+    pub(crate) default_product: String,
+    /// Basearch assumed for requests without one.
+    pub(crate) default_basearch: Option<String>,
+    /// Stream assumed for requests without one.
+    pub(crate) default_stream: Option<String>,
+    /// Key for deriving rollout wariness from node IDs.
+    pub(crate) wariness_key: [u8; 16],
+    /// Canary nodes, with a rollout wariness pinned to zero.
+    pub(crate) canary_nodes: NodeSet,
+    /// Nodes denied graph requests.
+    pub(crate) denied_nodes: NodeSet,
+    /// Maximum graph requests per minute from a single node, before a ban.
+    pub(crate) node_ban_rate: Option<u32>,
+    /// Duration of temporary bans of nodes.
+    pub(crate) node_ban_duration: Duration,
+    /// Per-client rate limiting of graph requests.
+    pub(crate) rate_limit: RateLimitSettings,
+    /// Maximum graph requests in flight (unlimited if unset).
+    pub(crate) max_in_flight: Option<usize>,
+    /// Fraction of graph requests to log (access logs disabled if zero).
+    pub(crate) access_log_sample_rate: f64,
+    /// Deadline for serving graph requests (disabled if unset).
+    pub(crate) request_timeout: Option<Duration>,
+    /// Minimum release lag for routing old clients through barriers only.
+    pub(crate) old_client_release_lag: Option<u64>,
+    /// Window of the requests by client version summary, in hours.
+    pub(crate) version_heatmap_hours: usize,
+    /// File for snapshots of unique nodes estimates (disabled if unset).
+    pub(crate) population_snapshot_path: Option<PathBuf>,
+    pub(crate) population_snapshot_interval: Duration,
 }
 
 impl ServiceSettings {
-    /// Default maximum expected unique IDs to track in the Bloom filter.
-    const DEFAULT_BLOOM_MAX_MEMBERS: usize = 1_000_000;
-    /// Default size of the Bloom filter for unique IDs tracking.
-    const DEFAULT_BLOOM_SIZE: usize = 10 * 1024 * 1024; // 10 MiB
     /// Default IP address for policy-engine main service.
     const DEFAULT_PE_SERVICE_ADDR: Ipv4Addr = Ipv4Addr::UNSPECIFIED;
     /// Default TCP port for policy-engine main service.
@@ -44,9 +162,326 @@ impl ServiceSettings {
     const DEFAULT_UP_ENDPOINT: &'static str = "http://127.0.0.1:8080/v1/graph";
     /// Default timeout for HTTP requests (30 minutes).
     const DEFAULT_UP_REQ_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+    /// Default time-to-live of cached upstream graphs.
+    const DEFAULT_UP_CACHE_TTL: Duration = Duration::from_secs(10);
+    /// Default maximum age of last-known-good graphs.
+    const DEFAULT_UP_STALE_MAX_AGE: Duration = Duration::from_secs(15 * 60);
+    /// Default window of the requests by client version summary, in hours.
+    const DEFAULT_VERSION_HEATMAP_HOURS: usize = 24;
+    /// Maximum window of the requests by client version summary (one week).
+    const MAX_VERSION_HEATMAP_HOURS: usize = 7 * 24;
+    /// Default duration of temporary bans of nodes.
+    const DEFAULT_NODE_BAN_DURATION: Duration = Duration::from_secs(60 * 60);
+    /// Default interval between snapshots of unique nodes estimates.
+    const DEFAULT_POPULATION_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+    /// Default content for `/robots.txt`, keeping all crawlers away from the API.
+    const DEFAULT_ROBOTS_TXT: &'static str = "User-agent: *\nDisallow: /\n";
 
+    /// First listen address, e.g. for local probes.
     pub fn socket_addr(&self) -> SocketAddr {
-        SocketAddr::new(self.ip_addr, self.port)
+        SocketAddr::new(self.ip_addrs[0], self.port)
+    }
+
+    /// All listen addresses.
+    pub fn socket_addrs(&self) -> Vec<SocketAddr> {
+        self.ip_addrs
+            .iter()
+            .map(|addr| SocketAddr::new(*addr, self.port))
+            .collect()
+    }
+
+    /// Upstream graph-builder endpoints, i.e. all replicas or the single base.
+    pub(crate) fn upstream_endpoints(&self) -> Vec<reqwest::Url> {
+        match &self.upstream_replicas {
+            Some(replicas) => replicas.clone(),
+            None => vec![self.upstream_base.clone()],
+        }
+    }
+
+    /// Processing steps applied to upstream graphs, in order.
+    pub(crate) fn policy_pipeline(&self, features: &FeatureFlags) -> Vec<String> {
+        let mut steps = vec![];
+        if features.is_enabled(Feature::WarinessTiers) {
+            steps.push("wariness_tier_variant (unless rollout_wariness is set)".to_string());
+            steps.push("throttle_rollouts (only if rollout_wariness is set)".to_string());
+        } else if features.is_enabled(Feature::WarinessBuckets) {
+            steps.push(format!(
+                "wariness_bucket (buckets={}, unless rollout_wariness is set)",
+                policy::WARINESS_BUCKETS
+            ));
+            steps.push("throttle_rollouts (only if rollout_wariness is set)".to_string());
+        } else {
+            steps.push("throttle_rollouts".to_string());
+        }
+        if let Some(lag) = self.old_client_release_lag {
+            steps.push(format!(
+                "prune_for_old_client (old_client_release_lag={})",
+                lag
+            ));
+        }
+        steps.push("filter_deadends".to_string());
+        steps
+    }
+
+    fn apply_config(&mut self, cfg: ServiceConfig) -> Fallible<()> {
+        if let Some(addrs) = commons::listen::listen_addresses(cfg.address, cfg.addresses)? {
+            self.ip_addrs = addrs;
+        }
+        if let Some(port) = cfg.port {
+            self.port = port;
+        }
+        if let Some(allowlist) = cfg.origin_allowlist {
+            if allowlist.iter().any(|origin| origin.trim().is_empty()) {
+                bail!("empty entry in 'origin_allowlist'");
+            }
+            self.origin_allowlist = Some(allowlist);
+        }
+        if cfg.upstream_base.is_some() && cfg.upstream_replicas.is_some() {
+            bail!("only one of 'upstream_base' and 'upstream_replicas' may be set");
+        }
+        if let Some(base) = cfg.upstream_base {
+            self.upstream_base = reqwest::Url::parse(&base)
+                .map_err(|e| format_err!("invalid 'upstream_base' '{}': {}", base, e))?;
+            self.upstream_replicas = None;
+        }
+        if let Some(replicas) = cfg.upstream_replicas {
+            if replicas.is_empty() {
+                bail!("invalid 'upstream_replicas': must be non-empty");
+            }
+            let mut endpoints = Vec::with_capacity(replicas.len());
+            for replica in replicas {
+                let url = reqwest::Url::parse(&replica).map_err(|e| {
+                    format_err!("invalid 'upstream_replicas' entry '{}': {}", replica, e)
+                })?;
+                if endpoints.contains(&url) {
+                    bail!("duplicate 'upstream_replicas' entry '{}'", url);
+                }
+                endpoints.push(url);
+            }
+            self.upstream_replicas = Some(endpoints);
+        }
+        if let Some(shards) = cfg.upstream_shards {
+            if shards.is_empty() {
+                bail!("invalid 'upstream_shards': must be non-empty");
+            }
+            let mut endpoints = Vec::with_capacity(shards.len());
+            for shard in shards {
+                let url = reqwest::Url::parse(&shard).map_err(|e| {
+                    format_err!("invalid 'upstream_shards' entry '{}': {}", shard, e)
+                })?;
+                endpoints.push(url);
+            }
+            self.upstream_shards = Some(endpoints);
+        }
+        if let Some(timeout) = cfg.upstream_timeout {
+            if timeout.0 == Duration::from_secs(0) {
+                bail!("invalid 'upstream_timeout': must be non-zero");
+            }
+            self.upstream_req_timeout = timeout.0;
+        }
+        if let Some(proxy) = cfg.upstream_proxy {
+            self.upstream_proxy
+                .apply_config(proxy)
+                .context("invalid 'upstream_proxy' configuration")?;
+        }
+        if let Some(ttl) = cfg.upstream_cache_ttl {
+            self.upstream_cache_ttl = ttl.0;
+        }
+        if let Some(max_age) = cfg.upstream_stale_max_age {
+            self.upstream_stale_max_age = max_age.0;
+        }
+        if let Some(interval) = cfg.upstream_prefetch_interval {
+            if interval.0 == Duration::from_secs(0) {
+                bail!("invalid 'upstream_prefetch_interval': must be non-zero");
+            }
+            self.upstream_prefetch_interval = Some(interval.0);
+        }
+        if let Some(interval) = self.upstream_prefetch_interval {
+            // Otherwise, requests would still hit upstream between refreshes.
+            if interval >= self.upstream_cache_ttl {
+                bail!(
+                    "invalid 'upstream_prefetch_interval': must be shorter than 'upstream_cache_ttl'"
+                );
+            }
+        }
+        if let Some(product) = cfg.default_product {
+            if product.trim().is_empty() {
+                bail!("invalid 'default_product': must be non-empty");
+            }
+            self.default_product = product.trim().to_string();
+        }
+        if let Some(scopes) = cfg.scopes {
+            let mut allowlist = HashSet::with_capacity(scopes.len());
+            for entry in scopes {
+                let product = entry
+                    .product
+                    .unwrap_or_else(|| self.default_product.clone());
+                if product.trim().is_empty()
+                    || entry.basearch.trim().is_empty()
+                    || entry.stream.trim().is_empty()
+                {
+                    bail!("invalid 'scopes' entry: empty product, basearch or stream");
+                }
+                let scope = GraphScope {
+                    product,
+                    basearch: entry.basearch,
+                    stream: entry.stream,
+                    oci: entry.oci,
+                };
+                if allowlist.contains(&scope) {
+                    bail!(
+                        "duplicate 'scopes' entry: product='{}', basearch='{}', stream='{}', oci='{}'",
+                        scope.product,
+                        scope.basearch,
+                        scope.stream,
+                        scope.oci
+                    );
+                }
+                allowlist.insert(scope);
+            }
+            self.scope_allowlist = Some(allowlist);
+        }
+        if let Some(aliases) = cfg.basearch_aliases {
+            self.scope_aliases.basearch =
+                commons::web::check_aliases(aliases).context("invalid 'basearch_aliases'")?;
+        }
+        if let Some(aliases) = cfg.stream_aliases {
+            self.scope_aliases.stream =
+                commons::web::check_aliases(aliases).context("invalid 'stream_aliases'")?;
+        }
+        if let Some(basearch) = cfg.default_basearch {
+            if basearch.trim().is_empty() {
+                bail!("invalid 'default_basearch': must be non-empty");
+            }
+            self.default_basearch = Some(basearch.trim().to_ascii_lowercase());
+        }
+        if let Some(stream) = cfg.default_stream {
+            if stream.trim().is_empty() {
+                bail!("invalid 'default_stream': must be non-empty");
+            }
+            self.default_stream = Some(stream.trim().to_ascii_lowercase());
+        }
+        if let Some(key) = cfg.wariness_key {
+            self.wariness_key = commons::siphash::parse_key(key.trim())
+                .ok_or_else(|| format_err!("invalid 'wariness_key': must be 32 hex digits"))?;
+        }
+        if let Some(entries) = cfg.canary_nodes {
+            self.canary_nodes = NodeSet::parse(&entries).context("invalid 'canary_nodes'")?;
+        }
+        if let Some(entries) = cfg.denied_nodes {
+            self.denied_nodes = NodeSet::parse(&entries).context("invalid 'denied_nodes'")?;
+        }
+        if let Some(rate) = cfg.node_ban_rate {
+            if rate == 0 {
+                bail!("invalid 'node_ban_rate': must be non-zero");
+            }
+            self.node_ban_rate = Some(rate);
+        }
+        if let Some(duration) = cfg.node_ban_duration {
+            self.node_ban_duration = duration.0;
+        }
+        if let Some(rate_limit) = cfg.rate_limit {
+            self.rate_limit
+                .apply_config(rate_limit)
+                .context("invalid 'rate_limit' configuration")?;
+        }
+        if let Some(max) = cfg.max_in_flight {
+            if max == 0 {
+                bail!("invalid 'max_in_flight': must be non-zero");
+            }
+            self.max_in_flight = Some(max);
+        }
+        if let Some(rate) = cfg.access_log_sample_rate {
+            if !(0.0..=1.0).contains(&rate) {
+                bail!("invalid 'access_log_sample_rate': must be between 0.0 and 1.0");
+            }
+            self.access_log_sample_rate = rate;
+        }
+        if let Some(timeout) = cfg.request_timeout {
+            if timeout.0 == Duration::from_secs(0) {
+                bail!("invalid 'request_timeout': must be non-zero");
+            }
+            self.request_timeout = Some(timeout.0);
+        }
+        if let Some(lag) = cfg.old_client_release_lag {
+            if lag == 0 {
+                bail!("invalid 'old_client_release_lag': must be non-zero");
+            }
+            self.old_client_release_lag = Some(lag);
+        }
+        if let Some(hours) = cfg.version_heatmap_hours {
+            if !(1..=Self::MAX_VERSION_HEATMAP_HOURS).contains(&hours) {
+                bail!(
+                    "invalid 'version_heatmap_hours': must be between 1 and {}",
+                    Self::MAX_VERSION_HEATMAP_HOURS
+                );
+            }
+            self.version_heatmap_hours = hours;
+        }
+        if let Some(path) = cfg.population_snapshot_path {
+            if path.as_os_str().is_empty() {
+                bail!("invalid 'population_snapshot_path': must be non-empty");
+            }
+            self.population_snapshot_path = Some(path);
+        }
+        if let Some(interval) = cfg.population_snapshot_interval {
+            if interval.0.as_secs() == 0 {
+                bail!("invalid 'population_snapshot_interval': must be at least one second");
+            }
+            self.population_snapshot_interval = interval.0;
+        }
+        if let Some(content) = cfg.robots_txt {
+            self.robots_txt = content;
+        }
+        if let Some(content) = cfg.security_txt {
+            self.security_txt = Some(content);
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for ServiceSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never leak the wariness key, e.g. when printing effective settings.
+        f.debug_struct("ServiceSettings")
+            .field("origin_allowlist", &self.origin_allowlist)
+            .field("ip_addrs", &self.ip_addrs)
+            .field("port", &self.port)
+            .field("upstream_base", &self.upstream_base)
+            .field("upstream_replicas", &self.upstream_replicas)
+            .field("upstream_shards", &self.upstream_shards)
+            .field("upstream_req_timeout", &self.upstream_req_timeout)
+            .field("upstream_proxy", &self.upstream_proxy)
+            .field("upstream_cache_ttl", &self.upstream_cache_ttl)
+            .field("upstream_stale_max_age", &self.upstream_stale_max_age)
+            .field(
+                "upstream_prefetch_interval",
+                &self.upstream_prefetch_interval,
+            )
+            .field("robots_txt", &self.robots_txt)
+            .field("security_txt", &self.security_txt)
+            .field("scope_allowlist", &self.scope_allowlist)
+            .field("scope_aliases", &self.scope_aliases)
+            .field("default_product", &self.default_product)
+            .field("default_basearch", &self.default_basearch)
+            .field("default_stream", &self.default_stream)
+            .field("wariness_key", &"<redacted>")
+            .field("canary_nodes", &self.canary_nodes)
+            .field("denied_nodes", &self.denied_nodes)
+            .field("node_ban_rate", &self.node_ban_rate)
+            .field("node_ban_duration", &self.node_ban_duration)
+            .field("rate_limit", &self.rate_limit)
+            .field("max_in_flight", &self.max_in_flight)
+            .field("access_log_sample_rate", &self.access_log_sample_rate)
+            .field("request_timeout", &self.request_timeout)
+            .field("old_client_release_lag", &self.old_client_release_lag)
+            .field("version_heatmap_hours", &self.version_heatmap_hours)
+            .field("population_snapshot_path", &self.population_snapshot_path)
+            .field(
+                "population_snapshot_interval",
+                &self.population_snapshot_interval,
+            )
+            .finish()
     }
 }
 
@@ -54,13 +489,37 @@ impl Default for ServiceSettings {
     fn default() -> Self {
         Self {
             origin_allowlist: None,
-            bloom_max_population: Self::DEFAULT_BLOOM_MAX_MEMBERS,
-            bloom_size: Self::DEFAULT_BLOOM_SIZE,
-            ip_addr: Self::DEFAULT_PE_SERVICE_ADDR.into(),
+            ip_addrs: vec![Self::DEFAULT_PE_SERVICE_ADDR.into()],
             port: Self::DEFAULT_PE_SERVICE_PORT,
             upstream_base: reqwest::Url::parse(Self::DEFAULT_UP_ENDPOINT)
                 .expect("invalid default upstream base endpoint"),
+            upstream_replicas: None,
+            upstream_shards: None,
             upstream_req_timeout: Self::DEFAULT_UP_REQ_TIMEOUT,
+            upstream_proxy: ProxySettings::default(),
+            upstream_cache_ttl: Self::DEFAULT_UP_CACHE_TTL,
+            upstream_stale_max_age: Self::DEFAULT_UP_STALE_MAX_AGE,
+            upstream_prefetch_interval: None,
+            robots_txt: Self::DEFAULT_ROBOTS_TXT.to_string(),
+            security_txt: None,
+            scope_allowlist: None,
+            scope_aliases: ScopeAliases::default(),
+            default_product: commons::metadata::DEFAULT_PRODUCT.to_string(),
+            default_basearch: None,
+            default_stream: None,
+            wariness_key: [0; 16],
+            canary_nodes: NodeSet::default(),
+            denied_nodes: NodeSet::default(),
+            node_ban_rate: None,
+            node_ban_duration: Self::DEFAULT_NODE_BAN_DURATION,
+            rate_limit: RateLimitSettings::default(),
+            max_in_flight: None,
+            access_log_sample_rate: 0.0,
+            request_timeout: None,
+            old_client_release_lag: None,
+            version_heatmap_hours: Self::DEFAULT_VERSION_HEATMAP_HOURS,
+            population_snapshot_path: None,
+            population_snapshot_interval: Self::DEFAULT_POPULATION_SNAPSHOT_INTERVAL,
         }
     }
 }
@@ -68,8 +527,10 @@ impl Default for ServiceSettings {
 /// Runtime settings for the status server.
 #[derive(Clone, Debug)]
 pub struct StatusSettings {
-    pub(crate) ip_addr: IpAddr,
+    pub(crate) ip_addrs: Vec<IpAddr>,
     pub(crate) port: u16,
+    /// Mutual TLS (plain HTTP if unset).
+    pub(crate) tls: Option<TlsSettings>,
 }
 
 impl StatusSettings {
@@ -78,16 +539,230 @@ impl StatusSettings {
     /// Default TCP port for policy-engine status.
     const DEFAULT_PE_STATUS_PORT: u16 = 9081;
 
+    /// First listen address, e.g. for local probes.
     pub fn socket_addr(&self) -> SocketAddr {
-        SocketAddr::new(self.ip_addr, self.port)
+        SocketAddr::new(self.ip_addrs[0], self.port)
+    }
+
+    /// All listen addresses.
+    pub fn socket_addrs(&self) -> Vec<SocketAddr> {
+        self.ip_addrs
+            .iter()
+            .map(|addr| SocketAddr::new(*addr, self.port))
+            .collect()
+    }
+
+    fn apply_config(&mut self, cfg: StatusConfig) -> Fallible<()> {
+        if let Some(addrs) = commons::listen::listen_addresses(cfg.address, cfg.addresses)? {
+            self.ip_addrs = addrs;
+        }
+        if let Some(port) = cfg.port {
+            self.port = port;
+        }
+        if let Some(tls) = cfg.tls {
+            let tls = TlsSettings::from_config(tls).context("invalid 'tls'")?;
+            self.tls = Some(tls);
+        }
+        Ok(())
     }
 }
 
 impl Default for StatusSettings {
     fn default() -> Self {
         Self {
-            ip_addr: Self::DEFAULT_PE_SERVICE_ADDR.into(),
+            ip_addrs: vec![Self::DEFAULT_PE_SERVICE_ADDR.into()],
             port: Self::DEFAULT_PE_STATUS_PORT,
+            tls: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_config() {
+        let input = r#"
+            [service]
+            port = 8091
+            upstream_base = "http://gb.example.com:8080/v1/graph"
+            upstream_timeout = "30s"
+            upstream_cache_ttl = "5s"
+            upstream_prefetch_interval = "4s"
+            scopes = [
+                { basearch = "x86_64", stream = "stable" },
+                { basearch = "x86_64", stream = "stable", oci = true },
+                { product = "other-os", basearch = "x86_64", stream = "stable" },
+            ]
+            basearch_aliases = { X64 = "x86_64" }
+            stream_aliases = { prod = "stable" }
+            default_stream = "Stable"
+            wariness_key = "000102030405060708090a0b0c0d0e0f"
+            canary_nodes = ["CAFE*"]
+            node_ban_rate = 30
+            node_ban_duration = "10m"
+            request_timeout = "1m"
+            population_snapshot_path = "/var/lib/fcos-policy-engine/population"
+
+            [status]
+            port = 9091
+
+            [runtime]
+            workers = 4
+
+            [tracing]
+            otlp_endpoint = "http://otel.example.com:4318/v1/traces"
+            sample_rate = 0.1
+        "#;
+        let cfg: FileConfig = toml::from_str(input).unwrap();
+        let settings = PolicyEngineSettings::validate_config(cfg).unwrap();
+        assert_eq!(settings.service.port, 8091);
+        assert_eq!(
+            settings.service.upstream_base.as_str(),
+            "http://gb.example.com:8080/v1/graph"
+        );
+        assert_eq!(
+            settings.service.upstream_req_timeout,
+            Duration::from_secs(30)
+        );
+        assert_eq!(settings.service.upstream_cache_ttl, Duration::from_secs(5));
+        assert_eq!(
+            settings.service.upstream_prefetch_interval,
+            Some(Duration::from_secs(4))
+        );
+        let allowlist = settings.service.scope_allowlist.as_ref().unwrap();
+        assert_eq!(allowlist.len(), 3);
+        assert_eq!(
+            allowlist
+                .iter()
+                .filter(|s| s.product == commons::metadata::DEFAULT_PRODUCT)
+                .count(),
+            2
+        );
+        assert_eq!(
+            settings.service.scope_aliases.basearch,
+            maplit::hashmap! {"x64".to_string() => "x86_64".to_string()}
+        );
+        assert_eq!(
+            settings.service.scope_aliases.resolve_stream("prod".into()),
+            "stable"
+        );
+        assert_eq!(settings.service.default_basearch, None);
+        assert_eq!(settings.service.default_stream.as_deref(), Some("stable"));
+        assert_eq!(settings.service.wariness_key[15], 0x0f);
+        let printed = format!("{:?}", settings.service);
+        assert!(printed.contains("wariness_key: \"<redacted>\""));
+        assert!(!printed.contains("[0, 1, 2,"));
+        assert!(settings
+            .service
+            .canary_nodes
+            .contains("cafe0000000000000000000000000000"));
+        assert_eq!(settings.service.node_ban_rate, Some(30));
+        assert_eq!(
+            settings.service.node_ban_duration,
+            Duration::from_secs(10 * 60)
+        );
+        assert_eq!(
+            settings.service.request_timeout,
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            settings.service.population_snapshot_path,
+            Some(PathBuf::from("/var/lib/fcos-policy-engine/population"))
+        );
+        assert_eq!(
+            settings.service.population_snapshot_interval,
+            Duration::from_secs(5 * 60)
+        );
+        assert_eq!(settings.status.port, 9091);
+        assert_eq!(settings.runtime.workers, 4);
+        assert_eq!(
+            settings.tracing.otlp_endpoint.as_ref().unwrap().as_str(),
+            "http://otel.example.com:4318/v1/traces"
+        );
+        assert_eq!(settings.tracing.sample_rate, 0.1);
+        assert_eq!(
+            settings.service.policy_pipeline(&settings.features),
+            vec!["throttle_rollouts", "filter_deadends"]
+        );
+
+        let bad_timeout = r#"
+            [service]
+            upstream_timeout = 30
+        "#;
+        toml::from_str::<FileConfig>(bad_timeout).unwrap_err();
+
+        // Explicit default product is the same scope as an implicit one.
+        let duplicate_scope = r#"
+            [service]
+            scopes = [
+                { basearch = "x86_64", stream = "stable" },
+                { product = "fedora-coreos", basearch = "x86_64", stream = "stable" },
+            ]
+        "#;
+        let cfg: FileConfig = toml::from_str(duplicate_scope).unwrap();
+        PolicyEngineSettings::validate_config(cfg).unwrap_err();
+
+        // Scopes without a product follow the configured default product.
+        let custom_product = r#"
+            [service]
+            default_product = "other-os"
+            scopes = [{ basearch = "x86_64", stream = "stable" }]
+        "#;
+        let cfg: FileConfig = toml::from_str(custom_product).unwrap();
+        let settings = PolicyEngineSettings::validate_config(cfg).unwrap();
+        assert_eq!(settings.service.default_product, "other-os");
+        let allowlist = settings.service.scope_allowlist.unwrap();
+        assert!(allowlist.iter().all(|s| s.product == "other-os"));
+
+        let empty_product = r#"
+            [service]
+            default_product = " "
+        "#;
+        let cfg: FileConfig = toml::from_str(empty_product).unwrap();
+        PolicyEngineSettings::validate_config(cfg).unwrap_err();
+
+        let slow_prefetch = r#"
+            [service]
+            upstream_prefetch_interval = "1m"
+        "#;
+        let cfg: FileConfig = toml::from_str(slow_prefetch).unwrap();
+        PolicyEngineSettings::validate_config(cfg).unwrap_err();
+
+        let bad_base = r#"
+            [service]
+            upstream_base = "not a url"
+        "#;
+        let cfg: FileConfig = toml::from_str(bad_base).unwrap();
+        PolicyEngineSettings::validate_config(cfg).unwrap_err();
+
+        for (hours, valid) in &[(0, false), (1, true), (168, true), (169, false)] {
+            let heatmap = format!("[service]\nversion_heatmap_hours = {}", hours);
+            let cfg: FileConfig = toml::from_str(&heatmap).unwrap();
+            let validated = PolicyEngineSettings::validate_config(cfg);
+            assert_eq!(validated.is_ok(), *valid, "{} hours", hours);
+        }
+
+        let replicas = r#"
+            [service]
+            upstream_replicas = [
+                "http://gb-0.example.com:8080/v1/graph",
+                "http://gb-1.example.com:8080/v1/graph",
+            ]
+        "#;
+        let cfg: FileConfig = toml::from_str(replicas).unwrap();
+        let settings = PolicyEngineSettings::validate_config(cfg).unwrap();
+        assert_eq!(settings.service.upstream_endpoints().len(), 2);
+
+        let duplicate_replicas = r#"
+            [service]
+            upstream_replicas = [
+                "http://gb-0.example.com:8080/v1/graph",
+                "http://gb-0.example.com:8080/v1/graph",
+            ]
+        "#;
+        let cfg: FileConfig = toml::from_str(duplicate_replicas).unwrap();
+        PolicyEngineSettings::validate_config(cfg).unwrap_err();
+    }
+}
@@ -0,0 +1,116 @@
+//! Throttled graphs, precomputed for all rollout wariness buckets.
+
+use crate::cache::GraphKey;
+use crate::utils::UpstreamGraph;
+use commons::graph::Graph;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Length of the window within which rollout progress is considered constant, in seconds.
+const WINDOW_SECS: i64 = 60;
+
+/// Throttled graphs for an upstream graph, within a time window.
+#[derive(Debug)]
+struct Entry {
+    /// Upstream graph the buckets were computed from.
+    upstream: UpstreamGraph,
+    window: i64,
+    /// Throttled graphs, by wariness bucket.
+    graphs: Vec<Arc<Graph>>,
+}
+
+/// Precomputed throttled graphs, by upstream graph.
+#[derive(Debug, Default)]
+pub(crate) struct ThrottledGraphs {
+    entries: HashMap<GraphKey, Entry>,
+}
+
+impl ThrottledGraphs {
+    /// Look up the graph for a wariness bucket, if precomputed from the same
+    /// upstream graph generation within the current window.
+    pub(crate) fn get(
+        &self,
+        key: &GraphKey,
+        upstream: &UpstreamGraph,
+        bucket: u8,
+        now: i64,
+    ) -> Option<Arc<Graph>> {
+        self.entries
+            .get(key)
+            .filter(|entry| {
+                entry.window == now.div_euclid(WINDOW_SECS)
+                    && entry.upstream.same_generation(upstream)
+            })
+            .and_then(|entry| entry.graphs.get(usize::from(bucket)))
+            .cloned()
+    }
+
+    /// Store the graphs for all buckets, dropping those from past windows.
+    pub(crate) fn insert(
+        &mut self,
+        key: GraphKey,
+        upstream: UpstreamGraph,
+        graphs: Vec<Arc<Graph>>,
+        now: i64,
+    ) {
+        let window = now.div_euclid(WINDOW_SECS);
+        self.entries.retain(|_, entry| entry.window == window);
+        self.entries.insert(
+            key,
+            Entry {
+                upstream,
+                window,
+                graphs,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::web::Bytes;
+    use commons::graph::GraphScope;
+
+    fn upstream(body: &'static str, version: Option<&str>) -> UpstreamGraph {
+        UpstreamGraph {
+            body: Bytes::from_static(body.as_bytes()),
+            source: None,
+            oci: None,
+            last_modified: None,
+            version: version.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_throttled_graphs() {
+        let key = GraphKey {
+            upstream: reqwest::Url::parse("http://127.0.0.1:8080/v1/graph").unwrap(),
+            scope: GraphScope {
+                product: "fedora-coreos".to_string(),
+                basearch: "x86_64".to_string(),
+                stream: "stable".to_string(),
+                oci: false,
+            },
+            tier: None,
+        };
+        let body = upstream("{}", None);
+        let graphs = (0..3).map(|_| Arc::new(Graph::default())).collect();
+
+        let mut throttled = ThrottledGraphs::default();
+        assert!(throttled.get(&key, &body, 1, 120).is_none());
+        throttled.insert(key.clone(), body.clone(), graphs, 120);
+        assert!(throttled.get(&key, &body, 1, 179).is_some());
+        assert!(throttled.get(&key, &body, 3, 179).is_none());
+        assert!(throttled.get(&key, &upstream("[]", None), 1, 179).is_none());
+        assert!(throttled.get(&key, &body, 1, 180).is_none());
+
+        let versioned = upstream("{}", Some("0123456789abcdef-120"));
+        let graphs = (0..3).map(|_| Arc::new(Graph::default())).collect();
+        throttled.insert(key.clone(), versioned.clone(), graphs, 120);
+        assert!(throttled.get(&key, &versioned, 1, 179).is_some());
+        assert!(throttled
+            .get(&key, &upstream("{}", Some("0123456789abcdef-150")), 1, 179)
+            .is_none());
+    }
+}
@@ -0,0 +1,130 @@
+//! Interchangeable upstream graph-builder replicas, with failover.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// An upstream graph-builder replica.
+#[derive(Debug)]
+struct Replica {
+    url: reqwest::Url,
+    healthy: AtomicBool,
+}
+
+/// Upstream graph-builder replicas, used in round-robin order.
+#[derive(Debug)]
+pub(crate) struct UpstreamPool {
+    replicas: Vec<Replica>,
+    /// Round-robin position.
+    next: AtomicUsize,
+}
+
+impl UpstreamPool {
+    /// Create a pool of replicas, all initially assumed healthy.
+    pub(crate) fn new(urls: Vec<reqwest::Url>) -> Self {
+        let replicas = urls
+            .into_iter()
+            .map(|url| {
+                crate::UPSTREAM_REPLICA_UP
+                    .with_label_values(&[url.as_str()])
+                    .set(1);
+                Replica {
+                    url,
+                    healthy: AtomicBool::new(true),
+                }
+            })
+            .collect();
+        Self {
+            replicas,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// First configured replica, identifying the whole pool.
+    pub(crate) fn primary(&self) -> &reqwest::Url {
+        &self.replicas[0].url
+    }
+
+    /// All replicas.
+    pub(crate) fn urls(&self) -> impl Iterator<Item = &reqwest::Url> {
+        self.replicas.iter().map(|replica| &replica.url)
+    }
+
+    /// Replicas to try for a request, in order.
+    ///
+    /// Healthy replicas come first, in round-robin order, while unhealthy
+    /// ones are only kept as a last resort.
+    pub(crate) fn candidates(&self) -> Vec<reqwest::Url> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let count = self.replicas.len();
+        let (healthy, unhealthy): (Vec<&Replica>, Vec<&Replica>) = (0..count)
+            .map(|n| &self.replicas[(start + n) % count])
+            .partition(|replica| replica.healthy.load(Ordering::Relaxed));
+        healthy
+            .into_iter()
+            .chain(unhealthy)
+            .map(|replica| replica.url.clone())
+            .collect()
+    }
+
+    /// Record the health of a replica, logging changes.
+    ///
+    /// Unknown URLs (e.g. shard endpoints) are ignored.
+    pub(crate) fn set_healthy(&self, url: &reqwest::Url, healthy: bool) {
+        let replica = match self.replicas.iter().find(|replica| replica.url == *url) {
+            Some(replica) => replica,
+            None => return,
+        };
+        if replica.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+            if healthy {
+                log::info!("upstream replica '{}' is healthy again", url);
+            } else {
+                log::warn!("upstream replica '{}' is unhealthy", url);
+            }
+        }
+        crate::UPSTREAM_REPLICA_UP
+            .with_label_values(&[url.as_str()])
+            .set(i64::from(healthy));
+    }
+}
+
+/// Whether a failed upstream request should be retried on another replica.
+///
+/// This holds for transport errors and server errors, while other errors
+/// (e.g. unknown scopes) would fail on any replica.
+pub(crate) fn is_replica_failure(err: &failure::Error) -> bool {
+    match err.downcast_ref::<reqwest::Error>() {
+        Some(e) => match e.status() {
+            Some(status) => status.is_server_error(),
+            None => true,
+        },
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upstream_pool() {
+        let urls: Vec<reqwest::Url> = ["http://gb-0:8080/v1/graph", "http://gb-1:8080/v1/graph"]
+            .iter()
+            .map(|url| reqwest::Url::parse(url).unwrap())
+            .collect();
+        let pool = UpstreamPool::new(urls.clone());
+        assert_eq!(pool.primary(), &urls[0]);
+        assert_eq!(pool.candidates(), vec![urls[0].clone(), urls[1].clone()]);
+        assert_eq!(pool.candidates(), vec![urls[1].clone(), urls[0].clone()]);
+
+        pool.set_healthy(&urls[0], false);
+        assert_eq!(pool.candidates(), vec![urls[1].clone(), urls[0].clone()]);
+        assert_eq!(pool.candidates(), vec![urls[1].clone(), urls[0].clone()]);
+        pool.set_healthy(&urls[0], true);
+        assert_eq!(pool.candidates()[0], urls[0]);
+
+        let unknown = reqwest::Url::parse("http://gb-2:8080/v1/graph").unwrap();
+        pool.set_healthy(&unknown, false);
+        assert_eq!(pool.urls().count(), 2);
+
+        assert!(!is_replica_failure(&failure::err_msg("missing stream")));
+    }
+}
@@ -1,27 +1,69 @@
+use actix_web::web::Bytes;
 use commons::graph;
 use failure::{bail, Error, Fallible, SyncFailure};
+use reqwest::header::LAST_MODIFIED;
 use reqwest::Method;
-use std::time::Duration;
-
-/// Return a request builder with base URL and parameters set.
-fn new_request(
-    method: reqwest::Method,
-    url: reqwest::Url,
-    req_timeout: Duration,
-) -> Fallible<reqwest::RequestBuilder> {
-    let client = reqwest::ClientBuilder::new().timeout(req_timeout).build()?;
-    let builder = client.request(method, url);
-    Ok(builder)
+use std::cell::Cell;
+use std::time::Instant;
+
+thread_local! {
+    /// Size of the last graph serialized on this thread, to pre-size buffers.
+    static LAST_GRAPH_SIZE: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Graph fetched from the fcos-graph-builder, with relevant response metadata.
+#[derive(Clone, Debug)]
+pub(crate) struct UpstreamGraph {
+    /// Serialized graph, as returned by the fcos-graph-builder.
+    pub(crate) body: Bytes,
+    /// Provenance of the graph, as reported by the `X-Graph-Source` header.
+    pub(crate) source: Option<String>,
+    /// Whether the graph has OCI payloads, as reported by the `X-Graph-OCI` header.
+    ///
+    /// This is unset for graph-builders which do not support OCI graphs.
+    pub(crate) oci: Option<bool>,
+    /// Publication time of the newest release, as reported by the `Last-Modified` header.
+    pub(crate) last_modified: Option<String>,
+    /// Graph generation, as reported by the `X-Graph-Version` header.
+    ///
+    /// This is unset for graph-builders which do not report versions.
+    pub(crate) version: Option<String>,
+}
+
+impl UpstreamGraph {
+    /// Decode the upstream graph.
+    pub(crate) fn parse(&self) -> Fallible<graph::Graph> {
+        let graph = serde_json::from_slice(&self.body)?;
+        Ok(graph)
+    }
+
+    /// Whether another upstream graph is the same generation as this one,
+    /// comparing versions if both are known, or else contents.
+    pub(crate) fn same_generation(&self, other: &Self) -> bool {
+        match (&self.version, &other.version) {
+            (Some(version), Some(other)) => version == other,
+            _ => self.body == other.body,
+        }
+    }
 }
 
 /// Fetch the graph from the fcos-graph-builder instance with the query specified.
+///
+/// If a wariness tier is specified, the pre-built graph variant for that tier
+/// is fetched instead of the full graph. The request ID and trace context are
+/// forwarded upstream, for tracing.
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn fetch_graph_from_gb(
     upstream_base: reqwest::Url,
+    product: Option<String>,
     stream: String,
     basearch: String,
     oci: bool,
-    req_timeout: Duration,
-) -> Result<graph::Graph, Error> {
+    wariness_tier: Option<u8>,
+    client: &reqwest::Client,
+    request_id: &str,
+    traceparent: &str,
+) -> Result<UpstreamGraph, Error> {
     if stream.trim().is_empty() {
         bail!("unexpected missing stream");
     }
@@ -29,21 +71,98 @@ pub(crate) async fn fetch_graph_from_gb(
         bail!("unexpected missing basearch");
     }
     let query = crate::GraphQuery {
+        product,
         stream: Some(stream),
         basearch: Some(basearch),
         rollout_wariness: None,
         node_uuid: None,
+        os_version: None,
         oci: Some(oci),
+        wariness_tier,
+        pretty: None,
     };
     // Cannot use `?` directly here otherwise will produce the error:
     //   the trait `std::marker::Sync` is not implemented for `(dyn std::error::Error + std::marker::Send + 'static)`
     // Reference: https://github.com/rust-lang-nursery/failure/issues/284
     let query_str = serde_qs::to_string(&query).map_err(SyncFailure::new)?;
     let mut target = upstream_base;
-    target.set_query(Some(&query_str));
-    let req = new_request(Method::GET, target, req_timeout)?;
+    target.set_query(Some(&commons::web::canonical_query(&query_str)));
+    let req = client
+        .request(Method::GET, target)
+        .header(commons::web::REQUEST_ID_HEADER, request_id)
+        .header(commons::trace::TRACEPARENT_HEADER, traceparent);
     let resp = req.send().await?;
     let content = resp.error_for_status()?;
-    let json = content.json::<graph::Graph>().await?;
-    Ok(json)
+    let source = content
+        .headers()
+        .get(commons::web::GRAPH_SOURCE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+    let oci = content
+        .headers()
+        .get(commons::web::GRAPH_OCI_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok());
+    let last_modified = content
+        .headers()
+        .get(LAST_MODIFIED)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+    let version = content
+        .headers()
+        .get(commons::web::GRAPH_VERSION_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+    let body = content.bytes().await?;
+    Ok(UpstreamGraph {
+        body,
+        source,
+        oci,
+        last_modified,
+        version,
+    })
+}
+
+/// Check that a fcos-graph-builder instance is reachable.
+///
+/// Any HTTP response counts, as graph requests without parameters are
+/// rejected by design.
+pub(crate) async fn check_upstream(
+    client: &reqwest::Client,
+    upstream_base: reqwest::Url,
+) -> Fallible<()> {
+    client.request(Method::GET, upstream_base).send().await?;
+    Ok(())
+}
+
+/// Fetch the graph scopes served by a fcos-graph-builder instance.
+pub(crate) async fn fetch_streams_from_gb(
+    client: &reqwest::Client,
+    upstream_base: &reqwest::Url,
+) -> Fallible<commons::web::StreamsIndex> {
+    let target = upstream_base.join("streams")?;
+    let resp = client.request(Method::GET, target).send().await?;
+    let index = resp.error_for_status()?.json().await?;
+    Ok(index)
+}
+
+/// Serialize a graph into a response body, compact unless `pretty` is set.
+///
+/// The output buffer is pre-sized based on the previous graph, so that the
+/// common case (similar graphs across requests) serializes without regrowing.
+pub(crate) fn serialize_graph(graph: &graph::Graph, pretty: bool) -> Fallible<Bytes> {
+    let started = Instant::now();
+    let mut buf = Vec::with_capacity(LAST_GRAPH_SIZE.with(Cell::get));
+    if pretty {
+        serde_json::to_writer_pretty(&mut buf, graph)
+    } else {
+        serde_json::to_writer(&mut buf, graph)
+    }
+    .map_err(|e| failure::format_err!("{}", e))?;
+    LAST_GRAPH_SIZE.with(|size| size.set(buf.len()));
+
+    let elapsed = started.elapsed();
+    crate::GRAPH_SERIALIZATION_DURATION.observe(elapsed.as_secs_f64());
+    log::trace!("serialized graph: {} bytes in {:?}", buf.len(), elapsed);
+    Ok(Bytes::from(buf))
 }